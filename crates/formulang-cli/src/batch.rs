@@ -0,0 +1,283 @@
+//! Solving many formulas in one run, as for `formulang solve --all`
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use formulang_lang::{SolveResult, SolveStatus};
+use serde::{Deserialize, Serialize};
+
+/// Outcome of one formula in a batch: a result, or the compile error message
+pub type Outcome = (String, Result<SolveResult, String>);
+
+/// Costs from a run, saved with `--save` and compared with `--compare`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ResultsFile {
+    pub formulas: BTreeMap<String, SavedResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedResult {
+    pub status: String,
+    /// Missing unless the formula was solved to optimality
+    pub cost_per_tonne: Option<f64>,
+}
+
+impl ResultsFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+        serde_json::from_str(&text).map_err(|e| format!("Error reading {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json + "\n").map_err(|e| format!("Error writing {}: {}", path.display(), e))
+    }
+
+    pub fn from_outcomes(outcomes: &[Outcome]) -> Self {
+        let formulas = outcomes
+            .iter()
+            .map(|(name, outcome)| {
+                let saved = SavedResult {
+                    status: status_label(outcome).to_string(),
+                    cost_per_tonne: cost_per_tonne(outcome),
+                };
+                (name.clone(), saved)
+            })
+            .collect();
+        Self { formulas }
+    }
+
+    fn previous_cost(&self, name: &str) -> Option<f64> {
+        self.formulas.get(name).and_then(|saved| saved.cost_per_tonne)
+    }
+}
+
+/// Cost of 1000 units of the batch, for optimal solutions
+pub fn cost_per_tonne(outcome: &Result<SolveResult, String>) -> Option<f64> {
+    match outcome {
+        Ok(result) if result.status == SolveStatus::Optimal && result.batch_size > 0.0 => {
+            Some(result.total_cost / result.batch_size * 1000.0)
+        }
+        _ => None,
+    }
+}
+
+fn status_label(outcome: &Result<SolveResult, String>) -> &'static str {
+    match outcome {
+        Ok(result) => match result.status {
+            SolveStatus::Optimal => "optimal",
+            SolveStatus::Infeasible => "infeasible",
+            SolveStatus::Unbounded => "unbounded",
+            SolveStatus::NodeLimit => "node limit",
+            SolveStatus::Error => "error",
+        },
+        Err(_) => "compile error",
+    }
+}
+
+/// Match a formula name against a pattern where `*` stands for any run of
+/// characters and `?` for any one character
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it was tried at
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, from)) => {
+                    p = star + 1;
+                    n = from + 1;
+                    backtrack = Some((star, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether a formula argument is a pattern rather than a single name
+pub fn is_pattern(formula: &str) -> bool {
+    formula.contains(['*', '?'])
+}
+
+/// Print one line per formula with its status, cost per tonne, and the
+/// change against a previous run
+pub fn print_summary(outcomes: &[Outcome], previous: Option<&ResultsFile>) {
+    println!(
+        "{:30} {:>13} {:>12} {:>12} {:>20}",
+        "Formula", "Status", "Cost/t", "Previous", "Change"
+    );
+    for (name, outcome) in outcomes {
+        let cost = cost_per_tonne(outcome);
+        let before = previous.and_then(|p| p.previous_cost(name));
+        let change = match (cost, before) {
+            (Some(cost), Some(before)) => format_change(cost, before),
+            _ => String::new(),
+        };
+        println!(
+            "{:30} {:>13} {:>12} {:>12} {:>20}",
+            name,
+            status_label(outcome),
+            format_cost(cost),
+            format_cost(before),
+            change
+        );
+    }
+
+    let errors: Vec<(&String, &String)> = outcomes
+        .iter()
+        .filter_map(|(name, outcome)| outcome.as_ref().err().map(|e| (name, e)))
+        .collect();
+    if !errors.is_empty() {
+        println!();
+        for (name, message) in errors {
+            println!("{}: {}", name, message);
+        }
+    }
+}
+
+/// The summary as CSV, with empty fields where a value is unknown
+pub fn print_summary_csv(outcomes: &[Outcome], previous: Option<&ResultsFile>) {
+    println!("formula,status,cost_per_tonne,previous_cost_per_tonne,change");
+    for (name, outcome) in outcomes {
+        let cost = cost_per_tonne(outcome);
+        let before = previous.and_then(|p| p.previous_cost(name));
+        let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+        println!(
+            "{},{},{},{},{}",
+            name,
+            status_label(outcome),
+            optional(cost),
+            optional(before),
+            optional(change(cost, before))
+        );
+    }
+}
+
+/// A formula's full result in a batch's JSON
+#[derive(Serialize)]
+struct BatchResult {
+    #[serde(flatten)]
+    result: SolveResult,
+    /// Only when comparing against a previous run
+    #[serde(flatten)]
+    comparison: Option<Comparison>,
+}
+
+#[derive(Serialize)]
+struct Comparison {
+    cost_per_tonne: Option<f64>,
+    previous_cost_per_tonne: Option<f64>,
+    change: Option<f64>,
+}
+
+/// Each formula's result as JSON, with its cost per tonne against a
+/// previous run when there is one
+pub fn print_summary_json(outcomes: &[Outcome], previous: Option<&ResultsFile>) {
+    match serde_json::to_string_pretty(&batch_results(outcomes, previous)) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("Error serializing results: {}", e),
+    }
+}
+
+fn batch_results(outcomes: &[Outcome], previous: Option<&ResultsFile>) -> Vec<BatchResult> {
+    outcomes
+        .iter()
+        .map(|(name, outcome)| {
+            let comparison = previous.map(|previous| {
+                let cost = cost_per_tonne(outcome);
+                let before = previous.previous_cost(name);
+                Comparison {
+                    cost_per_tonne: cost,
+                    previous_cost_per_tonne: before,
+                    change: change(cost, before),
+                }
+            });
+            let result = match outcome {
+                Ok(result) => result.clone(),
+                Err(message) => SolveResult::error(name, message.clone()),
+            };
+            BatchResult { result, comparison }
+        })
+        .collect()
+}
+
+/// Change in cost per tonne, when both runs have one
+fn change(cost: Option<f64>, before: Option<f64>) -> Option<f64> {
+    Some(cost? - before?)
+}
+
+fn format_cost(cost: Option<f64>) -> String {
+    cost.map(|c| format!("{:.2}", c)).unwrap_or_else(|| "-".to_string())
+}
+
+fn format_change(cost: f64, before: f64) -> String {
+    let change = cost - before;
+    if before != 0.0 {
+        format!("{:+.2} ({:+.2}%)", change, change / before * 100.0)
+    } else {
+        format!("{:+.2}", change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "starter"));
+        assert!(glob_match("broiler_*", "broiler_starter"));
+        assert!(glob_match("*_starter", "broiler_starter"));
+        assert!(glob_match("b*r*r", "broiler_starter"));
+        assert!(glob_match("phase?", "phase2"));
+        assert!(!glob_match("phase?", "phase10"));
+        assert!(!glob_match("broiler_*", "layer_starter"));
+        assert!(glob_match("starter", "starter"));
+        assert!(!glob_match("starter", "starter2"));
+    }
+
+    #[test]
+    fn test_summary_json_comparison() {
+        let source = r#"
+            nutrient protein {}
+            ingredient corn { cost 150 nutrients { protein 8 } }
+            formula grower { batch 100 nutrients { protein min 5 } ingredients { corn } }
+        "#;
+        let mut compiler = formulang_lang::Compiler::new();
+        compiler.load(&formulang_lang::Parser::parse(source).unwrap()).unwrap();
+        let compiled = compiler.compile_formula("grower").unwrap();
+        let solution = formulang_solver::Solver::new().solve(&compiled.lp_problem);
+        let outcomes: Vec<Outcome> = vec![
+            ("grower".to_string(), Ok(SolveResult::new(&compiler, &compiled, &solution))),
+            ("broken".to_string(), Err("Unknown nutrient: fat".to_string())),
+        ];
+
+        // Without a previous run, the results are as for a single formula
+        let json = serde_json::to_value(batch_results(&outcomes, None)).unwrap();
+        assert!(json[0].get("change").is_none());
+        assert_eq!(json[0]["total_cost"], 15000.0);
+
+        let mut previous = ResultsFile::from_outcomes(&outcomes);
+        previous.formulas.get_mut("grower").unwrap().cost_per_tonne = Some(140000.0);
+        let json = serde_json::to_value(batch_results(&outcomes, Some(&previous))).unwrap();
+        assert_eq!(json[0]["cost_per_tonne"], 150000.0);
+        assert_eq!(json[0]["previous_cost_per_tonne"], 140000.0);
+        assert_eq!(json[0]["change"], 10000.0);
+        assert_eq!(json[1]["status"], "error");
+        assert!(json[1]["change"].is_null());
+    }
+}
@@ -0,0 +1,367 @@
+//! Language server over stdio, for editors that speak LSP
+//!
+//! Wraps the same completions, hover, diagnostics and semantic tokens the
+//! Monaco package gets through WASM. Imports are followed on disk, so names
+//! from other files are known.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use formulang_lang::analysis::{self, ImportedNames, Severity};
+use formulang_lang::{ide, Compiler, Item, Parser};
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, HoverRequest, Request as _, SemanticTokensFullRequest};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InsertTextFormat, MarkupContent, MarkupKind, NumberOrString, Position,
+    PublishDiagnosticsParams, Range, SemanticToken, SemanticTokenType, SemanticTokens,
+    SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions, SemanticTokensParams,
+    SemanticTokensResult, SemanticTokensServerCapabilities, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+/// Token types in the order they are advertised; `ide` names them the same way
+const TOKEN_TYPES: &[&str] = &["keyword", "type", "class", "variable", "number", "string", "comment", "operator"];
+
+/// Serve requests on stdin/stdout until the client shuts the server down
+pub fn run() -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (connection, io_threads) = Connection::stdio();
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+
+    let mut server = Server::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = server.handle_request(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                for params in server.handle_notification(notification) {
+                    let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+                    connection.sender.send(Message::Notification(notification))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    // The writer thread finishes once the connection is gone
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".to_string()]),
+            ..Default::default()
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: SemanticTokensLegend {
+                    token_types: TOKEN_TYPES.iter().map(|&t| SemanticTokenType::new(t)).collect(),
+                    token_modifiers: Vec::new(),
+                },
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    }
+}
+
+/// An open document and what its imports provide
+struct Document {
+    text: String,
+    imported: Option<ImportedNames>,
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Url, Document>,
+}
+
+impl Server {
+    fn handle_request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            Completion::METHOD => extract(request).map(|p| serde_json::to_value(self.completion(p))),
+            HoverRequest::METHOD => extract(request).map(|p| serde_json::to_value(self.hover(p))),
+            SemanticTokensFullRequest::METHOD => {
+                extract(request).map(|p| serde_json::to_value(self.semantic_tokens(p)))
+            }
+            _ => return Response::new_err(id, lsp_server::ErrorCode::MethodNotFound as i32, request.method),
+        };
+        match result {
+            Ok(Ok(value)) => Response::new_ok(id, value),
+            Ok(Err(e)) => Response::new_err(id, lsp_server::ErrorCode::InternalError as i32, e.to_string()),
+            Err(e) => Response::new_err(id, lsp_server::ErrorCode::InvalidParams as i32, e),
+        }
+    }
+
+    /// Update documents and return diagnostics to publish
+    fn handle_notification(&mut self, notification: Notification) -> Vec<PublishDiagnosticsParams> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                if let Ok(params) = notification.extract::<DidOpenTextDocumentParams>(DidOpenTextDocument::METHOD) {
+                    return vec![self.update(params.text_document.uri, params.text_document.text)];
+                }
+            }
+            DidChangeTextDocument::METHOD => {
+                if let Ok(mut params) =
+                    notification.extract::<DidChangeTextDocumentParams>(DidChangeTextDocument::METHOD)
+                    && let Some(change) = params.content_changes.pop()
+                {
+                    return vec![self.update(params.text_document.uri, change.text)];
+                }
+            }
+            DidSaveTextDocument::METHOD => {
+                // Another open file may import the saved one
+                let open: Vec<(Url, String)> =
+                    self.documents.iter().map(|(uri, doc)| (uri.clone(), doc.text.clone())).collect();
+                return open.into_iter().map(|(uri, text)| self.update(uri, text)).collect();
+            }
+            DidCloseTextDocument::METHOD => {
+                if let Ok(params) = notification.extract::<DidCloseTextDocumentParams>(DidCloseTextDocument::METHOD) {
+                    self.documents.remove(&params.text_document.uri);
+                    return vec![PublishDiagnosticsParams::new(params.text_document.uri, Vec::new(), None)];
+                }
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    /// Store new text for a document and check it, following its imports
+    fn update(&mut self, uri: Url, text: String) -> PublishDiagnosticsParams {
+        let (program, parse_errors) = Parser::parse_resilient(&text);
+        let mut diagnostics: Vec<analysis::Diagnostic> = parse_errors
+            .iter()
+            .map(|e| analysis::parse_error_diagnostic(&text, e))
+            .collect();
+
+        let path: Option<PathBuf> = uri.to_file_path().ok();
+        let mut imported = None;
+        if let Some(path) = &path {
+            let mut compiler = Compiler::new();
+            let loaded = compiler.load_program(&program, path);
+            imported = compiler.imported_names(path);
+            if loaded.is_ok() {
+                diagnostics.extend(analysis::check_nutrient_data(&compiler, &program));
+            }
+
+            // Imports that failed to load are reported on the first import
+            if let Err(e) = loaded
+                && imported.is_none()
+                && let Some(span) = program.items.iter().find_map(|item| match item {
+                    Item::Import(import) => Some(import.span),
+                    _ => None,
+                })
+            {
+                diagnostics.push(analysis::Diagnostic::error(
+                    analysis::DiagnosticCode::ImportError,
+                    span,
+                    e.to_string(),
+                ));
+            }
+        }
+        diagnostics.extend(analysis::check_program(&program, imported.as_ref()));
+
+        let index = LineIndex::new(&text);
+        let diagnostics: Vec<Diagnostic> = diagnostics
+            .into_iter()
+            .map(|d| Diagnostic {
+                range: index.range(d.span.start, d.span.end),
+                severity: Some(match d.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                code: Some(NumberOrString::String(d.code.to_string())),
+                source: Some("formulang".to_string()),
+                message: d.message,
+                ..Default::default()
+            })
+            .collect();
+
+        self.documents.insert(uri.clone(), Document { text, imported });
+        PublishDiagnosticsParams::new(uri, diagnostics, None)
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let position = params.text_document_position;
+        let doc = self.documents.get(&position.text_document.uri)?;
+        let offset = LineIndex::new(&doc.text).offset(position.position);
+
+        let items = ide::compute_completions(&doc.text, offset, doc.imported.as_ref())
+            .into_iter()
+            .map(|c| CompletionItem {
+                kind: Some(match c.kind.as_str() {
+                    "keyword" => CompletionItemKind::KEYWORD,
+                    "property" => CompletionItemKind::PROPERTY,
+                    _ => CompletionItemKind::VARIABLE,
+                }),
+                insert_text_format: Some(if c.insert_text.contains('$') {
+                    InsertTextFormat::SNIPPET
+                } else {
+                    InsertTextFormat::PLAIN_TEXT
+                }),
+                label: c.label,
+                detail: c.detail,
+                insert_text: Some(c.insert_text),
+                ..Default::default()
+            })
+            .collect();
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let doc = self.documents.get(&position.text_document.uri)?;
+        let index = LineIndex::new(&doc.text);
+
+        let hover = ide::compute_hover(&doc.text, index.offset(position.position), doc.imported.as_ref())?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value: hover.contents }),
+            range: Some(index.range(hover.start, hover.end)),
+        })
+    }
+
+    fn semantic_tokens(&self, params: SemanticTokensParams) -> Option<SemanticTokensResult> {
+        let doc = self.documents.get(&params.text_document.uri)?;
+        let index = LineIndex::new(&doc.text);
+
+        // Positions are relative to the previous token
+        let mut data = Vec::new();
+        let mut previous = Position::new(0, 0);
+        for token in ide::compute_semantic_tokens(&doc.text) {
+            let Some(token_type) = TOKEN_TYPES.iter().position(|&t| t == token.token_type) else {
+                continue;
+            };
+            let Range { start, end } = index.range(token.start, token.end);
+            if start.line != end.line {
+                continue;
+            }
+            let delta_line = start.line - previous.line;
+            data.push(SemanticToken {
+                delta_line,
+                delta_start: if delta_line == 0 { start.character - previous.character } else { start.character },
+                length: end.character - start.character,
+                token_type: token_type as u32,
+                token_modifiers_bitset: 0,
+            });
+            previous = start;
+        }
+        Some(SemanticTokensResult::Tokens(SemanticTokens { result_id: None, data }))
+    }
+}
+
+fn extract<P: serde::de::DeserializeOwned>(request: Request) -> Result<P, String> {
+    let method = request.method.clone();
+    request.extract(&method).map(|(_, params): (RequestId, P)| params).map_err(|e| e.to_string())
+}
+
+/// Converts byte offsets to LSP positions and back. LSP counts characters
+/// in UTF-16 code units.
+struct LineIndex<'a> {
+    text: &'a str,
+    /// Byte offset where each line starts
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        Self { text, line_starts }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.text.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.line_starts[line];
+        let character = self.text[start..offset].encode_utf16().count();
+        Position::new(line as u32, character as u32)
+    }
+
+    fn range(&self, start: usize, end: usize) -> Range {
+        Range::new(self.position(start), self.position(end))
+    }
+
+    /// Byte offset of a position, clamped to the end of its line
+    fn offset(&self, position: Position) -> usize {
+        let Some(&start) = self.line_starts.get(position.line as usize) else {
+            return self.text.len();
+        };
+        let line = self.text[start..].split('\n').next().unwrap_or("");
+        let mut units = 0;
+        for (i, c) in line.char_indices() {
+            if units >= position.character as usize {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        start + line.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_index() {
+        // "é" is two bytes but one UTF-16 unit; "𝄞" is four bytes and two units
+        let text = "nutrient é {}\n// 𝄞 x\nfoo";
+        let index = LineIndex::new(text);
+
+        let x = text.find('x').unwrap();
+        assert_eq!(index.position(x), Position::new(1, 6));
+        assert_eq!(index.offset(Position::new(1, 6)), x);
+
+        let brace = text.find('{').unwrap();
+        assert_eq!(index.position(brace), Position::new(0, 11));
+        assert_eq!(index.offset(Position::new(0, 11)), brace);
+
+        // Past the end of a line or the file
+        assert_eq!(index.offset(Position::new(0, 99)), text.find('\n').unwrap());
+        assert_eq!(index.offset(Position::new(9, 0)), text.len());
+        assert_eq!(index.position(text.len()), Position::new(2, 3));
+    }
+
+    #[test]
+    fn test_diagnostics_follow_imports() {
+        let dir = std::env::temp_dir().join(format!("formulang-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("nutrients.fm"), "nutrient protein {}\n").unwrap();
+        let path = dir.join("main.fm");
+        std::fs::write(&path, "").unwrap();
+
+        let text = "import ./nutrients.fm { protein }\n\ningredient corn {\n  cost 1\n  nutrients { protein 8 ash 1 }\n}\n";
+        let mut server = Server::default();
+        let uri = Url::from_file_path(&path).unwrap();
+        let published = server.update(uri.clone(), text.to_string());
+
+        // `protein` comes from the import; only `ash` is undefined
+        assert_eq!(published.diagnostics.len(), 1);
+        let diagnostic = &published.diagnostics[0];
+        assert_eq!(diagnostic.code, Some(NumberOrString::String("undefined-nutrient".to_string())));
+        assert_eq!(diagnostic.range.start, Position::new(4, 24));
+
+        let doc = &server.documents[&uri];
+        let completions = ide::compute_completions(text, text.find("ash").unwrap(), doc.imported.as_ref());
+        assert!(completions.iter().any(|c| c.label == "protein"));
+    }
+}
@@ -1,5 +1,12 @@
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+mod batch;
+mod lsp;
+mod output;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use formulang_lang::analysis::{self, Diagnostic, Severity};
+use formulang_lang::{CompileError, Compiler, PlanResult, SolveResult, SolveStatus};
+use output::{OutputFormat, PlanFormat, exit_code};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(name = "formulang")]
@@ -9,6 +16,15 @@ struct Cli {
     command: Commands,
 }
 
+/// Text format of `formulang export-lp`
+#[derive(Clone, Copy, ValueEnum)]
+enum LpFormat {
+    /// Free-format MPS
+    Mps,
+    /// CPLEX LP
+    Lp,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Parse a .fm file and output the AST
@@ -23,17 +39,72 @@ enum Commands {
     Solve {
         /// The file containing the formula
         file: PathBuf,
-        /// The formula name to solve
-        formula: String,
-        /// Show detailed analysis
+        /// The formula name to solve, or a pattern like `broiler_*` to solve
+        /// every matching formula
+        #[arg(required_unless_present = "all")]
+        formula: Option<String>,
+        /// Solve every formula that is not a template
+        #[arg(long, conflicts_with = "formula")]
+        all: bool,
+        /// Show detailed analysis (table format; JSON always includes it).
+        /// With a summary, each formula's solution is shown before it.
+        #[arg(short, long)]
+        analysis: bool,
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+        /// Compare costs against a results file saved by an earlier run.
+        /// Output is then a cost summary, even for one formula. As JSON,
+        /// each result also has its cost per tonne, the previous one and the change.
+        #[arg(long, value_name = "FILE")]
+        compare: Option<PathBuf>,
+        /// Save the costs of this run to a results file. Output is then a
+        /// cost summary, even for one formula.
+        #[arg(long, value_name = "FILE")]
+        save: Option<PathBuf>,
+    },
+    /// Solve the formulas of a plan together, sharing the available stock
+    Plan {
+        /// The file containing the plan
+        file: PathBuf,
+        /// The plan name to solve
+        plan: String,
+        /// Show detailed analysis of each formula (table format; JSON always includes it)
         #[arg(short, long)]
         analysis: bool,
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = PlanFormat::Table)]
+        format: PlanFormat,
+    },
+    /// Write the LP problem a formula compiles to, for use in other solvers
+    ExportLp {
+        /// The file containing the formula
+        file: PathBuf,
+        /// The formula name to export
+        formula: String,
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = LpFormat::Mps)]
+        format: LpFormat,
+        /// Write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
     },
     /// Check a .fm file for errors
     Check {
         /// The file to check
         file: PathBuf,
     },
+    /// Format .fm files in place
+    Fmt {
+        /// The files to format
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// List files that are not formatted instead of rewriting them
+        #[arg(long)]
+        check: bool,
+    },
+    /// Run a language server on stdin/stdout for editor integration
+    Lsp,
 }
 
 fn main() {
@@ -65,7 +136,87 @@ fn main() {
                 }
             }
         }
-        Commands::Solve { file, formula, analysis } => {
+        Commands::Solve { file, formula, all, analysis, format, compare, save } => {
+            let compiler = load_compiler(&file);
+            let formula = formula.unwrap_or_default();
+
+            // Costs are saved and compared from a summary, even of one formula
+            if all || batch::is_pattern(&formula) || compare.is_some() || save.is_some() {
+                let pattern = if all { "*" } else { formula.as_str() };
+                let compiler = compiler.unwrap_or_else(|message| {
+                    eprintln!("{}", message);
+                    std::process::exit(exit_code::COMPILE_ERROR);
+                });
+                solve_batch(&compiler, pattern, format, analysis, compare.as_deref(), save.as_deref());
+            }
+
+            let result = match compiler.and_then(|compiler| solve_formula(&compiler, &formula)) {
+                Ok(result) => result,
+                Err(message) => {
+                    if format == OutputFormat::Json {
+                        output::print_json(&SolveResult::error(&formula, message));
+                    } else {
+                        eprintln!("{}", message);
+                    }
+                    std::process::exit(exit_code::COMPILE_ERROR);
+                }
+            };
+
+            match format {
+                OutputFormat::Table => output::print_table(&result, analysis),
+                OutputFormat::Json => output::print_json(&result),
+                OutputFormat::Csv => output::print_csv(&result),
+            }
+            std::process::exit(output::status_exit_code(result.status));
+        }
+        Commands::Plan { file, plan, analysis, format } => {
+            let result = load_compiler(&file).and_then(|compiler| {
+                let compiled = compiler.compile_plan(&plan).map_err(|e| located(&e, Some(&file)))?;
+                let solution = formulang_solver::Solver::new().solve(&compiled.lp_problem);
+                Ok(PlanResult::new(&compiler, &compiled, &solution))
+            });
+            let result = result.unwrap_or_else(|message| {
+                if format == PlanFormat::Json {
+                    output::print_json(&PlanResult::error(&plan, message));
+                } else {
+                    eprintln!("{}", message);
+                }
+                std::process::exit(exit_code::COMPILE_ERROR);
+            });
+
+            match format {
+                PlanFormat::Table => output::print_plan_table(&result, analysis),
+                PlanFormat::Json => output::print_json(&result),
+                PlanFormat::Csv => output::print_plan_csv(&result),
+            }
+            std::process::exit(output::status_exit_code(result.status));
+        }
+        Commands::ExportLp { file, formula, format, output } => {
+            let compiled = load_compiler(&file).and_then(|compiler| {
+                compiler
+                    .compile_formula(&formula)
+                    .map_err(|e| located(&e, compiler.symbols.formula_files.get(&formula).map(PathBuf::as_path)))
+            });
+            let compiled = compiled.unwrap_or_else(|message| {
+                eprintln!("{}", message);
+                std::process::exit(exit_code::COMPILE_ERROR);
+            });
+
+            let text = match format {
+                LpFormat::Mps => compiled.lp_problem.to_mps(&compiled.name),
+                LpFormat::Lp => compiled.lp_problem.to_cplex_lp(&compiled.name),
+            };
+            match output {
+                Some(path) => {
+                    if let Err(e) = std::fs::write(&path, text) {
+                        eprintln!("Error writing {}: {}", path.display(), e);
+                        std::process::exit(1);
+                    }
+                }
+                None => print!("{}", text),
+            }
+        }
+        Commands::Check { file } => {
             let source = match std::fs::read_to_string(&file) {
                 Ok(s) => s,
                 Err(e) => {
@@ -74,142 +225,253 @@ fn main() {
                 }
             };
 
-            let program = match formulang_lang::Parser::parse(&source) {
-                Ok(p) => p,
-                Err(e) => {
-                    eprintln!("Parse error: {}", e);
-                    std::process::exit(1);
-                }
-            };
+            let (program, parse_errors) = formulang_lang::Parser::parse_resilient(&source);
+            let mut diagnostics: Vec<Diagnostic> = parse_errors
+                .iter()
+                .map(|e| analysis::parse_error_diagnostic(&source, e))
+                .collect();
 
-            // Compile
+            // Follow imports so that imported names count as defined
             let mut compiler = formulang_lang::Compiler::new();
-            if let Err(e) = compiler.load(&program) {
-                eprintln!("Compile error: {}", e);
-                std::process::exit(1);
+            let loaded = compiler.load_file(&file);
+            let imported = compiler.imported_names(&file);
+            diagnostics.extend(analysis::check_program(&program, imported.as_ref()));
+            if loaded.is_ok() {
+                diagnostics.extend(analysis::check_nutrient_data(&compiler, &program));
             }
 
-            let compiled = match compiler.compile_formula(&formula) {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Compile error: {}", e);
-                    std::process::exit(1);
+            let mut has_errors = diagnostics.iter().any(|d| d.severity == Severity::Error);
+            let mut messages: Vec<String> = diagnostics
+                .iter()
+                .map(|d| {
+                    let (line, column) = line_col(&source, d.span.start);
+                    format!("{}:{}:{}: {}[{}]: {}", file.display(), line, column, d.severity, d.code, d.message)
+                })
+                .collect();
+
+            // Loading catches what the checks can't, such as broken imports.
+            // An error with a span is in the file itself once its imports
+            // have loaded, and is left out if the checks report it there.
+            // Without one, it is left out if other errors explain it, as a
+            // parse error in the file itself does.
+            if let Err(e) = loaded {
+                let span = e.span().filter(|_| imported.is_some());
+                let reported = match span {
+                    Some(span) => diagnostics.iter().any(|d| d.span == span),
+                    None => has_errors && (imported.is_some() || !parse_errors.is_empty()),
+                };
+                if !reported {
+                    let location = match span {
+                        Some(span) => {
+                            let (line, column) = line_col(&source, span.start);
+                            format!("{}:{}:{}", file.display(), line, column)
+                        }
+                        None => file.display().to_string(),
+                    };
+                    messages.push(format!("{}: error: {}", location, e));
+                    has_errors = true;
                 }
-            };
+            }
 
-            // Solve
-            let solver = formulang_solver::Solver::new();
-            let solution = solver.solve(&compiled.lp_problem);
+            if has_errors {
+                eprintln!("✗ {} has errors:", file.display());
+                for message in &messages {
+                    eprintln!("  {}", message);
+                }
+                std::process::exit(1);
+            }
 
-            // Output results
-            println!("Formula: {}", compiled.name);
-            if let Some(ref desc) = compiled.description {
-                println!("Description: {}", desc);
+            for message in &messages {
+                eprintln!("{}", message);
             }
-            println!("Batch size: {}", compiled.batch_size);
-            println!();
 
-            match solution.status {
-                formulang_solver::SolutionStatus::Optimal => {
-                    println!("Status: OPTIMAL");
-                    println!("Total cost: {:.2}", solution.objective_value);
-                    println!();
-                    println!("Ingredients:");
-                    for (i, name) in compiled.ingredients.iter().enumerate() {
-                        let amount = solution.values[i];
-                        let pct = amount / compiled.batch_size * 100.0;
-                        if amount > 0.001 {
-                            println!("  {:20} {:10.2} ({:5.2}%)", name, amount, pct);
-                        }
-                    }
+            let mut nutrients = 0;
+            let mut ingredients = 0;
+            let mut formulas = 0;
+            let mut imports = 0;
+            let mut constants = 0;
+            let mut plans = 0;
 
-                    if analysis {
-                        println!();
-                        println!("Analysis:");
-                        println!();
-
-                        if !solution.analysis.binding_constraints.is_empty() {
-                            println!("Binding constraints (pinch points):");
-                            for name in &solution.analysis.binding_constraints {
-                                println!("  - {}", name);
-                            }
-                            println!();
-                        }
+            for item in &program.items {
+                match item {
+                    formulang_lang::Item::Nutrient(_) => nutrients += 1,
+                    formulang_lang::Item::Ingredient(_) => ingredients += 1,
+                    formulang_lang::Item::Formula(_) => formulas += 1,
+                    formulang_lang::Item::Import(_) => imports += 1,
+                    formulang_lang::Item::Const(_) => constants += 1,
+                    formulang_lang::Item::Plan(_) => plans += 1,
+                }
+            }
 
-                        println!("Shadow prices:");
-                        for sp in &solution.analysis.shadow_prices {
-                            if sp.value.abs() > 0.001 {
-                                println!("  {:30} {:10.4}", sp.constraint, sp.value);
-                                println!("    {}", sp.interpretation);
-                            }
-                        }
-                        println!();
-
-                        println!("Reduced costs (ingredients not in solution):");
-                        for rc in &solution.analysis.reduced_costs {
-                            if !rc.is_basic && rc.reduced_cost.abs() > 0.001 {
-                                println!(
-                                    "  {:20} cost must decrease by {:.2} to enter solution",
-                                    rc.variable, rc.reduced_cost
-                                );
-                            }
-                        }
+            println!("✓ {} is valid", file.display());
+            println!("  {} imports", imports);
+            println!("  {} constants", constants);
+            println!("  {} nutrients", nutrients);
+            println!("  {} ingredients", ingredients);
+            println!("  {} formulas", formulas);
+            println!("  {} plans", plans);
+        }
+        Commands::Fmt { files, check } => {
+            let mut failed = false;
+            let mut unformatted = Vec::new();
+
+            for file in &files {
+                let source = match std::fs::read_to_string(file) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        eprintln!("Error reading {}: {}", file.display(), e);
+                        failed = true;
+                        continue;
                     }
+                };
+                let formatted = match formulang_lang::format_source(&source) {
+                    Ok(formatted) => formatted,
+                    Err(e) => {
+                        eprintln!("{}: parse error: {}", file.display(), e);
+                        failed = true;
+                        continue;
+                    }
+                };
+                if formatted == source {
+                    continue;
                 }
-                formulang_solver::SolutionStatus::Infeasible => {
-                    println!("Status: INFEASIBLE");
-                    println!("No solution exists that satisfies all constraints.");
-                    std::process::exit(1);
-                }
-                formulang_solver::SolutionStatus::Unbounded => {
-                    println!("Status: UNBOUNDED");
-                    println!("The problem has no finite optimal solution.");
-                    std::process::exit(1);
+
+                if check {
+                    unformatted.push(file);
+                } else if let Err(e) = std::fs::write(file, formatted) {
+                    eprintln!("Error writing {}: {}", file.display(), e);
+                    failed = true;
                 }
-                formulang_solver::SolutionStatus::Error => {
-                    println!("Status: ERROR");
-                    println!("Solver encountered an error.");
-                    std::process::exit(1);
+            }
+
+            if !unformatted.is_empty() {
+                eprintln!("✗ {} file(s) need formatting:", unformatted.len());
+                for file in &unformatted {
+                    eprintln!("  {}", file.display());
                 }
             }
+            if failed || !unformatted.is_empty() {
+                std::process::exit(1);
+            }
         }
-        Commands::Check { file } => {
-            let source = match std::fs::read_to_string(&file) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("Error reading file: {}", e);
-                    std::process::exit(1);
-                }
-            };
+        Commands::Lsp => {
+            if let Err(e) = lsp::run() {
+                eprintln!("Language server error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
 
-            match formulang_lang::Parser::parse(&source) {
-                Ok(program) => {
-                    let mut nutrients = 0;
-                    let mut ingredients = 0;
-                    let mut formulas = 0;
-                    let mut imports = 0;
-
-                    for item in &program.items {
-                        match item {
-                            formulang_lang::Item::Nutrient(_) => nutrients += 1,
-                            formulang_lang::Item::Ingredient(_) => ingredients += 1,
-                            formulang_lang::Item::Formula(_) => formulas += 1,
-                            formulang_lang::Item::Import(_) => imports += 1,
-                        }
-                    }
+/// 1-based line and column of a byte offset
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
 
-                    println!("✓ {} is valid", file.display());
-                    println!("  {} imports", imports);
-                    println!("  {} nutrients", nutrients);
-                    println!("  {} ingredients", ingredients);
-                    println!("  {} formulas", formulas);
-                }
-                Err(e) => {
-                    eprintln!("✗ {} has errors:", file.display());
-                    eprintln!("  {}", e);
-                    std::process::exit(1);
+/// Load a file and its imports. Errors are the message to show.
+fn load_compiler(file: &Path) -> Result<Compiler, String> {
+    let mut compiler = Compiler::new();
+    match compiler.load_file(file) {
+        Ok(()) => Ok(compiler),
+        Err(e @ (CompileError::IoError(_) | CompileError::ParseError(..))) => Err(e.to_string()),
+        // Once its imports have loaded, an error is in the file's own declarations
+        Err(e) => Err(located(&e, compiler.imported_names(file).is_some().then_some(file))),
+    }
+}
+
+/// A compile error, prefixed with `file:line:col` when it points into `file`
+fn located(error: &CompileError, file: Option<&Path>) -> String {
+    let message = format!("Compile error: {}", error);
+    let (Some(span), Some(file)) = (error.span(), file) else {
+        return message;
+    };
+    match std::fs::read_to_string(file) {
+        Ok(source) if span.end <= source.len() => {
+            let (line, column) = line_col(&source, span.start);
+            format!("{}:{}:{}: {}", file.display(), line, column, message)
+        }
+        _ => message,
+    }
+}
+
+/// Compile and solve one formula. Errors are the message to show for a
+/// formula that cannot be compiled.
+fn solve_formula(compiler: &Compiler, formula: &str) -> Result<SolveResult, String> {
+    let compiled = compiler
+        .compile_formula(formula)
+        .map_err(|e| located(&e, compiler.symbols.formula_files.get(formula).map(PathBuf::as_path)))?;
+
+    let solution = formulang_solver::Solver::new().solve(&compiled.lp_problem);
+    Ok(SolveResult::new(compiler, &compiled, &solution))
+}
+
+/// Solve every formula matching `pattern` and exit. Exits with the code of
+/// the first formula, by name, that was not solved to optimality.
+fn solve_batch(
+    compiler: &Compiler,
+    pattern: &str,
+    format: OutputFormat,
+    analysis: bool,
+    compare: Option<&Path>,
+    save: Option<&Path>,
+) -> ! {
+    let mut names: Vec<String> = compiler
+        .solvable_formula_names()
+        .into_iter()
+        .filter(|name| batch::glob_match(pattern, name))
+        .collect();
+    names.sort();
+    if names.is_empty() {
+        eprintln!("No formulas match {}", pattern);
+        std::process::exit(exit_code::COMPILE_ERROR);
+    }
+
+    let previous = compare.map(|path| {
+        batch::ResultsFile::load(path).unwrap_or_else(|message| {
+            eprintln!("{}", message);
+            std::process::exit(exit_code::IO_ERROR);
+        })
+    });
+
+    let outcomes: Vec<batch::Outcome> = names
+        .into_iter()
+        .map(|name| {
+            let outcome = solve_formula(compiler, &name);
+            (name, outcome)
+        })
+        .collect();
+
+    match format {
+        OutputFormat::Table => {
+            if analysis {
+                for result in outcomes.iter().filter_map(|(_, outcome)| outcome.as_ref().ok()) {
+                    output::print_table(result, true);
+                    println!();
                 }
             }
+            batch::print_summary(&outcomes, previous.as_ref())
         }
+        OutputFormat::Csv => batch::print_summary_csv(&outcomes, previous.as_ref()),
+        OutputFormat::Json => batch::print_summary_json(&outcomes, previous.as_ref()),
     }
+
+    if let Some(path) = save
+        && let Err(message) = batch::ResultsFile::from_outcomes(&outcomes).save(path)
+    {
+        eprintln!("{}", message);
+        std::process::exit(exit_code::IO_ERROR);
+    }
+
+    let code = outcomes
+        .iter()
+        .find_map(|(_, outcome)| match outcome {
+            Ok(result) if result.status == SolveStatus::Optimal => None,
+            Ok(result) => Some(output::status_exit_code(result.status)),
+            Err(_) => Some(exit_code::COMPILE_ERROR),
+        })
+        .unwrap_or(exit_code::OPTIMAL);
+    std::process::exit(code);
 }
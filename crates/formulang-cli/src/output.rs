@@ -0,0 +1,427 @@
+//! Rendering of solve results for `formulang solve` and `formulang plan`
+
+use clap::ValueEnum;
+use formulang_lang::{PlanResult, SolveResult, SolveStatus};
+
+/// Output format of `formulang solve`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable report
+    Table,
+    /// The full result as JSON, as returned by the WASM `solve`
+    Json,
+    /// One row per ingredient and nutrient
+    Csv,
+}
+
+/// Output format of `formulang plan`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PlanFormat {
+    /// Human-readable report
+    Table,
+    /// The plan's quantities, costs and supplies as JSON, with each formula's full result
+    Json,
+    /// One row per formula and per limited ingredient
+    Csv,
+}
+
+/// Process exit codes of `formulang solve`. Usage errors exit with 2.
+pub mod exit_code {
+    pub const OPTIMAL: i32 = 0;
+    pub const COMPILE_ERROR: i32 = 1;
+    pub const INFEASIBLE: i32 = 3;
+    pub const UNBOUNDED: i32 = 4;
+    pub const SOLVER_ERROR: i32 = 5;
+    pub const NODE_LIMIT: i32 = 6;
+    /// A results file for `--compare` or `--save` could not be read or written
+    pub const IO_ERROR: i32 = 7;
+}
+
+/// Exit code telling scripts how a solve ended
+pub fn status_exit_code(status: SolveStatus) -> i32 {
+    match status {
+        SolveStatus::Optimal => exit_code::OPTIMAL,
+        SolveStatus::Infeasible => exit_code::INFEASIBLE,
+        SolveStatus::Unbounded => exit_code::UNBOUNDED,
+        SolveStatus::NodeLimit => exit_code::NODE_LIMIT,
+        SolveStatus::Error => exit_code::SOLVER_ERROR,
+    }
+}
+
+pub fn print_json(result: &impl serde::Serialize) {
+    match serde_json::to_string_pretty(result) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("Error serializing result: {}", e),
+    }
+}
+
+/// Print ingredients and nutrients in one CSV table. Ingredient rows fill
+/// the amount and cost columns, nutrient rows the value and unit columns.
+/// A final `total` row carries the batch size and total cost. The
+/// `dry_matter` column holds nutrient levels in the dry matter, and the
+/// batch's dry matter on the total row, when those are known.
+pub fn print_csv(result: &SolveResult) {
+    println!("section,id,name,code,amount,percentage,unit_cost,cost,value,unit,dry_matter");
+    let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+    for ing in &result.ingredients {
+        println!(
+            "ingredient,{},{},{},{},{},{},{},,,",
+            csv_field(&ing.id),
+            csv_field(ing.name.as_deref().unwrap_or("")),
+            csv_field(ing.code.as_deref().unwrap_or("")),
+            ing.amount,
+            ing.percentage,
+            ing.unit_cost,
+            ing.cost
+        );
+    }
+    for nut in &result.nutrients {
+        println!(
+            "nutrient,{},{},{},,,,,{},{},{}",
+            csv_field(&nut.id),
+            csv_field(nut.name.as_deref().unwrap_or("")),
+            csv_field(nut.code.as_deref().unwrap_or("")),
+            nut.value,
+            csv_field(nut.unit.as_deref().unwrap_or("")),
+            optional(nut.dry_matter_value)
+        );
+    }
+    if result.has_solution() {
+        println!(
+            "total,{},,,{},100,,{},,,{}",
+            csv_field(&result.formula),
+            result.batch_size,
+            result.total_cost,
+            optional(result.dry_matter)
+        );
+    }
+}
+
+/// Print one row per formula and per limited ingredient of a plan. Formula
+/// rows fill the quantity and cost columns, supply rows the stock columns.
+pub fn print_plan_csv(result: &PlanResult) {
+    println!("section,id,name,quantity,cost,available,used,shadow_price");
+    for planned in &result.formulas {
+        println!(
+            "formula,{},{},{},{},,,",
+            csv_field(&planned.result.formula),
+            csv_field(planned.result.formula_name.as_deref().unwrap_or("")),
+            planned.quantity,
+            planned.cost
+        );
+    }
+    for supply in &result.supplies {
+        println!(
+            "supply,{},{},,,{},{},{}",
+            csv_field(&supply.ingredient),
+            csv_field(supply.name.as_deref().unwrap_or("")),
+            supply.available,
+            supply.used,
+            supply.shadow_price
+        );
+    }
+    if result.has_solution() {
+        println!("total,{},,,{},,,", csv_field(&result.plan), result.total_cost);
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote, or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn print_table(result: &SolveResult, analysis: bool) {
+    println!("Formula: {}", result.formula);
+    if let Some(ref desc) = result.description {
+        println!("Description: {}", desc);
+    }
+    println!("Batch size: {}", result.batch_size);
+    println!();
+
+    if !result.missing_values.is_empty() {
+        println!("Missing nutrient data (taken as 0):");
+        for m in &result.missing_values {
+            println!("  {:20} {}", m.ingredient, m.nutrient);
+        }
+        println!();
+    }
+
+    match result.status {
+        SolveStatus::NodeLimit if !result.has_solution() => {
+            println!("Status: NODE LIMIT");
+            println!("The search for whole-number choices stopped at its node limit before finding a solution.");
+        }
+        SolveStatus::Optimal | SolveStatus::NodeLimit => {
+            print_status(result.status);
+            println!("Total cost: {:.2}", result.total_cost);
+            if let Some(dry_matter) = result.dry_matter {
+                println!("Dry matter: {:.2}%", dry_matter);
+            }
+            println!();
+            println!("Ingredients:");
+            for ing in &result.ingredients {
+                println!("  {:20} {:10.2} ({:5.2}%)", ing.id, ing.amount, ing.percentage);
+            }
+
+            if !result.nutrients.is_empty() {
+                println!();
+                println!("Nutrients:");
+                match result.dry_matter {
+                    Some(_) => {
+                        println!("  {:20} {:>10} {:>10}", "", "as fed", "DM");
+                        for nut in &result.nutrients {
+                            println!(
+                                "  {:20} {:10.4} {:10.4} {}",
+                                nut.id,
+                                nut.value,
+                                nut.dry_matter_value.unwrap_or(0.0),
+                                nut.unit.as_deref().unwrap_or("")
+                            );
+                        }
+                    }
+                    None => {
+                        for nut in &result.nutrients {
+                            println!("  {:20} {:10.4} {}", nut.id, nut.value, nut.unit.as_deref().unwrap_or(""));
+                        }
+                    }
+                }
+            }
+
+            if !result.soft_constraints.is_empty() {
+                println!();
+                println!("Soft constraints:");
+                for soft in &result.soft_constraints {
+                    if soft.deviation > 1e-6 {
+                        let measure = soft.measure.as_ref().map(|m| format!(" in {}", m)).unwrap_or_default();
+                        println!(
+                            "  {:30} {} {:.4}{} (penalty {:.2})",
+                            soft.constraint, soft.kind, soft.deviation, measure, soft.penalty_cost
+                        );
+                    } else {
+                        println!("  {:30} met", soft.constraint);
+                    }
+                }
+            }
+
+            if analysis && let Some(ref analysis) = result.analysis {
+                println!();
+                println!("Analysis:");
+                println!();
+
+                if !analysis.binding_constraints.is_empty() {
+                    println!("Binding constraints (pinch points):");
+                    for name in &analysis.binding_constraints {
+                        println!("  - {}", name);
+                    }
+                    println!();
+                }
+
+                println!("Shadow prices:");
+                for sp in &analysis.shadow_prices {
+                    if sp.value.abs() > 0.001 {
+                        println!("  {:30} {:10.4}", sp.constraint, sp.value);
+                        println!("    {}", sp.interpretation);
+                        if let Some(location) = source_location(result, &sp.constraint) {
+                            println!("    defined at {}", location);
+                        }
+                    }
+                }
+                println!();
+
+                println!("Reduced costs (ingredients not in solution):");
+                for rc in &analysis.reduced_costs {
+                    if !rc.is_basic && rc.reduced_cost.abs() > 0.001 {
+                        println!(
+                            "  {:20} cost must decrease by {:.2} to enter solution",
+                            rc.variable, rc.reduced_cost
+                        );
+                    }
+                }
+                println!();
+
+                println!("Cost ranging (formula stays optimal between):");
+                for range in &analysis.objective_sensitivity {
+                    println!(
+                        "  {:20} {:>12} {:>12} {:>12}",
+                        range.name,
+                        format_bound(range.lower_bound),
+                        format!("{:.2}", range.current),
+                        format_bound(range.upper_bound)
+                    );
+                }
+                println!();
+
+                println!("RHS ranging (shadow prices valid between):");
+                for (range, sp) in analysis.rhs_sensitivity.iter().zip(&analysis.shadow_prices) {
+                    if sp.value.abs() > 0.001 {
+                        println!(
+                            "  {:30} {:>12} {:>12} {:>12}",
+                            range.name,
+                            format_bound(range.lower_bound),
+                            format!("{:.2}", range.current),
+                            format_bound(range.upper_bound)
+                        );
+                    }
+                }
+            }
+        }
+        SolveStatus::Infeasible => {
+            println!("Status: INFEASIBLE");
+            println!("No solution exists that satisfies all constraints.");
+            if !result.conflict.is_empty() {
+                println!();
+                println!("Conflicting constraints (cannot all hold together):");
+                for v in &result.violations {
+                    println!("  - {}", v.description);
+                    if let Some(location) = source_location(result, &v.constraint) {
+                        println!("    defined at {}", location);
+                    }
+                }
+                println!();
+                println!("Nutrient bounds marked `soft <penalty>` may be missed at a cost instead.");
+            }
+        }
+        SolveStatus::Unbounded => {
+            println!("Status: UNBOUNDED");
+            println!("The problem has no finite optimal solution.");
+        }
+        SolveStatus::Error => {
+            println!("Status: ERROR");
+            println!("Solver encountered an error.");
+        }
+    }
+
+    for sub in &result.sub_formulas {
+        let used = result.ingredients.iter().find(|ing| ing.id == sub.formula);
+        println!();
+        println!("{}", "-".repeat(60));
+        if let Some(used) = used {
+            println!(
+                "Used as an ingredient of {}: {:.2} ({:.2}%) at {:.4} per unit",
+                result.formula, used.amount, used.percentage, used.unit_cost
+            );
+        }
+        print_table(sub, analysis);
+    }
+}
+
+/// Status line of a solved formula or plan, warning when the node limit
+/// means it may not be the cheapest
+fn print_status(status: SolveStatus) {
+    if status == SolveStatus::NodeLimit {
+        println!("Status: NODE LIMIT (best solution found, not proven optimal)");
+    } else {
+        println!("Status: OPTIMAL");
+    }
+}
+
+pub fn print_plan_table(result: &PlanResult, analysis: bool) {
+    println!("Plan: {}", result.plan);
+    if let Some(ref desc) = result.description {
+        println!("Description: {}", desc);
+    }
+    println!();
+
+    match result.status {
+        SolveStatus::NodeLimit if !result.has_solution() => {
+            println!("Status: NODE LIMIT");
+            println!("The search for whole-number choices stopped at its node limit before finding a plan.");
+            return;
+        }
+        SolveStatus::Optimal | SolveStatus::NodeLimit => {
+            print_status(result.status);
+            println!("Total cost: {:.2}", result.total_cost);
+            println!();
+            println!("Formulas:");
+            for planned in &result.formulas {
+                println!(
+                    "  {:20} {:12.2} at {:10.2} = {:.2}",
+                    planned.result.formula,
+                    planned.quantity,
+                    planned.result.total_cost / planned.result.batch_size,
+                    planned.cost
+                );
+            }
+
+            if !result.supplies.is_empty() {
+                println!();
+                println!("Available ingredients:");
+                for supply in &result.supplies {
+                    println!("  {:20} {:12.2} of {:12.2} used", supply.ingredient, supply.used, supply.available);
+                    if supply.shadow_price.abs() > 0.001 {
+                        println!("    one more unit would change the plan's cost by {:.4}", supply.shadow_price);
+                    }
+                }
+            }
+        }
+        SolveStatus::Infeasible => {
+            println!("Status: INFEASIBLE");
+            println!("No plan makes every formula within the available stock.");
+            if !result.conflict.is_empty() {
+                println!();
+                println!("Conflicting constraints (cannot all hold together):");
+                for v in &result.violations {
+                    println!("  - {}", v.description);
+                }
+            }
+            return;
+        }
+        SolveStatus::Unbounded => {
+            println!("Status: UNBOUNDED");
+            println!("The problem has no finite optimal solution.");
+            return;
+        }
+        SolveStatus::Error => {
+            println!("Status: ERROR");
+            println!("Solver encountered an error.");
+            return;
+        }
+    }
+
+    for planned in &result.formulas {
+        println!();
+        println!("{}", "-".repeat(60));
+        println!(
+            "Planned quantity: {:.2} ({:.2} batches)",
+            planned.quantity,
+            planned.quantity / planned.result.batch_size
+        );
+        print_table(&planned.result, analysis);
+    }
+}
+
+/// `file:line (formula)` of the definition of a constraint, followed by the
+/// formulas it was inherited through or overrides
+fn source_location(result: &SolveResult, constraint: &str) -> Option<String> {
+    let source = result.constraint_source(constraint)?;
+    let mut location = match &source.file {
+        Some(file) => {
+            let text = std::fs::read_to_string(file).unwrap_or_default();
+            let (line, _) = crate::line_col(&text, source.span.start);
+            format!("{}:{} ({})", file.display(), line, source.formula)
+        }
+        None => source.formula.clone(),
+    };
+    if source.chain.len() > 1 {
+        location.push_str(&format!(", via {}", source.chain.join(" -> ")));
+    }
+    if !source.overrides.is_empty() {
+        location.push_str(&format!(", overriding {}", source.overrides.join(", ")));
+    }
+    Some(location)
+}
+
+/// Format a sensitivity bound, showing unbounded ends as "-inf"/"inf"
+fn format_bound(value: f64) -> String {
+    if value == f64::NEG_INFINITY {
+        "-inf".to_string()
+    } else if value == f64::INFINITY {
+        "inf".to_string()
+    } else {
+        format!("{:.2}", value)
+    }
+}
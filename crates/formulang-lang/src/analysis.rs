@@ -0,0 +1,1327 @@
+//! Semantic checks on parsed programs
+//!
+//! These run without compiling, so they report every problem in a file at
+//! once, with spans. The CLI, WASM bindings and editor tooling all use them.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::compiler::{CompileError, Compiler, arguments, closest_name, get_string_property, nonlinear_term};
+use crate::formatter::expr_text;
+use crate::lexer::Span;
+use crate::parser::{ParseError, Parser};
+use crate::units::{self, UnitError};
+
+/// Valid properties for each declaration type
+const NUTRIENT_PROPERTIES: &[&str] = &["name", "code", "desc", "description", "unit"];
+const INGREDIENT_PROPERTIES: &[&str] = &["name", "code", "desc", "description", "cost", "dry_matter", "moisture"];
+const FORMULA_PROPERTIES: &[&str] = &[
+    "name",
+    "code",
+    "desc",
+    "description",
+    "batch",
+    "batch_size",
+    "max_ingredients",
+    "missing_data",
+    "template",
+];
+const PLAN_PROPERTIES: &[&str] = &["name", "code", "desc", "description"];
+
+/// Properties whose value is a number, so a name there must be a constant
+const NUMBER_PROPERTIES: &[&str] = &["batch", "batch_size", "cost", "max_ingredients"];
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Stable identifier for each kind of diagnostic
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticCode {
+    ParseError,
+    /// An imported file could not be loaded
+    ImportError,
+    DuplicateDefinition,
+    InvalidProperty,
+    MissingCost,
+    MissingBatchSize,
+    UndefinedNutrient,
+    UndefinedIngredient,
+    UndefinedConstant,
+    UndefinedTemplate,
+    UndefinedFormula,
+    /// A formula instantiates something that is not a template, or passes
+    /// the wrong number of arguments
+    InvalidTemplateInstance,
+    /// An ingredient where only nutrients are allowed
+    ExpectedNutrient,
+    /// A nutrient where only ingredients are allowed
+    ExpectedIngredient,
+    /// An ingredient has no value for a nutrient its formula constrains
+    MissingNutrientData,
+    /// An ingredient gives a value for a nutrient computed from others
+    DerivedNutrientValue,
+    /// A derived nutrient is computed from itself, directly or through others
+    CircularNutrient,
+    /// A constant's value uses the constant itself, directly or through others
+    CircularConstant,
+    /// A value or bound is given in a unit that isn't known
+    UnknownUnit,
+    /// A value or bound is given in a unit that doesn't convert to the
+    /// nutrient's, or nutrients in different units are added together
+    IncompatibleUnits,
+    /// Nutrients are multiplied together or divided by one another outside a ratio
+    NonlinearExpression,
+}
+
+impl DiagnosticCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticCode::ParseError => "parse-error",
+            DiagnosticCode::ImportError => "import-error",
+            DiagnosticCode::DuplicateDefinition => "duplicate-definition",
+            DiagnosticCode::InvalidProperty => "invalid-property",
+            DiagnosticCode::MissingCost => "missing-cost",
+            DiagnosticCode::MissingBatchSize => "missing-batch-size",
+            DiagnosticCode::UndefinedNutrient => "undefined-nutrient",
+            DiagnosticCode::UndefinedIngredient => "undefined-ingredient",
+            DiagnosticCode::UndefinedConstant => "undefined-constant",
+            DiagnosticCode::UndefinedTemplate => "undefined-template",
+            DiagnosticCode::UndefinedFormula => "undefined-formula",
+            DiagnosticCode::InvalidTemplateInstance => "invalid-template-instance",
+            DiagnosticCode::ExpectedNutrient => "expected-nutrient",
+            DiagnosticCode::ExpectedIngredient => "expected-ingredient",
+            DiagnosticCode::MissingNutrientData => "missing-nutrient-data",
+            DiagnosticCode::DerivedNutrientValue => "derived-nutrient-value",
+            DiagnosticCode::CircularNutrient => "circular-nutrient",
+            DiagnosticCode::CircularConstant => "circular-constant",
+            DiagnosticCode::UnknownUnit => "unknown-unit",
+            DiagnosticCode::IncompatibleUnits => "incompatible-units",
+            DiagnosticCode::NonlinearExpression => "nonlinear-expression",
+        }
+    }
+}
+
+impl std::fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A problem found in a source file
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: DiagnosticCode,
+    pub severity: Severity,
+    /// Byte range in the source
+    pub span: Span,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(code: DiagnosticCode, span: Span, message: impl Into<String>) -> Self {
+        Self { code, severity: Severity::Error, span, message: message.into() }
+    }
+
+    pub fn warning(code: DiagnosticCode, span: Span, message: impl Into<String>) -> Self {
+        Self { code, severity: Severity::Warning, span, message: message.into() }
+    }
+}
+
+/// Names a file sees through its imports, by kind
+#[derive(Debug, Default, Clone)]
+pub struct ImportedNames {
+    pub nutrients: HashSet<String>,
+    pub ingredients: HashSet<String>,
+    pub formulas: HashSet<String>,
+    pub constants: HashSet<String>,
+    /// Module names usable as qualifiers; qualified names such as
+    /// `grains.corn` are listed in the sets above
+    pub modules: HashSet<String>,
+    /// Units of the nutrients above that declare one
+    pub units: HashMap<String, String>,
+    /// Nutrients above that are computed from others
+    pub derived: HashSet<String>,
+}
+
+/// Parse and check a single source file. Imports are not followed, so
+/// references are only checked in files without imports.
+pub fn check_source(source: &str) -> Vec<Diagnostic> {
+    let (program, parse_errors) = Parser::parse_resilient(source);
+    let mut diagnostics: Vec<Diagnostic> = parse_errors
+        .iter()
+        .map(|e| parse_error_diagnostic(source, e))
+        .collect();
+    diagnostics.extend(check_program(&program, None));
+    diagnostics
+}
+
+/// Convert a parse error to a diagnostic located in `source`
+pub fn parse_error_diagnostic(source: &str, error: &ParseError) -> Diagnostic {
+    let (span, message) = match error {
+        ParseError::UnexpectedToken { span, expected, found } => {
+            // Use the actual token span for precise error location
+            (Span::new(span.start, span.end.max(span.start + 1)), format!("Expected {}, found {}", expected, found))
+        }
+        ParseError::UnexpectedEof => {
+            // For EOF errors, highlight the end of the file
+            let end = source.len();
+            let start = source.rfind(|c: char| !c.is_whitespace()).map(|i| i + 1).unwrap_or(0);
+            (Span::new(start, end.max(start + 1)), "Unexpected end of file".to_string())
+        }
+        ParseError::InvalidNumber(s) => {
+            // For invalid numbers, try to find where it might be
+            (Span::new(0, source.len().min(20)), format!("Invalid number: {}", s))
+        }
+    };
+    Diagnostic::error(DiagnosticCode::ParseError, span, message)
+}
+
+/// Check a parsed program. `imported` lists the names its imports provide;
+/// without it, references are only checked if the program has no imports.
+pub fn check_program(program: &Program, imported: Option<&ImportedNames>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let has_imports = program.items.iter().any(|item| matches!(item, Item::Import(_)));
+    let mut names = Names {
+        known: imported.cloned().unwrap_or_default(),
+        local: HashSet::new(),
+        check_references: imported.is_some() || !has_imports,
+    };
+
+    // First pass: collect definitions and check for duplicates
+    let mut seen: [HashSet<&str>; 5] = Default::default();
+    for item in &program.items {
+        let (kind, name, span, set) = match item {
+            Item::Nutrient(n) => ("nutrient", &n.name, n.span, 0),
+            Item::Ingredient(i) => ("ingredient", &i.name, i.span, 1),
+            Item::Formula(f) => ("formula", &f.name, f.span, 2),
+            Item::Const(c) => ("constant", &c.name, c.span, 3),
+            Item::Plan(p) => ("plan", &p.name, p.span, 4),
+            Item::Import(_) => continue,
+        };
+        if !seen[set].insert(name) {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticCode::DuplicateDefinition,
+                span,
+                format!("Duplicate {} definition: '{}'", kind, name),
+            ));
+        }
+        names.local.insert(name.clone());
+        if let Item::Nutrient(n) = item {
+            if let Some(unit) = get_string_property(&n.properties, "unit") {
+                names.known.units.insert(name.clone(), unit);
+            }
+            if n.derived.is_some() {
+                names.known.derived.insert(name.clone());
+            }
+        }
+        match set {
+            0 => names.known.nutrients.insert(name.clone()),
+            1 => names.known.ingredients.insert(name.clone()),
+            2 => names.known.formulas.insert(name.clone()),
+            3 => names.known.constants.insert(name.clone()),
+            _ => false,
+        };
+    }
+
+    // Second pass: check references and property scopes
+    for item in &program.items {
+        match item {
+            Item::Const(c) => {
+                names.check_constant_expr(&c.value, &mut diagnostics);
+                names.check_constant_cycle(c, program, &mut diagnostics);
+            }
+            Item::Nutrient(n) => {
+                check_properties(&n.properties, "nutrient", NUTRIENT_PROPERTIES, "name, code, desc, unit", &mut diagnostics);
+                if let Some(derived) = &n.derived {
+                    names.check_derived(&derived.expr, &mut diagnostics);
+                    check_linear(&derived.expr, &mut diagnostics);
+                    names.check_derived_cycle(n, program, &mut diagnostics);
+                }
+            }
+            Item::Ingredient(ing) => {
+                check_properties(&ing.properties, "ingredient", INGREDIENT_PROPERTIES, "name, code, desc, cost, dry_matter, moisture", &mut diagnostics);
+                names.check_property_constants(&ing.properties, &mut diagnostics);
+
+                // Check for missing cost (skip for templates)
+                let has_cost = ing.properties.iter().any(|p| p.name == "cost");
+                if !has_cost && !ing.is_template {
+                    diagnostics.push(Diagnostic::warning(
+                        DiagnosticCode::MissingCost,
+                        Span::new(ing.span.start, ing.span.start + 10), // "ingredient"
+                        format!("Ingredient '{}' is missing required 'cost' property", ing.name),
+                    ));
+                }
+
+                // Ingredient nuts block: nutrients or composition references allowed
+                for nv in &ing.nutrients {
+                    names.check_ingredient_nutrient(&nv.nutrient, nv.span, &mut diagnostics);
+                    if let Some(constant) = &nv.constant {
+                        names.check_constant(constant, &mut diagnostics);
+                    }
+                    names.check_value_unit(nv, &mut diagnostics);
+                    if nv.value.is_some() || nv.constant.is_some() {
+                        names.check_not_derived(&nv.nutrient, &ing.name, nv.span, &mut diagnostics);
+                    }
+                }
+            }
+            Item::Formula(formula) => {
+                // A template's parameters can stand in for constants
+                let scoped;
+                let names = if formula.params.is_empty() {
+                    &names
+                } else {
+                    scoped = names.with_params(&formula.params);
+                    &scoped
+                };
+                if let Some(instance) = &formula.instance {
+                    names.check_instance(instance, program, &mut diagnostics);
+                }
+
+                check_properties(
+                    &formula.properties,
+                    "formula",
+                    FORMULA_PROPERTIES,
+                    "name, code, desc, batch, max_ingredients, missing_data",
+                    &mut diagnostics,
+                );
+                names.check_property_constants(&formula.properties, &mut diagnostics);
+
+                // Formula nuts block: only nutrients allowed
+                for nc in &formula.nutrients {
+                    names.check_expr(&nc.expr, Block::Nutrients, &mut diagnostics);
+                    names.check_bound_constants(&nc.bounds, &mut diagnostics);
+                    names.check_constraint_units(nc, &mut diagnostics);
+                    match &nc.expr {
+                        Expr::BinaryOp { left, op: BinaryOp::Div, right } if has_reference(right) => {
+                            check_linear(left, &mut diagnostics);
+                            check_linear(right, &mut diagnostics);
+                        }
+                        expr => check_linear(expr, &mut diagnostics),
+                    }
+                }
+
+                // Formula ings block: only ingredients allowed
+                for ic in &formula.ingredients {
+                    names.check_expr(&ic.expr, Block::Ingredients, &mut diagnostics);
+                    names.check_bound_constants(&ic.bounds, &mut diagnostics);
+                }
+
+                // Check for missing batch_size (skip for templates)
+                let has_batch = formula.properties.iter().any(|p| p.name == "batch_size" || p.name == "batch");
+                if !has_batch && !formula.is_template && formula.instance.is_none() {
+                    diagnostics.push(Diagnostic::warning(
+                        DiagnosticCode::MissingBatchSize,
+                        Span::new(formula.span.start, formula.span.start + 7), // "formula"
+                        format!("Formula '{}' is missing required 'batch' property", formula.name),
+                    ));
+                }
+            }
+            Item::Plan(plan) => {
+                check_properties(&plan.properties, "plan", PLAN_PROPERTIES, "name, code, desc", &mut diagnostics);
+                names.check_plan(plan, &mut diagnostics);
+            }
+            Item::Import(_) => {}
+        }
+    }
+
+    diagnostics
+}
+
+/// Report ingredients with no value for a nutrient their formula constrains.
+/// Unlike the checks above this compiles each formula of `program`, which
+/// must be loaded into `compiler`. Formulas that fail to compile for other
+/// reasons are skipped.
+pub fn check_nutrient_data(compiler: &Compiler, program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for item in &program.items {
+        let Item::Formula(formula) = item else {
+            continue;
+        };
+        let (severity, missing) = match compiler.compile_formula(&formula.name) {
+            Ok(compiled) => (Severity::Warning, compiled.missing_values),
+            Err(CompileError::MissingNutrientData { missing, .. }) => (Severity::Error, missing),
+            Err(_) => continue,
+        };
+        for m in missing {
+            // Constraints inherited from another file are reported on the formula
+            let local = program
+                .items
+                .iter()
+                .any(|item| matches!(item, Item::Formula(f) if f.name == m.formula));
+            let span = if local {
+                m.span
+            } else {
+                Span::new(formula.span.start, formula.span.start + 7) // "formula"
+            };
+            let message = match severity {
+                Severity::Warning => format!(
+                    "Ingredient '{}' has no '{}' value, so formula '{}' assumes 0",
+                    m.ingredient, m.nutrient, formula.name
+                ),
+                Severity::Error => format!(
+                    "Ingredient '{}' has no '{}' value, which formula '{}' requires (missing_data error)",
+                    m.ingredient, m.nutrient, formula.name
+                ),
+            };
+            diagnostics.push(Diagnostic {
+                code: DiagnosticCode::MissingNutrientData,
+                severity,
+                span,
+                message,
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Check that a nutrient expression is a sum of nutrients times constants,
+/// as the compiler requires
+fn check_linear(expr: &Expr, diagnostics: &mut Vec<Diagnostic>) {
+    if let Some(term) = nonlinear_term(expr) {
+        diagnostics.push(Diagnostic::error(
+            DiagnosticCode::NonlinearExpression,
+            term.span().unwrap_or(Span::new(0, 0)),
+            format!("Nutrient expression '{}' is not linear. Only sums of nutrients times constants are allowed", expr_text(term)),
+        ));
+    }
+}
+
+/// The path from `start` back to itself through `inputs`, the names each
+/// name is computed from, if there is one
+fn find_cycle(start: &str, inputs: &HashMap<&str, Vec<String>>) -> Option<Vec<String>> {
+    // Depth-first from the start, looking for a way back to it
+    let mut path = vec![start.to_string()];
+    let mut visited = HashSet::new();
+    let mut pending = vec![inputs.get(start).cloned().unwrap_or_default()];
+    while let Some(next) = pending.last_mut() {
+        let Some(name) = next.pop() else {
+            pending.pop();
+            path.pop();
+            continue;
+        };
+        if name == start {
+            path.push(name);
+            return Some(path);
+        }
+        if visited.insert(name.clone())
+            && let Some(names) = inputs.get(name.as_str())
+        {
+            path.push(name);
+            pending.push(names.clone());
+        }
+    }
+    None
+}
+
+/// Whether an expression names anything, as the divisor of a ratio does
+fn has_reference(expr: &Expr) -> bool {
+    match expr {
+        Expr::Reference(_) => true,
+        Expr::BinaryOp { left, right, .. } => has_reference(left) || has_reference(right),
+        Expr::Paren(inner) => has_reference(inner),
+        Expr::Number(_) => false,
+    }
+}
+
+/// "Undefined nutrient: 'lysne'", with the closest known name if there is one
+fn undefined<S: AsRef<str>>(kind: &str, name: &str, known: impl IntoIterator<Item = S>) -> String {
+    match closest_name(name, known) {
+        Some(suggestion) => format!("Undefined {}: '{}'. Did you mean '{}'?", kind, name, suggestion),
+        None => format!("Undefined {}: '{}'", kind, name),
+    }
+}
+
+/// Diagnostic for a unit that doesn't convert
+fn unit_diagnostic(subject: &str, span: Span, error: UnitError) -> Diagnostic {
+    let code = match error {
+        UnitError::Unknown { .. } => DiagnosticCode::UnknownUnit,
+        _ => DiagnosticCode::IncompatibleUnits,
+    };
+    Diagnostic::error(code, span, format!("Unit of {}: {}", subject, error))
+}
+
+fn check_properties(
+    properties: &[Property],
+    kind: &str,
+    valid: &[&str],
+    listed: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for prop in properties {
+        if !valid.contains(&prop.name.as_str()) {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticCode::InvalidProperty,
+                prop.span,
+                format!("'{}' is not a valid property for {}. Valid properties: {}", prop.name, kind, listed),
+            ));
+        }
+    }
+}
+
+/// Which block of a formula an expression appears in
+#[derive(Clone, Copy)]
+enum Block {
+    Nutrients,
+    Ingredients,
+}
+
+/// Every name visible in the checked program
+struct Names {
+    /// Imported and local names together, by kind
+    known: ImportedNames,
+    /// Names declared in the program itself, which shadow module qualifiers
+    local: HashSet<String>,
+    /// Whether undefined references can be reported
+    check_references: bool,
+}
+
+impl Names {
+    /// These names with a template's parameters added as constants
+    fn with_params(&self, params: &[String]) -> Names {
+        let mut known = self.known.clone();
+        known.constants.extend(params.iter().cloned());
+        Names {
+            known,
+            local: self.local.clone(),
+            check_references: self.check_references,
+        }
+    }
+
+    /// Check that a formula instantiates a template it can see, with one
+    /// argument per parameter when the template is in `program`
+    fn check_instance(&self, instance: &TemplateInstance, program: &Program, diagnostics: &mut Vec<Diagnostic>) {
+        for constant in instance.args.iter().filter_map(|arg| arg.constant.as_ref()) {
+            self.check_constant(constant, diagnostics);
+        }
+        let Some((name, _)) = self.head(&instance.template) else {
+            return;
+        };
+        if self.check_references && !self.known.formulas.contains(&name) {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticCode::UndefinedTemplate,
+                instance.template.span,
+                undefined("template", &name, &self.known.formulas),
+            ));
+            return;
+        }
+
+        let template = program.items.iter().find_map(|item| match item {
+            Item::Formula(f) if f.name == name => Some(f),
+            _ => None,
+        });
+        let message = match template {
+            Some(t) if !t.is_template => format!("'{}' is not a template, so it cannot be instantiated", name),
+            Some(t) if t.params.len() != instance.args.len() => format!(
+                "Template '{}' takes {}, got {}",
+                name,
+                arguments(t.params.len()),
+                instance.args.len()
+            ),
+            _ => return,
+        };
+        diagnostics.push(Diagnostic::error(DiagnosticCode::InvalidTemplateInstance, instance.span, message));
+    }
+
+    /// Check that a plan lists known formulas and ingredients
+    fn check_plan(&self, plan: &Plan, diagnostics: &mut Vec<Diagnostic>) {
+        for entry in plan.formulas.iter().chain(&plan.available) {
+            if let Some(constant) = &entry.quantity.constant {
+                self.check_constant(constant, diagnostics);
+            }
+        }
+        if !self.check_references {
+            return;
+        }
+        for entry in &plan.formulas {
+            if let Some((name, _)) = self.head(&entry.name)
+                && !self.known.formulas.contains(&name)
+            {
+                diagnostics.push(Diagnostic::error(
+                    DiagnosticCode::UndefinedFormula,
+                    entry.name.span,
+                    undefined("formula", &name, &self.known.formulas),
+                ));
+            }
+        }
+        // Formulas used as ingredients can be in limited supply too
+        for entry in &plan.available {
+            if let Some((name, _)) = self.head(&entry.name)
+                && !self.known.ingredients.contains(&name)
+                && !self.known.formulas.contains(&name)
+            {
+                diagnostics.push(Diagnostic::error(
+                    DiagnosticCode::UndefinedIngredient,
+                    entry.name.span,
+                    undefined("ingredient", &name, self.known.ingredients.iter().chain(&self.known.formulas)),
+                ));
+            }
+        }
+    }
+
+    /// The name a reference starts with, joining a module qualifier if there is
+    /// one, and the index of the part after it
+    fn head(&self, r: &Reference) -> Option<(String, usize)> {
+        let Some(ReferencePart::Ident(first)) = r.parts.first() else {
+            return None;
+        };
+        if self.known.modules.contains(first)
+            && !self.local.contains(first)
+            && let Some(ReferencePart::Ident(second)) = r.parts.get(1)
+        {
+            return Some((format!("{}.{}", first, second), 2));
+        }
+        Some((first.clone(), 1))
+    }
+
+    /// The identifier following the head, such as `nutrients` in `corn.nutrients`
+    fn block_after(r: &Reference, index: usize) -> Option<&str> {
+        match r.parts.get(index) {
+            Some(ReferencePart::Ident(block)) => Some(block.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Check that a reference names a constant
+    fn check_constant(&self, r: &Reference, diagnostics: &mut Vec<Diagnostic>) {
+        let Some((name, _)) = self.head(r) else {
+            return;
+        };
+        if self.check_references && !self.known.constants.contains(&name) {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticCode::UndefinedConstant,
+                r.span,
+                undefined("constant", &name, &self.known.constants),
+            ));
+        }
+    }
+
+    /// Check the names in a constant's value. References with a property,
+    /// such as `corn.cost`, are left to the compiler.
+    fn check_constant_expr(&self, expr: &Expr, diagnostics: &mut Vec<Diagnostic>) {
+        match expr {
+            Expr::Reference(r) => {
+                if let Some((_, next)) = self.head(r)
+                    && next == r.parts.len()
+                {
+                    self.check_constant(r, diagnostics);
+                }
+            }
+            Expr::BinaryOp { left, right, .. } => {
+                self.check_constant_expr(left, diagnostics);
+                self.check_constant_expr(right, diagnostics);
+            }
+            Expr::Paren(inner) => self.check_constant_expr(inner, diagnostics),
+            Expr::Number(_) => {}
+        }
+    }
+
+    fn check_bound_constants(&self, bounds: &Bounds, diagnostics: &mut Vec<Diagnostic>) {
+        for bound in [&bounds.min, &bounds.max].into_iter().flatten() {
+            if let Some(constant) = &bound.constant {
+                self.check_constant(constant, diagnostics);
+            }
+        }
+    }
+
+    /// Check names given as the value of a numeric property, such as `batch mixer_size`
+    fn check_property_constants(&self, properties: &[Property], diagnostics: &mut Vec<Diagnostic>) {
+        for p in properties {
+            match &p.value {
+                PropertyValue::Ident(name)
+                    if NUMBER_PROPERTIES.contains(&p.name.as_str())
+                        && self.check_references
+                        && !self.known.constants.contains(name) =>
+                {
+                    diagnostics.push(Diagnostic::error(
+                        DiagnosticCode::UndefinedConstant,
+                        p.span,
+                        undefined("constant", name, &self.known.constants),
+                    ));
+                }
+                PropertyValue::Expr(expr) => self.check_constant_expr(expr, diagnostics),
+                _ => {}
+            }
+        }
+    }
+
+    /// Check a reference in an ingredient's nuts block
+    fn check_ingredient_nutrient(&self, r: &Reference, span: Span, diagnostics: &mut Vec<Diagnostic>) {
+        let Some((name, next)) = self.head(r) else {
+            return;
+        };
+
+        // Composition reference (e.g., corn.nutrients)
+        if self.known.ingredients.contains(&name)
+            && matches!(Self::block_after(r, next), Some("nutrients" | "nuts"))
+        {
+            return;
+        }
+
+        if self.known.ingredients.contains(&name) {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticCode::ExpectedNutrient,
+                span,
+                format!("'{}' is an ingredient, not a nutrient. Use '{}.nutrients' to inherit nutrients.", name, name),
+            ));
+        } else if self.check_references && !self.known.nutrients.contains(&name) {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticCode::UndefinedNutrient,
+                span,
+                undefined("nutrient", &name, &self.known.nutrients),
+            ));
+        }
+    }
+
+    /// Check that a derived nutrient is computed from nutrients only
+    fn check_derived(&self, expr: &Expr, diagnostics: &mut Vec<Diagnostic>) {
+        match expr {
+            Expr::Reference(r) => {
+                let Some((name, _)) = self.head(r) else {
+                    return;
+                };
+                if self.known.ingredients.contains(&name) || self.known.formulas.contains(&name) {
+                    diagnostics.push(Diagnostic::error(
+                        DiagnosticCode::ExpectedNutrient,
+                        r.span,
+                        format!("'{}' is not a nutrient. Derived nutrients are computed from other nutrients.", name),
+                    ));
+                } else if self.check_references && !self.known.nutrients.contains(&name) {
+                    diagnostics.push(Diagnostic::error(
+                        DiagnosticCode::UndefinedNutrient,
+                        r.span,
+                        undefined("nutrient", &name, &self.known.nutrients),
+                    ));
+                }
+            }
+            Expr::BinaryOp { left, right, .. } => {
+                self.check_derived(left, diagnostics);
+                self.check_derived(right, diagnostics);
+            }
+            Expr::Paren(inner) => self.check_derived(inner, diagnostics),
+            Expr::Number(_) => {}
+        }
+    }
+
+    /// Check that a derived nutrient doesn't depend on itself through the
+    /// derived nutrients of the program
+    fn check_derived_cycle(&self, nutrient: &Nutrient, program: &Program, diagnostics: &mut Vec<Diagnostic>) {
+        let mut inputs: HashMap<&str, Vec<String>> = HashMap::new();
+        for item in &program.items {
+            if let Item::Nutrient(n) = item
+                && let Some(derived) = &n.derived
+            {
+                let mut names = Vec::new();
+                self.derived_inputs(&derived.expr, &mut names);
+                inputs.insert(n.name.as_str(), names);
+            }
+        }
+
+        if let Some(path) = find_cycle(&nutrient.name, &inputs) {
+            let span = nutrient.derived.as_ref().map_or(nutrient.span, |d| d.span);
+            diagnostics.push(Diagnostic::error(
+                DiagnosticCode::CircularNutrient,
+                span,
+                format!("Nutrient '{}' is derived from itself: {}", nutrient.name, path.join(" -> ")),
+            ));
+        }
+    }
+
+    /// Check that a constant's value doesn't use the constant itself through
+    /// the constants of the program
+    fn check_constant_cycle(&self, constant: &Const, program: &Program, diagnostics: &mut Vec<Diagnostic>) {
+        let mut inputs: HashMap<&str, Vec<String>> = HashMap::new();
+        for item in &program.items {
+            if let Item::Const(c) = item {
+                let mut names = Vec::new();
+                self.constant_inputs(&c.value, &mut names);
+                inputs.insert(c.name.as_str(), names);
+            }
+        }
+
+        if let Some(path) = find_cycle(&constant.name, &inputs) {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticCode::CircularConstant,
+                constant.span,
+                format!("Constant '{}' is defined in terms of itself: {}", constant.name, path.join(" -> ")),
+            ));
+        }
+    }
+
+    /// Names of the constants a constant's value uses
+    fn constant_inputs(&self, expr: &Expr, names: &mut Vec<String>) {
+        match expr {
+            Expr::Reference(r) => {
+                if let Some((name, next)) = self.head(r)
+                    && next == r.parts.len()
+                {
+                    names.push(name);
+                }
+            }
+            Expr::BinaryOp { left, right, .. } => {
+                self.constant_inputs(left, names);
+                self.constant_inputs(right, names);
+            }
+            Expr::Paren(inner) => self.constant_inputs(inner, names),
+            Expr::Number(_) => {}
+        }
+    }
+
+    /// Names of the nutrients a derived nutrient uses
+    fn derived_inputs(&self, expr: &Expr, names: &mut Vec<String>) {
+        match expr {
+            Expr::Reference(r) => names.extend(self.head(r).map(|(name, _)| name)),
+            Expr::BinaryOp { left, right, .. } => {
+                self.derived_inputs(left, names);
+                self.derived_inputs(right, names);
+            }
+            Expr::Paren(inner) => self.derived_inputs(inner, names),
+            Expr::Number(_) => {}
+        }
+    }
+
+    /// Check that an ingredient doesn't give a value for a derived nutrient
+    fn check_not_derived(&self, r: &Reference, ingredient: &str, span: Span, diagnostics: &mut Vec<Diagnostic>) {
+        if let Some((name, _)) = self.head(r)
+            && self.known.derived.contains(&name)
+        {
+            diagnostics.push(Diagnostic::error(
+                DiagnosticCode::DerivedNutrientValue,
+                span,
+                format!("Ingredient '{}' gives a value for '{}', which is derived from other nutrients", ingredient, name),
+            ));
+        }
+    }
+
+    /// Unit of a nutrient: None if it isn't a known nutrient, Some(None) if it
+    /// has no unit
+    fn unit_of(&self, r: &Reference) -> Option<Option<&str>> {
+        let (name, next) = self.head(r)?;
+        if next != r.parts.len() || !self.known.nutrients.contains(&name) {
+            return None;
+        }
+        Some(self.known.units.get(&name).map(String::as_str))
+    }
+
+    /// Check that an ingredient value's unit converts to its nutrient's
+    fn check_value_unit(&self, nv: &NutrientValue, diagnostics: &mut Vec<Diagnostic>) {
+        let Some(unit) = &nv.unit else {
+            return;
+        };
+        if let Some(target) = self.unit_of(&nv.nutrient)
+            && let Err(error) = units::conversion(unit, target)
+        {
+            let subject = self.head(&nv.nutrient).map(|(name, _)| name).unwrap_or_default();
+            diagnostics.push(unit_diagnostic(&subject, nv.span, error));
+        }
+    }
+
+    /// Check the units of a formula's nutrient constraint as the compiler
+    /// converts them: each nutrient to the unit of the first one with a unit,
+    /// and bounds to that unit. Each side of a ratio converts on its own, and
+    /// a ratio's bounds have no unit.
+    fn check_constraint_units(&self, nc: &NutrientConstraint, diagnostics: &mut Vec<Diagnostic>) {
+        let bound_units: Vec<&String> = [&nc.bounds.min, &nc.bounds.max]
+            .into_iter()
+            .flatten()
+            .filter_map(|b| b.unit.as_ref())
+            .collect();
+        let subject = expr_text(&nc.expr);
+
+        if let Expr::BinaryOp { left, op: BinaryOp::Div, right } = &nc.expr
+            && has_reference(right)
+        {
+            self.check_terms(left, diagnostics);
+            self.check_terms(right, diagnostics);
+            if let Some(unit) = bound_units.first() {
+                diagnostics.push(unit_diagnostic(&subject, nc.span, UnitError::Ratio { unit: unit.to_string() }));
+            }
+            return;
+        }
+
+        let Some(unit) = self.check_terms(&nc.expr, diagnostics) else {
+            return;
+        };
+        for bound_unit in bound_units {
+            if let Err(error) = units::conversion(bound_unit, unit) {
+                diagnostics.push(unit_diagnostic(&subject, nc.span, error));
+            }
+        }
+    }
+
+    /// Check that every nutrient of an expression converts to the unit of its
+    /// first nutrient with a unit, and return that unit. None if a nutrient
+    /// doesn't convert or isn't known.
+    fn check_terms<'a>(&'a self, expr: &Expr, diagnostics: &mut Vec<Diagnostic>) -> Option<Option<&'a str>> {
+        let mut terms = Vec::new();
+        if !self.expr_terms(expr, &mut terms) {
+            return None;
+        }
+        let unit = terms.iter().find_map(|(_, unit, _)| *unit);
+        let mut converts = true;
+        for (name, own, span) in &terms {
+            let result = match (own, unit) {
+                (Some(own), Some(_)) => units::conversion(own, unit).map(|_| ()),
+                (None, Some(unit)) => Err(UnitError::Undeclared { to: unit.to_string() }),
+                (_, None) => Ok(()),
+            };
+            if let Err(error) = result {
+                diagnostics.push(unit_diagnostic(name, *span, error));
+                converts = false;
+            }
+        }
+        converts.then_some(unit)
+    }
+
+    /// Collect the nutrients of an expression with their units and spans.
+    /// False if it names something other than a known nutrient.
+    fn expr_terms<'a>(&'a self, expr: &Expr, terms: &mut Vec<(String, Option<&'a str>, Span)>) -> bool {
+        match expr {
+            Expr::Reference(r) => match (self.unit_of(r), self.head(r)) {
+                (Some(unit), Some((name, _))) => {
+                    terms.push((name, unit, r.span));
+                    true
+                }
+                _ => false,
+            },
+            Expr::Paren(inner) => self.expr_terms(inner, terms),
+            Expr::BinaryOp { left, right, .. } => self.expr_terms(left, terms) && self.expr_terms(right, terms),
+            Expr::Number(_) => true,
+        }
+    }
+
+    /// Check that an expression in a formula block only references the right kind of name
+    fn check_expr(&self, expr: &Expr, block: Block, diagnostics: &mut Vec<Diagnostic>) {
+        match expr {
+            Expr::Reference(r) => self.check_reference(r, block, diagnostics),
+            Expr::BinaryOp { left, right, .. } => {
+                self.check_expr(left, block, diagnostics);
+                self.check_expr(right, block, diagnostics);
+            }
+            Expr::Paren(inner) => self.check_expr(inner, block, diagnostics),
+            Expr::Number(_) => {}
+        }
+    }
+
+    fn check_reference(&self, r: &Reference, block: Block, diagnostics: &mut Vec<Diagnostic>) {
+        let Some((name, next)) = self.head(r) else {
+            return;
+        };
+
+        // Composition reference (e.g., base.nutrients, base.ingredients.corn)
+        if self.known.formulas.contains(&name)
+            && matches!(Self::block_after(r, next), Some("nutrients" | "nuts" | "ingredients" | "ings"))
+        {
+            return;
+        }
+
+        let (wrong_kind, right_kind, code, code_undefined) = match block {
+            Block::Nutrients => (
+                &self.known.ingredients,
+                &self.known.nutrients,
+                DiagnosticCode::ExpectedNutrient,
+                DiagnosticCode::UndefinedNutrient,
+            ),
+            Block::Ingredients => (
+                &self.known.nutrients,
+                &self.known.ingredients,
+                DiagnosticCode::ExpectedIngredient,
+                DiagnosticCode::UndefinedIngredient,
+            ),
+        };
+
+        if wrong_kind.contains(&name) {
+            let message = match block {
+                Block::Nutrients => format!(
+                    "'{}' is an ingredient, not a nutrient. Only nutrients can be referenced in a formula's nuts block.",
+                    name
+                ),
+                Block::Ingredients => format!(
+                    "'{}' is a nutrient, not an ingredient. Only ingredients can be referenced in a formula's ings block.",
+                    name
+                ),
+            };
+            diagnostics.push(Diagnostic::error(code, r.span, message));
+        } else if self.check_references
+            && !right_kind.contains(&name)
+            && !self.known.formulas.contains(&name)
+        {
+            let kind = match block {
+                Block::Nutrients => "nutrient",
+                Block::Ingredients => "ingredient",
+            };
+            diagnostics.push(Diagnostic::error(code_undefined, r.span, undefined(kind, &name, right_kind)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(diagnostics: &[Diagnostic]) -> Vec<DiagnosticCode> {
+        diagnostics.iter().map(|d| d.code).collect()
+    }
+
+    #[test]
+    fn test_check_source() {
+        let source = r#"
+            nutrient protein { unit "%" color "red" }
+            nutrient protein {}
+
+            ingredient corn {
+                nutrients { protein 8 fiber 2 }
+            }
+
+            formula starter {
+                nutrients { protein min 18 corn max 5 }
+                ingredients { corn protein soy }
+            }
+        "#;
+
+        let diagnostics = check_source(source);
+        assert_eq!(
+            codes(&diagnostics),
+            vec![
+                DiagnosticCode::DuplicateDefinition,
+                DiagnosticCode::InvalidProperty,
+                DiagnosticCode::MissingCost,
+                DiagnosticCode::UndefinedNutrient,
+                DiagnosticCode::ExpectedNutrient,
+                DiagnosticCode::ExpectedIngredient,
+                DiagnosticCode::UndefinedIngredient,
+                DiagnosticCode::MissingBatchSize,
+            ]
+        );
+
+        let fiber = &diagnostics[3];
+        assert_eq!(fiber.severity, Severity::Error);
+        assert_eq!(&source[fiber.span.start..fiber.span.end], "fiber 2");
+        let soy = &diagnostics[6];
+        assert_eq!(&source[soy.span.start..soy.span.end], "soy");
+        assert_eq!(diagnostics[7].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_check_with_imports() {
+        let source = r#"
+            import ./feeds.fm as feeds
+            import ./nutrients.fm { protein }
+
+            formula grower {
+                batch 100
+                nutrients { protein min 18 feeds.fiber max 5 }
+                ingredients { feeds.corn wheat feeds.corm }
+            }
+        "#;
+        let (program, errors) = Parser::parse_resilient(source);
+        assert!(errors.is_empty());
+
+        // Unresolved imports: references can't be checked
+        assert!(check_program(&program, None).is_empty());
+
+        let imported = ImportedNames {
+            nutrients: ["protein", "feeds.fiber"].into_iter().map(String::from).collect(),
+            ingredients: ["feeds.corn"].into_iter().map(String::from).collect(),
+            formulas: HashSet::new(),
+            constants: HashSet::new(),
+            modules: ["feeds"].into_iter().map(String::from).collect(),
+            ..Default::default()
+        };
+        let diagnostics = check_program(&program, Some(&imported));
+        assert_eq!(codes(&diagnostics), vec![DiagnosticCode::UndefinedIngredient; 2]);
+        assert_eq!(diagnostics[0].message, "Undefined ingredient: 'wheat'");
+        assert_eq!(diagnostics[1].message, "Undefined ingredient: 'feeds.corm'. Did you mean 'feeds.corn'?");
+    }
+
+    #[test]
+    fn test_check_units() {
+        let source = r#"
+            nutrient energy { unit "kcal/kg" }
+            nutrient lysine { unit "%" }
+            nutrient methionine { unit "g/kg" }
+            nutrient zinc { unit "mg/kg" }
+            nutrient phosphorus {}
+            ingredient wheat {
+                cost 200
+                nutrients { energy 13.8 "MJ/kg" lysine 3 "g/kg" zinc 30 "MJ/kg" methionine 2 "g/kkg" }
+            }
+            formula broiler {
+                batch 1000
+                nutrients {
+                    energy min 12 "MJ/kg"
+                    lysine + methionine min 0.8
+                    energy + zinc max 10
+                    zinc max 40 "%"
+                    lysine / methionine min 2 "%"
+                    energy / lysine min 300
+                    lysine + phosphorus min 1
+                }
+                ingredients { wheat }
+            }
+        "#;
+        let (program, errors) = Parser::parse_resilient(source);
+        assert!(errors.is_empty());
+
+        let diagnostics = check_program(&program, None);
+        assert_eq!(
+            codes(&diagnostics),
+            vec![
+                DiagnosticCode::IncompatibleUnits,
+                DiagnosticCode::UnknownUnit,
+                DiagnosticCode::IncompatibleUnits,
+                DiagnosticCode::IncompatibleUnits,
+                DiagnosticCode::IncompatibleUnits,
+            ]
+        );
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+        assert_eq!(diagnostics[0].message, "Unit of zinc: MJ/kg is an energy, but mg/kg is a concentration");
+        assert_eq!(diagnostics[1].message, "Unit of methionine: unknown unit g/kkg. Did you mean g/kg?");
+        assert_eq!(diagnostics[2].message, "Unit of zinc: mg/kg is a concentration, but kcal/kg is an energy");
+        assert_eq!(diagnostics[3].message, "Unit of lysine / methionine: a ratio has no unit, so it cannot be given in %");
+        assert_eq!(diagnostics[4].message, "Unit of phosphorus: none is declared, so it cannot be combined with %");
+        // Sums and ratios of one kind convert, `zinc max 40 "%"` converts, and
+        // a ratio may compare different kinds
+    }
+
+    #[test]
+    fn test_check_derived_nutrients() {
+        let source = r#"
+            nutrient lysine {}
+            nutrient dig_lysine { = lysine * 0.88 }
+            nutrient a { = b + lysine }
+            nutrient b { = a * 2 }
+            nutrient c { = corn * 2 + phosphorus }
+            ingredient corn { cost 150 nutrients { lysine 0.25 dig_lysine 0.22 } }
+        "#;
+        let (program, errors) = Parser::parse_resilient(source);
+        assert!(errors.is_empty());
+
+        let diagnostics = check_program(&program, None);
+        assert_eq!(
+            codes(&diagnostics),
+            vec![
+                DiagnosticCode::CircularNutrient,
+                DiagnosticCode::CircularNutrient,
+                DiagnosticCode::ExpectedNutrient,
+                DiagnosticCode::UndefinedNutrient,
+                DiagnosticCode::DerivedNutrientValue,
+            ]
+        );
+        assert_eq!(diagnostics[0].message, "Nutrient 'a' is derived from itself: a -> b -> a");
+        assert_eq!(
+            diagnostics[4].message,
+            "Ingredient 'corn' gives a value for 'dig_lysine', which is derived from other nutrients"
+        );
+    }
+
+    #[test]
+    fn test_check_circular_constants() {
+        let source = r#"
+            const a b + 1
+            const b a * 2
+            const c c
+            const d a + b
+            const e 3
+        "#;
+
+        let diagnostics = check_source(source);
+        assert_eq!(codes(&diagnostics), vec![DiagnosticCode::CircularConstant; 3]);
+        assert!(source[diagnostics[0].span.start..].starts_with("const a b + 1"));
+        assert_eq!(diagnostics[0].message, "Constant 'a' is defined in terms of itself: a -> b -> a");
+        assert_eq!(diagnostics[2].message, "Constant 'c' is defined in terms of itself: c -> c");
+        // d uses the cycle without being part of it
+    }
+
+    #[test]
+    fn test_check_nonlinear_expressions() {
+        let source = r#"
+            nutrient protein {}
+            nutrient lysine {}
+            nutrient balance { = protein / lysine }
+            ingredient corn { cost 150 nutrients { protein 8 lysine 0.25 } }
+            formula grower {
+                batch 100
+                nutrients {
+                    protein * lysine min 5
+                    (protein + 2 * lysine) / lysine min 3
+                    protein / 2 + lysine * (1 + 1) max 20
+                }
+                ingredients { corn }
+            }
+        "#;
+
+        let diagnostics = check_source(source);
+        assert_eq!(codes(&diagnostics), vec![DiagnosticCode::NonlinearExpression; 2]);
+        assert_eq!(&source[diagnostics[0].span.start..diagnostics[0].span.end], "protein / lysine");
+        assert_eq!(&source[diagnostics[1].span.start..diagnostics[1].span.end], "protein * lysine");
+        assert_eq!(
+            diagnostics[1].message,
+            "Nutrient expression 'protein * lysine' is not linear. Only sums of nutrients times constants are allowed"
+        );
+        // A ratio's sides and scaling by numbers are linear
+    }
+
+    #[test]
+    fn test_check_nutrient_data() {
+        let source = r#"
+            nutrient protein {}
+            nutrient lysine {}
+
+            ingredient corn {
+                cost 100
+                nutrients { protein 8 }
+            }
+
+            ingredient soy {
+                cost 300
+                nutrients { protein 44 lysine 2.8 }
+            }
+
+            formula grower {
+                batch 100
+                nutrients { protein min 18 lysine min 1 }
+                ingredients { corn soy }
+            }
+
+            formula strict {
+                batch 100
+                missing_data error
+                nutrients { lysine min 1 }
+                ingredients { corn soy }
+            }
+
+            formula lenient {
+                batch 100
+                missing_data zero
+                nutrients { lysine min 1 }
+                ingredients { corn soy }
+            }
+        "#;
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        let diagnostics = check_nutrient_data(&compiler, &program);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|d| d.code == DiagnosticCode::MissingNutrientData));
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].message, "Ingredient 'corn' has no 'lysine' value, so formula 'grower' assumes 0");
+        assert_eq!(&source[diagnostics[0].span.start..diagnostics[0].span.end], "lysine min 1");
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert!(diagnostics[1].message.contains("'strict'"));
+    }
+
+    #[test]
+    fn test_check_constants() {
+        let source = r#"
+            const mixer_size 2000
+            const ceiling target + 4
+
+            nutrient protein {}
+
+            ingredient corn {
+                cost 100
+                nutrients { protein corn_protein }
+            }
+
+            formula starter {
+                batch_size mixer_sise
+                nutrients { protein min 18 max ceiling }
+                ingredients { corn }
+            }
+        "#;
+
+        let diagnostics = check_source(source);
+        assert_eq!(codes(&diagnostics), vec![DiagnosticCode::UndefinedConstant; 3]);
+        let names: Vec<&str> = diagnostics.iter().map(|d| &source[d.span.start..d.span.end]).collect();
+        assert!(names.iter().any(|n| n.contains("target")), "{:?}", names);
+        assert!(names.iter().any(|n| n.contains("corn_protein")), "{:?}", names);
+        assert!(names.iter().any(|n| n.contains("mixer_sise")), "{:?}", names);
+    }
+
+    #[test]
+    fn test_check_template_instances() {
+        let source = r#"
+            nutrient protein {}
+            ingredient corn { cost 100 }
+
+            template formula broiler(phase_protein, size) {
+                batch size
+                nutrients { protein min phase_protein max ceiling }
+                ingredients { corn }
+            }
+
+            formula plain { batch 10 }
+
+            formula starter = broiler(22, 1000)
+            formula grower = broiler(20)
+            formula finisher = plain(18)
+            formula layer = hen(18, 1000)
+        "#;
+
+        let diagnostics = check_source(source);
+        assert_eq!(
+            codes(&diagnostics),
+            vec![
+                DiagnosticCode::UndefinedConstant,
+                DiagnosticCode::InvalidTemplateInstance,
+                DiagnosticCode::InvalidTemplateInstance,
+                DiagnosticCode::UndefinedTemplate,
+            ]
+        );
+        assert_eq!(diagnostics[0].message, "Undefined constant: 'ceiling'");
+        assert_eq!(diagnostics[1].message, "Template 'broiler' takes 2 arguments, got 1");
+        assert_eq!(&source[diagnostics[3].span.start..diagnostics[3].span.end], "hen");
+    }
+
+    #[test]
+    fn test_check_plans() {
+        let source = r#"
+            nutrient protein {}
+            ingredient corn { cost 100 }
+            formula starter { batch 1000 ingredients { corn } }
+
+            plan week {
+                start "monday"
+                formulas {
+                    starter 20000
+                    grower grower_tonnage
+                }
+                available {
+                    corn 15000
+                    protein 10
+                }
+            }
+        "#;
+
+        let diagnostics = check_source(source);
+        assert_eq!(
+            codes(&diagnostics),
+            vec![
+                DiagnosticCode::InvalidProperty,
+                DiagnosticCode::UndefinedConstant,
+                DiagnosticCode::UndefinedFormula,
+                DiagnosticCode::UndefinedIngredient,
+            ]
+        );
+        assert_eq!(diagnostics[2].message, "Undefined formula: 'grower'");
+        assert_eq!(&source[diagnostics[3].span.start..diagnostics[3].span.end], "protein");
+    }
+}
@@ -13,6 +13,8 @@ pub enum Item {
     Nutrient(Nutrient),
     Ingredient(Ingredient),
     Formula(Formula),
+    Const(Const),
+    Plan(Plan),
 }
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -31,12 +33,33 @@ pub enum ImportSelections {
     Named(Vec<String>),   // { protein, energy }
 }
 
+/// A named number, such as `const mixer_size 2000`. The value may use other
+/// constants, as in `const grower_protein starter_protein - 2`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Const {
+    pub span: Span,
+    pub name: String,
+    pub value: Expr,
+}
+
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct Nutrient {
     pub span: Span,
     pub name: String,
     pub properties: Vec<Property>,
+    /// Set for a nutrient computed from others, such as `= lysine * 0.88`
+    pub derived: Option<Derived>,
+}
+
+/// Linear expression a derived nutrient is computed from
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Derived {
+    /// From the `=` to the end of the expression
+    pub span: Span,
+    pub expr: Expr,
 }
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -54,8 +77,26 @@ pub struct Ingredient {
 pub struct NutrientValue {
     pub span: Span,
     pub nutrient: Reference,
-    /// The nutrient value. None means this is a composition reference (e.g., `corn.nutrients`)
+    /// The nutrient value. None with no `constant` means this is a composition
+    /// reference (e.g., `corn.nutrients`)
     pub value: Option<f64>,
+    /// Constant the value is given by (e.g., `lysine base_lysine`), whose
+    /// value the compiler fills in
+    pub constant: Option<Reference>,
+    /// Unit the value is given in, when not the nutrient's own: `energy 13.8 "MJ/kg"`
+    pub unit: Option<String>,
+    /// Basis the value is given on, set by a `basis` marker earlier in the block
+    pub basis: Basis,
+}
+
+/// Whether nutrient values count the ingredient's water (`as_fed`, the
+/// default) or only its dry matter (`dry_matter`)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Basis {
+    #[default]
+    AsFed,
+    DryMatter,
 }
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -64,11 +105,50 @@ pub struct Formula {
     pub span: Span,
     pub name: String,
     pub is_template: bool,
+    /// Parameters of a template, usable in its bounds and properties:
+    /// `template formula broiler(protein, max_corn) { ... }`
+    pub params: Vec<String>,
+    /// The template this formula instantiates: `formula starter = broiler(22, 50%)`
+    pub instance: Option<TemplateInstance>,
     pub properties: Vec<Property>,
     pub nutrients: Vec<NutrientConstraint>,
     pub ingredients: Vec<IngredientConstraint>,
 }
 
+/// A parameterized template applied to arguments. Each argument is a
+/// number, a percentage or a constant, like a bound.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateInstance {
+    pub span: Span,
+    pub template: Reference,
+    pub args: Vec<BoundValue>,
+}
+
+/// Formulas made together from shared ingredient stock:
+/// `plan week { formulas { starter 20000 } available { corn 15000 } }`
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub span: Span,
+    pub name: String,
+    pub properties: Vec<Property>,
+    /// Each formula to make, with the quantity to make of it
+    pub formulas: Vec<PlanEntry>,
+    /// Each limited ingredient, with the stock all the formulas share
+    pub available: Vec<PlanEntry>,
+}
+
+/// A formula or ingredient of a plan with its quantity: `starter 20000`.
+/// The quantity may be a constant, like a bound.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanEntry {
+    pub span: Span,
+    pub name: Reference,
+    pub quantity: BoundValue,
+}
+
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct Property {
@@ -93,7 +173,11 @@ pub struct NutrientConstraint {
     pub span: Span,
     pub expr: Expr,
     pub bounds: Bounds,
+    /// `soft <penalty>`: bounds may be missed at this cost per unit of deviation
+    pub soft: Option<f64>,
     pub alias: Option<String>,
+    /// Basis the bounds are given on, set by a `basis` marker earlier in the block
+    pub basis: Basis,
 }
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -102,6 +186,8 @@ pub struct IngredientConstraint {
     pub span: Span,
     pub expr: Expr,
     pub bounds: Bounds,
+    /// `or 0`: the bounds only apply when the expression is used at all
+    pub or_zero: bool,
     pub alias: Option<String>,
 }
 
@@ -135,15 +221,20 @@ impl Bounds {
 pub struct BoundValue {
     pub value: f64,
     pub is_percent: bool,
+    /// Constant the bound is given by (e.g., `protein min target_protein`),
+    /// whose value the compiler fills in
+    pub constant: Option<Reference>,
+    /// Unit of a nutrient bound, when not the nutrient's own: `energy min 12 "MJ/kg"`
+    pub unit: Option<String>,
 }
 
 impl BoundValue {
     pub fn absolute(value: f64) -> Self {
-        Self { value, is_percent: false }
+        Self { value, is_percent: false, constant: None, unit: None }
     }
 
     pub fn percent(value: f64) -> Self {
-        Self { value, is_percent: true }
+        Self { value, is_percent: true, constant: None, unit: None }
     }
 }
 
@@ -160,6 +251,21 @@ pub enum Expr {
     Paren(Box<Expr>),
 }
 
+impl Expr {
+    /// From the first name in the expression to the last. None for a plain number.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Expr::Number(_) => None,
+            Expr::Reference(r) => Some(r.span),
+            Expr::BinaryOp { left, right, .. } => match (left.span(), right.span()) {
+                (Some(l), Some(r)) => Some(l.merge(r)),
+                (l, r) => l.or(r),
+            },
+            Expr::Paren(inner) => inner.span(),
+        }
+    }
+}
+
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct Reference {
@@ -1,18 +1,24 @@
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
-use formulang_solver::{ConstraintOp, LpProblem};
+use formulang_solver::{
+    Analysis, ConstraintOp, LpProblem, ReducedCost, SensitivityRange, ShadowPrice, Solution, SolutionStatus, Solver,
+    VariableKind,
+};
 use thiserror::Error;
 
+use crate::analysis::ImportedNames;
 use crate::ast::*;
+use crate::formatter::{bound_text, expr_text};
+use crate::lexer::Span;
+use crate::units::{self, UnitError};
 use crate::Parser;
 
 /// Details extracted from a base formula reference
-/// e.g., `base.nutrients.protein.min` -> formula="base", block="nutrients", item=Some("protein"), min_only=true
+/// e.g., `base.nutrients.protein.min` -> formula="base", item=Some("protein"), min_only=true
 #[derive(Debug, Clone)]
 struct ReferenceDetails {
     formula_name: String,
-    block_type: String,
     item_name: Option<String>,
     min_only: bool,
     max_only: bool,
@@ -20,18 +26,41 @@ struct ReferenceDetails {
 
 #[derive(Error, Debug)]
 pub enum CompileError {
-    #[error("Unknown nutrient: {0}")]
-    UnknownNutrient(String),
-    #[error("Unknown ingredient: {0}")]
-    UnknownIngredient(String),
+    #[error("Unknown nutrient: {name}{}", did_you_mean(.suggestion))]
+    UnknownNutrient {
+        name: String,
+        span: Span,
+        /// A known nutrient with a similar name
+        suggestion: Option<String>,
+    },
+    #[error("Unknown ingredient: {name}{}", did_you_mean(.suggestion))]
+    UnknownIngredient {
+        name: String,
+        span: Span,
+        /// A known ingredient with a similar name
+        suggestion: Option<String>,
+    },
+    #[error("Unknown constant: {name}{}{}", located_in(.within), did_you_mean(.suggestion))]
+    UnknownConstant {
+        name: String,
+        span: Span,
+        /// The formula or plan using it, e.g. `formula starter`
+        within: Option<String>,
+        /// A known constant with a similar name
+        suggestion: Option<String>,
+    },
     #[error("Unknown formula: {0}")]
     UnknownFormula(String),
     #[error("Missing batch_size in formula {0}")]
     MissingBatchSize(String),
     #[error("Missing cost in ingredient {0}")]
     MissingCost(String),
-    #[error("Circular reference detected: {0}")]
-    CircularReference(String),
+    #[error("Circular reference detected: {path}")]
+    CircularReference {
+        path: String,
+        /// The constant the cycle starts from. None for formulas and derived nutrients
+        span: Option<Span>,
+    },
     #[error("Percentage not allowed in nutrient constraints")]
     PercentInNutrientConstraint,
     #[error("Invalid reference: {0}")]
@@ -42,12 +71,142 @@ pub enum CompileError {
     ParseError(String, String),
     #[error("Import cycle detected: {0}")]
     ImportCycle(String),
+    #[error("Module {0} has no declaration named {1}")]
+    UnknownImport(String, String),
+    #[error("{0} is not in scope. Import it or use a qualified name")]
+    NotInScope(String),
     #[error("Cannot solve template formula '{0}'. Templates are for composition only.")]
     CannotSolveTemplate(String),
+    #[error("{0} is not a template, so it cannot be instantiated")]
+    NotATemplate(String),
+    #[error("Template {template} takes {}, got {found}", arguments(*.expected))]
+    TemplateArgumentCount {
+        template: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    #[error("Template {0} has parameters. Instantiate it with `formula name = {0}(...)` instead")]
+    ParameterizedTemplate(String),
+    #[error("Formula {0} is used as an ingredient but has no optimal solution ({1:?})")]
+    SubFormulaNotOptimal(String, SolutionStatus),
+    #[error("Unknown plan: {0}")]
+    UnknownPlan(String),
+    #[error("{name} is listed twice in plan {plan}")]
+    DuplicatePlanEntry { plan: String, name: String },
+    #[error("Quantity of {name} in plan {plan} must be {}, got {quantity}", if *.available { "zero or more" } else { "positive" })]
+    InvalidPlanQuantity {
+        plan: String,
+        name: String,
+        quantity: f64,
+        /// Whether the quantity is an ingredient's stock rather than a formula's amount
+        available: bool,
+    },
     #[error("Invalid property reference: {0}")]
     InvalidPropertyReference(String),
+    #[error("Nutrient expression {expr} is not linear. Only sums of nutrients times constants are allowed")]
+    NonLinearExpression { expr: String, span: Span },
     #[error("Division by zero in expression")]
     DivisionByZero,
+    #[error("max_ingredients in formula {0} must be a non-negative whole number, got {1}")]
+    InvalidMaxIngredients(String, f64),
+    #[error("Soft penalty for {0} must be positive, got {1}")]
+    InvalidSoftPenalty(String, f64),
+    #[error("{0} subtracts ingredients, so `or 0` can't tell whether it is used. Use `or 0` on each ingredient instead")]
+    InvalidOrZero(String),
+    #[error("missing_data in formula {0} must be error, warn or zero, got {1}")]
+    InvalidMissingDataPolicy(String, String),
+    #[error("Dry matter of ingredient {0} must be between 0 and 100, got {1}")]
+    InvalidDryMatter(String, f64),
+    #[error("Ingredient {0} sets both dry_matter and moisture. Give only one of them")]
+    ConflictingDryMatter(String),
+    #[error("Ingredient {0} has nutrient values on a dry matter basis but no dry_matter or moisture")]
+    MissingDryMatter(String),
+    #[error("Formula {formula} has bounds on a dry matter basis but ingredient {ingredient} has no dry_matter or moisture")]
+    MissingDryMatterInFormula { formula: String, ingredient: String },
+    #[error("Ingredient {ingredient} gives a value for {nutrient}, which is derived from other nutrients")]
+    DerivedNutrientValue { nutrient: String, ingredient: String },
+    #[error("Unit of {subject}: {error}")]
+    InvalidUnit {
+        /// The nutrient or constraint the unit is given for
+        subject: String,
+        span: Span,
+        error: UnitError,
+    },
+    #[error("Formula {formula} is missing nutrient data: {}", describe_missing(.missing))]
+    MissingNutrientData {
+        formula: String,
+        missing: Vec<MissingNutrientValue>,
+    },
+}
+
+impl CompileError {
+    /// Where in the source the error is, for errors tied to a reference
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CompileError::UnknownNutrient { span, .. }
+            | CompileError::UnknownIngredient { span, .. }
+            | CompileError::UnknownConstant { span, .. }
+            | CompileError::TemplateArgumentCount { span, .. }
+            | CompileError::NonLinearExpression { span, .. }
+            | CompileError::InvalidUnit { span, .. } => Some(*span),
+            CompileError::CircularReference { span, .. } => *span,
+            CompileError::MissingNutrientData { missing, .. } => missing.first().map(|m| m.span),
+            _ => None,
+        }
+    }
+}
+
+fn describe_missing(missing: &[MissingNutrientValue]) -> String {
+    missing
+        .iter()
+        .map(|m| format!("{} has no {} value", m.ingredient, m.nutrient))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// "1 argument" or "n arguments", for messages
+pub(crate) fn arguments(count: usize) -> String {
+    match count {
+        1 => "1 argument".to_string(),
+        n => format!("{} arguments", n),
+    }
+}
+
+fn located_in(location: &Option<String>) -> String {
+    location.as_ref().map(|l| format!(" in {}", l)).unwrap_or_default()
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    suggestion
+        .as_ref()
+        .map(|s| format!(". Did you mean {}?", s))
+        .unwrap_or_default()
+}
+
+/// How a formula treats ingredients with no value for a constrained nutrient,
+/// set with the `missing_data` property
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingDataPolicy {
+    /// Refuse to compile the formula
+    Error,
+    /// Assume zero and report the missing values
+    #[default]
+    Warn,
+    /// Assume zero without reporting
+    Zero,
+}
+
+/// An ingredient in a formula with no value for a nutrient the formula constrains
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MissingNutrientValue {
+    pub ingredient: String,
+    pub nutrient: String,
+    /// Formula defining the first constraint on the nutrient
+    pub formula: String,
+    /// Span of that constraint
+    pub span: Span,
 }
 
 /// Compiled representation of a nutrient
@@ -57,6 +216,8 @@ pub struct CompiledNutrient {
     pub display_name: Option<String>,
     pub code: Option<String>,
     pub unit: Option<String>,
+    /// Expression the nutrient is computed from, for a derived nutrient
+    pub derived: Option<Expr>,
 }
 
 /// Compiled representation of an ingredient
@@ -67,7 +228,10 @@ pub struct CompiledIngredient {
     pub code: Option<String>,
     pub is_template: bool,
     pub cost: f64,
+    /// Nutrient values as fed
     pub nutrients: HashMap<String, f64>,
+    /// Dry matter in percent, from `dry_matter` or `moisture`
+    pub dry_matter: Option<f64>,
 }
 
 /// Compiled representation of a formula ready for solving
@@ -81,21 +245,345 @@ pub struct CompiledFormula {
     pub ingredients: Vec<String>,
     pub ingredient_costs: Vec<f64>,
     pub ingredient_nutrients: Vec<HashMap<String, f64>>,
+    /// Dry matter of each ingredient in percent, when known
+    pub ingredient_dry_matter: Vec<Option<f64>>,
     pub nutrient_names: Vec<String>,
     pub nutrient_units: Vec<Option<String>>,
+    pub soft_constraints: Vec<SoftConstraint>,
+    /// Rows bounding a level in the batch's dry matter. The bound is moved
+    /// into their coefficients, so their right-hand side is 0.
+    pub dry_matter_rows: Vec<String>,
+    /// Rows of `or 0` constraints and ingredient count links, which hold
+    /// only when their ingredient is used and have 0 on the right
+    pub conditional_rows: Vec<String>,
+    /// Where each LP constraint came from, in constraint order
+    pub constraint_sources: Vec<ConstraintSource>,
+    /// Constrained nutrients some ingredients have no value for, taken as
+    /// zero. Empty under the `zero` policy.
+    pub missing_values: Vec<MissingNutrientValue>,
+    /// Formulas used as ingredients, in ingredient order
+    pub sub_formulas: Vec<SubFormula>,
+    pub lp_problem: LpProblem,
+}
+
+/// A formula used as an ingredient of another, such as a premix. It is
+/// solved on its own first, and its cost and nutrients per unit come from
+/// that solution.
+#[derive(Debug, Clone)]
+pub struct SubFormula {
+    pub compiled: CompiledFormula,
+    pub solution: Solution,
+}
+
+impl SubFormula {
+    /// Cost per unit of the sub-formula
+    pub fn unit_cost(&self) -> f64 {
+        self.compiled.ingredient_cost(&self.solution.values) / self.compiled.batch_size
+    }
+
+    /// Dry matter of the sub-formula in percent, when known
+    pub fn dry_matter(&self) -> Option<f64> {
+        self.compiled.dry_matter(&self.solution.values)
+    }
+
+    /// Nutrient levels of the sub-formula, as an ingredient would list them
+    pub fn nutrients(&self) -> HashMap<String, f64> {
+        let mut levels = HashMap::new();
+        for (values, amount) in self.compiled.ingredient_nutrients.iter().zip(&self.solution.values) {
+            for (nutrient, value) in values {
+                *levels.entry(nutrient.clone()).or_insert(0.0) += value * amount / self.compiled.batch_size;
+            }
+        }
+        levels
+    }
+
+    /// Amount of `ingredient` in one unit of the sub-formula, including what
+    /// its own sub-formulas contain
+    pub fn content_of(&self, ingredient: &str) -> f64 {
+        let per_batch: f64 = self
+            .compiled
+            .ingredient_content(ingredient)
+            .iter()
+            .zip(&self.solution.values)
+            .map(|(content, amount)| content * amount)
+            .sum();
+        per_batch / self.compiled.batch_size
+    }
+}
+
+/// The formulas of a plan compiled into one LP problem. Each formula has its
+/// own block of variables and rows, scaled from one batch to its planned
+/// quantity, and each `available` ingredient a row limiting its use across
+/// the blocks.
+#[derive(Debug, Clone)]
+pub struct CompiledPlan {
+    pub name: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub formulas: Vec<PlannedFormula>,
+    pub supplies: Vec<Supply>,
     pub lp_problem: LpProblem,
 }
 
+/// A formula of a plan and where its block is in the plan's LP problem.
+/// Variables and rows of the block are named `formula.name`.
+#[derive(Debug, Clone)]
+pub struct PlannedFormula {
+    pub compiled: CompiledFormula,
+    /// Amount of the formula to make
+    pub quantity: f64,
+    pub first_variable: usize,
+    pub first_constraint: usize,
+}
+
+/// Stock of an ingredient shared by the formulas of a plan
+#[derive(Debug, Clone)]
+pub struct Supply {
+    pub ingredient: String,
+    pub available: f64,
+    /// Index of the row limiting its use (`corn_available`)
+    pub constraint: usize,
+}
+
+impl PlannedFormula {
+    /// Number of batches the planned quantity makes
+    pub fn scale(&self) -> f64 {
+        self.quantity / self.compiled.batch_size
+    }
+
+    /// The formula's part of a plan solution, scaled back to one batch and
+    /// named as if the formula had been solved alone
+    pub fn solution(&self, plan: &Solution) -> Solution {
+        let lp = &self.compiled.lp_problem;
+        let variables = self.first_variable..self.first_variable + lp.num_variables();
+        let rows = self.first_constraint..self.first_constraint + lp.num_constraints();
+        let scale = self.scale();
+
+        // Amounts grow with the quantity; whole-number indicators don't
+        let values: Vec<f64> = plan
+            .values
+            .get(variables.clone())
+            .unwrap_or_default()
+            .iter()
+            .zip(&lp.variable_kinds)
+            .map(|(value, kind)| if *kind == VariableKind::Continuous { value / scale } else { *value })
+            .collect();
+        let objective_value = lp.objective.coefficients.iter().zip(&values).map(|(c, v)| c * v).sum();
+
+        let mut analysis = Analysis::empty();
+        let full = &plan.analysis;
+        if full.shadow_prices.len() >= rows.end && full.reduced_costs.len() >= variables.end {
+            let prefix = format!("{}.", self.compiled.name);
+            analysis.shadow_prices = full.shadow_prices[rows.clone()]
+                .iter()
+                .zip(&lp.constraints)
+                .map(|(sp, c)| ShadowPrice { constraint: c.name.clone(), ..sp.clone() })
+                .collect();
+            analysis.rhs_sensitivity = full.rhs_sensitivity[rows]
+                .iter()
+                .zip(&lp.constraints)
+                .map(|(range, c)| SensitivityRange {
+                    name: c.name.clone(),
+                    current: range.current / scale,
+                    lower_bound: range.lower_bound / scale,
+                    upper_bound: range.upper_bound / scale,
+                })
+                .collect();
+            analysis.reduced_costs = full.reduced_costs[variables.clone()]
+                .iter()
+                .zip(lp.variables.iter().zip(&values))
+                .map(|(rc, (name, value))| ReducedCost { variable: name.clone(), value: *value, ..rc.clone() })
+                .collect();
+            analysis.objective_sensitivity = full.objective_sensitivity[variables]
+                .iter()
+                .zip(&lp.variables)
+                .map(|(range, name)| SensitivityRange { name: name.clone(), ..range.clone() })
+                .collect();
+            analysis.binding_constraints = full
+                .binding_constraints
+                .iter()
+                .filter_map(|name| name.strip_prefix(&prefix))
+                .map(String::from)
+                .collect();
+        }
+
+        Solution {
+            status: plan.status,
+            values,
+            objective_value,
+            analysis,
+            violations: Vec::new(),
+            conflict: Vec::new(),
+        }
+    }
+}
+
+impl CompiledFormula {
+    /// Cost of the ingredients alone, leaving out soft constraint penalties
+    pub fn ingredient_cost(&self, values: &[f64]) -> f64 {
+        self.ingredient_costs
+            .iter()
+            .zip(values)
+            .map(|(cost, amount)| cost * amount)
+            .sum()
+    }
+
+    /// Dry matter of the batch in percent, known when it is known for every
+    /// ingredient used
+    pub fn dry_matter(&self, values: &[f64]) -> Option<f64> {
+        let mut dry_matter = 0.0;
+        for (dm, amount) in self.ingredient_dry_matter.iter().zip(values) {
+            if *amount > 1e-9 {
+                dry_matter += (*dm)? * amount;
+            }
+        }
+        Some(dry_matter / self.batch_size)
+    }
+
+    /// How far a soft constraint was missed, in the constraint's own units.
+    /// On a dry matter basis that is a level in the batch's dry matter.
+    pub fn soft_deviation(&self, soft: &SoftConstraint, values: &[f64]) -> f64 {
+        let deviation = values.get(soft.variable).copied().unwrap_or(0.0) / self.batch_size;
+        match self.dry_matter(values) {
+            Some(dm) if dm > 0.0 && self.dry_matter_rows.contains(&soft.constraint) => deviation / dm * 100.0,
+            _ => deviation,
+        }
+    }
+
+    /// Penalty charged for missing a soft constraint. On a dry matter basis
+    /// it is charged on the deviation's share of the batch as fed.
+    pub fn soft_penalty(&self, soft: &SoftConstraint, values: &[f64]) -> f64 {
+        values.get(soft.variable).copied().unwrap_or(0.0) / self.batch_size * soft.penalty
+    }
+
+    /// Amount of `ingredient` in one unit of each of the formula's
+    /// ingredients: 1 for the ingredient itself, and for a sub-formula what
+    /// its solution contains
+    pub fn ingredient_content(&self, ingredient: &str) -> Vec<f64> {
+        self.ingredients
+            .iter()
+            .map(|id| {
+                if id == ingredient {
+                    1.0
+                } else {
+                    self.sub_formulas
+                        .iter()
+                        .find(|sub| &sub.compiled.name == id)
+                        .map_or(0.0, |sub| sub.content_of(ingredient))
+                }
+            })
+            .collect()
+    }
+
+    /// Why a row's right-hand side isn't the bound it was written with, so
+    /// its shadow price, ranging and conflict amounts can't be quoted as the
+    /// bound's
+    pub fn moved_bound(&self, row: &str) -> Option<String> {
+        if self.dry_matter_rows.iter().any(|r| r == row) {
+            return Some("a level in the dry matter".to_string());
+        }
+        // Named `corn_min`, `corn_max` or `corn_link`
+        let (used, _) = row.rsplit_once('_').filter(|_| self.conditional_rows.iter().any(|r| r == row))?;
+        Some(format!("which only applies when {} is used", used))
+    }
+}
+
+/// A nutrient bound that may be missed at a cost
+#[derive(Debug, Clone)]
+pub struct SoftConstraint {
+    /// LP constraint name (e.g. `protein_min`)
+    pub constraint: String,
+    /// `Ge` for a minimum (deviation is a shortfall), `Le` for a maximum (an excess)
+    pub op: ConstraintOp,
+    /// Index of the deviation variable in the LP problem
+    pub variable: usize,
+    /// Cost per unit of deviation
+    pub penalty: f64,
+    /// What the deviation is measured in: the nutrient's unit, or for a
+    /// ratio the linearized row, e.g. `calcium - 1.5 × phosphorus`
+    pub measure: Option<String>,
+}
+
+/// Where a generated LP constraint was defined
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConstraintSource {
+    /// LP constraint name (e.g. `protein_min`)
+    pub constraint: String,
+    /// Formula whose block or property defines the constraint
+    pub formula: String,
+    /// File that formula is declared in, if it was loaded from a file
+    pub file: Option<PathBuf>,
+    /// Span of the definition in that file (or in the loaded source)
+    pub span: Span,
+    /// Formulas the constraint was inherited through, from the compiled
+    /// formula to `formula`. Just the compiled formula if it is its own.
+    pub chain: Vec<String>,
+    /// Formulas whose bounds for the same constraint were overridden by
+    /// this definition, nearest first
+    pub overrides: Vec<String>,
+}
+
+/// Inheritance of a resolved constraint, before it becomes LP rows
+#[derive(Debug, Clone)]
+struct Origin {
+    chain: Vec<String>,
+    overrides: Vec<String>,
+}
+
+impl Origin {
+    fn own(formula: &str) -> Self {
+        Self {
+            chain: vec![formula.to_string()],
+            overrides: Vec::new(),
+        }
+    }
+
+    /// The same definition, as seen from a formula that includes it
+    fn included_by(mut self, formula: &str) -> Self {
+        self.chain.insert(0, formula.to_string());
+        self
+    }
+
+    /// A definition in `formula` replacing the one from this origin
+    fn overridden_by(&self, formula: &str) -> Self {
+        let mut overrides = vec![self.formula().to_string()];
+        overrides.extend(self.overrides.iter().cloned());
+        Self {
+            chain: vec![formula.to_string()],
+            overrides,
+        }
+    }
+
+    fn formula(&self) -> &str {
+        self.chain.last().map(String::as_str).unwrap_or_default()
+    }
+}
+
 /// Symbol table for resolving references
 #[derive(Debug, Default, Clone)]
 pub struct SymbolTable {
     pub nutrients: HashMap<String, CompiledNutrient>,
     pub ingredients: HashMap<String, CompiledIngredient>,
     pub formulas: HashMap<String, Formula>,
+    /// Values of `const` declarations
+    pub constants: HashMap<String, f64>,
+    pub plans: HashMap<String, Plan>,
     /// Resolved nutrient constraints from base formulas
     pub nutrient_constraints: HashMap<String, Vec<NutrientConstraint>>,
     /// Resolved ingredient constraints from base formulas
     pub ingredient_constraints: HashMap<String, Vec<IngredientConstraint>>,
+    /// File each formula was declared in, for formulas loaded from files
+    pub formula_files: HashMap<String, PathBuf>,
+}
+
+/// Names visible inside one file, mapped to their symbol table keys
+#[derive(Debug, Default, Clone)]
+struct Scope {
+    /// Declared and directly imported names
+    names: HashMap<String, String>,
+    /// Imported modules (file stem or alias) and the names they export
+    modules: HashMap<String, HashMap<String, String>>,
 }
 
 /// Compiler for converting AST to LP problems
@@ -106,6 +594,12 @@ pub struct Compiler {
     base_dir: Option<PathBuf>,
     /// Track loaded files to prevent cycles
     loaded_files: HashSet<PathBuf>,
+    /// Names exported by each loaded file
+    modules: HashMap<PathBuf, HashMap<String, String>>,
+    /// Symbol table keys claimed by loaded files
+    keys: HashSet<String>,
+    /// Names visible in each loaded file, kept for `imported_names`
+    scopes: HashMap<PathBuf, Scope>,
 }
 
 impl Compiler {
@@ -114,6 +608,9 @@ impl Compiler {
             symbols: SymbolTable::default(),
             base_dir: None,
             loaded_files: HashSet::new(),
+            modules: HashMap::new(),
+            keys: HashSet::new(),
+            scopes: HashMap::new(),
         }
     }
 
@@ -123,18 +620,50 @@ impl Compiler {
             symbols: SymbolTable::default(),
             base_dir: Some(base_dir.as_ref().to_path_buf()),
             loaded_files: HashSet::new(),
+            modules: HashMap::new(),
+            keys: HashSet::new(),
+            scopes: HashMap::new(),
         }
     }
 
     /// Load a file and all its imports
+    ///
+    /// Declarations in the file keep their own names. Imported declarations
+    /// keep theirs too unless that would clash, in which case they are keyed
+    /// by their qualified name (e.g. `grains.corn`).
     pub fn load_file(&mut self, path: impl AsRef<Path>) -> Result<(), CompileError> {
+        self.load_module(path.as_ref(), true)?;
+        Ok(())
+    }
+
+    /// Load an already parsed program as the file at `path`, resolving its
+    /// imports relative to it. Lets editors check a buffer that has unsaved
+    /// changes.
+    pub fn load_program(&mut self, program: &Program, path: impl AsRef<Path>) -> Result<(), CompileError> {
         let path = path.as_ref();
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.loaded_files.insert(canonical.clone());
+        if self.base_dir.is_none() {
+            self.base_dir = canonical.parent().map(|p| p.to_path_buf());
+        }
+
+        let exports = self.load_with_base(program, &canonical, true)?;
+        self.modules.insert(canonical, exports);
+        Ok(())
+    }
+
+    /// Load a file as a module and return the names it exports
+    fn load_module(&mut self, path: &Path, entry: bool) -> Result<HashMap<String, String>, CompileError> {
         let canonical = path.canonicalize().map_err(|e| {
             CompileError::IoError(format!("Cannot resolve path {}: {}", path.display(), e))
         })?;
 
+        if let Some(exports) = self.modules.get(&canonical) {
+            return Ok(exports.clone()); // Already loaded
+        }
         if self.loaded_files.contains(&canonical) {
-            return Ok(()); // Already loaded
+            // Still loading its own imports
+            return Err(CompileError::ImportCycle(canonical.display().to_string()));
         }
         self.loaded_files.insert(canonical.clone());
 
@@ -149,46 +678,138 @@ impl Compiler {
         let program = Parser::parse(&source)
             .map_err(|e| CompileError::ParseError(path.display().to_string(), e.to_string()))?;
 
-        self.load_with_base(&program, canonical.parent())?;
+        let exports = self.load_with_base(&program, &canonical, entry)?;
+        self.modules.insert(canonical, exports.clone());
 
-        Ok(())
+        Ok(exports)
     }
 
-    /// Load a program with a specific base directory for imports
-    fn load_with_base(&mut self, program: &Program, base: Option<&Path>) -> Result<(), CompileError> {
-        // First, process imports
+    /// Load a program in its own scope, with imports resolved relative to `path`
+    fn load_with_base(
+        &mut self,
+        program: &Program,
+        path: &Path,
+        entry: bool,
+    ) -> Result<HashMap<String, String>, CompileError> {
+        // Claim keys for this file's declarations before its imports do,
+        // so the importing file keeps the unqualified names
+        let module = module_name(path);
+        let mut scope = Scope::default();
+        for name in declared_names(program) {
+            if scope.names.contains_key(&name) {
+                continue;
+            }
+            let key = if entry { name.clone() } else { self.claim_key(&module, &name) };
+            self.keys.insert(key.clone());
+            scope.names.insert(name, key);
+        }
+
+        // Then process imports
         for item in &program.items {
             if let Item::Import(import) = item {
-                self.process_import(import, base)?;
+                self.process_import(import, path.parent(), &mut scope)?;
             }
         }
 
-        // Then load declarations
-        self.load(program)
+        // Then load declarations, with every name replaced by its key
+        self.scopes.insert(path.to_path_buf(), scope.clone());
+        let resolved = scope.resolve_program(program, &self.keys)?;
+        for item in &resolved.items {
+            if let Item::Formula(f) = item {
+                self.symbols.formula_files.insert(f.name.clone(), path.to_path_buf());
+            }
+        }
+        self.load(&resolved)?;
+
+        // Everything visible unqualified is exported, which is how `{ * }` re-exports
+        Ok(scope.names)
+    }
+
+    /// Names visible in a loaded file, including those from its imports, for
+    /// checking the file on its own with [`analysis::check_program`](crate::analysis::check_program).
+    /// Available once the file's imports have loaded, even if its own
+    /// declarations then failed to.
+    pub fn imported_names(&self, path: impl AsRef<Path>) -> Option<ImportedNames> {
+        let scope = self.scopes.get(&path.as_ref().canonicalize().ok()?)?;
+        let mut imported = ImportedNames::default();
+        let mut add = |name: String, key: &String| {
+            if let Some(nutrient) = self.symbols.nutrients.get(key) {
+                if let Some(unit) = &nutrient.unit {
+                    imported.units.insert(name.clone(), unit.clone());
+                }
+                if nutrient.derived.is_some() {
+                    imported.derived.insert(name.clone());
+                }
+                imported.nutrients.insert(name);
+            } else if self.symbols.ingredients.contains_key(key) {
+                imported.ingredients.insert(name);
+            } else if self.symbols.formulas.contains_key(key) {
+                imported.formulas.insert(name);
+            } else if self.symbols.constants.contains_key(key) {
+                imported.constants.insert(name);
+            }
+        };
+        for (name, key) in &scope.names {
+            add(name.clone(), key);
+        }
+        for (module, exports) in &scope.modules {
+            for (name, key) in exports {
+                add(format!("{}.{}", module, name), key);
+            }
+        }
+        imported.modules = scope.modules.keys().cloned().collect();
+        Some(imported)
     }
 
-    fn process_import(&mut self, import: &Import, base: Option<&Path>) -> Result<(), CompileError> {
+    fn process_import(&mut self, import: &Import, base: Option<&Path>, scope: &mut Scope) -> Result<(), CompileError> {
         let import_path = self.resolve_import_path(&import.path, base)?;
+        let exports = self.load_module(&import_path, false)?;
 
-        if self.loaded_files.contains(&import_path) {
-            return Ok(()); // Already loaded, skip
+        // Local declarations win over imported names, and earlier imports over later ones
+        match &import.selections {
+            Some(ImportSelections::All) => {
+                for (name, key) in &exports {
+                    scope.names.entry(name.clone()).or_insert_with(|| key.clone());
+                }
+            }
+            Some(ImportSelections::Named(names)) => {
+                for name in names {
+                    let key = exports
+                        .get(name)
+                        .ok_or_else(|| CompileError::UnknownImport(import.path.clone(), name.clone()))?;
+                    scope.names.entry(name.clone()).or_insert_with(|| key.clone());
+                }
+            }
+            None => {}
         }
 
-        self.load_file(&import_path)?;
+        let module = import.alias.clone().unwrap_or_else(|| module_name(&import_path));
+        scope.modules.insert(module, exports);
 
         Ok(())
     }
 
+    /// Key for a declaration from an imported file: its own name if free,
+    /// otherwise qualified by the module name
+    fn claim_key(&self, module: &str, name: &str) -> String {
+        if !self.keys.contains(name) {
+            return name.to_string();
+        }
+        let mut key = format!("{}.{}", module, name);
+        let mut n = 2;
+        while self.keys.contains(&key) {
+            key = format!("{}{}.{}", module, n, name);
+            n += 1;
+        }
+        key
+    }
+
     fn resolve_import_path(&self, path: &str, base: Option<&Path>) -> Result<PathBuf, CompileError> {
         let base = base
             .or(self.base_dir.as_deref())
             .ok_or_else(|| CompileError::IoError("No base directory for import".to_string()))?;
 
-        let import_path = if path.starts_with("./") || path.starts_with("../") {
-            base.join(path)
-        } else {
-            base.join(path)
-        };
+        let import_path = base.join(path);
 
         import_path.canonicalize().map_err(|e| {
             CompileError::IoError(format!("Cannot resolve import {}: {}", path, e))
@@ -197,19 +818,27 @@ impl Compiler {
 
     /// Load a program into the symbol table
     pub fn load(&mut self, program: &Program) -> Result<(), CompileError> {
+        self.load_constants(program)?;
+
+        // Nutrients first, as ingredient values convert to their units
+        for item in &program.items {
+            if let Item::Nutrient(n) = item {
+                self.symbols.nutrients.insert(
+                    n.name.clone(),
+                    CompiledNutrient {
+                        name: n.name.clone(),
+                        display_name: get_string_property(&n.properties, "name"),
+                        code: get_string_property(&n.properties, "code"),
+                        unit: get_string_property(&n.properties, "unit"),
+                        derived: n.derived.as_ref().map(|d| d.expr.clone()),
+                    },
+                );
+            }
+        }
+
+        let mut instances = Vec::new();
         for item in &program.items {
             match item {
-                Item::Nutrient(n) => {
-                    self.symbols.nutrients.insert(
-                        n.name.clone(),
-                        CompiledNutrient {
-                            name: n.name.clone(),
-                            display_name: get_string_property(&n.properties, "name"),
-                            code: get_string_property(&n.properties, "code"),
-                            unit: get_string_property(&n.properties, "unit"),
-                        },
-                    );
-                }
                 Item::Ingredient(i) => {
                     // Templates don't require cost
                     let cost = if i.is_template {
@@ -219,15 +848,35 @@ impl Compiler {
                             .ok_or_else(|| CompileError::MissingCost(i.name.clone()))?
                     };
 
+                    let dry_matter = self.ingredient_dry_matter(i)?;
+                    // Values are stored in the nutrient's unit, and as fed when
+                    // given on a dry matter basis
+                    let as_fed = |value: f64, nv: &NutrientValue| {
+                        let value = self.in_nutrient_unit(value, nv)?;
+                        match nv.basis {
+                            Basis::AsFed => Ok(value),
+                            Basis::DryMatter => dry_matter
+                                .map(|dm| value * dm / 100.0)
+                                .ok_or_else(|| CompileError::MissingDryMatter(i.name.clone())),
+                        }
+                    };
+
                     let mut nutrients = HashMap::new();
                     for nv in &i.nutrients {
-                        match nv.value {
-                            Some(value) => {
+                        let nutrient_name = reference_to_string(&nv.nutrient);
+                        if nv.value.is_some() || nv.constant.is_some() {
+                            self.check_not_derived(&nutrient_name, &i.name)?;
+                        }
+                        match (nv.value, &nv.constant) {
+                            (Some(value), _) => {
                                 // Direct nutrient value: `protein 8.5`
-                                let nutrient_name = reference_to_string(&nv.nutrient);
-                                nutrients.insert(nutrient_name, value);
+                                nutrients.insert(nutrient_name, as_fed(value, nv)?);
+                            }
+                            (None, Some(constant)) => {
+                                // Value from a constant: `lysine base_lysine`
+                                nutrients.insert(nutrient_name, as_fed(self.lookup_constant(constant)?, nv)?);
                             }
-                            None => {
+                            (None, None) => {
                                 // Composition reference: `corn.nutrients`
                                 self.resolve_ingredient_nutrient_reference(
                                     &nv.nutrient,
@@ -246,101 +895,505 @@ impl Compiler {
                             is_template: i.is_template,
                             cost,
                             nutrients,
+                            dry_matter,
                         },
                     );
                 }
                 Item::Formula(f) => {
-                    self.symbols.formulas.insert(f.name.clone(), f.clone());
+                    // Constants in bounds are looked up when the formula is
+                    // compiled, so a typo only fails the formulas using it
+                    if f.instance.is_some() {
+                        // Once every template of the program is loaded
+                        instances.push(f.clone());
+                    } else {
+                        self.symbols.formulas.insert(f.name.clone(), f.clone());
+                    }
                 }
-                Item::Import(_) => {
-                    // Already processed in load_with_base
+                Item::Plan(p) => {
+                    self.symbols.plans.insert(p.name.clone(), p.clone());
+                }
+                Item::Import(_) | Item::Const(_) | Item::Nutrient(_) => {
+                    // Already processed in load_with_base, load_constants and above
                 }
             }
         }
-        Ok(())
-    }
 
-    /// Check if a formula is marked as a template (not solvable)
-    pub fn is_template(&self, name: &str) -> bool {
-        self.symbols
-            .formulas
-            .get(name)
-            .map(|f| f.is_template || get_bool_property(&f.properties, "template").unwrap_or(false))
-            .unwrap_or(false)
+        for formula in instances {
+            self.instantiate(formula);
+        }
+        self.derive_nutrients()
     }
 
-    /// Check if an ingredient is marked as a template
-    pub fn is_ingredient_template(&self, name: &str) -> bool {
-        self.symbols
-            .ingredients
-            .get(name)
-            .map(|i| i.is_template)
-            .unwrap_or(false)
+    /// Derived nutrients can't be given a value
+    fn check_not_derived(&self, nutrient: &str, ingredient: &str) -> Result<(), CompileError> {
+        match self.symbols.nutrients.get(nutrient) {
+            Some(n) if n.derived.is_some() => Err(CompileError::DerivedNutrientValue {
+                nutrient: nutrient.to_string(),
+                ingredient: ingredient.to_string(),
+            }),
+            _ => Ok(()),
+        }
     }
 
-    /// Resolve a property value that may be an expression
-    /// Supports references like `corn.cost` and arithmetic like `corn.cost * 2`
-    fn resolve_number_property(&self, properties: &[Property], name: &str) -> Result<Option<f64>, CompileError> {
-        for p in properties {
-            if property_matches(&p.name, name) {
-                return match &p.value {
-                    PropertyValue::Number(n) => Ok(Some(*n)),
-                    PropertyValue::Expr(expr) => Ok(Some(self.evaluate_property_expr(expr)?)),
-                    _ => Ok(None),
-                };
+    /// Compute the derived nutrients of every loaded ingredient. Ingredients
+    /// loaded earlier are updated too, as a later file may derive nutrients
+    /// from theirs, and values inherited through `corn.nutrients` are
+    /// recomputed from the inheriting ingredient's own values.
+    fn derive_nutrients(&mut self) -> Result<(), CompileError> {
+        let order = self.derived_order()?;
+        if order.is_empty() {
+            return Ok(());
+        }
+        let names: Vec<String> = self.symbols.ingredients.keys().cloned().collect();
+        for name in names {
+            let mut nutrients = self.symbols.ingredients[&name].nutrients.clone();
+            for (nutrient, expr, inputs) in &order {
+                // With none of its inputs the value is missing, not 0
+                if inputs.iter().any(|input| nutrients.contains_key(input)) {
+                    let value = self.expr_to_nutrient_coeffs(expr, std::slice::from_ref(&nutrients))?[0];
+                    nutrients.insert(nutrient.clone(), value);
+                } else {
+                    nutrients.remove(nutrient);
+                }
+            }
+            if let Some(ingredient) = self.symbols.ingredients.get_mut(&name) {
+                ingredient.nutrients = nutrients;
             }
         }
-        Ok(None)
+        Ok(())
     }
 
-    /// Evaluate an expression to a number (for property values)
-    fn evaluate_property_expr(&self, expr: &Expr) -> Result<f64, CompileError> {
-        match expr {
-            Expr::Number(n) => Ok(*n),
-            Expr::Reference(r) => self.resolve_property_reference(r),
-            Expr::BinaryOp { left, op, right } => {
-                let left_val = self.evaluate_property_expr(left)?;
-                let right_val = self.evaluate_property_expr(right)?;
-                Ok(match op {
-                    BinaryOp::Add => left_val + right_val,
-                    BinaryOp::Sub => left_val - right_val,
-                    BinaryOp::Mul => left_val * right_val,
-                    BinaryOp::Div => {
-                        if right_val == 0.0 {
-                            return Err(CompileError::DivisionByZero);
-                        }
-                        left_val / right_val
-                    }
-                })
-            }
-            Expr::Paren(inner) => self.evaluate_property_expr(inner),
+    /// Derived nutrients, each after the derived nutrients it is computed
+    /// from, with its expression and the nutrients it uses
+    fn derived_order(&self) -> Result<Vec<(String, Expr, Vec<String>)>, CompileError> {
+        let mut names: Vec<&String> = self
+            .symbols
+            .nutrients
+            .iter()
+            .filter(|(_, n)| n.derived.is_some())
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        let mut order = Vec::new();
+        for name in names {
+            self.visit_derived(name, &mut order, &mut Vec::new())?;
         }
+        Ok(order)
     }
 
-    /// Resolve a property reference like `corn.cost` or `base_formula.batch`
-    fn resolve_property_reference(&self, r: &Reference) -> Result<f64, CompileError> {
-        if r.parts.len() != 2 {
-            return Err(CompileError::InvalidPropertyReference(
-                r.parts.iter().filter_map(|p| match p {
-                    ReferencePart::Ident(s) => Some(s.clone()),
-                    _ => None,
-                }).collect::<Vec<_>>().join(".")
-            ));
+    fn visit_derived(
+        &self,
+        name: &str,
+        order: &mut Vec<(String, Expr, Vec<String>)>,
+        stack: &mut Vec<String>,
+    ) -> Result<(), CompileError> {
+        let Some(expr) = self.symbols.nutrients.get(name).and_then(|n| n.derived.as_ref()) else {
+            return Ok(());
+        };
+        if order.iter().any(|(done, ..)| done == name) {
+            return Ok(());
+        }
+        if stack.iter().any(|s| s == name) {
+            stack.push(name.to_string());
+            return Err(CompileError::CircularReference { path: stack.join(" -> "), span: None });
         }
 
-        let item_name = match &r.parts[0] {
-            ReferencePart::Ident(name) => name,
-            _ => return Err(CompileError::InvalidPropertyReference("invalid reference".to_string())),
+        // Fails on unknown nutrients and products of nutrients
+        self.expr_to_nutrient_coeffs(expr, &[HashMap::new()])?;
+        let mut inputs = Vec::new();
+        collect_references(expr, &mut inputs);
+
+        stack.push(name.to_string());
+        for input in &inputs {
+            self.visit_derived(input, order, stack)?;
+        }
+        stack.pop();
+        order.push((name.to_string(), expr.clone(), inputs));
+        Ok(())
+    }
+
+    /// Load a formula written as `formula starter = broiler(22, 50%)`. The
+    /// template, with its parameters replaced by the arguments, is stored as
+    /// a template of its own named after the call, and the formula includes
+    /// it like a base formula. Constraints and properties in the formula's
+    /// own body override the template's. A call that can't be expanded is
+    /// stored as written and reported when the formula is compiled.
+    fn instantiate(&mut self, mut formula: Formula) {
+        let Some(instance) = formula.instance.clone() else {
+            return;
         };
-        let prop_name = match &r.parts[1] {
-            ReferencePart::Ident(name) => name,
-            _ => return Err(CompileError::InvalidPropertyReference("invalid property".to_string())),
+        let Ok(args) = self.instance_args(&formula.name, &instance) else {
+            self.symbols.formulas.insert(formula.name.clone(), formula);
+            return;
         };
+        formula.instance = None;
+
+        let template_name = reference_to_string(&instance.template);
+        let template = &self.symbols.formulas[&template_name];
+        let key = format!(
+            "{}({})",
+            template_name,
+            instance.args.iter().map(bound_text).collect::<Vec<_>>().join(", ")
+        );
+        let mut expanded = template.clone();
+        expanded.name = key.clone();
+        expanded.params.clear();
+        for bound in formula_bounds(&mut expanded.nutrients, &mut expanded.ingredients) {
+            if let Some(arg) = bound.constant.as_ref().and_then(|c| args.get(&reference_to_string(c))) {
+                bound.value = arg.value;
+                bound.is_percent |= arg.is_percent;
+                bound.constant = None;
+            }
+        }
+        for p in &mut expanded.properties {
+            substitute_property(&mut p.value, &args);
+        }
 
-        // Try to find in ingredients
-        if let Some(ing) = self.symbols.ingredients.get(item_name) {
+        // Template properties the formula doesn't set, located at the call
+        let own: HashSet<String> = formula.properties.iter().map(|p| canonical_property(&p.name).to_string()).collect();
+        let inherited = expanded.properties.iter().filter(|p| {
+            let name = canonical_property(&p.name);
+            name != "template" && !own.contains(name)
+        });
+        let mut properties: Vec<Property> = inherited
+            .map(|p| Property { span: instance.span, ..p.clone() })
+            .collect();
+        properties.append(&mut formula.properties);
+        formula.properties = properties;
+
+        // Include the expanded template ahead of the formula's own constraints
+        let include = |block: &str| {
+            Expr::Reference(Reference {
+                span: instance.span,
+                parts: vec![ReferencePart::Ident(key.clone()), ReferencePart::Ident(block.to_string())],
+            })
+        };
+        formula.nutrients.insert(0, NutrientConstraint {
+            span: instance.span,
+            expr: include("nutrients"),
+            bounds: Bounds::none(),
+            soft: None,
+            alias: None,
+            basis: Basis::AsFed,
+        });
+        formula.ingredients.insert(0, IngredientConstraint {
+            span: instance.span,
+            expr: include("ingredients"),
+            bounds: Bounds::none(),
+            or_zero: false,
+            alias: None,
+        });
+
+        if let Some(file) = self.symbols.formula_files.get(&template_name).cloned() {
+            self.symbols.formula_files.insert(key.clone(), file);
+        }
+        self.symbols.formulas.insert(key, expanded);
+        self.symbols.formulas.insert(formula.name.clone(), formula);
+    }
+
+    /// Arguments of a template call by parameter name, once the template
+    /// and the arguments given are checked
+    fn instance_args(
+        &self,
+        formula: &str,
+        instance: &TemplateInstance,
+    ) -> Result<HashMap<String, BoundValue>, CompileError> {
+        let template_name = reference_to_string(&instance.template);
+        let template = self
+            .symbols
+            .formulas
+            .get(&template_name)
+            .ok_or_else(|| CompileError::UnknownFormula(template_name.clone()))?;
+        if !self.is_template(&template_name) {
+            return Err(CompileError::NotATemplate(template_name));
+        }
+        if template.params.len() != instance.args.len() {
+            return Err(CompileError::TemplateArgumentCount {
+                template: template_name,
+                expected: template.params.len(),
+                found: instance.args.len(),
+                span: instance.span,
+            });
+        }
+
+        let mut args = HashMap::new();
+        for (param, arg) in template.params.iter().zip(&instance.args) {
+            let mut arg = arg.clone();
+            if let Some(constant) = &arg.constant {
+                arg.value = self.lookup_constant_in(constant, &format!("formula {}", formula))?;
+            }
+            args.insert(param.clone(), arg);
+        }
+        Ok(args)
+    }
+
+    /// Evaluate the program's constants, each after those it uses
+    fn load_constants(&mut self, program: &Program) -> Result<(), CompileError> {
+        let pending: HashMap<&str, &Const> = program
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Const(c) => Some((c.name.as_str(), c)),
+                _ => None,
+            })
+            .collect();
+        for name in pending.keys() {
+            self.symbols.constants.remove(*name);
+        }
+        for item in &program.items {
+            if let Item::Const(c) = item {
+                self.evaluate_constant(c, &pending, &mut Vec::new())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Value of a constant, evaluating the pending ones it uses first.
+    /// `stack` holds the constants being evaluated, to report cycles.
+    fn evaluate_constant(
+        &mut self,
+        constant: &Const,
+        pending: &HashMap<&str, &Const>,
+        stack: &mut Vec<String>,
+    ) -> Result<f64, CompileError> {
+        if let Some(value) = self.symbols.constants.get(&constant.name) {
+            return Ok(*value);
+        }
+        if stack.contains(&constant.name) {
+            stack.push(constant.name.clone());
+            return Err(CompileError::CircularReference {
+                path: stack.join(" -> "),
+                span: Some(constant.span),
+            });
+        }
+
+        stack.push(constant.name.clone());
+        let value = self.evaluate_constant_expr(&constant.value, pending, stack)?;
+        stack.pop();
+
+        self.symbols.constants.insert(constant.name.clone(), value);
+        Ok(value)
+    }
+
+    fn evaluate_constant_expr(
+        &mut self,
+        expr: &Expr,
+        pending: &HashMap<&str, &Const>,
+        stack: &mut Vec<String>,
+    ) -> Result<f64, CompileError> {
+        match expr {
+            Expr::Number(n) => Ok(*n),
+            Expr::Reference(r) => match &r.parts[..] {
+                [ReferencePart::Ident(name)] if pending.contains_key(name.as_str()) => {
+                    self.evaluate_constant(pending[name.as_str()], pending, stack)
+                }
+                [ReferencePart::Ident(_)] => self.lookup_constant(r),
+                // Properties of loaded declarations, such as `corn.cost`
+                _ => self.resolve_property_reference(r),
+            },
+            Expr::BinaryOp { left, op, right } => {
+                let left_val = self.evaluate_constant_expr(left, pending, stack)?;
+                let right_val = self.evaluate_constant_expr(right, pending, stack)?;
+                Ok(match op {
+                    BinaryOp::Add => left_val + right_val,
+                    BinaryOp::Sub => left_val - right_val,
+                    BinaryOp::Mul => left_val * right_val,
+                    BinaryOp::Div => {
+                        if right_val == 0.0 {
+                            return Err(CompileError::DivisionByZero);
+                        }
+                        left_val / right_val
+                    }
+                })
+            }
+            Expr::Paren(inner) => self.evaluate_constant_expr(inner, pending, stack),
+        }
+    }
+
+    /// Value of a loaded constant
+    fn lookup_constant(&self, r: &Reference) -> Result<f64, CompileError> {
+        let name = reference_to_string(r);
+        self.symbols.constants.get(&name).copied().ok_or_else(|| CompileError::UnknownConstant {
+            suggestion: closest_name(&name, self.symbols.constants.keys()),
+            name,
+            span: r.span,
+            within: None,
+        })
+    }
+
+    /// Value of a constant used by a formula or plan, named in the error
+    fn lookup_constant_in(&self, r: &Reference, location: &str) -> Result<f64, CompileError> {
+        self.lookup_constant(r).map_err(|e| match e {
+            CompileError::UnknownConstant { name, span, suggestion, .. } => CompileError::UnknownConstant {
+                name,
+                span,
+                within: Some(location.to_string()),
+                suggestion,
+            },
+            e => e,
+        })
+    }
+
+    /// Fill in the bounds of a constraint that are given by a constant
+    fn resolve_bound_constants(&self, bounds: &mut Bounds, formula: &str) -> Result<(), CompileError> {
+        for bound in [&mut bounds.min, &mut bounds.max].into_iter().flatten() {
+            if let Some(constant) = &bound.constant {
+                bound.value = self.lookup_constant_in(constant, &format!("formula {}", formula))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if a formula is marked as a template (not solvable)
+    pub fn is_template(&self, name: &str) -> bool {
+        self.symbols
+            .formulas
+            .get(name)
+            .map(|f| f.is_template || get_bool_property(&f.properties, "template").unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    /// Check if an ingredient is marked as a template
+    pub fn is_ingredient_template(&self, name: &str) -> bool {
+        self.symbols
+            .ingredients
+            .get(name)
+            .map(|i| i.is_template)
+            .unwrap_or(false)
+    }
+
+    /// Resolve a property value that may be an expression
+    /// Supports references like `corn.cost` and arithmetic like `corn.cost * 2`
+    fn resolve_number_property(&self, properties: &[Property], name: &str) -> Result<Option<f64>, CompileError> {
+        for p in properties {
+            if property_matches(&p.name, name) {
+                return match &p.value {
+                    PropertyValue::Number(n) => Ok(Some(*n)),
+                    PropertyValue::Ident(name) => match self.symbols.constants.get(name) {
+                        Some(value) => Ok(Some(*value)),
+                        None => Err(CompileError::UnknownConstant {
+                            name: name.clone(),
+                            span: p.span,
+                            within: None,
+                            suggestion: closest_name(name, self.symbols.constants.keys()),
+                        }),
+                    },
+                    PropertyValue::Expr(expr) => Ok(Some(self.evaluate_property_expr(expr)?)),
+                    _ => Ok(None),
+                };
+            }
+        }
+        Ok(None)
+    }
+
+    /// Evaluate an expression to a number (for property values)
+    fn evaluate_property_expr(&self, expr: &Expr) -> Result<f64, CompileError> {
+        match expr {
+            Expr::Number(n) => Ok(*n),
+            Expr::Reference(r) => self.resolve_property_reference(r),
+            Expr::BinaryOp { left, op, right } => {
+                let left_val = self.evaluate_property_expr(left)?;
+                let right_val = self.evaluate_property_expr(right)?;
+                Ok(match op {
+                    BinaryOp::Add => left_val + right_val,
+                    BinaryOp::Sub => left_val - right_val,
+                    BinaryOp::Mul => left_val * right_val,
+                    BinaryOp::Div => {
+                        if right_val == 0.0 {
+                            return Err(CompileError::DivisionByZero);
+                        }
+                        left_val / right_val
+                    }
+                })
+            }
+            Expr::Paren(inner) => self.evaluate_property_expr(inner),
+        }
+    }
+
+    /// An ingredient's nutrient value in the nutrient's own unit
+    fn in_nutrient_unit(&self, value: f64, nv: &NutrientValue) -> Result<f64, CompileError> {
+        let Some(unit) = &nv.unit else {
+            return Ok(value);
+        };
+        let nutrient = reference_to_string(&nv.nutrient);
+        let target = self.symbols.nutrients.get(&nutrient).and_then(|n| n.unit.as_deref());
+        units::conversion(unit, target)
+            .map(|factor| value * factor)
+            .map_err(|error| CompileError::InvalidUnit { subject: nutrient, span: nv.span, error })
+    }
+
+    /// Unit of a nutrient expression: that of its first nutrient with a unit.
+    /// The expression's other nutrients convert to it.
+    fn expression_unit(&self, expr: &Expr) -> Option<String> {
+        let mut nutrients = Vec::new();
+        collect_references(expr, &mut nutrients);
+        nutrients
+            .iter()
+            .find_map(|name| self.symbols.nutrients.get(name).and_then(|n| n.unit.clone()))
+    }
+
+    /// Factor converting a nutrient's values to `unit`, the unit of the
+    /// expression it is part of
+    fn term_factor(&self, nutrient: &str, span: Span, unit: Option<&str>) -> Result<f64, CompileError> {
+        let own = self.symbols.nutrients.get(nutrient).and_then(|n| n.unit.as_deref());
+        let factor = match (own, unit) {
+            (Some(own), Some(_)) => units::conversion(own, unit),
+            (None, Some(unit)) => Err(UnitError::Undeclared { to: unit.to_string() }),
+            (_, None) => Ok(1.0),
+        };
+        factor.map_err(|error| CompileError::InvalidUnit { subject: nutrient.to_string(), span, error })
+    }
+
+    /// Dry matter of an ingredient in percent, from its `dry_matter` or
+    /// `moisture` property
+    fn ingredient_dry_matter(&self, ingredient: &Ingredient) -> Result<Option<f64>, CompileError> {
+        let dry_matter = self.resolve_number_property(&ingredient.properties, "dry_matter")?;
+        let moisture = self.resolve_number_property(&ingredient.properties, "moisture")?;
+        let dry_matter = match (dry_matter, moisture) {
+            (Some(_), Some(_)) => return Err(CompileError::ConflictingDryMatter(ingredient.name.clone())),
+            (Some(dry_matter), None) => dry_matter,
+            (None, Some(moisture)) => 100.0 - moisture,
+            (None, None) => return Ok(None),
+        };
+        if !(0.0..=100.0).contains(&dry_matter) {
+            return Err(CompileError::InvalidDryMatter(ingredient.name.clone(), dry_matter));
+        }
+        Ok(Some(dry_matter))
+    }
+
+    /// Resolve a property reference like `corn.cost` or `base_formula.batch`,
+    /// or a constant
+    fn resolve_property_reference(&self, r: &Reference) -> Result<f64, CompileError> {
+        if let [ReferencePart::Ident(_)] = &r.parts[..] {
+            return self.lookup_constant(r);
+        }
+        if r.parts.len() != 2 {
+            return Err(CompileError::InvalidPropertyReference(
+                r.parts.iter().filter_map(|p| match p {
+                    ReferencePart::Ident(s) => Some(s.clone()),
+                    _ => None,
+                }).collect::<Vec<_>>().join(".")
+            ));
+        }
+
+        let item_name = match &r.parts[0] {
+            ReferencePart::Ident(name) => name,
+            _ => return Err(CompileError::InvalidPropertyReference("invalid reference".to_string())),
+        };
+        let prop_name = match &r.parts[1] {
+            ReferencePart::Ident(name) => name,
+            _ => return Err(CompileError::InvalidPropertyReference("invalid property".to_string())),
+        };
+
+        // Try to find in ingredients
+        if let Some(ing) = self.symbols.ingredients.get(item_name) {
             match prop_name.as_str() {
                 "cost" => return Ok(ing.cost),
+                "dry_matter" => {
+                    return ing.dry_matter.ok_or_else(|| {
+                        CompileError::InvalidPropertyReference(format!("{}.{}", item_name, prop_name))
+                    });
+                }
                 _ => return Err(CompileError::InvalidPropertyReference(
                     format!("{}.{}", item_name, prop_name)
                 )),
@@ -382,8 +1435,19 @@ impl Compiler {
             .collect()
     }
 
-    /// Compile a formula by name into an LP problem
+    /// Compile a formula by name into an LP problem. Formulas it uses as
+    /// ingredients are solved first.
     pub fn compile_formula(&self, name: &str) -> Result<CompiledFormula, CompileError> {
+        self.compile_formula_within(name, &mut Vec::new())
+    }
+
+    /// Compile a formula used as an ingredient by those in `stack`
+    fn compile_formula_within(&self, name: &str, stack: &mut Vec<String>) -> Result<CompiledFormula, CompileError> {
+        if stack.iter().any(|s| s == name) {
+            stack.push(name.to_string());
+            return Err(CompileError::CircularReference { path: stack.join(" -> "), span: None });
+        }
+
         let formula = self
             .symbols
             .formulas
@@ -396,19 +1460,30 @@ impl Compiler {
             return Err(CompileError::CannotSolveTemplate(name.to_string()));
         }
 
+        // A template call that couldn't be expanded when loading
+        if let Some(instance) = &formula.instance {
+            self.instance_args(name, instance)?;
+        }
+
         let batch_size = self.resolve_number_property(&formula.properties, "batch_size")?
             .or(self.resolve_number_property(&formula.properties, "batch")?)
             .ok_or_else(|| CompileError::MissingBatchSize(name.to_string()))?;
 
         // Resolve all nutrient constraints (including from base formulas)
-        let resolved_nutrients = self.resolve_nutrient_constraints(&formula.nutrients)?;
+        let mut resolved_nutrients = self.resolve_nutrient_constraints(name, &formula.nutrients)?;
 
         // Resolve all ingredient constraints (including from base formulas)
-        let resolved_ingredients = self.resolve_ingredient_constraints(&formula.ingredients)?;
+        let mut resolved_ingredients = self.resolve_ingredient_constraints(name, &formula.ingredients)?;
+        for (nc, origin) in &mut resolved_nutrients {
+            self.resolve_bound_constants(&mut nc.bounds, origin.formula())?;
+        }
+        for (ic, origin) in &mut resolved_ingredients {
+            self.resolve_bound_constants(&mut ic.bounds, origin.formula())?;
+        }
 
         // Collect all ingredients used in this formula
         let mut ingredient_names: Vec<String> = Vec::new();
-        for ic in &resolved_ingredients {
+        for (ic, _) in &resolved_ingredients {
             self.collect_ingredients_from_expr(&ic.expr, &mut ingredient_names)?;
         }
 
@@ -416,32 +1491,75 @@ impl Compiler {
         let mut seen = std::collections::HashSet::new();
         ingredient_names.retain(|x| seen.insert(x.clone()));
 
-        // Build LP problem
-        let mut lp = LpProblem::new(ingredient_names.clone());
+        // Solve the formulas used as ingredients
+        stack.push(name.to_string());
+        let mut sub_formulas = Vec::new();
+        for ingredient in &ingredient_names {
+            if !self.symbols.ingredients.contains_key(ingredient) {
+                sub_formulas.push(self.solve_sub_formula(ingredient, stack)?);
+            }
+        }
+        stack.pop();
+        let sub_formula = |name: &String| sub_formulas.iter().find(|sub| &sub.compiled.name == name);
 
         // Collect ingredient costs and nutrient data
         let ingredient_costs: Vec<f64> = ingredient_names
             .iter()
-            .map(|name| {
-                self.symbols
-                    .ingredients
-                    .get(name)
-                    .map(|i| i.cost)
-                    .unwrap_or(0.0)
+            .map(|name| match self.symbols.ingredients.get(name) {
+                Some(i) => i.cost,
+                None => sub_formula(name).map(|sub| sub.unit_cost()).unwrap_or(0.0),
             })
             .collect();
 
         let ingredient_nutrients: Vec<HashMap<String, f64>> = ingredient_names
             .iter()
-            .map(|name| {
-                self.symbols
-                    .ingredients
-                    .get(name)
-                    .map(|i| i.nutrients.clone())
-                    .unwrap_or_default()
+            .map(|name| match self.symbols.ingredients.get(name) {
+                Some(i) => i.nutrients.clone(),
+                None => sub_formula(name).map(|sub| sub.nutrients()).unwrap_or_default(),
+            })
+            .collect();
+
+        let ingredient_dry_matter: Vec<Option<f64>> = ingredient_names
+            .iter()
+            .map(|name| match self.symbols.ingredients.get(name) {
+                Some(i) => i.dry_matter,
+                None => sub_formula(name).and_then(|sub| sub.dry_matter()),
             })
             .collect();
 
+        // Dry matter fractions, needed once a bound is on a dry matter basis
+        let dry_matter = if resolved_nutrients.iter().any(|(nc, _)| nc.basis == Basis::DryMatter) {
+            ingredient_names
+                .iter()
+                .zip(&ingredient_dry_matter)
+                .map(|(ingredient, dm)| {
+                    dm.map(|dm| dm / 100.0).ok_or_else(|| CompileError::MissingDryMatterInFormula {
+                        formula: name.to_string(),
+                        ingredient: ingredient.clone(),
+                    })
+                })
+                .collect::<Result<Vec<f64>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        // Ingredients without lab data for a constrained nutrient
+        let mut missing_values =
+            self.missing_nutrient_values(&resolved_nutrients, &ingredient_names, &ingredient_nutrients);
+        match missing_data_policy(name, &formula.properties)? {
+            MissingDataPolicy::Error if !missing_values.is_empty() => {
+                return Err(CompileError::MissingNutrientData {
+                    formula: name.to_string(),
+                    missing: missing_values,
+                });
+            }
+            MissingDataPolicy::Zero => missing_values.clear(),
+            _ => {}
+        }
+
+        // Build LP problem
+        let mut lp = LpProblem::new(ingredient_names.clone());
+
         // Collect all unique nutrients used
         let mut nutrient_set: std::collections::HashSet<String> = std::collections::HashSet::new();
         for ing_nuts in &ingredient_nutrients {
@@ -465,25 +1583,54 @@ impl Compiler {
         lp.set_objective(ingredient_costs.clone(), true);
 
         // Add nutrient constraints
-        for nc in &resolved_nutrients {
-            self.add_nutrient_constraint(&mut lp, nc, &ingredient_names, batch_size)?;
+        let mut soft_constraints = Vec::new();
+        let mut dry_matter_rows = Vec::new();
+        let mut sources = Vec::new();
+        for (nc, origin) in &resolved_nutrients {
+            let first_row = lp.num_constraints();
+            self.add_nutrient_constraint(&mut lp, nc, &ingredient_nutrients, &dry_matter, batch_size)?;
+            if nc.basis == Basis::DryMatter {
+                dry_matter_rows.extend(lp.constraints[first_row..].iter().map(|c| c.name.clone()));
+            }
+            if let Some(penalty) = nc.soft {
+                let measure = |op| self.deviation_measure(nc, op);
+                soften_rows(&mut lp, first_row, penalty, batch_size, measure, &mut soft_constraints)?;
+            }
+            self.record_sources(&lp, first_row, nc.span, origin, &mut sources);
         }
 
         // Add ingredient constraints
-        for ic in &resolved_ingredients {
-            self.add_ingredient_constraint(&mut lp, ic, &ingredient_names, batch_size)?;
+        // Binary "is used" indicators, keyed by ingredient, shared with the count limit
+        let mut indicators: HashMap<String, usize> = HashMap::new();
+        let mut conditional_rows = Vec::new();
+        for (ic, origin) in &resolved_ingredients {
+            let first_row = lp.num_constraints();
+            self.add_ingredient_constraint(&mut lp, ic, &ingredient_names, batch_size, &mut indicators)?;
+            if ic.or_zero {
+                conditional_rows.extend(lp.constraints[first_row..].iter().map(|c| c.name.clone()));
+            }
+            self.record_sources(&lp, first_row, ic.span, origin, &mut sources);
+        }
+
+        // Limit the number of ingredients used at once
+        if let Some(max_count) = self.resolve_number_property(&formula.properties, "max_ingredients")? {
+            if max_count < 0.0 || max_count.fract() != 0.0 {
+                return Err(CompileError::InvalidMaxIngredients(name.to_string(), max_count));
+            }
+            let first_row = lp.num_constraints();
+            self.add_ingredient_count_constraint(&mut lp, max_count, &ingredient_names, batch_size, &mut indicators);
+            let links = &lp.constraints[first_row..lp.num_constraints() - 1];
+            conditional_rows.extend(links.iter().map(|c| c.name.clone()));
+            let span = property_span(&formula, "max_ingredients");
+            self.record_sources(&lp, first_row, span, &Origin::own(name), &mut sources);
         }
 
         // Add batch size constraint: sum of all ingredients = batch_size
         let ones = vec![1.0; ingredient_names.len()];
+        let first_row = lp.num_constraints();
         lp.add_constraint("batch_size", ones, ConstraintOp::Eq, batch_size);
-
-        // Add non-negativity (implicit in most solvers, but explicit here)
-        for (i, name) in ingredient_names.iter().enumerate() {
-            let mut coeffs = vec![0.0; ingredient_names.len()];
-            coeffs[i] = 1.0;
-            lp.add_constraint(format!("{}_nonneg", name), coeffs, ConstraintOp::Ge, 0.0);
-        }
+        let span = property_span(&formula, "batch_size");
+        self.record_sources(&lp, first_row, span, &Origin::own(name), &mut sources);
 
         Ok(CompiledFormula {
             name: formula.name.clone(),
@@ -494,17 +1641,222 @@ impl Compiler {
             ingredients: ingredient_names,
             ingredient_costs,
             ingredient_nutrients,
+            ingredient_dry_matter,
             nutrient_names,
             nutrient_units,
+            soft_constraints,
+            dry_matter_rows,
+            conditional_rows,
+            constraint_sources: sources,
+            missing_values,
+            sub_formulas,
+            lp_problem: lp,
+        })
+    }
+
+    /// Get list of all plan names
+    pub fn plan_names(&self) -> Vec<String> {
+        self.symbols.plans.keys().cloned().collect()
+    }
+
+    /// Compile a plan into one LP problem over all its formulas, so formulas
+    /// competing for a limited ingredient share it at the least total cost
+    pub fn compile_plan(&self, name: &str) -> Result<CompiledPlan, CompileError> {
+        let mut plan = self
+            .symbols
+            .plans
+            .get(name)
+            .ok_or_else(|| CompileError::UnknownPlan(name.to_string()))?
+            .clone();
+        for entry in plan.formulas.iter_mut().chain(&mut plan.available) {
+            if let Some(constant) = &entry.quantity.constant {
+                entry.quantity.value = self.lookup_constant_in(constant, &format!("plan {}", name))?;
+            }
+        }
+        let invalid_quantity = |entry: &PlanEntry, available: bool| CompileError::InvalidPlanQuantity {
+            plan: name.to_string(),
+            name: reference_to_string(&entry.name),
+            quantity: entry.quantity.value,
+            available,
+        };
+
+        // Each formula gets a block of variables, in the order listed
+        let mut lp = LpProblem::new(Vec::new());
+        let mut formulas: Vec<PlannedFormula> = Vec::new();
+        for entry in &plan.formulas {
+            let formula = reference_to_string(&entry.name);
+            if formulas.iter().any(|planned| planned.compiled.name == formula) {
+                return Err(CompileError::DuplicatePlanEntry { plan: name.to_string(), name: formula });
+            }
+            if entry.quantity.value <= 0.0 {
+                return Err(invalid_quantity(entry, false));
+            }
+            let planned = PlannedFormula {
+                compiled: self.compile_formula(&formula)?,
+                quantity: entry.quantity.value,
+                first_variable: lp.num_variables(),
+                first_constraint: 0,
+            };
+
+            // x per batch becomes x * scale for the quantity
+            let block = &planned.compiled.lp_problem;
+            let scale = planned.scale();
+            for (j, variable) in block.variables.iter().enumerate() {
+                let kind = block.variable_kinds[j];
+                let index = lp.add_variable(format!("{}.{}", formula, variable), kind);
+                if kind == VariableKind::Continuous {
+                    lp.set_bounds(index, block.lower_bounds[j] * scale, block.upper_bounds[j] * scale);
+                    lp.objective.coefficients[index] = block.objective.coefficients[j];
+                } else {
+                    lp.set_bounds(index, block.lower_bounds[j], block.upper_bounds[j]);
+                    lp.objective.coefficients[index] = block.objective.coefficients[j] * scale;
+                }
+            }
+            formulas.push(planned);
+        }
+
+        // Each block's rows, multiplied through by its scale
+        for planned in &mut formulas {
+            planned.first_constraint = lp.num_constraints();
+            let block = &planned.compiled.lp_problem;
+            let scale = planned.scale();
+            for c in &block.constraints {
+                let mut coefficients = vec![0.0; lp.num_variables()];
+                for (j, a) in c.coefficients.iter().enumerate() {
+                    let factor = if block.variable_kinds[j] == VariableKind::Continuous { 1.0 } else { scale };
+                    coefficients[planned.first_variable + j] = a * factor;
+                }
+                let row = format!("{}.{}", planned.compiled.name, c.name);
+                lp.add_constraint(row, coefficients, c.op, c.rhs * scale);
+            }
+        }
+
+        // Shared stock: the blocks together use at most what is available
+        let mut supplies: Vec<Supply> = Vec::new();
+        for entry in &plan.available {
+            let ingredient = reference_to_string(&entry.name);
+            if !self.symbols.ingredients.contains_key(&ingredient) && !self.symbols.formulas.contains_key(&ingredient) {
+                return Err(self.unknown_ingredient(&ingredient, entry.name.span));
+            }
+            if supplies.iter().any(|supply| supply.ingredient == ingredient) {
+                return Err(CompileError::DuplicatePlanEntry { plan: name.to_string(), name: ingredient });
+            }
+            if entry.quantity.value < 0.0 {
+                return Err(invalid_quantity(entry, true));
+            }
+
+            // Stock drawn through a sub-formula counts at its share of the
+            // sub-formula's solution
+            let mut coefficients = vec![0.0; lp.num_variables()];
+            for planned in &formulas {
+                for (j, content) in planned.compiled.ingredient_content(&ingredient).into_iter().enumerate() {
+                    coefficients[planned.first_variable + j] = content;
+                }
+            }
+            supplies.push(Supply {
+                ingredient: ingredient.clone(),
+                available: entry.quantity.value,
+                constraint: lp.num_constraints(),
+            });
+            lp.add_constraint(format!("{}_available", ingredient), coefficients, ConstraintOp::Le, entry.quantity.value);
+        }
+
+        Ok(CompiledPlan {
+            name: plan.name.clone(),
+            display_name: get_string_property(&plan.properties, "name"),
+            description: get_string_property(&plan.properties, "description"),
+            formulas,
+            supplies,
             lp_problem: lp,
         })
     }
 
-    /// Resolve nutrient constraints, expanding base formula references
+    /// Compile and solve a formula used as an ingredient
+    fn solve_sub_formula(&self, name: &str, stack: &mut Vec<String>) -> Result<SubFormula, CompileError> {
+        let compiled = self.compile_formula_within(name, stack)?;
+        let solution = Solver::new().solve(&compiled.lp_problem);
+        if solution.status != SolutionStatus::Optimal {
+            return Err(CompileError::SubFormulaNotOptimal(name.to_string(), solution.status));
+        }
+        Ok(SubFormula { compiled, solution })
+    }
+
+    /// Pairs of ingredient and constrained nutrient with no value, in
+    /// constraint order
+    fn missing_nutrient_values(
+        &self,
+        constraints: &[(NutrientConstraint, Origin)],
+        ingredients: &[String],
+        ingredient_nutrients: &[HashMap<String, f64>],
+    ) -> Vec<MissingNutrientValue> {
+        let mut missing = Vec::new();
+        let mut checked = HashSet::new();
+        for (nc, origin) in constraints {
+            let mut nutrients = Vec::new();
+            collect_references(&nc.expr, &mut nutrients);
+            for nutrient in nutrients {
+                for (ingredient, values) in ingredients.iter().zip(ingredient_nutrients) {
+                    // Formulas used as ingredients have every nutrient of their solution
+                    let derived_here = self.symbols.ingredients.contains_key(ingredient);
+                    for lacking in self.lacking_values(&nutrient, values, derived_here) {
+                        if checked.insert((ingredient.clone(), lacking.clone())) {
+                            missing.push(MissingNutrientValue {
+                                ingredient: ingredient.clone(),
+                                nutrient: lacking,
+                                formula: origin.formula().to_string(),
+                                span: nc.span,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        missing
+    }
+
+    /// Nutrients without a value in `values` that `nutrient` needs: itself, or
+    /// for a nutrient derived from the values, each input it was computed
+    /// without, as those count as 0
+    fn lacking_values(&self, nutrient: &str, values: &HashMap<String, f64>, derived_here: bool) -> Vec<String> {
+        match self.symbols.nutrients.get(nutrient).and_then(|n| n.derived.as_ref()) {
+            Some(expr) if derived_here => {
+                let mut inputs = Vec::new();
+                collect_references(expr, &mut inputs);
+                inputs.iter().flat_map(|input| self.lacking_values(input, values, true)).collect()
+            }
+            _ if values.contains_key(nutrient) => Vec::new(),
+            _ => vec![nutrient.to_string()],
+        }
+    }
+
+    /// Record where the rows from `first_row` on came from
+    fn record_sources(
+        &self,
+        lp: &LpProblem,
+        first_row: usize,
+        span: Span,
+        origin: &Origin,
+        sources: &mut Vec<ConstraintSource>,
+    ) {
+        for row in &lp.constraints[first_row..] {
+            sources.push(ConstraintSource {
+                constraint: row.name.clone(),
+                formula: origin.formula().to_string(),
+                file: self.symbols.formula_files.get(origin.formula()).cloned(),
+                span,
+                chain: origin.chain.clone(),
+                overrides: origin.overrides.clone(),
+            });
+        }
+    }
+
+    /// Resolve the nutrient constraints of `formula`, expanding base formula
+    /// references, with the inheritance of each
     fn resolve_nutrient_constraints(
         &self,
+        formula: &str,
         constraints: &[NutrientConstraint],
-    ) -> Result<Vec<NutrientConstraint>, CompileError> {
+    ) -> Result<Vec<(NutrientConstraint, Origin)>, CompileError> {
         let mut resolved = Vec::new();
         let mut overrides: HashMap<String, NutrientConstraint> = HashMap::new();
 
@@ -512,39 +1864,51 @@ impl Compiler {
             if let Some(details) = self.get_base_reference(&nc.expr) {
                 // This is a reference like `poultry_base.nutrients` or `poultry_base.nutrients.protein.min`
                 let base_constraints = self.resolve_base_nutrient_reference(&details)?;
-                for bc in base_constraints {
+                for (bc, origin) in base_constraints {
                     let key = self.constraint_key(&bc.expr);
                     if !overrides.contains_key(&key) {
-                        resolved.push(bc);
+                        resolved.push((bc, origin.included_by(formula)));
                     }
                 }
             } else {
                 // Regular constraint - may override base
                 let key = self.constraint_key(&nc.expr);
                 overrides.insert(key.clone(), nc.clone());
-                resolved.push(nc.clone());
+                resolved.push((nc.clone(), Origin::own(formula)));
             }
         }
 
         // Apply overrides (replace matching constraints)
-        for constraint in &mut resolved {
+        for (constraint, origin) in &mut resolved {
             let key = self.constraint_key(&constraint.expr);
             if let Some(override_c) = overrides.get(&key) {
                 // Merge bounds: override wins
                 if override_c.bounds.min.is_some() || override_c.bounds.max.is_some() {
+                    if origin.chain.len() > 1 {
+                        *origin = origin.overridden_by(formula);
+                    }
                     *constraint = override_c.clone();
                 }
             }
         }
 
+        // An override that replaced an inherited constraint takes its place
+        let mut placed = HashSet::new();
+        resolved.retain(|(c, _)| {
+            let key = self.constraint_key(&c.expr);
+            !overrides.contains_key(&key) || placed.insert(key)
+        });
+
         Ok(resolved)
     }
 
-    /// Resolve ingredient constraints, expanding base formula references
+    /// Resolve the ingredient constraints of `formula`, expanding base
+    /// formula references, with the inheritance of each
     fn resolve_ingredient_constraints(
         &self,
+        formula: &str,
         constraints: &[IngredientConstraint],
-    ) -> Result<Vec<IngredientConstraint>, CompileError> {
+    ) -> Result<Vec<(IngredientConstraint, Origin)>, CompileError> {
         let mut resolved = Vec::new();
         let mut overrides: HashMap<String, IngredientConstraint> = HashMap::new();
 
@@ -552,30 +1916,40 @@ impl Compiler {
             if let Some(details) = self.get_base_reference(&ic.expr) {
                 // This is a reference like `starter.ingredients` or `starter.ingredients.corn.max`
                 let base_constraints = self.resolve_base_ingredient_reference(&details)?;
-                for bc in base_constraints {
+                for (bc, origin) in base_constraints {
                     let key = self.constraint_key(&bc.expr);
                     if !overrides.contains_key(&key) {
-                        resolved.push(bc);
+                        resolved.push((bc, origin.included_by(formula)));
                     }
                 }
             } else {
                 // Regular constraint - may override base
                 let key = self.constraint_key(&ic.expr);
                 overrides.insert(key.clone(), ic.clone());
-                resolved.push(ic.clone());
+                resolved.push((ic.clone(), Origin::own(formula)));
             }
         }
 
         // Apply overrides
-        for constraint in &mut resolved {
+        for (constraint, origin) in &mut resolved {
             let key = self.constraint_key(&constraint.expr);
-            if let Some(override_c) = overrides.get(&key) {
-                if override_c.bounds.min.is_some() || override_c.bounds.max.is_some() {
-                    *constraint = override_c.clone();
+            if let Some(override_c) = overrides.get(&key)
+                && (override_c.bounds.min.is_some() || override_c.bounds.max.is_some())
+            {
+                if origin.chain.len() > 1 {
+                    *origin = origin.overridden_by(formula);
                 }
+                *constraint = override_c.clone();
             }
         }
 
+        // An override that replaced an inherited constraint takes its place
+        let mut placed = HashSet::new();
+        resolved.retain(|(c, _)| {
+            let key = self.constraint_key(&c.expr);
+            !overrides.contains_key(&key) || placed.insert(key)
+        });
+
         Ok(resolved)
     }
 
@@ -614,15 +1988,15 @@ impl Compiler {
             .symbols
             .ingredients
             .get(ingredient_name)
-            .ok_or_else(|| CompileError::UnknownIngredient(ingredient_name.clone()))?;
+            .ok_or_else(|| self.unknown_ingredient(ingredient_name, reference.span))?;
 
         // Check if we're getting a specific nutrient or all nutrients
         if reference.parts.len() >= 3 {
             // Specific nutrient: `corn.nutrients.protein`
-            if let ReferencePart::Ident(nutrient_name) = &reference.parts[2] {
-                if let Some(value) = source.nutrients.get(nutrient_name) {
-                    target.insert(nutrient_name.clone(), *value);
-                }
+            if let ReferencePart::Ident(nutrient_name) = &reference.parts[2]
+                && let Some(value) = source.nutrients.get(nutrient_name)
+            {
+                target.insert(nutrient_name.clone(), *value);
             }
         } else {
             // All nutrients: `corn.nutrients`
@@ -637,49 +2011,43 @@ impl Compiler {
     /// Check if an expression is a base formula reference
     /// Supports: `base.nutrients`, `base.nutrients.protein`, `base.nutrients.protein.min`
     fn get_base_reference(&self, expr: &Expr) -> Option<ReferenceDetails> {
-        if let Expr::Reference(r) = expr {
-            if r.parts.len() >= 2 {
-                if let (ReferencePart::Ident(formula), ReferencePart::Ident(block)) =
-                    (&r.parts[0], &r.parts[1])
-                {
-                    if block == "nutrients" || block == "ingredients" || block == "nuts" || block == "ings" {
-                        let block_normalized = if block == "nuts" { "nutrients" } else if block == "ings" { "ingredients" } else { block }.to_string();
-
-                        let mut item_name = None;
-                        let mut min_only = false;
-                        let mut max_only = false;
-
-                        // Check for item name (part 2)
-                        if r.parts.len() >= 3 {
-                            match &r.parts[2] {
-                                ReferencePart::Ident(name) => {
-                                    item_name = Some(name.clone());
-                                }
-                                ReferencePart::Min => min_only = true,
-                                ReferencePart::Max => max_only = true,
-                                _ => {}
-                            }
-                        }
-
-                        // Check for min/max after item name (part 3)
-                        if r.parts.len() >= 4 && item_name.is_some() {
-                            match &r.parts[3] {
-                                ReferencePart::Min => min_only = true,
-                                ReferencePart::Max => max_only = true,
-                                _ => {}
-                            }
-                        }
-
-                        return Some(ReferenceDetails {
-                            formula_name: formula.clone(),
-                            block_type: block_normalized,
-                            item_name,
-                            min_only,
-                            max_only,
-                        });
+        if let Expr::Reference(r) = expr
+            && r.parts.len() >= 2
+            && let (ReferencePart::Ident(formula), ReferencePart::Ident(block)) =
+                (&r.parts[0], &r.parts[1])
+            && (block == "nutrients" || block == "ingredients" || block == "nuts" || block == "ings")
+        {
+            let mut item_name = None;
+            let mut min_only = false;
+            let mut max_only = false;
+
+            // Check for item name (part 2)
+            if r.parts.len() >= 3 {
+                match &r.parts[2] {
+                    ReferencePart::Ident(name) => {
+                        item_name = Some(name.clone());
                     }
+                    ReferencePart::Min => min_only = true,
+                    ReferencePart::Max => max_only = true,
+                    _ => {}
+                }
+            }
+
+            // Check for min/max after item name (part 3)
+            if r.parts.len() >= 4 && item_name.is_some() {
+                match &r.parts[3] {
+                    ReferencePart::Min => min_only = true,
+                    ReferencePart::Max => max_only = true,
+                    _ => {}
                 }
             }
+
+            return Some(ReferenceDetails {
+                formula_name: formula.clone(),
+                item_name,
+                min_only,
+                max_only,
+            });
         }
         None
     }
@@ -688,46 +2056,46 @@ impl Compiler {
     fn resolve_base_nutrient_reference(
         &self,
         details: &ReferenceDetails,
-    ) -> Result<Vec<NutrientConstraint>, CompileError> {
+    ) -> Result<Vec<(NutrientConstraint, Origin)>, CompileError> {
         let formula = self
             .symbols
             .formulas
             .get(&details.formula_name)
             .ok_or_else(|| CompileError::UnknownFormula(details.formula_name.clone()))?;
+        if !formula.params.is_empty() {
+            return Err(CompileError::ParameterizedTemplate(details.formula_name.clone()));
+        }
 
         // Recursively resolve (to support chained inheritance)
-        let mut constraints = self.resolve_nutrient_constraints(&formula.nutrients)?;
+        let mut constraints = self.resolve_nutrient_constraints(&details.formula_name, &formula.nutrients)?;
 
         // Filter by item name if specified
         if let Some(ref item_name) = details.item_name {
-            constraints = constraints
-                .into_iter()
-                .filter(|c| {
-                    // Check if constraint expression matches the item name
-                    if let Expr::Reference(r) = &c.expr {
-                        if let Some(ReferencePart::Ident(name)) = r.parts.first() {
-                            return name == item_name;
-                        }
-                    }
-                    false
-                })
-                .collect();
+            constraints.retain(|(c, _)| {
+                // Check if constraint expression matches the item name
+                if let Expr::Reference(r) = &c.expr
+                    && let Some(ReferencePart::Ident(name)) = r.parts.first()
+                {
+                    return name == item_name;
+                }
+                false
+            });
         }
 
         // Filter bounds if min_only or max_only specified
         if details.min_only || details.max_only {
             constraints = constraints
                 .into_iter()
-                .map(|mut c| {
+                .map(|(mut c, origin)| {
                     if details.min_only {
                         c.bounds.max = None;
                     }
                     if details.max_only {
                         c.bounds.min = None;
                     }
-                    c
+                    (c, origin)
                 })
-                .filter(|c| c.bounds.min.is_some() || c.bounds.max.is_some())
+                .filter(|(c, _)| c.bounds.min.is_some() || c.bounds.max.is_some())
                 .collect();
         }
 
@@ -738,45 +2106,45 @@ impl Compiler {
     fn resolve_base_ingredient_reference(
         &self,
         details: &ReferenceDetails,
-    ) -> Result<Vec<IngredientConstraint>, CompileError> {
+    ) -> Result<Vec<(IngredientConstraint, Origin)>, CompileError> {
         let formula = self
             .symbols
             .formulas
             .get(&details.formula_name)
             .ok_or_else(|| CompileError::UnknownFormula(details.formula_name.clone()))?;
+        if !formula.params.is_empty() {
+            return Err(CompileError::ParameterizedTemplate(details.formula_name.clone()));
+        }
 
-        let mut constraints = self.resolve_ingredient_constraints(&formula.ingredients)?;
+        let mut constraints = self.resolve_ingredient_constraints(&details.formula_name, &formula.ingredients)?;
 
         // Filter by item name if specified
         if let Some(ref item_name) = details.item_name {
-            constraints = constraints
-                .into_iter()
-                .filter(|c| {
-                    // Check if constraint expression matches the item name
-                    if let Expr::Reference(r) = &c.expr {
-                        if let Some(ReferencePart::Ident(name)) = r.parts.first() {
-                            return name == item_name;
-                        }
-                    }
-                    false
-                })
-                .collect();
+            constraints.retain(|(c, _)| {
+                // Check if constraint expression matches the item name
+                if let Expr::Reference(r) = &c.expr
+                    && let Some(ReferencePart::Ident(name)) = r.parts.first()
+                {
+                    return name == item_name;
+                }
+                false
+            });
         }
 
         // Filter bounds if min_only or max_only specified
         if details.min_only || details.max_only {
             constraints = constraints
                 .into_iter()
-                .map(|mut c| {
+                .map(|(mut c, origin)| {
                     if details.min_only {
                         c.bounds.max = None;
                     }
                     if details.max_only {
                         c.bounds.min = None;
                     }
-                    c
+                    (c, origin)
                 })
-                .filter(|c| c.bounds.min.is_some() || c.bounds.max.is_some())
+                .filter(|(c, _)| c.bounds.min.is_some() || c.bounds.max.is_some())
                 .collect();
         }
 
@@ -800,6 +2168,14 @@ impl Compiler {
         }
     }
 
+    fn unknown_ingredient(&self, name: &str, span: Span) -> CompileError {
+        CompileError::UnknownIngredient {
+            name: name.to_string(),
+            span,
+            suggestion: closest_name(name, self.symbols.ingredients.keys()),
+        }
+    }
+
     fn collect_ingredients_from_expr(
         &self,
         expr: &Expr,
@@ -809,11 +2185,11 @@ impl Compiler {
             Expr::Number(_) => {}
             Expr::Reference(r) => {
                 if let Some(ReferencePart::Ident(name)) = r.parts.first() {
-                    // Check if it's an ingredient
-                    if self.symbols.ingredients.contains_key(name) {
-                        ingredients.push(name.clone());
+                    // Formulas can be used as ingredients, such as premixes
+                    if !self.symbols.ingredients.contains_key(name) && !self.symbols.formulas.contains_key(name) {
+                        return Err(self.unknown_ingredient(name, r.span));
                     }
-                    // Could also be a base formula reference - TODO
+                    ingredients.push(name.clone());
                 }
             }
             Expr::BinaryOp { left, right, .. } => {
@@ -831,32 +2207,71 @@ impl Compiler {
         &self,
         lp: &mut LpProblem,
         constraint: &NutrientConstraint,
-        ingredients: &[String],
+        ingredients: &[HashMap<String, f64>],
+        dry_matter: &[f64],
         batch_size: f64,
     ) -> Result<(), CompileError> {
+        // On a dry matter basis, levels are shares of the batch's dry matter
+        // rather than of the batch itself
+        let on_dry_matter = constraint.basis == Basis::DryMatter;
+        let coeffs = |expr: &Expr| -> Result<Vec<f64>, CompileError> {
+            let coeffs = self.expr_to_nutrient_coeffs(expr, ingredients)?;
+            if on_dry_matter {
+                self.dry_matter_coeffs(expr, coeffs, dry_matter)
+            } else {
+                Ok(coeffs)
+            }
+        };
+
+        // Bounds given in another unit convert to the expression's
+        let in_unit = |bound: &BoundValue| -> Result<f64, CompileError> {
+            let Some(unit) = &bound.unit else {
+                return Ok(bound.value);
+            };
+            let target = self.expression_unit(&constraint.expr);
+            units::conversion(unit, target.as_deref())
+                .map(|factor| bound.value * factor)
+                .map_err(|error| CompileError::InvalidUnit {
+                    subject: self.constraint_key(&constraint.expr),
+                    span: constraint.span,
+                    error,
+                })
+        };
+
         // Check if this is a ratio constraint (e.g., calcium / phosphorus)
-        if let Expr::BinaryOp { left, op: BinaryOp::Div, right } = &constraint.expr {
-            return self.add_ratio_constraint(lp, left, right, &constraint.bounds, &constraint.alias, ingredients, batch_size);
+        if let Expr::BinaryOp { left, op: BinaryOp::Div, right } = &constraint.expr
+            && constant_value(right).is_none()
+        {
+            if let Some(unit) = constraint.bounds.min.iter().chain(&constraint.bounds.max).find_map(|b| b.unit.clone()) {
+                return Err(CompileError::InvalidUnit {
+                    subject: self.constraint_key(&constraint.expr),
+                    span: constraint.span,
+                    error: UnitError::Ratio { unit },
+                });
+            }
+            return self.add_ratio_constraint(lp, left, right, &constraint.bounds, &constraint.alias, coeffs);
         }
 
-        // For simple nutrient constraints
-        let nutrient_name = self.expr_to_nutrient_name(&constraint.expr)?;
+        // Build coefficient vector: each ingredient's value of the expression
+        let coeffs = coeffs(&constraint.expr)?;
 
-        // Use alias if present, otherwise use nutrient name
-        let base_name = constraint.alias.as_ref().unwrap_or(&nutrient_name);
+        // A bound on the dry matter moves to the left as `sum(amount_i * (value_i - bound * dm_i)) >= 0`,
+        // as the batch's dry matter depends on the amounts
+        let row = |bound: f64| -> (Vec<f64>, f64) {
+            if on_dry_matter {
+                let row = coeffs.iter().zip(dry_matter).map(|(c, dm)| c - bound * dm).collect();
+                (row, 0.0)
+            } else {
+                (coeffs.clone(), bound * batch_size)
+            }
+        };
 
-        // Build coefficient vector: each ingredient's contribution to this nutrient
-        let coeffs: Vec<f64> = ingredients
-            .iter()
-            .map(|ing_name| {
-                self.symbols
-                    .ingredients
-                    .get(ing_name)
-                    .and_then(|ing| ing.nutrients.get(&nutrient_name))
-                    .copied()
-                    .unwrap_or(0.0)
-            })
-            .collect();
+        // Use alias if present, otherwise the nutrient name or the expression
+        let base_name = match (&constraint.alias, &constraint.expr) {
+            (Some(alias), _) => alias.clone(),
+            (None, Expr::Reference(r)) => reference_to_string(r),
+            (None, expr) => self.constraint_key(expr),
+        };
 
         // Add min constraint if present
         if let Some(ref min_bound) = constraint.bounds.min {
@@ -867,10 +2282,10 @@ impl Compiler {
             // Constraint protein min 20 means: final formula should have >= 20% protein
             // Formula: sum(amount_i * nutrient_pct_i) / batch_size >= required_pct
             // Rearranged: sum(amount_i * nutrient_pct_i) >= required_pct * batch_size
-            let rhs = min_bound.value * batch_size;
+            let (row, rhs) = row(in_unit(min_bound)?);
             lp.add_constraint(
                 format!("{}_min", base_name),
-                coeffs.clone(),
+                row,
                 ConstraintOp::Ge,
                 rhs,
             );
@@ -881,10 +2296,10 @@ impl Compiler {
             if max_bound.is_percent {
                 return Err(CompileError::PercentInNutrientConstraint);
             }
-            let rhs = max_bound.value * batch_size;
+            let (row, rhs) = row(in_unit(max_bound)?);
             lp.add_constraint(
                 format!("{}_max", base_name),
-                coeffs,
+                row,
                 ConstraintOp::Le,
                 rhs,
             );
@@ -897,6 +2312,9 @@ impl Compiler {
     /// This is linearized as:
     /// - For min R: calcium >= R * phosphorus => calcium - R*phosphorus >= 0
     /// - For max R: calcium <= R * phosphorus => calcium - R*phosphorus <= 0
+    ///
+    /// Either side may be a linear nutrient expression, as in
+    /// `(methionine + cystine) / lysine min 0.75`.
     fn add_ratio_constraint(
         &self,
         lp: &mut LpProblem,
@@ -904,42 +2322,27 @@ impl Compiler {
         denominator: &Expr,
         bounds: &Bounds,
         alias: &Option<String>,
-        ingredients: &[String],
-        _batch_size: f64,
+        coeffs: impl Fn(&Expr) -> Result<Vec<f64>, CompileError>,
     ) -> Result<(), CompileError> {
-        let num_name = self.expr_to_nutrient_name(numerator)?;
-        let den_name = self.expr_to_nutrient_name(denominator)?;
-
-        // Use alias if present, otherwise derive from nutrient names
+        // Use alias if present, otherwise derive from both sides
+        let side_name = |expr: &Expr| match expr {
+            Expr::Reference(r) => reference_to_string(r),
+            _ => format!("({})", self.constraint_key(expr)),
+        };
         let base_name = alias
             .as_ref()
             .map(|s| s.to_string())
-            .unwrap_or_else(|| format!("{}/{}", num_name, den_name));
-
-        // Get nutrient coefficients for numerator and denominator
-        let num_coeffs: Vec<f64> = ingredients
-            .iter()
-            .map(|ing_name| {
-                self.symbols
-                    .ingredients
-                    .get(ing_name)
-                    .and_then(|ing| ing.nutrients.get(&num_name))
-                    .copied()
-                    .unwrap_or(0.0)
-            })
-            .collect();
-
-        let den_coeffs: Vec<f64> = ingredients
-            .iter()
-            .map(|ing_name| {
-                self.symbols
-                    .ingredients
-                    .get(ing_name)
-                    .and_then(|ing| ing.nutrients.get(&den_name))
-                    .copied()
-                    .unwrap_or(0.0)
-            })
-            .collect();
+            .unwrap_or_else(|| format!("{}/{}", side_name(numerator), side_name(denominator)));
+
+        // Get nutrient coefficients for numerator and denominator. Sides of
+        // the same kind compare in one unit, so lysine in % over methionine in
+        // g/kg is the ratio of their amounts.
+        let num_coeffs = coeffs(numerator)?;
+        let den_scale = match (self.expression_unit(numerator), self.expression_unit(denominator)) {
+            (Some(num), Some(den)) => units::conversion(&den, Some(&num)).unwrap_or(1.0),
+            _ => 1.0,
+        };
+        let den_coeffs: Vec<f64> = coeffs(denominator)?.iter().map(|d| d * den_scale).collect();
 
         // For min constraint: num/den >= R => num - R*den >= 0
         if let Some(ref min_bound) = bounds.min {
@@ -976,12 +2379,44 @@ impl Compiler {
         Ok(())
     }
 
+    /// What the deviation of a soft bound is measured in. A ratio's rows are
+    /// `num - R × den`, in the numerator's units rather than the ratio's.
+    fn deviation_measure(&self, constraint: &NutrientConstraint, op: ConstraintOp) -> Option<String> {
+        if let Expr::BinaryOp { left, op: BinaryOp::Div, right } = &constraint.expr
+            && constant_value(right).is_none()
+        {
+            let bound = match op {
+                ConstraintOp::Le => constraint.bounds.max.as_ref(),
+                _ => constraint.bounds.min.as_ref(),
+            };
+            return bound.map(|b| format!("{} - {} × {}", expr_text(left), b.value, expr_text(right)));
+        }
+        let unit = self.expression_unit(&constraint.expr);
+        if constraint.basis == Basis::DryMatter {
+            return Some(unit.map_or("dry matter".to_string(), |unit| format!("{} of dry matter", unit)));
+        }
+        unit
+    }
+
+    /// Coefficients of a nutrient expression on a dry matter basis. Nutrient
+    /// values are the same either way once stored as fed, but a constant
+    /// term applies to each unit of dry matter rather than of the batch.
+    fn dry_matter_coeffs(&self, expr: &Expr, coeffs: Vec<f64>, dry_matter: &[f64]) -> Result<Vec<f64>, CompileError> {
+        let constant = self.expr_to_nutrient_coeffs(expr, &[HashMap::new()])?[0];
+        Ok(coeffs
+            .iter()
+            .zip(dry_matter)
+            .map(|(c, dm)| c - constant + constant * dm)
+            .collect())
+    }
+
     fn add_ingredient_constraint(
         &self,
         lp: &mut LpProblem,
         constraint: &IngredientConstraint,
         ingredients: &[String],
         batch_size: f64,
+        indicators: &mut HashMap<String, usize>,
     ) -> Result<(), CompileError> {
         // Build coefficient vector from expression
         let coeffs = self.expr_to_ingredient_coeffs(&constraint.expr, ingredients)?;
@@ -993,26 +2428,64 @@ impl Compiler {
             .map(|s| s.to_string())
             .unwrap_or_else(|| self.expr_to_name(&constraint.expr));
 
-        // Add min constraint if present
-        if let Some(ref min_bound) = constraint.bounds.min {
-            let rhs = if min_bound.is_percent {
-                min_bound.value * batch_size / 100.0
+        let to_amount = |bound: &BoundValue| {
+            if bound.is_percent {
+                bound.value * batch_size / 100.0
             } else {
-                min_bound.value
-            };
+                bound.value
+            }
+        };
+        let min_rhs = constraint.bounds.min.as_ref().map(to_amount);
+        let max_rhs = constraint.bounds.max.as_ref().map(to_amount);
+
+        if constraint.or_zero {
+            // With a negative term the expression can be 0 while ingredients
+            // are used, so `y = 0` wouldn't mean "left out"
+            if coeffs.iter().any(|c| *c < 0.0) {
+                return Err(CompileError::InvalidOrZero(base_name));
+            }
+
+            // Semi-continuous: either zero or within bounds, switched by a binary y
+            //   expr >= min * y,  expr <= max * y
+            // Without a max, the largest value the batch allows stands in for
+            // it: amounts sum to batch_size, so the expression is at most
+            // batch_size times its largest coefficient.
+            let used = lp.add_variable(format!("{}_used", base_name), VariableKind::Binary);
+            let upper = max_rhs.unwrap_or_else(|| {
+                batch_size * coeffs.iter().cloned().fold(0.0, f64::max)
+            });
+
+            if let Some(min) = min_rhs {
+                let mut row = coeffs.clone();
+                row.resize(lp.num_variables(), 0.0);
+                row[used] = -min;
+                lp.add_constraint(format!("{}_min", base_name), row, ConstraintOp::Ge, 0.0);
+            }
+
+            let mut row = coeffs.clone();
+            row.resize(lp.num_variables(), 0.0);
+            row[used] = -upper;
+            let row_name = if max_rhs.is_some() { "max" } else { "link" };
+            lp.add_constraint(format!("{}_{}", base_name, row_name), row, ConstraintOp::Le, 0.0);
+
+            // A lone ingredient's indicator doubles as its "is used" flag
+            let nonzero: Vec<usize> = (0..coeffs.len()).filter(|&j| coeffs[j] != 0.0).collect();
+            if let [j] = nonzero[..]
+                && coeffs[j] == 1.0
+            {
+                indicators.entry(ingredients[j].clone()).or_insert(used);
+            }
+            return Ok(());
+        }
 
+        // Add min constraint if present
+        if let Some(rhs) = min_rhs {
             let constraint_name = format!("{}_min", base_name);
             lp.add_constraint(constraint_name, coeffs.clone(), ConstraintOp::Ge, rhs);
         }
 
         // Add max constraint if present
-        if let Some(ref max_bound) = constraint.bounds.max {
-            let rhs = if max_bound.is_percent {
-                max_bound.value * batch_size / 100.0
-            } else {
-                max_bound.value
-            };
-
+        if let Some(rhs) = max_rhs {
             let constraint_name = format!("{}_max", base_name);
             lp.add_constraint(constraint_name, coeffs, ConstraintOp::Le, rhs);
         }
@@ -1020,27 +2493,110 @@ impl Compiler {
         Ok(())
     }
 
-    fn expr_to_nutrient_name(&self, expr: &Expr) -> Result<String, CompileError> {
+    /// Allow at most `max_count` ingredients to be used, reusing indicators
+    /// from `or 0` constraints and linking new ones as `amount <= batch_size * used`
+    fn add_ingredient_count_constraint(
+        &self,
+        lp: &mut LpProblem,
+        max_count: f64,
+        ingredients: &[String],
+        batch_size: f64,
+        indicators: &mut HashMap<String, usize>,
+    ) {
+        for (i, name) in ingredients.iter().enumerate() {
+            if indicators.contains_key(name) {
+                continue;
+            }
+            let used = lp.add_variable(format!("{}_used", name), VariableKind::Binary);
+            let mut row = vec![0.0; lp.num_variables()];
+            row[i] = 1.0;
+            row[used] = -batch_size;
+            lp.add_constraint(format!("{}_link", name), row, ConstraintOp::Le, 0.0);
+            indicators.insert(name.clone(), used);
+        }
+
+        let mut row = vec![0.0; lp.num_variables()];
+        for name in ingredients {
+            row[indicators[name]] = 1.0;
+        }
+        lp.add_constraint("max_ingredients", row, ConstraintOp::Le, max_count);
+    }
+
+    /// Each ingredient's value of a linear nutrient expression, such as
+    /// `sodium * 434.8 + potassium * 255.7 - chloride * 282`, in the
+    /// expression's unit. A constant term adds to every ingredient since the
+    /// amounts sum to the batch.
+    fn expr_to_nutrient_coeffs(
+        &self,
+        expr: &Expr,
+        ingredients: &[HashMap<String, f64>],
+    ) -> Result<Vec<f64>, CompileError> {
+        let unit = self.expression_unit(expr);
+        self.nutrient_coeffs_in(expr, ingredients, unit.as_deref())
+    }
+
+    fn nutrient_coeffs_in(
+        &self,
+        expr: &Expr,
+        ingredients: &[HashMap<String, f64>],
+        unit: Option<&str>,
+    ) -> Result<Vec<f64>, CompileError> {
         match expr {
             Expr::Reference(r) => {
-                // Simple case: just a nutrient name
-                if r.parts.len() == 1 {
-                    if let ReferencePart::Ident(name) = &r.parts[0] {
-                        return Ok(name.clone());
-                    }
+                let nutrient_name = reference_to_string(r);
+                if !self.symbols.nutrients.contains_key(&nutrient_name) {
+                    return Err(CompileError::UnknownNutrient {
+                        suggestion: closest_name(&nutrient_name, self.symbols.nutrients.keys()),
+                        name: nutrient_name,
+                        span: r.span,
+                    });
                 }
-                // TODO: Handle ratio expressions like calcium / phosphorus
-                Ok(reference_to_string(r))
+                let factor = self.term_factor(&nutrient_name, r.span, unit)?;
+                Ok(ingredients
+                    .iter()
+                    .map(|nutrients| nutrients.get(&nutrient_name).copied().unwrap_or(0.0) * factor)
+                    .collect())
             }
+            Expr::Number(n) => Ok(vec![*n; ingredients.len()]),
+            Expr::Paren(inner) => self.nutrient_coeffs_in(inner, ingredients, unit),
             Expr::BinaryOp { left, op, right } => {
-                // For ratio constraints like calcium / phosphorus
-                let left_name = self.expr_to_nutrient_name(left)?;
-                let right_name = self.expr_to_nutrient_name(right)?;
-                Ok(format!("{}_{:?}_{}", left_name, op, right_name))
+                let (coeffs, factor) = match op {
+                    BinaryOp::Add | BinaryOp::Sub => {
+                        let left_coeffs = self.nutrient_coeffs_in(left, ingredients, unit)?;
+                        let right_coeffs = self.nutrient_coeffs_in(right, ingredients, unit)?;
+                        let sign = if *op == BinaryOp::Add { 1.0 } else { -1.0 };
+                        let coeffs = left_coeffs
+                            .iter()
+                            .zip(right_coeffs.iter())
+                            .map(|(l, r)| l + sign * r)
+                            .collect();
+                        return Ok(coeffs);
+                    }
+                    BinaryOp::Mul => match (constant_value(left), constant_value(right)) {
+                        (Some(n), _) => (self.nutrient_coeffs_in(right, ingredients, unit)?, n),
+                        (None, Some(n)) => (self.nutrient_coeffs_in(left, ingredients, unit)?, n),
+                        (None, None) => {
+                            return Err(self.nonlinear(expr));
+                        }
+                    },
+                    BinaryOp::Div => match constant_value(right) {
+                        Some(0.0) => return Err(CompileError::DivisionByZero),
+                        Some(n) => (self.nutrient_coeffs_in(left, ingredients, unit)?, 1.0 / n),
+                        None => {
+                            return Err(self.nonlinear(expr));
+                        }
+                    },
+                };
+                Ok(coeffs.iter().map(|c| c * factor).collect())
             }
-            _ => Err(CompileError::InvalidReference(
-                "Expected nutrient reference".to_string(),
-            )),
+        }
+    }
+
+    fn nonlinear(&self, expr: &Expr) -> CompileError {
+        CompileError::NonLinearExpression {
+            expr: self.constraint_key(expr),
+            // A product of nutrients, or a division by one, names something
+            span: expr.span().unwrap_or(Span::new(0, 0)),
         }
     }
 
@@ -1053,10 +2609,10 @@ impl Compiler {
 
         match expr {
             Expr::Reference(r) => {
-                if let Some(ReferencePart::Ident(name)) = r.parts.first() {
-                    if let Some(idx) = ingredients.iter().position(|x| x == name) {
-                        coeffs[idx] = 1.0;
-                    }
+                if let Some(ReferencePart::Ident(name)) = r.parts.first()
+                    && let Some(idx) = ingredients.iter().position(|x| x == name)
+                {
+                    coeffs[idx] = 1.0;
                 }
             }
             Expr::BinaryOp { left, op, right } => {
@@ -1139,46 +2695,293 @@ impl Compiler {
     }
 }
 
+impl Scope {
+    /// A copy of the program with declaration names and references replaced by
+    /// symbol table keys. `known` holds every key claimed so far, to report
+    /// names that exist elsewhere but were not imported.
+    fn resolve_program(&self, program: &Program, known: &HashSet<String>) -> Result<Program, CompileError> {
+        let mut program = program.clone();
+        for item in &mut program.items {
+            match item {
+                Item::Nutrient(n) => {
+                    n.name = self.names[&n.name].clone();
+                    self.resolve_properties(&mut n.properties, &[], known)?;
+                    if let Some(derived) = &mut n.derived {
+                        self.resolve_expr(&mut derived.expr, &[], known)?;
+                    }
+                }
+                Item::Ingredient(i) => {
+                    i.name = self.names[&i.name].clone();
+                    self.resolve_properties(&mut i.properties, &[], known)?;
+                    for nv in &mut i.nutrients {
+                        self.resolve_reference(&mut nv.nutrient, known)?;
+                        if let Some(constant) = &mut nv.constant {
+                            self.resolve_reference(constant, known)?;
+                        }
+                    }
+                }
+                Item::Formula(f) => {
+                    f.name = self.names[&f.name].clone();
+                    self.resolve_properties(&mut f.properties, &f.params, known)?;
+                    for nc in &mut f.nutrients {
+                        self.resolve_expr(&mut nc.expr, &[], known)?;
+                        self.resolve_bounds(&mut nc.bounds, &f.params, known)?;
+                    }
+                    for ic in &mut f.ingredients {
+                        self.resolve_expr(&mut ic.expr, &[], known)?;
+                        self.resolve_bounds(&mut ic.bounds, &f.params, known)?;
+                    }
+                    if let Some(instance) = &mut f.instance {
+                        self.resolve_reference(&mut instance.template, known)?;
+                        for arg in &mut instance.args {
+                            if let Some(constant) = &mut arg.constant {
+                                self.resolve_reference(constant, known)?;
+                            }
+                        }
+                    }
+                }
+                Item::Const(c) => {
+                    c.name = self.names[&c.name].clone();
+                    self.resolve_expr(&mut c.value, &[], known)?;
+                }
+                Item::Plan(p) => {
+                    p.name = self.names[&p.name].clone();
+                    self.resolve_properties(&mut p.properties, &[], known)?;
+                    for entry in p.formulas.iter_mut().chain(&mut p.available) {
+                        self.resolve_reference(&mut entry.name, known)?;
+                        if let Some(constant) = &mut entry.quantity.constant {
+                            self.resolve_reference(constant, known)?;
+                        }
+                    }
+                }
+                Item::Import(_) => {}
+            }
+        }
+        Ok(program)
+    }
+
+    /// Template parameters in `params` keep their names
+    fn resolve_properties(
+        &self,
+        properties: &mut [Property],
+        params: &[String],
+        known: &HashSet<String>,
+    ) -> Result<(), CompileError> {
+        for p in properties {
+            match &mut p.value {
+                PropertyValue::Expr(expr) => self.resolve_expr(expr, params, known)?,
+                // A constant, such as `batch mixer_size`
+                PropertyValue::Ident(name) if !params.contains(name) => {
+                    if let Some(key) = self.names.get(name) {
+                        *name = key.clone();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_bounds(&self, bounds: &mut Bounds, params: &[String], known: &HashSet<String>) -> Result<(), CompileError> {
+        for bound in [&mut bounds.min, &mut bounds.max].into_iter().flatten() {
+            if let Some(constant) = &mut bound.constant
+                && !is_param(params, constant)
+            {
+                self.resolve_reference(constant, known)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_expr(&self, expr: &mut Expr, params: &[String], known: &HashSet<String>) -> Result<(), CompileError> {
+        match expr {
+            Expr::Number(_) => Ok(()),
+            Expr::Reference(r) if is_param(params, r) => Ok(()),
+            Expr::Reference(r) => self.resolve_reference(r, known),
+            Expr::BinaryOp { left, right, .. } => {
+                self.resolve_expr(left, params, known)?;
+                self.resolve_expr(right, params, known)
+            }
+            Expr::Paren(inner) => self.resolve_expr(inner, params, known),
+        }
+    }
+
+    /// Resolve the leading name of a reference, and the item name after a
+    /// block in references like `base.nutrients.protein`
+    fn resolve_reference(&self, r: &mut Reference, known: &HashSet<String>) -> Result<(), CompileError> {
+        self.resolve_name(&mut r.parts, 0, known)?;
+
+        if let Some(ReferencePart::Ident(block)) = r.parts.get(1)
+            && matches!(block.as_str(), "nutrients" | "nuts" | "ingredients" | "ings")
+        {
+            match r.parts.get_mut(2) {
+                Some(ReferencePart::Ident(_)) => self.resolve_name(&mut r.parts, 2, known)?,
+                Some(ReferencePart::Selection(names)) => {
+                    for name in names {
+                        if let Some(key) = self.names.get(name) {
+                            *name = key.clone();
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the name at `parts[i]`, or the qualified `module.name` starting
+    /// there, with its key. Names that don't exist anywhere are left as they
+    /// are for the compiler to report.
+    fn resolve_name(&self, parts: &mut Vec<ReferencePart>, i: usize, known: &HashSet<String>) -> Result<(), CompileError> {
+        let ReferencePart::Ident(head) = &parts[i] else {
+            return Ok(());
+        };
+
+        if let Some(exports) = self.modules.get(head)
+            && !self.names.contains_key(head)
+            && let Some(ReferencePart::Ident(name)) = parts.get(i + 1)
+        {
+            let key = exports
+                .get(name)
+                .ok_or_else(|| CompileError::UnknownImport(head.clone(), name.clone()))?;
+            parts.splice(i..i + 2, [ReferencePart::Ident(key.clone())]);
+            return Ok(());
+        }
+
+        if let Some(key) = self.names.get(head) {
+            parts[i] = ReferencePart::Ident(key.clone());
+        } else if known.contains(head) {
+            return Err(CompileError::NotInScope(head.clone()));
+        }
+        Ok(())
+    }
+}
+
 impl Default for Compiler {
     fn default() -> Self {
         Self::new()
     }
 }
 
-// Helper functions
+/// Make the rows from `first_row` on elastic: each gets a deviation variable
+/// (shortfall for `>=`, excess for `<=`) charged `penalty` per unit in the
+/// objective. Rows are scaled by batch size, so one unit of deviation in the
+/// constraint's own units is `batch_size` in the row.
+fn soften_rows(
+    lp: &mut LpProblem,
+    first_row: usize,
+    penalty: f64,
+    batch_size: f64,
+    measure: impl Fn(ConstraintOp) -> Option<String>,
+    soft_constraints: &mut Vec<SoftConstraint>,
+) -> Result<(), CompileError> {
+    for row in first_row..lp.num_constraints() {
+        let name = lp.constraints[row].name.clone();
+        if penalty <= 0.0 {
+            return Err(CompileError::InvalidSoftPenalty(name, penalty));
+        }
 
-/// Property name aliases for shorter syntax
-fn property_matches(property_name: &str, target: &str) -> bool {
-    if property_name == target {
-        return true;
+        let op = lp.constraints[row].op;
+        let (suffix, sign) = match op {
+            ConstraintOp::Ge => ("shortfall", 1.0),
+            ConstraintOp::Le => ("excess", -1.0),
+            ConstraintOp::Eq => continue,
+        };
+        let variable = lp.add_variable(format!("{}_{}", name, suffix), VariableKind::Continuous);
+        lp.constraints[row].coefficients[variable] = sign;
+        lp.objective.coefficients[variable] = penalty / batch_size;
+
+        soft_constraints.push(SoftConstraint {
+            constraint: name,
+            op,
+            variable,
+            penalty,
+            measure: measure(op),
+        });
     }
-    // Check aliases
-    match (property_name, target) {
-        ("batch", "batch_size") => true,
-        ("desc", "description") => true,
-        _ => false,
+    Ok(())
+}
+
+// Helper functions
+
+/// Span of a formula property, or of the whole formula if it has none
+fn property_span(formula: &Formula, name: &str) -> Span {
+    formula
+        .properties
+        .iter()
+        .find(|p| property_matches(&p.name, name))
+        .map(|p| p.span)
+        .unwrap_or(formula.span)
+}
+
+/// Every bound in a formula's constraints
+fn formula_bounds<'a>(
+    nutrients: &'a mut [NutrientConstraint],
+    ingredients: &'a mut [IngredientConstraint],
+) -> impl Iterator<Item = &'a mut BoundValue> {
+    nutrients
+        .iter_mut()
+        .map(|nc| &mut nc.bounds)
+        .chain(ingredients.iter_mut().map(|ic| &mut ic.bounds))
+        .flat_map(|bounds| [&mut bounds.min, &mut bounds.max].into_iter().flatten())
+}
+
+/// Whether a reference names one of a template's parameters
+fn is_param(params: &[String], r: &Reference) -> bool {
+    matches!(&r.parts[..], [ReferencePart::Ident(name)] if params.contains(name))
+}
+
+/// Replace template parameters in a property value with their arguments
+fn substitute_property(value: &mut PropertyValue, args: &HashMap<String, BoundValue>) {
+    match value {
+        PropertyValue::Ident(name) => {
+            if let Some(arg) = args.get(name) {
+                *value = PropertyValue::Number(arg.value);
+            }
+        }
+        PropertyValue::Expr(expr) => substitute_expr(expr, args),
+        _ => {}
     }
 }
 
-fn get_string_property(properties: &[Property], name: &str) -> Option<String> {
-    properties.iter().find_map(|p| {
-        if property_matches(&p.name, name) {
-            match &p.value {
-                PropertyValue::String(s) => Some(s.clone()),
-                PropertyValue::Ident(s) => Some(s.clone()),
-                _ => None,
+fn substitute_expr(expr: &mut Expr, args: &HashMap<String, BoundValue>) {
+    match expr {
+        Expr::Reference(r) => {
+            if let [ReferencePart::Ident(name)] = &r.parts[..]
+                && let Some(arg) = args.get(name)
+            {
+                *expr = Expr::Number(arg.value);
             }
-        } else {
-            None
         }
-    })
+        Expr::BinaryOp { left, right, .. } => {
+            substitute_expr(left, args);
+            substitute_expr(right, args);
+        }
+        Expr::Paren(inner) => substitute_expr(inner, args),
+        Expr::Number(_) => {}
+    }
+}
+
+/// Property name aliases for shorter syntax
+fn property_matches(property_name: &str, target: &str) -> bool {
+    property_name == target || canonical_property(property_name) == target
 }
 
-fn get_number_property(properties: &[Property], name: &str) -> Option<f64> {
+/// The long form of a property name alias
+pub(crate) fn canonical_property(name: &str) -> &str {
+    match name {
+        "batch" => "batch_size",
+        "desc" => "description",
+        _ => name,
+    }
+}
+
+pub(crate) fn get_string_property(properties: &[Property], name: &str) -> Option<String> {
     properties.iter().find_map(|p| {
         if property_matches(&p.name, name) {
             match &p.value {
-                PropertyValue::Number(n) => Some(*n),
+                PropertyValue::String(s) => Some(s.clone()),
+                PropertyValue::Ident(s) => Some(s.clone()),
                 _ => None,
             }
         } else {
@@ -1205,6 +3008,133 @@ fn get_bool_property(properties: &[Property], name: &str) -> Option<bool> {
     })
 }
 
+fn missing_data_policy(formula: &str, properties: &[Property]) -> Result<MissingDataPolicy, CompileError> {
+    let Some(property) = properties.iter().find(|p| p.name == "missing_data") else {
+        return Ok(MissingDataPolicy::default());
+    };
+    match &property.value {
+        PropertyValue::Ident(value) | PropertyValue::String(value) => match value.as_str() {
+            "error" => Ok(MissingDataPolicy::Error),
+            "warn" => Ok(MissingDataPolicy::Warn),
+            "zero" => Ok(MissingDataPolicy::Zero),
+            _ => Err(CompileError::InvalidMissingDataPolicy(formula.to_string(), value.clone())),
+        },
+        _ => Err(CompileError::InvalidMissingDataPolicy(formula.to_string(), "a non-name value".to_string())),
+    }
+}
+
+/// Names referenced in an expression, such as the nutrients of a constraint
+fn collect_references(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Reference(r) => names.push(reference_to_string(r)),
+        Expr::BinaryOp { left, right, .. } => {
+            collect_references(left, names);
+            collect_references(right, names);
+        }
+        Expr::Paren(inner) => collect_references(inner, names),
+        Expr::Number(_) => {}
+    }
+}
+
+/// Module name of a file: its name without the extension
+fn module_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Names declared by a program's own items, in source order
+fn declared_names(program: &Program) -> Vec<String> {
+    program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Nutrient(n) => Some(n.name.clone()),
+            Item::Ingredient(i) => Some(i.name.clone()),
+            Item::Formula(f) => Some(f.name.clone()),
+            Item::Const(c) => Some(c.name.clone()),
+            Item::Plan(p) => Some(p.name.clone()),
+            Item::Import(_) => None,
+        })
+        .collect()
+}
+
+/// Value of an expression made only of numbers, or `None` if it has references
+fn constant_value(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Number(n) => Some(*n),
+        Expr::Reference(_) => None,
+        Expr::Paren(inner) => constant_value(inner),
+        Expr::BinaryOp { left, op, right } => {
+            let (l, r) = (constant_value(left)?, constant_value(right)?);
+            Some(match op {
+                BinaryOp::Add => l + r,
+                BinaryOp::Sub => l - r,
+                BinaryOp::Mul => l * r,
+                BinaryOp::Div => l / r,
+            })
+        }
+    }
+}
+
+/// The first part of an expression that isn't linear: a product of two
+/// nutrients, or a division by one
+pub(crate) fn nonlinear_term(expr: &Expr) -> Option<&Expr> {
+    match expr {
+        Expr::Number(_) | Expr::Reference(_) => None,
+        Expr::Paren(inner) => nonlinear_term(inner),
+        Expr::BinaryOp { left, op, right } => match op {
+            BinaryOp::Add | BinaryOp::Sub => nonlinear_term(left).or_else(|| nonlinear_term(right)),
+            BinaryOp::Mul => match (constant_value(left), constant_value(right)) {
+                (Some(_), _) => nonlinear_term(right),
+                (None, Some(_)) => nonlinear_term(left),
+                (None, None) => Some(expr),
+            },
+            BinaryOp::Div => match constant_value(right) {
+                Some(_) => nonlinear_term(left),
+                None => Some(expr),
+            },
+        },
+    }
+}
+
+/// The candidate closest to a misspelled `name`, if any is close enough to
+/// be what was meant
+pub(crate) fn closest_name<S: AsRef<str>>(name: &str, candidates: impl IntoIterator<Item = S>) -> Option<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c.as_ref()), c.as_ref().to_string()))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, c)| c)
+}
+
+/// Edits (insertions, deletions, substitutions and swaps of neighbouring
+/// characters) needed to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // d[i][j]: distance between the first i characters of a and j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
 fn reference_to_string(r: &Reference) -> String {
     r.parts
         .iter()
@@ -1273,8 +3203,8 @@ mod tests {
         // Check LP problem structure
         assert_eq!(compiled.lp_problem.num_variables(), 2);
         // Constraints: protein_min, protein_max, energy_min, corn_max, soybean_meal_min,
-        //              batch_size, corn_min (non-neg), soybean_meal_min (non-neg)
-        assert!(compiled.lp_problem.num_constraints() >= 5);
+        //              batch_size (non-negativity is a variable bound)
+        assert_eq!(compiled.lp_problem.num_constraints(), 6);
     }
 
     #[test]
@@ -1581,15 +3511,439 @@ mod tests {
     }
 
     #[test]
-    fn test_ingredient_composition() {
+    fn test_parameterized_template() {
         let source = r#"
+            const grower_protein 18
+
             nutrient protein {}
-            nutrient energy {}
-            nutrient fiber {}
 
             ingredient corn {
                 cost 100
-                nutrients {
+                nutrients { protein 8.0 }
+            }
+
+            ingredient soy {
+                cost 300
+                nutrients { protein 45.0 }
+            }
+
+            template formula broiler(phase_protein, max_corn, size) {
+                name "Broiler"
+                batch size
+                nutrients { protein min phase_protein }
+                ingredients {
+                    corn max max_corn
+                    soy
+                }
+            }
+
+            formula starter = broiler(22, 50%, 100)
+
+            formula grower = broiler(grower_protein, 90%, 200) {
+                name "Grower"
+                nutrients { protein min 16 }
+            }
+
+            formula direct {
+                batch 100
+                nutrients { broiler.nutrients }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        let solver = formulang_solver::Solver::new();
+
+        // Corn is capped at the 50% argument before protein binds
+        let compiled = compiler.compile_formula("starter").unwrap();
+        assert_eq!(compiled.batch_size, 100.0);
+        assert_eq!(compiled.display_name.as_deref(), Some("Broiler"));
+        let solution = solver.solve(&compiled.lp_problem);
+        assert!((solution.values[0] - 50.0).abs() < 1e-6);
+        let source_of = |compiled: &CompiledFormula, name: &str| {
+            compiled.constraint_sources.iter().find(|s| s.constraint == name).unwrap().clone()
+        };
+        let protein = source_of(&compiled, "protein_min");
+        assert_eq!(protein.formula, "broiler(22, 50%, 100)");
+        assert_eq!(protein.chain, vec!["starter", "broiler(22, 50%, 100)"]);
+
+        // The body's protein minimum overrides the template's
+        let compiled = compiler.compile_formula("grower").unwrap();
+        assert_eq!(compiled.batch_size, 200.0);
+        assert_eq!(compiled.display_name.as_deref(), Some("Grower"));
+        let solution = solver.solve(&compiled.lp_problem);
+        assert!((solution.values[0] - 200.0 * 29.0 / 37.0).abs() < 1e-6);
+        assert_eq!(source_of(&compiled, "protein_min").formula, "grower");
+
+        assert!(compiler.is_template("broiler"));
+        assert!(!compiler.solvable_formula_names().iter().any(|name| name.starts_with("broiler")));
+        assert!(matches!(
+            compiler.compile_formula("direct"),
+            Err(CompileError::ParameterizedTemplate(name)) if name == "broiler"
+        ));
+
+        // A bad call only fails the formula making it
+        let wrong_count = Parser::parse(&format!("{}\nformula bad = broiler(22)", source)).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&wrong_count).unwrap();
+        let err = compiler.compile_formula("bad").unwrap_err();
+        assert_eq!(err.to_string(), "Template broiler takes 3 arguments, got 1");
+        assert!(compiler.compile_formula("starter").is_ok());
+
+        let not_template = Parser::parse(&format!("{}\nformula bad = starter(22)", source)).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&not_template).unwrap();
+        let err = compiler.compile_formula("bad").unwrap_err();
+        assert!(matches!(err, CompileError::NotATemplate(name) if name == "starter"));
+
+        let unknown_arg = Parser::parse(&format!("{}\nformula bad = broiler(grower_protien, 50%, 100)", source)).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&unknown_arg).unwrap();
+        let err = compiler.compile_formula("bad").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown constant: grower_protien in formula bad. Did you mean grower_protein?"
+        );
+    }
+
+    #[test]
+    fn test_formula_as_ingredient() {
+        let source = r#"
+            nutrient protein {}
+            nutrient vitamin_a {}
+
+            ingredient corn {
+                cost 100
+                nutrients { protein 8 vitamin_a 0 }
+            }
+
+            ingredient soy {
+                cost 300
+                nutrients { protein 45 vitamin_a 0 }
+            }
+
+            ingredient vit_a_500 {
+                cost 5000
+                nutrients { protein 0 vitamin_a 500 }
+            }
+
+            ingredient carrier {
+                cost 50
+                nutrients { protein 2 vitamin_a 0 }
+            }
+
+            formula premix {
+                batch 100
+                nutrients { vitamin_a min 100 }
+                ingredients {
+                    vit_a_500
+                    carrier
+                }
+            }
+
+            formula feed {
+                batch 1000
+                nutrients {
+                    protein min 18
+                    vitamin_a min 1
+                }
+                ingredients {
+                    corn
+                    soy
+                    premix max 2%
+                }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        // 20 vit_a_500 and 80 carrier per 100: cost 1040, vitamin_a 100, protein 1.6
+        let compiled = compiler.compile_formula("feed").unwrap();
+        assert_eq!(compiled.ingredients, vec!["corn", "soy", "premix"]);
+        assert_eq!(compiled.sub_formulas.len(), 1);
+        let premix = &compiled.sub_formulas[0];
+        assert!((premix.unit_cost() - 1040.0).abs() < 1e-6);
+        assert!((premix.nutrients()["vitamin_a"] - 100.0).abs() < 1e-6);
+        assert!((premix.nutrients()["protein"] - 1.6).abs() < 1e-6);
+        assert!((compiled.ingredient_costs[2] - 1040.0).abs() < 1e-6);
+
+        // The feed needs 10 of premix for its vitamin_a
+        let solution = formulang_solver::Solver::new().solve(&compiled.lp_problem);
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        assert!((solution.values[2] - 10.0).abs() < 1e-6);
+
+        let cycle = Parser::parse(
+            r#"
+            nutrient protein {}
+            ingredient corn { cost 100 nutrients { protein 8 } }
+            formula a { batch 100 ingredients { corn b } }
+            formula b { batch 100 ingredients { corn a } }
+            "#,
+        )
+        .unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&cycle).unwrap();
+        let err = compiler.compile_formula("a").unwrap_err();
+        assert_eq!(err.to_string(), "Circular reference detected: a -> b -> a");
+
+        let infeasible = Parser::parse(&source.replace("vitamin_a min 100", "vitamin_a min 1000")).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&infeasible).unwrap();
+        assert!(matches!(
+            compiler.compile_formula("feed"),
+            Err(CompileError::SubFormulaNotOptimal(name, SolutionStatus::Infeasible)) if name == "premix"
+        ));
+    }
+
+    #[test]
+    fn test_dry_matter_basis() {
+        let source = r#"
+            nutrient protein {}
+            ingredient hay {
+                cost 100
+                moisture 20
+                nutrients { basis dry_matter protein 10 }
+            }
+            ingredient soy {
+                cost 300
+                dry_matter 90
+                nutrients { protein 45 }
+            }
+            formula calf {
+                batch 100
+                nutrients { basis dry_matter protein min 20 }
+                ingredients { hay soy }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        // Stored as fed: 10% of the dry matter is 8% of the hay
+        let compiled = compiler.compile_formula("calf").unwrap();
+        assert_eq!(compiled.ingredient_nutrients[0]["protein"], 8.0);
+        assert_eq!(compiled.ingredient_dry_matter, vec![Some(80.0), Some(90.0)]);
+
+        // 8 hay + 45 soy >= 20% of (0.8 hay + 0.9 soy): 27 soy >= 8 hay
+        let solution = formulang_solver::Solver::new().solve(&compiled.lp_problem);
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        assert!((solution.values[1] - 800.0 / 35.0).abs() < 1e-6);
+        let dry_matter = compiled.dry_matter(&solution.values).unwrap();
+        let protein = (8.0 * solution.values[0] + 45.0 * solution.values[1]) / 100.0;
+        assert!((protein / dry_matter * 100.0 - 20.0).abs() < 1e-6);
+
+        let errors = [
+            ("moisture 20", "", "MissingDryMatter"),
+            ("moisture 20", "moisture 20 dry_matter 80", "ConflictingDryMatter"),
+            ("dry_matter 90", "", "MissingDryMatterInFormula"),
+            ("moisture 20", "moisture 120", "InvalidDryMatter"),
+        ];
+        for (from, to, expected) in errors {
+            let program = Parser::parse(&source.replace(from, to)).unwrap();
+            let mut compiler = Compiler::new();
+            let error = compiler.load(&program).and_then(|_| compiler.compile_formula("calf").map(|_| ()));
+            let error = format!("{:?}", error.unwrap_err());
+            assert_eq!(error.split(['(', ' ']).next(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_unit_conversion() {
+        let source = r#"
+            nutrient energy { unit "kcal/kg" }
+            nutrient zinc { unit "mg/kg" }
+            ingredient wheat { cost 200 nutrients { energy 13.8 "MJ/kg" zinc 0.003 "%" } }
+            ingredient fat { cost 900 nutrients { energy 8000 zinc 0 } }
+            formula broiler {
+                batch 1
+                nutrients {
+                    energy min 13 "MJ/kg"
+                    zinc max 40
+                }
+                ingredients { wheat fat }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        let compiled = compiler.compile_formula("broiler").unwrap();
+        assert!((compiled.ingredient_nutrients[0]["energy"] - 13.8 * 1000.0 / 4.184).abs() < 1e-9);
+        assert!((compiled.ingredient_nutrients[0]["zinc"] - 30.0).abs() < 1e-9);
+        let rhs: Vec<f64> = compiled.lp_problem.constraints.iter().map(|c| c.rhs).collect();
+        assert!(rhs.iter().any(|r| (r - 13.0 * 1000.0 / 4.184).abs() < 1e-9), "rhs: {:?}", rhs);
+
+        // Sums convert to their first nutrient's unit, and ratios of one kind
+        // compare in one unit
+        let mixed = source.replace("zinc max 40", "zinc + energy max 40\n lysine + methionine min 0.8\n lysine / methionine min 2");
+        let mixed = mixed.replace("nutrient zinc", "nutrient lysine { unit \"%\" }\nnutrient methionine { unit \"g/kg\" }\nnutrient zinc");
+        let mixed = mixed.replace("zinc 0.003 \"%\"", "zinc 0.003 \"%\" lysine 0.3 methionine 2");
+        let mut compiler = Compiler::new();
+        compiler.load(&Parser::parse(&mixed).unwrap()).unwrap();
+        let error = compiler.compile_formula("broiler").unwrap_err();
+        assert_eq!(error.to_string(), "Unit of energy: kcal/kg is an energy, but mg/kg is a concentration");
+        let mixed = mixed.replace("zinc + energy max 40", "zinc max 40");
+        let mut compiler = Compiler::new();
+        compiler.load(&Parser::parse(&mixed).unwrap()).unwrap();
+        let compiled = compiler.compile_formula("broiler").unwrap();
+        let row = |name: &str| compiled.lp_problem.constraints.iter().find(|c| c.name == name).unwrap().clone();
+        // 0.3% lysine + 2 g/kg methionine = 0.5%
+        assert!((row("lysine+methionine_min").coefficients[0] - 0.5).abs() < 1e-9);
+        // 0.3% - 2 * 0.2%
+        assert!((row("lysine/methionine_min").coefficients[0] - (0.3 - 2.0 * 0.2)).abs() < 1e-9);
+
+        let errors = [
+            ("energy 13.8 \"MJ/kg\"", "energy 13.8 \"%\""),
+            ("energy min 13 \"MJ/kg\"", "energy min 13 \"MJ\""),
+            ("energy min 13 \"MJ/kg\"", "energy / zinc min 1 \"%\""),
+            ("zinc max 40", "zinc + energy max 40 \"ppm\""),
+        ];
+        for (from, to) in errors {
+            let program = Parser::parse(&source.replace(from, to)).unwrap();
+            let mut compiler = Compiler::new();
+            let error = compiler.load(&program).and_then(|_| compiler.compile_formula("broiler").map(|_| ()));
+            assert!(matches!(error, Err(CompileError::InvalidUnit { .. })), "{}: {:?}", to, error);
+        }
+    }
+
+    #[test]
+    fn test_plan_shared_supply() {
+        let source = r#"
+            nutrient protein {}
+            ingredient corn { cost 100 nutrients { protein 10 } }
+            ingredient barley { cost 120 nutrients { protein 10 } }
+
+            formula starter {
+                batch 1000
+                nutrients { protein min 10 }
+                ingredients { corn barley }
+            }
+
+            formula grower {
+                batch 1000
+                nutrients { protein min 10 }
+                ingredients { corn barley }
+            }
+
+            plan week {
+                formulas {
+                    starter 2000
+                    grower 1000
+                }
+                available { corn 2500 }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        assert_eq!(compiler.plan_names(), vec!["week"]);
+
+        let plan = compiler.compile_plan("week").unwrap();
+        assert_eq!(plan.formulas.len(), 2);
+        assert_eq!(plan.supplies.len(), 1);
+        assert_eq!(plan.lp_problem.num_variables(), 4);
+
+        // Both formulas want only corn; 500 of barley makes up the shortfall
+        let solution = formulang_solver::Solver::new().solve(&plan.lp_problem);
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        assert!((solution.objective_value - 310000.0).abs() < 1e-3);
+        let supply = &plan.supplies[0];
+        let shadow = &solution.analysis.shadow_prices[supply.constraint];
+        assert!((shadow.value + 20.0).abs() < 1e-6);
+
+        // Each formula's own solution is per batch
+        let mut corn = 0.0;
+        for planned in &plan.formulas {
+            let own = planned.solution(&solution);
+            assert!((own.values.iter().sum::<f64>() - 1000.0).abs() < 1e-6);
+            corn += own.values[0] * planned.scale();
+        }
+        assert!((corn - 2500.0).abs() < 1e-6);
+
+        let over = Parser::parse(&source.replace("corn 2500", "corn -1")).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&over).unwrap();
+        assert!(matches!(
+            compiler.compile_plan("week"),
+            Err(CompileError::InvalidPlanQuantity { available: true, .. })
+        ));
+        assert!(matches!(compiler.compile_plan("month"), Err(CompileError::UnknownPlan(_))));
+    }
+
+    #[test]
+    fn test_plan_supply_through_sub_formula() {
+        let source = r#"
+            nutrient protein {}
+            ingredient corn { cost 100 nutrients { protein 10 } }
+            ingredient soy { cost 300 nutrients { protein 45 } }
+            ingredient fishmeal { cost 900 nutrients { protein 60 } }
+
+            formula conc {
+                batch 100
+                nutrients { protein min 40 }
+                ingredients { soy fishmeal corn }
+            }
+
+            formula grower {
+                batch 1000
+                nutrients { protein min 20 }
+                ingredients { corn soy conc min 10% }
+            }
+
+            plan week {
+                formulas { grower 3000 }
+                available { soy 600 }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        let plan = compiler.compile_plan("week").unwrap();
+
+        // conc is 6/7 soy, so each unit of it draws that much soy stock
+        let grower = &plan.formulas[0].compiled;
+        let soy_share = grower.sub_formulas[0].content_of("soy");
+        assert!((soy_share - 6.0 / 7.0).abs() < 1e-6);
+        let row = &plan.lp_problem.constraints[plan.supplies[0].constraint];
+        assert_eq!(row.coefficients[1], 1.0);
+        assert!((row.coefficients[2] - soy_share).abs() < 1e-9);
+
+        // Above corn's level, grower's protein comes from soy, directly or
+        // through conc, and 600 of soy can't supply enough of it
+        let solution = formulang_solver::Solver::new().solve(&plan.lp_problem);
+        assert_eq!(solution.status, SolutionStatus::Infeasible);
+        assert!(solution.conflict.contains(&"soy_available".to_string()));
+
+        // With enough stock, the soy used counts what conc contains
+        let program = Parser::parse(&source.replace("soy 600", "soy 900")).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        let plan = compiler.compile_plan("week").unwrap();
+        let solution = formulang_solver::Solver::new().solve(&plan.lp_problem);
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        let own = plan.formulas[0].solution(&solution);
+        let scale = plan.formulas[0].scale();
+        let soy = (own.values[1] + own.values[2] * soy_share) * scale;
+        assert!(soy <= 900.0 + 1e-6);
+        assert!(own.values[2] > 0.0);
+    }
+
+    #[test]
+    fn test_ingredient_composition() {
+        let source = r#"
+            nutrient protein {}
+            nutrient energy {}
+            nutrient fiber {}
+
+            ingredient corn {
+                cost 100
+                nutrients {
                     protein 8.5
                     energy 3350
                     fiber 2.5
@@ -1647,4 +4001,670 @@ mod tests {
         assert_eq!(compiled.batch_size, 100.0);
         assert_eq!(compiled.ingredients.len(), 3);
     }
+
+    #[test]
+    fn test_optional_inclusion_and_ingredient_count() {
+        let source = r#"
+            nutrient protein {}
+
+            ingredient corn {
+                cost 100
+                nutrients { protein 8.0 }
+            }
+
+            ingredient soy {
+                cost 300
+                nutrients { protein 44.0 }
+            }
+
+            ingredient fish {
+                cost 250
+                nutrients { protein 60.0 }
+            }
+
+            formula optional {
+                batch_size 100
+                nutrients { protein min 20 }
+                ingredients {
+                    corn
+                    soy
+                    fish min 60% or 0
+                }
+            }
+
+            formula capped {
+                batch_size 100
+                max_ingredients 2
+                nutrients { protein min 20 }
+                ingredients {
+                    corn
+                    soy
+                    fish max 10%
+                }
+            }
+
+            formula uncapped {
+                batch_size 100
+                nutrients { protein min 20 }
+                ingredients {
+                    corn
+                    soy
+                    fish max 10%
+                }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        let solver = formulang_solver::Solver::new();
+
+        // 60% fish costs more than corn and soy alone, so it is left out entirely
+        let compiled = compiler.compile_formula("optional").unwrap();
+        assert!(compiled.lp_problem.has_integer_variables());
+        let solution = solver.solve(&compiled.lp_problem);
+        assert_eq!(solution.status, formulang_solver::SolutionStatus::Optimal);
+        assert!(solution.values[2].abs() < 1e-6);
+        assert!((solution.objective_value - 50000.0 / 3.0).abs() < 1e-3);
+
+        // Without a limit all three are used
+        let compiled = compiler.compile_formula("uncapped").unwrap();
+        let solution = solver.solve(&compiled.lp_problem);
+        assert!((solution.values[2] - 10.0).abs() < 1e-6);
+
+        // With at most two ingredients, fish drops out
+        let compiled = compiler.compile_formula("capped").unwrap();
+        let solution = solver.solve(&compiled.lp_problem);
+        assert_eq!(solution.status, formulang_solver::SolutionStatus::Optimal);
+        assert!(solution.values[2].abs() < 1e-6);
+        assert!((solution.values[1] - 100.0 / 3.0).abs() < 1e-6);
+        let used = solution.values[..3].iter().filter(|v| **v > 1e-6).count();
+        assert_eq!(used, 2);
+
+        // Terms that add up keep the big-M bound valid: 0 only when neither is used
+        let sum = source.replace("fish min 60% or 0", "fish\n soy + fish min 60% or 0 as protein_sources");
+        let program = Parser::parse(&sum).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        let compiled = compiler.compile_formula("optional").unwrap();
+        let solution = solver.solve(&compiled.lp_problem);
+        assert_eq!(solution.status, formulang_solver::SolutionStatus::Optimal);
+        assert!(solution.values[1] + solution.values[2] >= 60.0 - 1e-6);
+
+        // A difference can be 0 with both used
+        let difference = source.replace("fish min 60% or 0", "fish\n soy - fish min 10% or 0");
+        let program = Parser::parse(&difference).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        assert!(matches!(compiler.compile_formula("optional"), Err(CompileError::InvalidOrZero(_))));
+    }
+
+    #[test]
+    fn test_infeasible_conflict() {
+        let source = r#"
+            nutrient protein {}
+
+            ingredient corn {
+                cost 100
+                nutrients { protein 8.0 }
+            }
+
+            ingredient soy {
+                cost 300
+                nutrients { protein 44.0 }
+            }
+
+            formula test {
+                batch_size 100
+                nutrients { protein min 20 max 30 }
+                ingredients {
+                    corn min 10%
+                    soy max 20%
+                }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        let compiled = compiler.compile_formula("test").unwrap();
+        let solution = formulang_solver::Solver::new().solve(&compiled.lp_problem);
+
+        assert_eq!(solution.status, formulang_solver::SolutionStatus::Infeasible);
+        // corn_min and protein_max play no part in the conflict
+        assert_eq!(solution.conflict, vec!["protein_min", "soy_max", "batch_size"]);
+        assert_eq!(solution.violations.len(), 3);
+    }
+
+    #[test]
+    fn test_constraint_sources() {
+        let source = r#"
+            nutrient protein {}
+            nutrient fiber {}
+
+            ingredient corn {
+                cost 100
+                nutrients { protein 8.0 fiber 2.0 }
+            }
+
+            ingredient soy {
+                cost 300
+                nutrients { protein 44.0 fiber 6.0 }
+            }
+
+            formula base {
+                batch_size 100
+                nutrients {
+                    protein min 20
+                    fiber max 5
+                }
+                ingredients { corn soy }
+            }
+
+            formula grower {
+                batch_size 1000
+                nutrients {
+                    base.nutrients
+                    protein min 18
+                }
+                ingredients { base.ingredients }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        let compiled = compiler.compile_formula("grower").unwrap();
+        let names: Vec<&str> = compiled.lp_problem.constraints.iter().map(|c| c.name.as_str()).collect();
+        // The override replaces the inherited bound rather than adding a row
+        assert_eq!(names, vec!["protein_min", "fiber_max", "batch_size"]);
+        assert_eq!(compiled.constraint_sources.len(), names.len());
+
+        let text = |s: &ConstraintSource| &source[s.span.start..s.span.end];
+        let protein = &compiled.constraint_sources[0];
+        assert_eq!(protein.formula, "grower");
+        assert_eq!(protein.chain, vec!["grower"]);
+        assert_eq!(protein.overrides, vec!["base"]);
+        assert!(text(protein).starts_with("protein min 18"));
+
+        let fiber = &compiled.constraint_sources[1];
+        assert_eq!(fiber.formula, "base");
+        assert_eq!(fiber.chain, vec!["grower", "base"]);
+        assert!(fiber.overrides.is_empty());
+        assert!(text(fiber).starts_with("fiber max 5"));
+
+        let batch = &compiled.constraint_sources[2];
+        assert_eq!(batch.constraint, "batch_size");
+        assert!(text(batch).starts_with("batch_size 1000"));
+        assert_eq!(batch.file, None);
+    }
+
+    #[test]
+    fn test_unknown_references() {
+        let source = r#"
+            nutrient protein {}
+
+            ingredient corn {
+                cost 100
+                nutrients { protein 8.0 }
+            }
+
+            formula typo {
+                batch_size 100
+                ingredients { cron max 50% }
+            }
+
+            formula unknown {
+                batch_size 100
+                nutrients { lysine min 1 }
+                ingredients { corn }
+            }
+
+            formula misspelled {
+                batch_size 100
+                nutrients { protien min 1 }
+                ingredients { corn }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        let err = compiler.compile_formula("typo").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown ingredient: cron. Did you mean corn?");
+        let span = err.span().unwrap();
+        assert_eq!(&source[span.start..span.end], "cron");
+
+        let err = compiler.compile_formula("unknown").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown nutrient: lysine");
+
+        let err = compiler.compile_formula("misspelled").unwrap_err();
+        assert!(matches!(
+            err,
+            CompileError::UnknownNutrient { ref name, suggestion: Some(ref s), .. }
+                if name == "protien" && s == "protein"
+        ));
+
+        assert_eq!(edit_distance("cron", "corn"), 1);
+        assert_eq!(edit_distance("lysine", "protein"), 6);
+        assert_eq!(closest_name("lysne", ["lysine".to_string(), "fiber".to_string()].iter()), Some("lysine".to_string()));
+    }
+
+    #[test]
+    fn test_constants() {
+        let source = r#"
+            const mixer_size 2000
+            const protein_target 18
+            const protein_ceiling protein_target + 4
+            const corn_protein 8.5
+
+            nutrient protein {}
+
+            ingredient corn {
+                cost 100
+                nutrients { protein corn_protein }
+            }
+
+            ingredient soy {
+                cost 300
+                nutrients { protein 44.0 }
+            }
+
+            formula layer {
+                batch_size mixer_size
+                nutrients { protein min protein_target max protein_ceiling }
+                ingredients { corn soy }
+            }
+
+            formula typo {
+                batch_size mixer_sise
+                ingredients { corn }
+            }
+
+            formula typo_bound {
+                batch_size 1000
+                nutrients { protein min protein_targte }
+                ingredients { corn soy }
+            }
+
+            plan week {
+                formulas { layer mixer_sise }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        let compiled = compiler.compile_formula("layer").unwrap();
+        assert_eq!(compiled.batch_size, 2000.0);
+        assert_eq!(compiled.ingredient_nutrients[0]["protein"], 8.5);
+        let rhs: Vec<f64> = compiled.lp_problem.constraints.iter().map(|c| c.rhs).collect();
+        assert!(rhs.contains(&(18.0 * 2000.0)), "constraints: {:?}", compiled.lp_problem.constraints);
+        assert!(rhs.contains(&(22.0 * 2000.0)), "constraints: {:?}", compiled.lp_problem.constraints);
+
+        let err = compiler.compile_formula("typo").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown constant: mixer_sise. Did you mean mixer_size?");
+
+        // A bound's constant only fails the formula or plan using it
+        let err = compiler.compile_formula("typo_bound").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown constant: protein_targte in formula typo_bound. Did you mean protein_target?"
+        );
+        let err = compiler.compile_plan("week").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown constant: mixer_sise in plan week. Did you mean mixer_size?");
+
+        let source = "const a b + 1\nconst b a * 2";
+        let err = Compiler::new().load(&Parser::parse(source).unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "Circular reference detected: a -> b -> a");
+        assert!(source[err.span().unwrap().start..].starts_with("const a b + 1"));
+    }
+
+    #[test]
+    fn test_soft_constraint() {
+        let source = r#"
+            nutrient protein {}
+
+            ingredient corn {
+                cost 100
+                nutrients { protein 8.0 }
+            }
+
+            ingredient soy {
+                cost 300
+                nutrients { protein 44.0 }
+            }
+
+            formula cheap_penalty {
+                batch_size 100
+                nutrients { protein min 50 soft 100 }
+                ingredients { corn soy }
+            }
+
+            formula steep_penalty {
+                batch_size 100
+                nutrients { protein min 50 soft 1000 }
+                ingredients { corn soy }
+            }
+
+            formula ratio_penalty {
+                batch_size 100
+                nutrients { protein / protein min 2 soft 10 }
+                ingredients { corn soy }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        let solver = formulang_solver::Solver::new();
+
+        // Each unit of soy instead of corn costs 200 and closes 0.36 of shortfall
+        let compiled = compiler.compile_formula("cheap_penalty").unwrap();
+        let solution = solver.solve(&compiled.lp_problem);
+        assert_eq!(solution.status, formulang_solver::SolutionStatus::Optimal);
+        let soft = &compiled.soft_constraints[0];
+        assert_eq!(soft.constraint, "protein_min");
+        assert!((solution.values[0] - 100.0).abs() < 1e-6);
+        assert!((compiled.soft_deviation(soft, &solution.values) - 42.0).abs() < 1e-6);
+
+        let compiled = compiler.compile_formula("steep_penalty").unwrap();
+        let solution = solver.solve(&compiled.lp_problem);
+        let soft = &compiled.soft_constraints[0];
+        assert!((solution.values[1] - 100.0).abs() < 1e-6);
+        assert!((compiled.soft_deviation(soft, &solution.values) - 6.0).abs() < 1e-6);
+        assert!((compiled.ingredient_cost(&solution.values) - 30000.0).abs() < 1e-6);
+        assert!((solution.objective_value - 36000.0).abs() < 1e-6);
+        assert_eq!(soft.measure, None);
+
+        // A ratio's deviation is on its linearized row
+        let compiled = compiler.compile_formula("ratio_penalty").unwrap();
+        let soft = &compiled.soft_constraints[0];
+        assert_eq!(soft.measure.as_deref(), Some("protein - 2 × protein"));
+    }
+
+    #[test]
+    fn test_linear_nutrient_expression() {
+        let source = r#"
+            nutrient sodium {}
+            nutrient potassium {}
+            nutrient chloride {}
+
+            ingredient salt {
+                cost 100
+                nutrients { sodium 39 chloride 60 }
+            }
+
+            ingredient potash {
+                cost 300
+                nutrients { potassium 52 chloride 47 }
+            }
+
+            formula electrolytes {
+                batch_size 100
+                nutrients {
+                    sodium * 434.8 + potassium * 255.7 - chloride * 282 min 250
+                    (sodium + 1) / 2 max 30 as half_sodium
+                }
+                ingredients { salt potash }
+            }
+
+            formula product {
+                batch_size 100
+                nutrients { sodium * potassium min 1 }
+                ingredients { salt potash }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        let compiled = compiler.compile_formula("electrolytes").unwrap();
+        let deb = &compiled.lp_problem.constraints[0];
+        assert_eq!(deb.name, "sodium*434.8+potassium*255.7-chloride*282_min");
+        assert!((deb.coefficients[0] - (39.0 * 434.8 - 60.0 * 282.0)).abs() < 1e-9);
+        assert!((deb.coefficients[1] - (52.0 * 255.7 - 47.0 * 282.0)).abs() < 1e-9);
+        assert!((deb.rhs - 25000.0).abs() < 1e-9);
+
+        // Constant terms apply per unit of batch; division by a number is scaling
+        let half = &compiled.lp_problem.constraints[1];
+        assert_eq!(half.name, "half_sodium_max");
+        assert!((half.coefficients[0] - 20.0).abs() < 1e-9);
+        assert!((half.coefficients[1] - 0.5).abs() < 1e-9);
+
+        let err = compiler.compile_formula("product").unwrap_err();
+        assert!(matches!(err, CompileError::NonLinearExpression { ref expr, .. } if expr == "sodium*potassium"));
+        let span = err.span().unwrap();
+        assert_eq!(&source[span.start..span.end], "sodium * potassium");
+    }
+
+    #[test]
+    fn test_derived_nutrients() {
+        let source = r#"
+            nutrient deb {
+                name "Dietary electrolyte balance"
+                = sodium * 434.8 + potassium * 255.7 - chloride * 282
+            }
+            nutrient sodium {}
+            nutrient potassium {}
+            nutrient chloride {}
+            nutrient lysine {}
+            nutrient dig_lysine { = lysine * 0.88 }
+            nutrient dig_lysine_half { = dig_lysine / 2 }
+
+            ingredient salt { cost 100 nutrients { sodium 39 chloride 60 } }
+            ingredient soy { cost 400 nutrients { potassium 2 lysine 2.8 } }
+            ingredient low_salt { cost 90 nutrients { salt.nutrients sodium 30 } }
+            ingredient corn { cost 150 nutrients { lysine 0.25 } }
+
+            formula broiler {
+                batch_size 100
+                nutrients {
+                    deb min 0
+                    dig_lysine min 1
+                }
+                ingredients { salt soy corn }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        let ingredients = &compiler.symbols.ingredients;
+        assert!((ingredients["salt"].nutrients["deb"] - (39.0 * 434.8 - 60.0 * 282.0)).abs() < 1e-9);
+        assert!((ingredients["soy"].nutrients["dig_lysine"] - 2.464).abs() < 1e-9);
+        assert!((ingredients["soy"].nutrients["dig_lysine_half"] - 1.232).abs() < 1e-9);
+        // Recomputed from the inheriting ingredient's own sodium
+        assert!((ingredients["low_salt"].nutrients["deb"] - (30.0 * 434.8 - 60.0 * 282.0)).abs() < 1e-9);
+        // Salt has no lysine, so no digestible lysine either
+        assert!(!ingredients["salt"].nutrients.contains_key("dig_lysine"));
+
+        let compiled = compiler.compile_formula("broiler").unwrap();
+        let dig_lysine = compiled.lp_problem.constraints.iter().find(|c| c.name == "dig_lysine_min").unwrap();
+        assert!((dig_lysine.coefficients[1] - 2.464).abs() < 1e-9);
+        assert!((dig_lysine.coefficients[2] - 0.22).abs() < 1e-9);
+
+        // Inputs an ingredient lacks are missing data for the derived nutrient
+        let strict = source.replace("batch_size 100", "batch_size 100 missing_data error");
+        let mut compiler = Compiler::new();
+        compiler.load(&Parser::parse(&strict).unwrap()).unwrap();
+        let Err(CompileError::MissingNutrientData { missing, .. }) = compiler.compile_formula("broiler") else {
+            panic!("Expected missing nutrient data");
+        };
+        let missing: Vec<(&str, &str)> = missing.iter().map(|m| (m.ingredient.as_str(), m.nutrient.as_str())).collect();
+        assert_eq!(
+            missing,
+            vec![
+                ("salt", "potassium"),
+                ("soy", "sodium"),
+                ("soy", "chloride"),
+                ("corn", "sodium"),
+                ("corn", "potassium"),
+                ("corn", "chloride"),
+                ("salt", "lysine"),
+            ]
+        );
+
+        let errors = [
+            ("lysine * 0.88", "dig_lysine_half * 2"),
+            ("lysine * 0.88", "lysine * sodium"),
+            ("lysine * 0.88", "lysin * 0.88"),
+            ("lysine 0.25", "dig_lysine 0.22"),
+        ];
+        let expected = [
+            "Circular reference detected: dig_lysine -> dig_lysine_half -> dig_lysine",
+            "Nutrient expression lysine*sodium is not linear. Only sums of nutrients times constants are allowed",
+            "Unknown nutrient: lysin. Did you mean lysine?",
+            "Ingredient corn gives a value for dig_lysine, which is derived from other nutrients",
+        ];
+        for ((from, to), expected) in errors.into_iter().zip(expected) {
+            let program = Parser::parse(&source.replace(from, to)).unwrap();
+            let error = Compiler::new().load(&program).unwrap_err();
+            assert_eq!(error.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_ratio_of_nutrient_sums() {
+        let source = r#"
+            nutrient lysine {}
+            nutrient methionine {}
+            nutrient cystine {}
+
+            ingredient corn {
+                cost 100
+                nutrients { lysine 0.25 methionine 0.18 cystine 0.2 }
+            }
+
+            ingredient dl_met {
+                cost 3000
+                nutrients { methionine 99 }
+            }
+
+            formula grower {
+                batch_size 100
+                nutrients {
+                    (methionine + cystine) / lysine min 0.75 max 2
+                    methionine / (lysine * 0.5) max 3 as met_ratio
+                }
+                ingredients { corn dl_met }
+            }
+        "#;
+
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+
+        let compiled = compiler.compile_formula("grower").unwrap();
+        let rows = &compiled.lp_problem.constraints;
+        assert_eq!(rows[0].name, "(methionine+cystine)/lysine_min");
+        assert!((rows[0].coefficients[0] - (0.38 - 0.75 * 0.25)).abs() < 1e-9);
+        assert!((rows[0].coefficients[1] - 99.0).abs() < 1e-9);
+        assert_eq!(rows[1].name, "(methionine+cystine)/lysine_max");
+        assert!((rows[1].coefficients[0] - (0.38 - 2.0 * 0.25)).abs() < 1e-9);
+        assert_eq!(rows[2].name, "met_ratio_max");
+        assert!((rows[2].coefficients[0] - (0.18 - 3.0 * 0.125)).abs() < 1e-9);
+        assert_eq!(rows[2].op, ConstraintOp::Le);
+        assert_eq!(rows[2].rhs, 0.0);
+    }
+
+    /// Write `files` into a fresh directory under the system temp dir
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("formulang-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, source) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_import_namespaces() {
+        let dir = write_files("namespaces", &[
+            ("nutrients.fm", r#"
+                nutrient protein {}
+                nutrient energy {}
+            "#),
+            ("feeds/grains.fm", r#"
+                import ../nutrients.fm { protein }
+
+                ingredient corn {
+                    cost 100
+                    nutrients {
+                        protein 8
+                        nutrients.energy 3350
+                    }
+                }
+            "#),
+            ("main.fm", r#"
+                import ./nutrients.fm as n
+                import ./feeds/grains.fm as g
+
+                ingredient corn {
+                    cost 120
+                    nutrients { n.protein 9 }
+                }
+
+                ingredient soy {
+                    cost 300
+                    nutrients { n.protein 44 }
+                }
+
+                formula starter {
+                    batch_size 100
+                    nutrients { n.protein min 20 }
+                    ingredients {
+                        corn
+                        g.corn
+                        soy
+                    }
+                }
+            "#),
+        ]);
+
+        let mut compiler = Compiler::new();
+        compiler.load_file(dir.join("main.fm")).unwrap();
+
+        // The imported corn clashes with the local one, so it is keyed by module
+        let imported = &compiler.symbols.ingredients["grains.corn"];
+        assert_eq!(imported.cost, 100.0);
+        assert_eq!(imported.nutrients.get("energy"), Some(&3350.0));
+        assert_eq!(compiler.symbols.ingredients["corn"].cost, 120.0);
+
+        let compiled = compiler.compile_formula("starter").unwrap();
+        assert_eq!(compiled.ingredients, vec!["corn", "grains.corn", "soy"]);
+        assert_eq!(compiled.lp_problem.constraints[0].name, "protein_min");
+        assert_eq!(compiled.lp_problem.constraints[0].coefficients, vec![9.0, 8.0, 44.0]);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = write_files("import-errors", &[
+            ("nutrients.fm", "nutrient protein {}"),
+            ("selection.fm", "import ./nutrients.fm { protein, fiber }"),
+            ("unqualified.fm", r#"
+                import ./nutrients.fm
+
+                ingredient corn {
+                    cost 100
+                    nutrients { protein 8 }
+                }
+            "#),
+        ]);
+
+        let result = Compiler::new().load_file(dir.join("selection.fm"));
+        assert!(matches!(result, Err(CompileError::UnknownImport(_, name)) if name == "fiber"));
+
+        // Without a selection, only the qualified name is in scope
+        let result = Compiler::new().load_file(dir.join("unqualified.fm"));
+        assert!(matches!(result, Err(CompileError::NotInScope(name)) if name == "protein"));
+    }
 }
@@ -0,0 +1,853 @@
+//! Canonical layout for .fm source
+//!
+//! The formatter prints from the AST, so aliases come out in their long form
+//! (`nutrients`, `ingredients`, `batch_size`, `description`) and spacing is
+//! normalized. The AST has no comments, so each comment token is first
+//! attached to a node: a comment on its own line goes above the next node,
+//! a comment after code goes at the end of that node's line.
+
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::compiler::canonical_property;
+use crate::lexer::{Lexer, Span, Token, TokenKind};
+use crate::parser::{ParseError, Parser};
+
+const INDENT: &str = "  ";
+
+/// Format a source file. Fails if the source doesn't parse.
+pub fn format_source(source: &str) -> Result<String, ParseError> {
+    let program = Parser::parse(source)?;
+    let tokens = Lexer::tokenize(source);
+    let mut formatter = Formatter::new(source, &tokens, &program);
+    formatter.program(&program);
+    Ok(formatter.finish())
+}
+
+struct Comment {
+    span: Span,
+    text: String,
+}
+
+#[derive(Default)]
+struct Attached {
+    /// Comments on their own lines above the node
+    leading: Vec<Comment>,
+    /// Comments after the node on its last line, or inside it
+    trailing: Vec<String>,
+}
+
+impl Attached {
+    fn is_empty(&self) -> bool {
+        self.leading.is_empty() && self.trailing.is_empty()
+    }
+
+    fn trailing(&self) -> Option<String> {
+        (!self.trailing.is_empty()).then(|| self.trailing.join(" "))
+    }
+}
+
+/// Where the braces of an ingredient, nutrient, formula or plan are
+struct Layout {
+    /// From `template` or the item keyword through the opening brace
+    header: Span,
+    /// The closing brace
+    closer: Span,
+    /// Block name with its opening (`nuts {`) and closing brace
+    blocks: Vec<(&'static str, Span, Span)>,
+}
+
+/// One output line; `text` is empty for blank lines
+struct Line {
+    indent: usize,
+    text: String,
+    comment: Option<String>,
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    /// Tokens other than newlines and comments
+    code: Vec<&'a Token>,
+    /// Attached comments by the start of their node
+    comments: HashMap<usize, Attached>,
+    /// Comments after the last node
+    dangling: Vec<Comment>,
+    lines: Vec<Line>,
+    indent: usize,
+    /// Source end of the last line written in the current body, to keep
+    /// blank lines the author left between nodes
+    last_end: Option<usize>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, tokens: &'a [Token], program: &Program) -> Self {
+        let code = tokens
+            .iter()
+            .filter(|t| !matches!(t.kind, TokenKind::Newline | TokenKind::Comment | TokenKind::Eof))
+            .collect();
+        let mut formatter = Self {
+            source,
+            code,
+            comments: HashMap::new(),
+            dangling: Vec::new(),
+            lines: Vec::new(),
+            indent: 0,
+            last_end: None,
+        };
+
+        let anchors = formatter.anchors(program);
+        for (i, token) in tokens.iter().enumerate() {
+            if token.kind != TokenKind::Comment {
+                continue;
+            }
+            let comment = Comment { span: token.span, text: token.text.trim_end().to_string() };
+
+            // After code on the same line: belongs to the node that code is part of
+            let own_line = i == 0 || tokens[i - 1].kind == TokenKind::Newline;
+            let before = anchors.partition_point(|a| a.start < token.span.start);
+            if !own_line && before > 0 {
+                let anchor = anchors[before - 1];
+                if anchor.end >= token.span.start || !source[anchor.end..token.span.start].contains('\n') {
+                    formatter.comments.entry(anchor.start).or_default().trailing.push(comment.text);
+                    continue;
+                }
+            }
+
+            let next = anchors.partition_point(|a| a.start < token.span.end);
+            match anchors.get(next) {
+                Some(anchor) => formatter.comments.entry(anchor.start).or_default().leading.push(comment),
+                None => formatter.dangling.push(comment),
+            }
+        }
+        formatter
+    }
+
+    /// Spans of everything that is written on a line of its own, by start
+    fn anchors(&self, program: &Program) -> Vec<Span> {
+        let mut anchors = Vec::new();
+        for item in &program.items {
+            let (span, children): (Span, Vec<Span>) = match item {
+                Item::Import(import) => {
+                    anchors.push(import.span);
+                    continue;
+                }
+                Item::Const(c) => {
+                    anchors.push(c.span);
+                    continue;
+                }
+                Item::Formula(f) if is_one_line(f) => {
+                    anchors.push(f.span);
+                    continue;
+                }
+                Item::Nutrient(n) => (
+                    n.span,
+                    n.properties.iter().map(|p| p.span).chain(n.derived.iter().map(|d| d.span)).collect(),
+                ),
+                Item::Ingredient(i) => (
+                    i.span,
+                    i.properties.iter().map(|p| p.span).chain(i.nutrients.iter().map(|v| v.span)).collect(),
+                ),
+                Item::Formula(f) => (
+                    f.span,
+                    f.properties
+                        .iter()
+                        .map(|p| p.span)
+                        .chain(f.nutrients.iter().map(|c| c.span))
+                        .chain(f.ingredients.iter().map(|c| c.span))
+                        .collect(),
+                ),
+                Item::Plan(p) => (
+                    p.span,
+                    p.properties
+                        .iter()
+                        .map(|p| p.span)
+                        .chain(p.formulas.iter().chain(&p.available).map(|e| e.span))
+                        .collect(),
+                ),
+            };
+            let layout = self.layout(span);
+            anchors.extend([layout.header, layout.closer]);
+            anchors.extend(layout.blocks.iter().flat_map(|&(_, open, close)| [open, close]));
+            anchors.extend(children);
+        }
+        anchors.sort_by_key(|a| a.start);
+        anchors
+    }
+
+    fn layout(&self, span: Span) -> Layout {
+        let first = self.code.partition_point(|t| t.span.start < span.start);
+        let start = match first.checked_sub(1).map(|i| self.code[i]) {
+            Some(t) if t.kind == TokenKind::Template => t.span.start,
+            _ => span.start,
+        };
+        let last = self.code.partition_point(|t| t.span.start < span.end - 1);
+        let brace = (first..last).find(|&i| self.code[i].kind == TokenKind::LBrace).unwrap_or(last);
+
+        // Blocks are the only braces directly inside an item
+        let mut blocks = Vec::new();
+        let mut i = brace + 1;
+        while i + 1 < last {
+            let (keyword, open) = (self.code[i], self.code[i + 1]);
+            let name = match keyword.text.as_str() {
+                "nutrients" | "nuts" => "nutrients",
+                "ingredients" | "ings" => "ingredients",
+                "formulas" => "formulas",
+                "available" => "available",
+                _ => "",
+            };
+            if keyword.kind != TokenKind::Ident || name.is_empty() || open.kind != TokenKind::LBrace {
+                i += 1;
+                continue;
+            }
+            let mut depth = 0;
+            let mut close = i + 1;
+            while close < last {
+                match self.code[close].kind {
+                    TokenKind::LBrace => depth += 1,
+                    TokenKind::RBrace => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                close += 1;
+            }
+            let close_span = self.code.get(close).map(|t| t.span).unwrap_or(Span::new(span.end, span.end));
+            blocks.push((name, Span::new(keyword.span.start, open.span.end), close_span));
+            i = close + 1;
+        }
+
+        Layout {
+            header: Span::new(start, self.code.get(brace).map(|t| t.span.end).unwrap_or(span.end)),
+            closer: Span::new(span.end - 1, span.end),
+            blocks,
+        }
+    }
+
+    fn attached(&mut self, anchor: Span) -> Attached {
+        self.comments.remove(&anchor.start).unwrap_or_default()
+    }
+
+    fn has_comments(&self, anchor: Span) -> bool {
+        self.comments.get(&anchor.start).is_some_and(|a| !a.is_empty())
+    }
+
+    fn program(&mut self, program: &Program) {
+        let mut previous: Option<&Item> = None;
+        for item in &program.items {
+            // Imports, constants and one-line template instances stay grouped;
+            // everything else is separated by a blank line
+            if let Some(previous) = previous
+                && !(matches!(previous, Item::Import(_)) && matches!(item, Item::Import(_)))
+                && !(matches!(previous, Item::Const(_)) && matches!(item, Item::Const(_)))
+                && !(matches!(previous, Item::Formula(f) if is_one_line(f))
+                    && matches!(item, Item::Formula(f) if is_one_line(f)))
+            {
+                self.blank();
+            }
+            match item {
+                Item::Import(import) => self.node(import.span, import_text(import)),
+                Item::Const(c) => self.node(c.span, format!("const {} {}", c.name, expr_text(&c.value))),
+                Item::Nutrient(nutrient) => self.nutrient(nutrient),
+                Item::Ingredient(ingredient) => self.ingredient(ingredient),
+                Item::Formula(formula) => self.formula(formula),
+                Item::Plan(plan) => self.plan(plan),
+            }
+            previous = Some(item);
+        }
+
+        for comment in std::mem::take(&mut self.dangling) {
+            self.line(comment.span, comment.text, None);
+        }
+    }
+
+    fn nutrient(&mut self, nutrient: &Nutrient) {
+        let layout = self.layout(nutrient.span);
+        let header = format!("nutrient {}", nutrient.name);
+        if nutrient.properties.is_empty() && nutrient.derived.is_none() && self.is_bare(&layout) {
+            return self.empty_item(&layout, header);
+        }
+        self.open(layout.header, header);
+        self.properties(&nutrient.properties);
+        if let Some(derived) = &nutrient.derived {
+            self.node(derived.span, format!("= {}", expr_text(&derived.expr)));
+        }
+        self.close(layout.closer);
+    }
+
+    fn ingredient(&mut self, ingredient: &Ingredient) {
+        let layout = self.layout(ingredient.span);
+        let header = format!("{}ingredient {}", template_prefix(ingredient.is_template), ingredient.name);
+        if ingredient.properties.is_empty() && ingredient.nutrients.is_empty() && self.is_bare(&layout) {
+            return self.empty_item(&layout, header);
+        }
+        self.open(layout.header, header);
+        self.properties(&ingredient.properties);
+        let values = ingredient.nutrients.iter().map(|v| (v.span, v.basis, nutrient_value_text(v)));
+        let values = self.with_basis(values);
+        self.block(&layout, "nutrients", values);
+        self.close(layout.closer);
+    }
+
+    fn formula(&mut self, formula: &Formula) {
+        let mut header = format!("{}formula {}", template_prefix(formula.is_template), formula.name);
+        if !formula.params.is_empty() {
+            header.push_str(&format!("({})", formula.params.join(", ")));
+        }
+        if let Some(instance) = &formula.instance {
+            let args: Vec<String> = instance.args.iter().map(bound_text).collect();
+            header.push_str(&format!(" = {}({})", reference_text(&instance.template), args.join(", ")));
+            if is_one_line(formula) {
+                return self.node(formula.span, header);
+            }
+        }
+
+        let layout = self.layout(formula.span);
+        if formula.properties.is_empty()
+            && formula.nutrients.is_empty()
+            && formula.ingredients.is_empty()
+            && self.is_bare(&layout)
+        {
+            return self.empty_item(&layout, header);
+        }
+        self.open(layout.header, header);
+        self.properties(&formula.properties);
+        let nutrients = formula
+            .nutrients
+            .iter()
+            .map(|c| (c.span, c.basis, constraint_text(&c.expr, &c.bounds, c.soft, false, &c.alias)));
+        let nutrients = self.with_basis(nutrients);
+        self.block(&layout, "nutrients", nutrients);
+        let ingredients = formula
+            .ingredients
+            .iter()
+            .map(|c| (c.span, constraint_text(&c.expr, &c.bounds, None, c.or_zero, &c.alias)))
+            .collect();
+        self.block(&layout, "ingredients", ingredients);
+        self.close(layout.closer);
+    }
+
+    fn plan(&mut self, plan: &Plan) {
+        let layout = self.layout(plan.span);
+        let header = format!("plan {}", plan.name);
+        if plan.properties.is_empty() && plan.formulas.is_empty() && plan.available.is_empty() && self.is_bare(&layout) {
+            return self.empty_item(&layout, header);
+        }
+        self.open(layout.header, header);
+        self.properties(&plan.properties);
+        let formulas = plan.formulas.iter().map(|e| (e.span, plan_entry_text(e))).collect();
+        self.block(&layout, "formulas", formulas);
+        let available = plan.available.iter().map(|e| (e.span, plan_entry_text(e))).collect();
+        self.block(&layout, "available", available);
+        self.close(layout.closer);
+    }
+
+    fn properties(&mut self, properties: &[Property]) {
+        for property in properties {
+            self.node(property.span, property_text(property));
+        }
+    }
+
+    /// Whether an item with no children can be written as `name {}`
+    fn is_bare(&self, layout: &Layout) -> bool {
+        self.comments.get(&layout.closer.start).is_none_or(|a| a.leading.is_empty())
+            && layout.blocks.iter().all(|&(_, open, close)| !self.has_comments(open) && !self.has_comments(close))
+    }
+
+    fn empty_item(&mut self, layout: &Layout, header: String) {
+        let mut attached = self.attached(layout.header);
+        attached.trailing.extend(self.attached(layout.closer).trailing);
+        for comment in std::mem::take(&mut attached.leading) {
+            self.line(comment.span, comment.text, None);
+        }
+        self.line(Span::new(layout.header.start, layout.closer.end), format!("{header} {{}}"), attached.trailing());
+    }
+
+    /// Nutrient entries with a `basis` marker wherever the basis changes.
+    /// Markers have an empty span at their `basis` keyword.
+    fn with_basis(&self, entries: impl Iterator<Item = (Span, Basis, String)>) -> Vec<(Span, String)> {
+        let mut children = Vec::new();
+        let mut current = Basis::AsFed;
+        for (span, basis, text) in entries {
+            if basis != current {
+                let before = self.code.partition_point(|t| t.span.start < span.start);
+                let start = self.code[..before]
+                    .iter()
+                    .rfind(|t| t.kind == TokenKind::Basis)
+                    .map_or(span.start, |t| t.span.start);
+                children.push((Span::new(start, start), basis_text(basis).to_string()));
+                current = basis;
+            }
+            children.push((span, text));
+        }
+        children
+    }
+
+    /// A block of an item, such as `nutrients`. Several blocks with the same
+    /// name are merged into one, as the compiler would.
+    fn block(&mut self, layout: &Layout, name: &str, children: Vec<(Span, String)>) {
+        let blocks: Vec<(Span, Span)> =
+            layout.blocks.iter().filter(|b| b.0 == name).map(|&(_, open, close)| (open, close)).collect();
+        if children.is_empty() && blocks.iter().all(|&(open, close)| !self.has_comments(open) && !self.has_comments(close)) {
+            return;
+        }
+        if self.last_end.is_some() {
+            self.blank();
+        }
+
+        let mut opener = Attached::default();
+        let mut closer = Attached::default();
+        for &(open, close) in &blocks {
+            let attached = self.attached(open);
+            opener.leading.extend(attached.leading);
+            opener.trailing.extend(attached.trailing);
+            let attached = self.attached(close);
+            closer.leading.extend(attached.leading);
+            closer.trailing.extend(attached.trailing);
+        }
+        let open = blocks.first().map(|b| b.0).unwrap_or(layout.header);
+        let close = blocks.last().map(|b| b.1).unwrap_or(layout.closer);
+
+        for comment in std::mem::take(&mut opener.leading) {
+            self.line(comment.span, comment.text, None);
+        }
+        self.line(open, format!("{name} {{"), opener.trailing());
+        self.indent += 1;
+        self.last_end = None;
+        let mut children = children.into_iter().peekable();
+        while let Some((span, text)) = children.next() {
+            match children.peek() {
+                // A marker: comments above the node it applies to go above it
+                Some((next, _)) if span.start == span.end => {
+                    let leading = self.comments.get_mut(&next.start).map(|a| std::mem::take(&mut a.leading));
+                    for comment in leading.unwrap_or_default() {
+                        self.line(comment.span, comment.text, None);
+                    }
+                    self.line(span, text, None);
+                }
+                _ => self.node(span, text),
+            }
+        }
+        self.end_body(close, closer);
+    }
+
+    fn open(&mut self, header: Span, text: String) {
+        self.node(header, format!("{text} {{"));
+        self.indent += 1;
+        self.last_end = None;
+    }
+
+    fn close(&mut self, closer: Span) {
+        let attached = self.attached(closer);
+        self.end_body(closer, attached);
+    }
+
+    /// Comments left at the end of a body, then its closing brace
+    fn end_body(&mut self, closer: Span, attached: Attached) {
+        for comment in attached.leading.iter() {
+            self.line(comment.span, comment.text.clone(), None);
+        }
+        self.indent -= 1;
+        self.lines.push(Line { indent: self.indent, text: "}".to_string(), comment: attached.trailing() });
+        self.last_end = Some(closer.end);
+    }
+
+    /// Write a node with its comments
+    fn node(&mut self, anchor: Span, text: String) {
+        let attached = self.attached(anchor);
+        for comment in attached.leading.iter() {
+            self.line(comment.span, comment.text.clone(), None);
+        }
+        self.line(anchor, text, attached.trailing());
+    }
+
+    fn line(&mut self, span: Span, text: String, comment: Option<String>) {
+        if let Some(end) = self.last_end
+            && span.start > end
+            && self.source[end..span.start].matches('\n').count() > 1
+        {
+            self.blank();
+        }
+        self.lines.push(Line { indent: self.indent, text, comment });
+        self.last_end = Some(span.end);
+    }
+
+    fn blank(&mut self) {
+        if self.lines.last().is_some_and(|l| !l.text.is_empty()) {
+            self.lines.push(Line { indent: 0, text: String::new(), comment: None });
+        }
+    }
+
+    fn finish(self) -> String {
+        let lines = self.lines;
+        let mut out = String::new();
+        let mut i = 0;
+        while i < lines.len() {
+            // Trailing comments on consecutive lines share a column
+            let mut end = i + 1;
+            if lines[i].comment.is_some() {
+                while end < lines.len() && lines[end].comment.is_some() && lines[end].indent == lines[i].indent {
+                    end += 1;
+                }
+            }
+            let width = lines[i..end].iter().map(|l| l.text.chars().count()).max().unwrap_or(0);
+
+            for line in &lines[i..end] {
+                if !line.text.is_empty() {
+                    out.push_str(&INDENT.repeat(line.indent));
+                    out.push_str(&line.text);
+                }
+                if let Some(comment) = &line.comment {
+                    let padding = width - line.text.chars().count() + 2;
+                    out.push_str(&" ".repeat(padding));
+                    out.push_str(comment);
+                }
+                out.push('\n');
+            }
+            i = end;
+        }
+        out
+    }
+}
+
+/// A template instance written without a body: `formula starter = broiler(22, 50%)`
+fn is_one_line(formula: &Formula) -> bool {
+    formula.instance.as_ref().is_some_and(|instance| instance.span.end == formula.span.end)
+}
+
+fn template_prefix(is_template: bool) -> &'static str {
+    if is_template { "template " } else { "" }
+}
+
+fn import_text(import: &Import) -> String {
+    let mut text = format!("import {}", import.path);
+    if let Some(alias) = &import.alias {
+        text.push_str(&format!(" as {alias}"));
+    }
+    match &import.selections {
+        Some(ImportSelections::All) => text.push_str(" { * }"),
+        Some(ImportSelections::Named(names)) if names.is_empty() => text.push_str(" {}"),
+        Some(ImportSelections::Named(names)) => text.push_str(&format!(" {{ {} }}", names.join(", "))),
+        None => {}
+    }
+    text
+}
+
+fn property_text(property: &Property) -> String {
+    let value = match &property.value {
+        PropertyValue::String(s) => format!("\"{s}\""),
+        PropertyValue::Number(n) => n.to_string(),
+        PropertyValue::Ident(name) => name.clone(),
+        PropertyValue::Expr(expr) => expr_text(expr),
+    };
+    format!("{} {}", canonical_property(&property.name), value)
+}
+
+fn basis_text(basis: Basis) -> &'static str {
+    match basis {
+        Basis::AsFed => "basis as_fed",
+        Basis::DryMatter => "basis dry_matter",
+    }
+}
+
+fn nutrient_value_text(value: &NutrientValue) -> String {
+    let text = match (value.value, &value.constant) {
+        (Some(n), _) => format!("{} {}", reference_text(&value.nutrient), n),
+        (None, Some(constant)) => format!("{} {}", reference_text(&value.nutrient), reference_text(constant)),
+        (None, None) => reference_text(&value.nutrient),
+    };
+    match &value.unit {
+        Some(unit) => format!("{text} \"{unit}\""),
+        None => text,
+    }
+}
+
+fn plan_entry_text(entry: &PlanEntry) -> String {
+    format!("{} {}", reference_text(&entry.name), bound_text(&entry.quantity))
+}
+
+fn constraint_text(expr: &Expr, bounds: &Bounds, soft: Option<f64>, or_zero: bool, alias: &Option<String>) -> String {
+    let mut text = expr_text(expr);
+    for (keyword, bound) in [("min", &bounds.min), ("max", &bounds.max)] {
+        if let Some(bound) = bound {
+            text.push_str(&format!(" {keyword} {}", bound_text(bound)));
+        }
+    }
+    if let Some(penalty) = soft {
+        text.push_str(&format!(" soft {penalty}"));
+    }
+    if or_zero {
+        text.push_str(" or 0");
+    }
+    if let Some(alias) = alias {
+        text.push_str(&format!(" as {alias}"));
+    }
+    text
+}
+
+/// A bound or template argument: `18`, `50%` or `target`
+pub(crate) fn bound_text(bound: &BoundValue) -> String {
+    let percent = if bound.is_percent { "%" } else { "" };
+    let value = match &bound.constant {
+        Some(constant) => reference_text(constant),
+        None => bound.value.to_string(),
+    };
+    match &bound.unit {
+        Some(unit) => format!("{value}{percent} \"{unit}\""),
+        None => format!("{value}{percent}"),
+    }
+}
+
+pub(crate) fn expr_text(expr: &Expr) -> String {
+    match expr {
+        Expr::Number(n) => n.to_string(),
+        Expr::Reference(r) => reference_text(r),
+        Expr::BinaryOp { left, op, right } => format!("{} {} {}", expr_text(left), op, expr_text(right)),
+        Expr::Paren(inner) => format!("({})", expr_text(inner)),
+    }
+}
+
+fn reference_text(reference: &Reference) -> String {
+    reference
+        .parts
+        .iter()
+        .map(|part| match part {
+            ReferencePart::Ident(name) => name.clone(),
+            ReferencePart::Selection(names) => format!("[{}]", names.join(", ")),
+            ReferencePart::Min => "min".to_string(),
+            ReferencePart::Max => "max".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_canonical_layout() {
+        let source = r#"import ./nutrients.fm { * }
+import ./feeds.fm as feeds
+
+
+nutrient protein { name "Crude Protein" unit "%" }
+nutrient ash {}
+ingredient corn {
+    cost 150
+  nuts { protein 8.5 ash 1.20 }
+}
+template formula base { nuts { fiber max 7 } }
+formula starter {
+  nuts { base.nutrients
+    (protein+ash)/energy min 0.1 soft 500 as ratio }
+  batch 1000
+  ings { corn min 10% max 50.0% or 0
+  feeds.[soybean_meal, fish_meal] max 30% }
+}
+"#;
+        let expected = r#"import ./nutrients.fm { * }
+import ./feeds.fm as feeds
+
+nutrient protein {
+  name "Crude Protein"
+  unit "%"
+}
+
+nutrient ash {}
+
+ingredient corn {
+  cost 150
+
+  nutrients {
+    protein 8.5
+    ash 1.2
+  }
+}
+
+template formula base {
+  nutrients {
+    fiber max 7
+  }
+}
+
+formula starter {
+  batch_size 1000
+
+  nutrients {
+    base.nutrients
+    (protein + ash) / energy min 0.1 soft 500 as ratio
+  }
+
+  ingredients {
+    corn min 10% max 50% or 0
+    feeds.[soybean_meal, fish_meal] max 30%
+  }
+}
+"#;
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_keeps_comments() {
+        let source = r#"// Feeds
+ingredient corn { // yellow dent
+  cost 150 // per tonne
+  nutrients {
+    protein 8.5 // crude
+
+    /* dry basis */
+    fiber 2.2
+    // more to come
+  }
+} // corn
+
+// end
+"#;
+        let expected = r#"// Feeds
+ingredient corn {  // yellow dent
+  cost 150  // per tonne
+
+  nutrients {
+    protein 8.5  // crude
+
+    /* dry basis */
+    fiber 2.2
+    // more to come
+  }
+}  // corn
+
+// end
+"#;
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_constants() {
+        let source = "const mixer_size 2000\nconst ceiling   target+4\nformula f { batch mixer_size nuts { protein min target } }\n";
+        let expected = r#"const mixer_size 2000
+const ceiling target + 4
+
+formula f {
+  batch_size mixer_size
+
+  nutrients {
+    protein min target
+  }
+}
+"#;
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_template_instances() {
+        let source = "template formula broiler( p,max_corn ) { batch 1000 ings { corn max max_corn } }\nformula starter=broiler(22,50%)\nformula finisher = broiler( 18 , 70% )\nformula grower = broiler(20, 60%) { name \"Grower\" }\n";
+        let expected = r#"template formula broiler(p, max_corn) {
+  batch_size 1000
+
+  ingredients {
+    corn max max_corn
+  }
+}
+
+formula starter = broiler(22, 50%)
+formula finisher = broiler(18, 70%)
+
+formula grower = broiler(20, 60%) {
+  name "Grower"
+}
+"#;
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_plans() {
+        let source = "plan week { name \"Week 12\" available { corn 15000 } formulas { starter 20000 // Monday\n grower  grower_tonnage } }\n";
+        let expected = r#"plan week {
+  name "Week 12"
+
+  formulas {
+    starter 20000  // Monday
+    grower grower_tonnage
+  }
+
+  available {
+    corn 15000
+  }
+}
+"#;
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_basis() {
+        let source = "ingredient fish_meal { cost 900 moisture 8 nuts {\n  // Lab, dry matter\n  basis dm protein 68\n  basis as_fed\n\n  fat 9 } }\n";
+        let expected = r#"ingredient fish_meal {
+  cost 900
+  moisture 8
+
+  nutrients {
+    // Lab, dry matter
+    basis dry_matter
+    protein 68
+    basis as_fed
+
+    fat 9
+  }
+}
+"#;
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_units() {
+        let source = "ingredient wheat { cost 200 nuts { energy 13.8 \"MJ/kg\" zinc 30 } }\nformula broiler { batch 1000 nuts { energy min 12.5   \"MJ/kg\" } ings { wheat } }\n";
+        let expected = r#"ingredient wheat {
+  cost 200
+
+  nutrients {
+    energy 13.8 "MJ/kg"
+    zinc 30
+  }
+}
+
+formula broiler {
+  batch_size 1000
+
+  nutrients {
+    energy min 12.5 "MJ/kg"
+  }
+
+  ingredients {
+    wheat
+  }
+}
+"#;
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_derived_nutrient() {
+        let source = "nutrient dig_lysine { = lysine*0.88 // SID\n unit \"%\" }\nnutrient deb {\n  // mEq/kg\n  = sodium * 434.8 + potassium * 255.7 - chloride * 282 }\n";
+        let expected = r#"nutrient dig_lysine {
+  unit "%"
+  = lysine * 0.88  // SID
+}
+
+nutrient deb {
+  // mEq/kg
+  = sodium * 434.8 + potassium * 255.7 - chloride * 282
+}
+"#;
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+}
@@ -0,0 +1,776 @@
+//! Editor features shared by the WASM bindings and the language server
+//!
+//! Positions are byte offsets into the source. Callers convert them to
+//! whatever their editor expects.
+
+use std::collections::HashSet;
+
+use crate::analysis::ImportedNames;
+use crate::ast::*;
+use crate::formatter::expr_text;
+use crate::lexer::{Lexer, TokenKind};
+use crate::parser::Parser;
+
+/// Classify every token for syntax highlighting
+pub fn compute_semantic_tokens(source: &str) -> Vec<SemanticToken> {
+    let raw_tokens = Lexer::tokenize(source);
+    let mut tokens: Vec<SemanticToken> = Vec::new();
+    let mut prev_was_as = false;
+
+    for i in 0..raw_tokens.len() {
+        let t = &raw_tokens[i];
+
+        // Look ahead to find next non-whitespace token
+        let next_significant = raw_tokens[i + 1..]
+            .iter()
+            .find(|t| !matches!(t.kind, TokenKind::Newline | TokenKind::Comment));
+
+        let token_type = match t.kind {
+            TokenKind::Nutrient
+            | TokenKind::Ingredient
+            | TokenKind::Formula
+            | TokenKind::Import
+            | TokenKind::Template
+            | TokenKind::Const
+            | TokenKind::Plan => "keyword",
+            TokenKind::Min | TokenKind::Max | TokenKind::As | TokenKind::Or | TokenKind::Soft | TokenKind::Basis => "keyword",
+            TokenKind::Ident => {
+                if prev_was_as {
+                    "type" // Highlight alias names distinctly (teal)
+                } else if matches!(next_significant.map(|t| t.kind), Some(TokenKind::Dot | TokenKind::LParen)) {
+                    // Base identifier before dot (e.g., `someformula` in `someformula.ingredients`)
+                    // or a template name (e.g., `broiler` in `broiler(22, 50%)`)
+                    "class"
+                } else {
+                    "variable"
+                }
+            }
+            TokenKind::Number => "number",
+            TokenKind::String => "string",
+            TokenKind::Comment => "comment",
+            TokenKind::Colon | TokenKind::Comma => "delimiter",
+            TokenKind::LBrace | TokenKind::RBrace => "delimiter",
+            TokenKind::LBracket | TokenKind::RBracket => "delimiter",
+            TokenKind::LParen | TokenKind::RParen => "delimiter",
+            TokenKind::Plus | TokenKind::Minus | TokenKind::Star | TokenKind::Slash => {
+                "operator"
+            }
+            TokenKind::Percent | TokenKind::Equals => "operator",
+            TokenKind::Dot => "delimiter",
+            TokenKind::Newline => "whitespace",
+            TokenKind::Error | TokenKind::Eof => "error",
+        };
+
+        // Track if we just saw `as` keyword (skip whitespace/newlines)
+        prev_was_as = match t.kind {
+            TokenKind::As => true,
+            TokenKind::Newline | TokenKind::Comment => prev_was_as, // Keep state
+            _ => false,
+        };
+
+        tokens.push(SemanticToken {
+            start: t.span.start,
+            end: t.span.end,
+            token_type: token_type.to_string(),
+        });
+    }
+
+    tokens
+}
+
+/// A token and its highlighting class (`keyword`, `variable`, `comment`, ...)
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone)]
+pub struct SemanticToken {
+    pub start: usize,
+    pub end: usize,
+    pub token_type: String,
+}
+
+/// A completion item. `kind` is `keyword`, `property` or `variable`, and
+/// `insert_text` may hold snippet placeholders like `${1:name}`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub label: String,
+    pub kind: String,
+    pub detail: Option<String>,
+    pub insert_text: String,
+}
+
+/// Context for completions based on cursor position
+#[derive(Debug)]
+enum CompletionContext {
+    /// At top-level (suggest nutrient, ingredient, formula, import)
+    TopLevel,
+    /// After a formula/ingredient name and dot (e.g., "base." or "corn.")
+    AfterNameDot(String), // name before dot
+    /// After name.block. (e.g., "base.nutrients." or "corn.nutrients.")
+    AfterBlockDot(String, String), // (name, block_type)
+    /// After name.block.item. (e.g., "base.nutrients.protein.")
+    AfterItemDot,
+    /// Inside a formula's nutrients block
+    InFormulaNutrientsBlock,
+    /// Inside a formula's ingredients block
+    InFormulaIngredientsBlock,
+    /// Inside an ingredient's nutrients block
+    InIngredientNutrientsBlock,
+    /// General context (suggest all symbols)
+    General,
+}
+
+/// Result of context detection - includes typed prefix for filtering
+#[derive(Debug)]
+struct CompletionInfo {
+    context: CompletionContext,
+    /// The partial text typed after the last delimiter (dot or whitespace)
+    typed_prefix: String,
+}
+
+/// Parse the text before cursor to determine completion context and typed prefix
+fn get_completion_info(source: &str, position: usize) -> CompletionInfo {
+    let prefix = &source[..position.min(source.len())];
+
+    // Find what block we're in by looking for unmatched braces
+    let mut brace_depth = 0;
+    let mut last_block_type: Option<&str> = None;
+    let mut in_formula = false;
+    let mut in_ingredient = false;
+
+    for (i, c) in prefix.char_indices() {
+        if c == '{' {
+            brace_depth += 1;
+            // Check what keyword preceded this brace
+            let before = prefix[..i].trim_end();
+            if before.ends_with("nutrients") || before.ends_with("nuts") {
+                if brace_depth >= 2 {
+                    if in_formula {
+                        last_block_type = Some("formula_nutrients");
+                    } else if in_ingredient {
+                        last_block_type = Some("ingredient_nutrients");
+                    }
+                }
+            } else if before.ends_with("ingredients") || before.ends_with("ings") {
+                if brace_depth >= 2 && in_formula {
+                    last_block_type = Some("formula_ingredients");
+                }
+            } else if before.split_whitespace().last() == Some("formula") ||
+                     before.split_whitespace().rev().nth(1) == Some("formula") {
+                in_formula = true;
+                in_ingredient = false;
+            } else if before.split_whitespace().last() == Some("ingredient") ||
+                     before.split_whitespace().rev().nth(1) == Some("ingredient") {
+                in_ingredient = true;
+                in_formula = false;
+            }
+        } else if c == '}' {
+            brace_depth -= 1;
+            if brace_depth < 2 {
+                last_block_type = None;
+            }
+            if brace_depth < 1 {
+                in_formula = false;
+                in_ingredient = false;
+            }
+        }
+    }
+
+    // Get the current line
+    let line_start = prefix.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = prefix[line_start..].trim_start();
+
+    // Check if we're in a dot-completion context
+    if let Some(dot_pos) = line.rfind('.') {
+        let before_dot = &line[..dot_pos];
+        let after_dot = &line[dot_pos + 1..];
+
+        // Parse the reference parts before the last dot
+        let parts: Vec<&str> = before_dot.split('.').collect();
+
+        match parts.len() {
+            1 => {
+                // name.prefix -> completing after first dot
+                let name = parts[0].trim();
+                if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return CompletionInfo {
+                        context: CompletionContext::AfterNameDot(name.to_string()),
+                        typed_prefix: after_dot.to_string(),
+                    };
+                }
+            }
+            2 => {
+                // name.block.prefix -> completing items in block
+                let name = parts[0].trim();
+                let block_name = parts[1].trim();
+                if !name.is_empty() && !block_name.is_empty() {
+                    let block_type = match block_name {
+                        "nutrients" | "nuts" => "nutrients",
+                        "ingredients" | "ings" => "ingredients",
+                        _ => block_name,
+                    };
+                    return CompletionInfo {
+                        context: CompletionContext::AfterBlockDot(name.to_string(), block_type.to_string()),
+                        typed_prefix: after_dot.to_string(),
+                    };
+                }
+            }
+            3 => {
+                // name.block.item.prefix -> completing min/max
+                let name = parts[0].trim();
+                let block_name = parts[1].trim();
+                let item_name = parts[2].trim();
+                if !name.is_empty() && !block_name.is_empty() && !item_name.is_empty() {
+                    return CompletionInfo {
+                        context: CompletionContext::AfterItemDot,
+                        typed_prefix: after_dot.to_string(),
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Not in dot-completion, get the word being typed
+    let word_start = line.rfind(|c: char| c.is_whitespace() || c == '{' || c == '}')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let typed_prefix = line[word_start..].to_string();
+
+    // Determine context based on block
+    let context = match last_block_type {
+        Some("formula_nutrients") => CompletionContext::InFormulaNutrientsBlock,
+        Some("ingredient_nutrients") => CompletionContext::InIngredientNutrientsBlock,
+        Some("formula_ingredients") => CompletionContext::InFormulaIngredientsBlock,
+        _ => {
+            // Check if at top level (outside any braces)
+            if brace_depth == 0 {
+                CompletionContext::TopLevel
+            } else {
+                CompletionContext::General
+            }
+        }
+    };
+
+    CompletionInfo { context, typed_prefix }
+}
+
+/// Completions at a byte offset. `imported` adds the names the file's
+/// imports provide, when they are known.
+pub fn compute_completions(source: &str, position: usize, imported: Option<&ImportedNames>) -> Vec<Completion> {
+    let info = get_completion_info(source, position);
+    let prefix_lower = info.typed_prefix.to_lowercase();
+
+    // Parse current document to get defined symbols (using resilient parsing)
+    let (program, _) = Parser::parse_resilient(source);
+    let program = Some(program);
+    let no_imports = ImportedNames::default();
+    let imported = imported.unwrap_or(&no_imports);
+
+    let mut completions = Vec::new();
+
+    match info.context {
+        CompletionContext::TopLevel => {
+            // Only suggest top-level keywords at top level
+            add_completion(&mut completions, "nutrient", "keyword", "Define a nutrient",
+                "nutrient ${1:name} {\n  name \"${2:Display Name}\"\n  unit \"${3:%}\"\n}");
+            add_completion(&mut completions, "ingredient", "keyword", "Define an ingredient",
+                "ingredient ${1:name} {\n  name \"${2:Display Name}\"\n  cost ${3:0}\n  nutrients {\n    ${4}\n  }\n}");
+            add_completion(&mut completions, "formula", "keyword", "Define a formula",
+                "formula ${1:name} {\n  name \"${2:Display Name}\"\n  batch ${3:1000}\n  nutrients {\n    ${4}\n  }\n  ingredients {\n    ${5}\n  }\n}");
+            add_completion(&mut completions, "template", "keyword", "Define a template (for composition)",
+                "template ${1|formula,ingredient|} ${2:name} {\n  ${3}\n}");
+            add_completion(&mut completions, "import", "keyword", "Import from another file",
+                "import \"${1:./file.fm}\"");
+            add_completion(&mut completions, "const", "keyword", "Define a named constant",
+                "const ${1:name} ${2:0}");
+            add_completion(&mut completions, "plan", "keyword", "Make several formulas from shared stock",
+                "plan ${1:name} {\n  formulas {\n    ${2}\n  }\n  available {\n    ${3}\n  }\n}");
+        }
+
+        CompletionContext::AfterNameDot(ref name) => {
+            // After "name." - suggest properties based on what name refers to
+            if let Some(ref prog) = program {
+                let is_formula = prog.items.iter().any(|item| {
+                    matches!(item, Item::Formula(f) if &f.name == name)
+                }) || imported.formulas.contains(name);
+                let is_ingredient = prog.items.iter().any(|item| {
+                    matches!(item, Item::Ingredient(i) if &i.name == name)
+                }) || imported.ingredients.contains(name);
+
+                if is_formula {
+                    add_completion(&mut completions, "nutrients", "property", "All nutrient constraints", "nutrients");
+                    add_completion(&mut completions, "ingredients", "property", "All ingredient constraints", "ingredients");
+                }
+
+                if is_ingredient {
+                    add_completion(&mut completions, "nutrients", "property", "All nutrient values", "nutrients");
+                }
+            }
+
+            // After "module." - suggest what the module exports
+            if imported.modules.contains(name) {
+                let prefix = format!("{}.", name);
+                let exports = |names: &HashSet<String>| -> Vec<String> {
+                    names.iter().filter_map(|n| n.strip_prefix(&prefix)).map(String::from).collect()
+                };
+                add_names(&mut completions, &exports(&imported.nutrients), "Nutrient");
+                add_names(&mut completions, &exports(&imported.ingredients), "Ingredient");
+                add_names(&mut completions, &exports(&imported.formulas), "Formula");
+                add_names(&mut completions, &exports(&imported.constants), "Constant");
+            }
+        }
+
+        CompletionContext::AfterBlockDot(ref name, ref block_type) => {
+            // After "name.block." - suggest items from that block
+            if let Some(ref prog) = program {
+                // Check formulas
+                for item in &prog.items {
+                    if let Item::Formula(f) = item
+                        && &f.name == name
+                    {
+                        if block_type == "nutrients" {
+                            for nc in &f.nutrients {
+                                // Suggest alias if present, otherwise the expression name
+                                if let Some(ref alias) = nc.alias {
+                                    add_completion(&mut completions, alias, "variable",
+                                        "Constraint alias", alias);
+                                }
+                                if let Some(nut_name) = get_expr_name(&nc.expr) {
+                                    add_completion(&mut completions, &nut_name, "variable",
+                                        &format!("{} constraint", nut_name), &nut_name);
+                                }
+                            }
+                        } else if block_type == "ingredients" {
+                            for ic in &f.ingredients {
+                                // Suggest alias if present, otherwise the expression name
+                                if let Some(ref alias) = ic.alias {
+                                    add_completion(&mut completions, alias, "variable",
+                                        "Constraint alias", alias);
+                                }
+                                if let Some(ing_name) = get_expr_name(&ic.expr) {
+                                    add_completion(&mut completions, &ing_name, "variable",
+                                        &format!("{} constraint", ing_name), &ing_name);
+                                }
+                            }
+                        }
+                        break;
+                    }
+                }
+
+                // Check ingredients (for ingredient.nutrients.)
+                if block_type == "nutrients" {
+                    for item in &prog.items {
+                        if let Item::Ingredient(ing) = item
+                            && &ing.name == name
+                        {
+                            for nv in &ing.nutrients {
+                                if let Some(ReferencePart::Ident(nut_name)) = nv.nutrient.parts.first()
+                                    && nv.value.is_some()
+                                {
+                                    add_completion(&mut completions, nut_name, "variable",
+                                        &format!("{} value", nut_name), nut_name);
+                                }
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        CompletionContext::AfterItemDot => {
+            // After "name.block.item." - only min/max
+            add_completion(&mut completions, "min", "keyword", "Minimum bound only", "min");
+            add_completion(&mut completions, "max", "keyword", "Maximum bound only", "max");
+        }
+
+        CompletionContext::InFormulaNutrientsBlock => {
+            // In formula nutrients block - suggest nutrients, formulas for composition, and constraint keywords
+            add_completion(&mut completions, "min", "keyword", "Set minimum bound", "min ${1:0}");
+            add_completion(&mut completions, "max", "keyword", "Set maximum bound", "max ${1:0}");
+            add_completion(&mut completions, "soft", "keyword", "Allow missing the bounds at a penalty", "soft ${1:100}");
+            add_completion(&mut completions, "as", "keyword", "Name this constraint", "as ${1:alias_name}");
+            add_completion(&mut completions, "basis", "keyword", "Give the following bounds on a dry matter basis", "basis dry_matter");
+            if let Some(ref prog) = program {
+                for item in &prog.items {
+                    match item {
+                        Item::Nutrient(n) => {
+                            add_completion(&mut completions, &n.name, "variable", "Nutrient", &n.name);
+                        }
+                        Item::Formula(f) => {
+                            add_completion(&mut completions, &f.name, "variable",
+                                "Formula (for composition)", &f.name);
+                        }
+                        Item::Const(c) => {
+                            add_completion(&mut completions, &c.name, "variable", "Constant", &c.name);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            add_names(&mut completions, &unqualified(&imported.nutrients), "Nutrient");
+            add_names(&mut completions, &unqualified(&imported.formulas), "Formula (for composition)");
+            add_names(&mut completions, &unqualified(&imported.constants), "Constant");
+            add_names(&mut completions, &unqualified(&imported.modules), "Module");
+        }
+
+        CompletionContext::InFormulaIngredientsBlock => {
+            // In formula ingredients block - suggest ingredients, formulas, and constraint keywords
+            add_completion(&mut completions, "min", "keyword", "Set minimum bound", "min ${1:0}%");
+            add_completion(&mut completions, "max", "keyword", "Set maximum bound", "max ${1:0}%");
+            add_completion(&mut completions, "or", "keyword", "Allow zero inclusion", "or 0");
+            add_completion(&mut completions, "as", "keyword", "Name this constraint", "as ${1:alias_name}");
+            if let Some(ref prog) = program {
+                for item in &prog.items {
+                    match item {
+                        Item::Ingredient(i) => {
+                            add_completion(&mut completions, &i.name, "variable", "Ingredient", &i.name);
+                        }
+                        Item::Formula(f) => {
+                            add_completion(&mut completions, &f.name, "variable",
+                                "Formula (for composition)", &f.name);
+                        }
+                        Item::Const(c) => {
+                            add_completion(&mut completions, &c.name, "variable", "Constant", &c.name);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            add_names(&mut completions, &unqualified(&imported.ingredients), "Ingredient");
+            add_names(&mut completions, &unqualified(&imported.formulas), "Formula (for composition)");
+            add_names(&mut completions, &unqualified(&imported.constants), "Constant");
+            add_names(&mut completions, &unqualified(&imported.modules), "Module");
+        }
+
+        CompletionContext::InIngredientNutrientsBlock => {
+            // In ingredient nutrients block - suggest nutrients and ingredients for composition
+            add_completion(&mut completions, "basis", "keyword", "Give the following values on a dry matter basis", "basis dry_matter");
+            if let Some(ref prog) = program {
+                for item in &prog.items {
+                    match item {
+                        // Derived nutrients are computed, not given
+                        Item::Nutrient(n) if n.derived.is_none() => {
+                            add_completion(&mut completions, &n.name, "variable", "Nutrient",
+                                &format!("{} ${{1:0}}", n.name));
+                        }
+                        Item::Ingredient(i) => {
+                            add_completion(&mut completions, &i.name, "variable",
+                                "Ingredient (for composition)", &i.name);
+                        }
+                        Item::Const(c) => {
+                            add_completion(&mut completions, &c.name, "variable", "Constant", &c.name);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            for name in unqualified(&imported.nutrients).into_iter().filter(|n| !imported.derived.contains(n)) {
+                add_completion(&mut completions, &name, "variable", "Nutrient", &format!("{} ${{1:0}}", name));
+            }
+            add_names(&mut completions, &unqualified(&imported.ingredients), "Ingredient (for composition)");
+            add_names(&mut completions, &unqualified(&imported.constants), "Constant");
+            add_names(&mut completions, &unqualified(&imported.modules), "Module");
+        }
+
+        CompletionContext::General => {
+            // General context inside a block - suggest symbols but NOT top-level keywords
+            if let Some(ref prog) = program {
+                for item in &prog.items {
+                    match item {
+                        Item::Nutrient(n) => {
+                            add_completion(&mut completions, &n.name, "variable", "Nutrient", &n.name);
+                        }
+                        Item::Ingredient(i) => {
+                            add_completion(&mut completions, &i.name, "variable", "Ingredient", &i.name);
+                        }
+                        Item::Formula(f) => {
+                            add_completion(&mut completions, &f.name, "variable", "Formula", &f.name);
+                        }
+                        Item::Const(c) => {
+                            add_completion(&mut completions, &c.name, "variable", "Constant", &c.name);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            add_names(&mut completions, &unqualified(&imported.nutrients), "Nutrient");
+            add_names(&mut completions, &unqualified(&imported.ingredients), "Ingredient");
+            add_names(&mut completions, &unqualified(&imported.formulas), "Formula");
+            add_names(&mut completions, &unqualified(&imported.constants), "Constant");
+        }
+    }
+
+    // Filter completions by typed prefix
+    if !prefix_lower.is_empty() {
+        completions.retain(|c| c.label.to_lowercase().starts_with(&prefix_lower));
+    }
+
+    completions
+}
+
+/// Helper to add a completion
+fn add_completion(completions: &mut Vec<Completion>, label: &str, kind: &str, detail: &str, insert_text: &str) {
+    // Avoid duplicates
+    if !completions.iter().any(|c| c.label == label) {
+        completions.push(Completion {
+            label: label.to_string(),
+            kind: kind.to_string(),
+            detail: Some(detail.to_string()),
+            insert_text: insert_text.to_string(),
+        });
+    }
+}
+
+/// Add names as variable completions, in sorted order
+fn add_names(completions: &mut Vec<Completion>, names: &[String], detail: &str) {
+    let mut names = names.to_vec();
+    names.sort();
+    for name in &names {
+        add_completion(completions, name, "variable", detail, name);
+    }
+}
+
+/// Names usable without a module qualifier
+fn unqualified(names: &HashSet<String>) -> Vec<String> {
+    names.iter().filter(|n| !n.contains('.')).cloned().collect()
+}
+
+/// Get the first identifier name from an expression
+fn get_expr_name(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Reference(r) => {
+            if let Some(ReferencePart::Ident(name)) = r.parts.first() {
+                Some(name.clone())
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Markdown hover text for the token spanning `start..end`
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone)]
+pub struct HoverInfo {
+    pub contents: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Hover information at a byte offset. `imported` describes names the
+/// file's imports provide, when they are known.
+pub fn compute_hover(source: &str, position: usize, imported: Option<&ImportedNames>) -> Option<HoverInfo> {
+    // Find the token at the position
+    let tokens = Lexer::tokenize(source);
+    let index = tokens
+        .iter()
+        .position(|t| t.span.start <= position && position <= t.span.end)?;
+    let token = &tokens[index];
+
+    // Return info based on token type
+    let contents = match token.kind {
+        TokenKind::Nutrient => "**nutrient**\n\nDefines a nutrient that can be tracked in ingredients and constrained in formulas.".to_string(),
+        TokenKind::Ingredient => "**ingredient**\n\nDefines a feed ingredient with cost and nutrient composition.".to_string(),
+        TokenKind::Formula => "**formula**\n\nDefines a feed formula with nutrient requirements and ingredient constraints.".to_string(),
+        TokenKind::Import => "**import**\n\nImports definitions from another .fm file.".to_string(),
+        TokenKind::Const => "**const**\n\nDefines a named number usable in properties, bounds and nutrient values.".to_string(),
+        TokenKind::Plan => "**plan**\n\nSolves several formulas together, sharing the available stock of ingredients.".to_string(),
+        TokenKind::Min => "**min**\n\nSets a minimum bound for a constraint.".to_string(),
+        TokenKind::Max => "**max**\n\nSets a maximum bound for a constraint.".to_string(),
+        TokenKind::As => "**as**\n\nNames a constraint expression for readability and referencing.".to_string(),
+        TokenKind::Soft => "**soft**\n\nLets a nutrient bound be missed, charging the given penalty per unit of deviation.".to_string(),
+        TokenKind::Basis => "**basis**\n\nSets whether the nutrient values that follow are `as_fed` (the default) or on a `dry_matter` basis.".to_string(),
+        TokenKind::Or => "**or 0**\n\nMakes ingredient bounds optional: the ingredient is either left out or used within its bounds.".to_string(),
+        TokenKind::Ident => {
+            // Try to find this identifier in the parsed program (using resilient parsing)
+            let (program, _) = Parser::parse_resilient(source);
+            for item in &program.items {
+                match item {
+                    Item::Nutrient(n) if n.name == token.text => {
+                        let derived = n.derived.as_ref().map(|d| format!(" = `{}`", expr_text(&d.expr))).unwrap_or_default();
+                        return Some(HoverInfo {
+                            contents: format!("**Nutrient** `{}`{}", n.name, derived),
+                            start: token.span.start,
+                            end: token.span.end,
+                        });
+                    }
+                    Item::Ingredient(i) if i.name == token.text => {
+                        return Some(HoverInfo {
+                            contents: format!("**Ingredient** `{}`", i.name),
+                            start: token.span.start,
+                            end: token.span.end,
+                        });
+                    }
+                    Item::Formula(f) if f.name == token.text => {
+                        let params = if f.params.is_empty() { String::new() } else { format!("({})", f.params.join(", ")) };
+                        return Some(HoverInfo {
+                            contents: format!("**Formula** `{}{}`", f.name, params),
+                            start: token.span.start,
+                            end: token.span.end,
+                        });
+                    }
+                    Item::Const(c) if c.name == token.text => {
+                        return Some(HoverInfo {
+                            contents: format!("**Constant** `{}` = `{}`", c.name, expr_text(&c.value)),
+                            start: token.span.start,
+                            end: token.span.end,
+                        });
+                    }
+                    Item::Plan(p) if p.name == token.text => {
+                        return Some(HoverInfo {
+                            contents: format!("**Plan** `{}`", p.name),
+                            start: token.span.start,
+                            end: token.span.end,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+
+            // Then names from imports, qualified if preceded by `module.`
+            let imported = imported?;
+            let name = match index.checked_sub(2).map(|i| &tokens[i..index]) {
+                Some([module, dot])
+                    if dot.kind == TokenKind::Dot && imported.modules.contains(module.text.as_str()) =>
+                {
+                    format!("{}.{}", module.text, token.text)
+                }
+                _ => token.text.to_string(),
+            };
+            let kind = if imported.nutrients.contains(&name) {
+                "Nutrient"
+            } else if imported.ingredients.contains(&name) {
+                "Ingredient"
+            } else if imported.formulas.contains(&name) {
+                "Formula"
+            } else if imported.constants.contains(&name) {
+                "Constant"
+            } else if imported.modules.contains(&name) {
+                "Module"
+            } else {
+                return None;
+            };
+            return Some(HoverInfo {
+                contents: format!("**{}** `{}` (imported)", kind, name),
+                start: token.span.start,
+                end: token.span.end,
+            });
+        }
+        _ => return None,
+    };
+
+    Some(HoverInfo {
+        contents,
+        start: token.span.start,
+        end: token.span.end,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_template_formula_alias() {
+        let source = r#"
+template formula ratios {
+  nutrients {
+    lysine / arginine min 0.1 as lysine_arginine
+    lysine / methionine min 0.1 as lysine_methionine
+  }
+  ingredients {}
+}
+
+formula test {
+  nutrients {
+    ratios.nutrients.
+  }
+  ingredients {}
+}
+"#;
+        // Position after "ratios.nutrients."
+        let line = "    ratios.nutrients.";
+        let pos = source.find(line).unwrap() + line.len();
+
+        let completions = compute_completions(source, pos, None);
+
+        // Should include both aliases
+        let labels: Vec<&str> = completions.iter().map(|c| c.label.as_str()).collect();
+        assert!(labels.contains(&"lysine_arginine"), "Should contain lysine_arginine alias, got: {:?}", labels);
+        assert!(labels.contains(&"lysine_methionine"), "Should contain lysine_methionine alias, got: {:?}", labels);
+    }
+
+    #[test]
+    fn test_parser_captures_alias_in_template() {
+        let source = r#"
+template formula ratios {
+  nutrients {
+    lysine / arginine   min 0.1 as lysine_arginine
+    lysine / methionine min 0.1 as lysine_methionine
+  }
+  ingredients {}
+}
+"#;
+        let (program, errors) = Parser::parse_resilient(source);
+        assert!(errors.is_empty(), "Parse errors: {:?}", errors);
+
+        // Find the ratios formula
+        let ratios = program.items.iter().find_map(|item| {
+            if let Item::Formula(f) = item {
+                if f.name == "ratios" { Some(f) } else { None }
+            } else { None }
+        }).expect("Should find ratios formula");
+
+        assert_eq!(ratios.nutrients.len(), 2, "Should have 2 nutrient constraints");
+
+        // Check aliases are captured
+        let aliases: Vec<Option<&str>> = ratios.nutrients.iter()
+            .map(|nc| nc.alias.as_deref())
+            .collect();
+
+        println!("Nutrient constraint aliases: {:?}", aliases);
+
+        assert_eq!(aliases[0], Some("lysine_arginine"), "First constraint should have lysine_arginine alias");
+        assert_eq!(aliases[1], Some("lysine_methionine"), "Second constraint should have lysine_methionine alias");
+    }
+
+    #[test]
+    fn test_completion_context_detection() {
+        let source = "ratios.nutrients.lys";
+        let pos = source.len();
+
+        let info = get_completion_info(source, pos);
+
+        match info.context {
+            CompletionContext::AfterBlockDot(name, block_type) => {
+                assert_eq!(name, "ratios");
+                assert_eq!(block_type, "nutrients");
+            }
+            _ => panic!("Expected AfterBlockDot context, got: {:?}", info.context),
+        }
+        assert_eq!(info.typed_prefix, "lys");
+    }
+
+    #[test]
+    fn test_constant_hover_and_completion() {
+        let source = r#"const mixer_size 1000 * 2
+
+formula test {
+  batch_size mixer_size
+  nutrients {
+    protein min mix
+  }
+}
+"#;
+        let pos = source.find("batch_size mixer_size").unwrap() + "batch_size ".len();
+        let hover = compute_hover(source, pos, None).unwrap();
+        assert_eq!(hover.contents, "**Constant** `mixer_size` = `1000 * 2`");
+
+        let pos = source.find("min mix").unwrap() + "min mix".len();
+        let completions = compute_completions(source, pos, None);
+        let labels: Vec<&str> = completions.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["mixer_size"]);
+    }
+}
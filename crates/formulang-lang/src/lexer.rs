@@ -28,9 +28,14 @@ pub enum TokenKind {
     Formula,
     Import,
     Template,
+    Const,
+    Plan,
     Min,
     Max,
     As,
+    Or,
+    Soft,
+    Basis,
 
     // Literals
     Ident,
@@ -46,6 +51,7 @@ pub enum TokenKind {
     Dot,
     Colon,
     Comma,
+    Equals,
 
     // Delimiters
     LBrace,
@@ -115,8 +121,8 @@ impl<'a> Lexer<'a> {
     fn advance(&mut self) -> Option<char> {
         let c = self.current;
         self.current = self.chars.next();
-        if c.is_some() {
-            self.pos += c.unwrap().len_utf8();
+        if let Some(c) = c {
+            self.pos += c.len_utf8();
         }
         c
     }
@@ -220,15 +226,15 @@ impl<'a> Lexer<'a> {
         if self.peek() == Some('.') {
             // Look ahead to see if it's a decimal or a dot operator
             let mut chars = self.chars.clone();
-            if let Some(next) = chars.next() {
-                if next.is_ascii_digit() {
-                    self.advance(); // consume the dot
-                    while let Some(c) = self.peek() {
-                        if c.is_ascii_digit() {
-                            self.advance();
-                        } else {
-                            break;
-                        }
+            if let Some(next) = chars.next()
+                && next.is_ascii_digit()
+            {
+                self.advance(); // consume the dot
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_digit() {
+                        self.advance();
+                    } else {
+                        break;
                     }
                 }
             }
@@ -257,9 +263,14 @@ impl<'a> Lexer<'a> {
             "formula" => TokenKind::Formula,
             "import" => TokenKind::Import,
             "template" => TokenKind::Template,
+            "const" => TokenKind::Const,
+            "plan" => TokenKind::Plan,
             "min" => TokenKind::Min,
             "max" => TokenKind::Max,
             "as" => TokenKind::As,
+            "or" => TokenKind::Or,
+            "soft" => TokenKind::Soft,
+            "basis" => TokenKind::Basis,
             _ => TokenKind::Ident,
         };
         Token::new(kind, Span::new(start, self.pos), text)
@@ -298,10 +309,10 @@ impl<'a> Lexer<'a> {
             '-' => {
                 // Could be negative number or minus operator
                 let mut chars = self.chars.clone();
-                if let Some(next) = chars.next() {
-                    if next.is_ascii_digit() {
-                        return self.read_number();
-                    }
+                if let Some(next) = chars.next()
+                    && next.is_ascii_digit()
+                {
+                    return self.read_number();
                 }
                 self.advance();
                 Token::new(TokenKind::Minus, Span::new(start, self.pos), "-")
@@ -326,6 +337,10 @@ impl<'a> Lexer<'a> {
                 self.advance();
                 Token::new(TokenKind::Comma, Span::new(start, self.pos), ",")
             }
+            '=' => {
+                self.advance();
+                Token::new(TokenKind::Equals, Span::new(start, self.pos), "=")
+            }
             '{' => {
                 self.advance();
                 Token::new(TokenKind::LBrace, Span::new(start, self.pos), "{")
@@ -370,7 +385,7 @@ mod tests {
 
     #[test]
     fn test_keywords() {
-        let tokens = Lexer::tokenize("nutrient ingredient formula import min max");
+        let tokens = Lexer::tokenize("nutrient ingredient formula import plan min max or soft basis");
         let kinds: Vec<_> = tokens.iter().map(|t| t.kind).collect();
         assert_eq!(
             kinds,
@@ -379,8 +394,12 @@ mod tests {
                 TokenKind::Ingredient,
                 TokenKind::Formula,
                 TokenKind::Import,
+                TokenKind::Plan,
                 TokenKind::Min,
                 TokenKind::Max,
+                TokenKind::Or,
+                TokenKind::Soft,
+                TokenKind::Basis,
                 TokenKind::Eof,
             ]
         );
@@ -402,7 +421,7 @@ mod tests {
 
     #[test]
     fn test_operators() {
-        let tokens = Lexer::tokenize("+ - * / % . : ,");
+        let tokens = Lexer::tokenize("+ - * / % . : , =");
         let kinds: Vec<_> = tokens.iter().map(|t| t.kind).collect();
         assert_eq!(
             kinds,
@@ -415,6 +434,7 @@ mod tests {
                 TokenKind::Dot,
                 TokenKind::Colon,
                 TokenKind::Comma,
+                TokenKind::Equals,
                 TokenKind::Eof,
             ]
         );
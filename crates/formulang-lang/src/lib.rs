@@ -1,12 +1,23 @@
+pub mod analysis;
 pub mod ast;
 pub mod compiler;
+pub mod formatter;
+pub mod ide;
 pub mod lexer;
 pub mod parser;
+pub mod report;
+pub mod units;
 
 #[cfg(feature = "wasm")]
 pub mod wasm;
 
+pub use analysis::{Diagnostic, DiagnosticCode, ImportedNames, Severity};
 pub use ast::*;
-pub use compiler::{CompiledFormula, CompiledIngredient, CompiledNutrient, CompileError, Compiler};
+pub use compiler::{
+    CompiledFormula, CompiledIngredient, CompiledNutrient, CompiledPlan, CompileError, Compiler, ConstraintSource,
+    MissingDataPolicy, MissingNutrientValue, PlannedFormula, SoftConstraint, SubFormula, Supply,
+};
+pub use formatter::format_source;
 pub use lexer::{Lexer, Token, TokenKind};
 pub use parser::{ParseError, Parser};
+pub use report::{PlanResult, PlannedFormulaResult, SolveResult, SolveStatus, SupplyResult};
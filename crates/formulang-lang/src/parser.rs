@@ -85,7 +85,9 @@ impl Parser {
                 }
                 // Top-level keywords at brace depth 0 indicate new item
                 TokenKind::Nutrient | TokenKind::Ingredient | TokenKind::Formula
-                | TokenKind::Template | TokenKind::Import if brace_depth == 0 => {
+                | TokenKind::Template | TokenKind::Import | TokenKind::Const | TokenKind::Plan
+                    if brace_depth == 0 =>
+                {
                     break;
                 }
                 _ => {
@@ -114,6 +116,15 @@ impl Parser {
                         }
                     }
                 }
+                TokenKind::Const => {
+                    match self.parse_const() {
+                        Ok(item) => items.push(Item::Const(item)),
+                        Err(e) => {
+                            errors.push(e);
+                            self.skip_to_next_item();
+                        }
+                    }
+                }
                 TokenKind::Nutrient => {
                     match self.parse_nutrient() {
                         Ok(item) => items.push(Item::Nutrient(item)),
@@ -141,6 +152,15 @@ impl Parser {
                         }
                     }
                 }
+                TokenKind::Plan => {
+                    match self.parse_plan() {
+                        Ok(item) => items.push(Item::Plan(item)),
+                        Err(e) => {
+                            errors.push(e);
+                            self.skip_to_next_item();
+                        }
+                    }
+                }
                 TokenKind::Template => {
                     self.advance(); // consume 'template'
                     self.skip_newlines_and_comments();
@@ -179,7 +199,7 @@ impl Parser {
                     // Unknown token at top level - record error and skip
                     if let Some(token) = self.current().cloned() {
                         errors.push(ParseError::UnexpectedToken {
-                            expected: "import, nutrient, ingredient, formula, or template".to_string(),
+                            expected: "import, const, nutrient, ingredient, formula, template, or plan".to_string(),
                             found: format!("{:?}", token.kind),
                             span: token.span,
                         });
@@ -218,9 +238,11 @@ impl Parser {
             match self.peek_kind() {
                 TokenKind::Eof => break,
                 TokenKind::Import => items.push(Item::Import(self.parse_import()?)),
+                TokenKind::Const => items.push(Item::Const(self.parse_const()?)),
                 TokenKind::Nutrient => items.push(Item::Nutrient(self.parse_nutrient()?)),
                 TokenKind::Ingredient => items.push(Item::Ingredient(self.parse_ingredient(false)?)),
                 TokenKind::Formula => items.push(Item::Formula(self.parse_formula(false)?)),
+                TokenKind::Plan => items.push(Item::Plan(self.parse_plan()?)),
                 TokenKind::Template => {
                     self.advance(); // consume 'template'
                     self.skip_newlines_and_comments();
@@ -240,7 +262,7 @@ impl Parser {
                 _ => {
                     let token = self.current().cloned().unwrap();
                     return Err(ParseError::UnexpectedToken {
-                        expected: "import, nutrient, ingredient, formula, or template".to_string(),
+                        expected: "import, const, nutrient, ingredient, formula, template, or plan".to_string(),
                         found: format!("{:?}", token.kind),
                         span: token.span,
                     });
@@ -287,14 +309,10 @@ impl Parser {
         // Check for alias: as name
         let mut alias = None;
         self.skip_newlines_and_comments();
-        if self.peek_kind() == TokenKind::Ident {
-            if let Some(token) = self.current() {
-                if token.text == "as" {
-                    self.advance();
-                    let name = self.expect(TokenKind::Ident)?;
-                    alias = Some(name.text);
-                }
-            }
+        if self.peek_kind() == TokenKind::As {
+            self.advance();
+            let name = self.expect(TokenKind::Ident)?;
+            alias = Some(name.text);
         }
 
         // Check for selections: { ... }
@@ -338,18 +356,45 @@ impl Parser {
         })
     }
 
+    fn parse_const(&mut self) -> Result<Const, ParseError> {
+        let start = self.expect(TokenKind::Const)?.span;
+        let name = self.expect(TokenKind::Ident)?.text;
+        let value = self.parse_expr()?;
+        let end = self.tokens.get(self.pos.saturating_sub(1))
+            .map(|t| t.span.end)
+            .unwrap_or(start.end);
+
+        Ok(Const {
+            span: Span::new(start.start, end),
+            name,
+            value,
+        })
+    }
+
     fn parse_nutrient(&mut self) -> Result<Nutrient, ParseError> {
         let start = self.expect(TokenKind::Nutrient)?.span;
         let name = self.expect(TokenKind::Ident)?.text;
         self.expect(TokenKind::LBrace)?;
 
         let mut properties = Vec::new();
+        let mut derived = None;
         loop {
             self.skip_newlines_and_comments();
-            if self.peek_kind() == TokenKind::RBrace {
-                break;
+            match self.peek_kind() {
+                TokenKind::RBrace => break,
+                // Derived nutrient: `= sodium * 434.8 + potassium * 255.7`
+                TokenKind::Equals if derived.is_none() => {
+                    let equals = self.advance().unwrap().span;
+                    let expr = self.parse_expr()?;
+                    // The expression may have looked past newlines for an operator
+                    let end = self.tokens[..self.pos]
+                        .iter()
+                        .rfind(|t| !matches!(t.kind, TokenKind::Newline | TokenKind::Comment))
+                        .map_or(equals.end, |t| t.span.end);
+                    derived = Some(Derived { span: Span::new(equals.start, end), expr });
+                }
+                _ => properties.push(self.parse_property()?),
             }
-            properties.push(self.parse_property()?);
         }
 
         let end = self.expect(TokenKind::RBrace)?.span;
@@ -358,6 +403,7 @@ impl Parser {
             span: Span::new(start.start, end.end),
             name,
             properties,
+            derived,
         })
     }
 
@@ -379,12 +425,14 @@ impl Parser {
                     if text == Some("nutrients") || text == Some("nuts") {
                         self.advance();
                         self.expect(TokenKind::LBrace)?;
+                        let mut basis = Basis::AsFed;
                         loop {
                             self.skip_newlines_and_comments();
-                            if self.peek_kind() == TokenKind::RBrace {
-                                break;
+                            match self.peek_kind() {
+                                TokenKind::RBrace => break,
+                                TokenKind::Basis => basis = self.parse_basis()?,
+                                _ => nutrients.push(self.parse_nutrient_value(basis)?),
                             }
-                            nutrients.push(self.parse_nutrient_value()?);
                         }
                         self.expect(TokenKind::RBrace)?;
                     } else {
@@ -416,11 +464,41 @@ impl Parser {
     fn parse_formula(&mut self, is_template: bool) -> Result<Formula, ParseError> {
         let start = self.expect(TokenKind::Formula)?.span;
         let name = self.expect(TokenKind::Ident)?.text;
-        self.expect(TokenKind::LBrace)?;
 
-        let mut properties = Vec::new();
-        let mut nutrients = Vec::new();
-        let mut ingredients = Vec::new();
+        // Only templates take parameters
+        let params = if is_template && self.peek_kind() == TokenKind::LParen {
+            self.parse_params()?
+        } else {
+            Vec::new()
+        };
+
+        // `formula starter = broiler(22, 50%)`, with an optional body of overrides
+        let mut formula = Formula {
+            span: start,
+            name,
+            is_template,
+            params,
+            instance: None,
+            properties: Vec::new(),
+            nutrients: Vec::new(),
+            ingredients: Vec::new(),
+        };
+        if self.peek_kind() == TokenKind::Equals {
+            self.advance();
+            let instance = self.parse_template_instance()?;
+            formula.span = start.merge(instance.span);
+            formula.instance = Some(instance);
+
+            let pos = self.pos;
+            self.skip_newlines_and_comments();
+            if self.peek_kind() != TokenKind::LBrace {
+                self.pos = pos;
+                return Ok(formula);
+            }
+        }
+
+        self.expect(TokenKind::LBrace)?;
+        let Formula { properties, nutrients, ingredients, .. } = &mut formula;
 
         loop {
             self.skip_newlines_and_comments();
@@ -432,12 +510,14 @@ impl Parser {
                         "nutrients" | "nuts" => {
                             self.advance();
                             self.expect(TokenKind::LBrace)?;
+                            let mut basis = Basis::AsFed;
                             loop {
                                 self.skip_newlines_and_comments();
-                                if self.peek_kind() == TokenKind::RBrace {
-                                    break;
+                                match self.peek_kind() {
+                                    TokenKind::RBrace => break,
+                                    TokenKind::Basis => basis = self.parse_basis()?,
+                                    _ => nutrients.push(self.parse_nutrient_constraint(basis)?),
                                 }
-                                nutrients.push(self.parse_nutrient_constraint()?);
                             }
                             self.expect(TokenKind::RBrace)?;
                         }
@@ -469,15 +549,126 @@ impl Parser {
             }
         }
 
+        let end = self.expect(TokenKind::RBrace)?.span;
+        formula.span = start.merge(end);
+
+        Ok(formula)
+    }
+
+    /// Parse a template's parameter list: `(protein, max_corn)`
+    fn parse_params(&mut self) -> Result<Vec<String>, ParseError> {
+        self.expect(TokenKind::LParen)?;
+        let mut params = Vec::new();
+        loop {
+            self.skip_newlines_and_comments();
+            if self.peek_kind() == TokenKind::RParen {
+                break;
+            }
+            params.push(self.expect(TokenKind::Ident)?.text);
+            self.skip_newlines_and_comments();
+            if self.peek_kind() != TokenKind::Comma {
+                break;
+            }
+            self.advance();
+        }
+        self.expect(TokenKind::RParen)?;
+        Ok(params)
+    }
+
+    /// Parse a template applied to arguments: `broiler(22, 50%)`
+    fn parse_template_instance(&mut self) -> Result<TemplateInstance, ParseError> {
+        self.skip_newlines_and_comments();
+        let template = self.parse_reference()?;
+        self.expect(TokenKind::LParen)?;
+        let mut args = Vec::new();
+        loop {
+            self.skip_newlines_and_comments();
+            if self.peek_kind() == TokenKind::RParen {
+                break;
+            }
+            args.push(self.parse_bound_value(true)?);
+            self.skip_newlines_and_comments();
+            if self.peek_kind() != TokenKind::Comma {
+                break;
+            }
+            self.advance();
+        }
+        let end = self.expect(TokenKind::RParen)?.span;
+        Ok(TemplateInstance {
+            span: template.span.merge(end),
+            template,
+            args,
+        })
+    }
+
+    fn parse_plan(&mut self) -> Result<Plan, ParseError> {
+        let start = self.expect(TokenKind::Plan)?.span;
+        let name = self.expect(TokenKind::Ident)?.text;
+        self.expect(TokenKind::LBrace)?;
+
+        let mut properties = Vec::new();
+        let mut formulas = Vec::new();
+        let mut available = Vec::new();
+
+        loop {
+            self.skip_newlines_and_comments();
+            match self.peek_kind() {
+                TokenKind::RBrace => break,
+                TokenKind::Ident => {
+                    let ident = self.current().unwrap().text.clone();
+                    let entries = match ident.as_str() {
+                        "formulas" => &mut formulas,
+                        "available" => &mut available,
+                        _ => {
+                            properties.push(self.parse_property()?);
+                            continue;
+                        }
+                    };
+                    self.advance();
+                    self.expect(TokenKind::LBrace)?;
+                    loop {
+                        self.skip_newlines_and_comments();
+                        if self.peek_kind() == TokenKind::RBrace {
+                            break;
+                        }
+                        entries.push(self.parse_plan_entry()?);
+                    }
+                    self.expect(TokenKind::RBrace)?;
+                }
+                _ => {
+                    let token = self.current().cloned().unwrap();
+                    return Err(ParseError::UnexpectedToken {
+                        expected: "property, formulas, or available block".to_string(),
+                        found: format!("{:?}", token.kind),
+                        span: token.span,
+                    });
+                }
+            }
+        }
+
         let end = self.expect(TokenKind::RBrace)?.span;
 
-        Ok(Formula {
+        Ok(Plan {
             span: Span::new(start.start, end.end),
             name,
-            is_template,
             properties,
-            nutrients,
-            ingredients,
+            formulas,
+            available,
+        })
+    }
+
+    /// Parse a name and its quantity in a plan: `starter 20000`
+    fn parse_plan_entry(&mut self) -> Result<PlanEntry, ParseError> {
+        let name = self.parse_reference()?;
+        let quantity = self.parse_bound_value(false)?;
+        let end = self.tokens.get(self.pos.saturating_sub(1))
+            .map(|t| t.span.end)
+            .unwrap_or(name.span.end);
+
+        Ok(PlanEntry {
+            span: Span::new(name.span.start, end),
+            name,
+            quantity,
         })
     }
 
@@ -494,7 +685,13 @@ impl Parser {
             TokenKind::Number | TokenKind::Ident => {
                 // Parse as expression to support references and arithmetic
                 let expr = self.parse_expr()?;
-                let span = self.expr_span(&expr);
+                // Numbers carry no span, so end at the last token consumed
+                let span = self.tokens[..self.pos]
+                    .iter()
+                    .rev()
+                    .find(|t| !matches!(t.kind, TokenKind::Newline | TokenKind::Comment))
+                    .map(|t| t.span)
+                    .unwrap_or(name_token.span);
 
                 // Convert simple expressions to simpler PropertyValue variants
                 let value = match &expr {
@@ -527,52 +724,91 @@ impl Parser {
         })
     }
 
-    /// Get the span of an expression
-    fn expr_span(&self, expr: &Expr) -> Span {
-        match expr {
-            Expr::Number(_) => Span::new(0, 0), // Fallback, numbers don't store span
-            Expr::Reference(r) => r.span,
-            Expr::BinaryOp { left, right, .. } => {
-                let left_span = self.expr_span(left);
-                let right_span = self.expr_span(right);
-                Span::new(left_span.start, right_span.end)
-            }
-            Expr::Paren(inner) => self.expr_span(inner),
+    /// Parse a `basis dry_matter` or `basis as_fed` marker, which applies to
+    /// the rest of its nutrients block
+    fn parse_basis(&mut self) -> Result<Basis, ParseError> {
+        self.expect(TokenKind::Basis)?;
+        let token = self.expect(TokenKind::Ident)?;
+        match token.text.as_str() {
+            "as_fed" => Ok(Basis::AsFed),
+            "dry_matter" | "dm" => Ok(Basis::DryMatter),
+            _ => Err(ParseError::UnexpectedToken {
+                expected: "as_fed or dry_matter".to_string(),
+                found: token.text,
+                span: token.span,
+            }),
         }
     }
 
-    fn parse_nutrient_value(&mut self) -> Result<NutrientValue, ParseError> {
+    fn parse_nutrient_value(&mut self, basis: Basis) -> Result<NutrientValue, ParseError> {
         let start = self.current().map(|t| t.span).unwrap_or(Span::new(0, 0));
         let nutrient = self.parse_reference()?;
         self.skip_newlines_and_comments();
 
+        // References into a `nutrients` block take no value
+        let composition = nutrient
+            .parts
+            .iter()
+            .any(|p| matches!(p, ReferencePart::Ident(name) if name == "nutrients" || name == "nuts"));
+
         // Value is optional - if not present, this is a composition reference
-        let (value, end) = if self.peek_kind() == TokenKind::Number {
+        let (value, constant, mut end) = if self.peek_kind() == TokenKind::Number {
             let value_token = self.advance().unwrap();
             let value: f64 = value_token.text.parse().map_err(|_| {
                 ParseError::InvalidNumber(value_token.text.clone())
             })?;
-            (Some(value), value_token.span.end)
+            (Some(value), None, value_token.span.end)
+        } else if !composition && self.peek_kind() == TokenKind::Ident {
+            let constant = self.parse_reference()?;
+            let end = constant.span.end;
+            (None, Some(constant), end)
         } else {
             // No value - this is a composition reference like `corn.nutrients`
             let end = self.tokens.get(self.pos.saturating_sub(1))
                 .map(|t| t.span.end)
                 .unwrap_or(start.end);
-            (None, end)
+            (None, None, end)
+        };
+
+        // Optional unit after the value: `energy 13.8 "MJ/kg"`
+        let unit = if (value.is_some() || constant.is_some()) && self.peek_kind() == TokenKind::String {
+            end = self.current().map_or(end, |t| t.span.end);
+            self.parse_unit()
+        } else {
+            None
         };
 
         Ok(NutrientValue {
             span: Span::new(start.start, end),
             nutrient,
             value,
+            constant,
+            unit,
+            basis,
         })
     }
 
-    fn parse_nutrient_constraint(&mut self) -> Result<NutrientConstraint, ParseError> {
+    /// Parse a unit string, such as `"MJ/kg"`
+    fn parse_unit(&mut self) -> Option<String> {
+        self.advance().map(|token| token.text.trim_matches('"').to_string())
+    }
+
+    fn parse_nutrient_constraint(&mut self, basis: Basis) -> Result<NutrientConstraint, ParseError> {
         let start = self.current().map(|t| t.span).unwrap_or(Span::new(0, 0));
         let expr = self.parse_expr()?;
         let bounds = self.parse_bounds(false)?;
 
+        // Parse optional penalty: `soft number`
+        self.skip_newlines_and_comments();
+        let soft = if self.peek_kind() == TokenKind::Soft {
+            self.advance();
+            let token = self.expect(TokenKind::Number)?;
+            let penalty: f64 = token.text.parse().map_err(|_| ParseError::InvalidNumber(token.text.clone()))?;
+            Some(penalty)
+        } else {
+            None
+        };
+
         // Parse optional alias: `as identifier`
         self.skip_newlines_and_comments();
         let alias = if self.peek_kind() == TokenKind::As {
@@ -591,7 +827,9 @@ impl Parser {
             span: Span::new(start.start, end),
             expr,
             bounds,
+            soft,
             alias,
+            basis,
         })
     }
 
@@ -600,6 +838,23 @@ impl Parser {
         let expr = self.parse_expr()?;
         let bounds = self.parse_bounds(true)?;
 
+        // Parse optional semi-continuous marker: `or 0`
+        self.skip_newlines_and_comments();
+        let or_zero = if self.peek_kind() == TokenKind::Or {
+            self.advance();
+            let zero = self.expect(TokenKind::Number)?;
+            if zero.text.parse::<f64>().ok() != Some(0.0) {
+                return Err(ParseError::UnexpectedToken {
+                    expected: "0".to_string(),
+                    found: zero.text.clone(),
+                    span: zero.span,
+                });
+            }
+            true
+        } else {
+            false
+        };
+
         // Parse optional alias: `as identifier`
         self.skip_newlines_and_comments();
         let alias = if self.peek_kind() == TokenKind::As {
@@ -618,6 +873,7 @@ impl Parser {
             span: Span::new(start.start, end),
             expr,
             bounds,
+            or_zero,
             alias,
         })
     }
@@ -625,6 +881,7 @@ impl Parser {
     fn parse_bounds(&mut self, allow_percent: bool) -> Result<Bounds, ParseError> {
         let mut min = None;
         let mut max = None;
+        let mut last_is_max = false;
 
         loop {
             self.skip_newlines_and_comments();
@@ -632,10 +889,25 @@ impl Parser {
                 TokenKind::Min => {
                     self.advance();
                     min = Some(self.parse_bound_value(allow_percent)?);
+                    last_is_max = false;
                 }
                 TokenKind::Max => {
                     self.advance();
                     max = Some(self.parse_bound_value(allow_percent)?);
+                    last_is_max = true;
+                }
+                // Nutrient bounds may give their unit: `energy min 12 "MJ/kg"`
+                TokenKind::String if !allow_percent => {
+                    let bound = if last_is_max { max.as_mut() } else { min.as_mut() };
+                    let Some(bound) = bound.filter(|b| b.unit.is_none()) else {
+                        let token = self.current().cloned().unwrap();
+                        return Err(ParseError::UnexpectedToken {
+                            expected: "min or max before a unit".to_string(),
+                            found: token.text,
+                            span: token.span,
+                        });
+                    };
+                    bound.unit = self.parse_unit();
                 }
                 _ => break,
             }
@@ -644,12 +916,18 @@ impl Parser {
         Ok(Bounds { min, max })
     }
 
+    /// A number or the name of a constant, with `%` after it where allowed
     fn parse_bound_value(&mut self, allow_percent: bool) -> Result<BoundValue, ParseError> {
         self.skip_newlines_and_comments();
-        let value_token = self.expect(TokenKind::Number)?;
-        let value: f64 = value_token.text.parse().map_err(|_| {
-            ParseError::InvalidNumber(value_token.text.clone())
-        })?;
+        let (value, constant) = if self.peek_kind() == TokenKind::Ident {
+            (0.0, Some(self.parse_reference()?))
+        } else {
+            let value_token = self.expect(TokenKind::Number)?;
+            let value: f64 = value_token.text.parse().map_err(|_| {
+                ParseError::InvalidNumber(value_token.text.clone())
+            })?;
+            (value, None)
+        };
 
         let is_percent = if allow_percent && self.peek_kind() == TokenKind::Percent {
             self.advance();
@@ -658,7 +936,7 @@ impl Parser {
             false
         };
 
-        Ok(BoundValue { value, is_percent })
+        Ok(BoundValue { value, is_percent, constant, unit: None })
     }
 
     fn parse_expr(&mut self) -> Result<Expr, ParseError> {
@@ -841,6 +1119,8 @@ mod tests {
             Item::Ingredient(i) => {
                 assert_eq!(i.name, "corn");
                 assert_eq!(i.nutrients.len(), 2);
+                let cost = &i.properties[1];
+                assert_eq!(&source[cost.span.start..cost.span.end], "cost 150");
             }
             _ => panic!("Expected ingredient"),
         }
@@ -910,6 +1190,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_import_alias_and_selection() {
+        let source = "import ../shared/nutrients.fm as n { protein, energy }";
+        let program = Parser::parse(source).unwrap();
+        match &program.items[0] {
+            Item::Import(i) => {
+                assert_eq!(i.path, "../shared/nutrients.fm");
+                assert_eq!(i.alias, Some("n".to_string()));
+                assert_eq!(
+                    i.selections,
+                    Some(ImportSelections::Named(vec!["protein".to_string(), "energy".to_string()]))
+                );
+            }
+            _ => panic!("Expected import"),
+        }
+    }
+
     #[test]
     fn test_parse_reference_with_selection() {
         let source = r#"formula test {
@@ -1085,4 +1382,254 @@ mod tests {
             _ => panic!("Expected formula"),
         }
     }
+
+    #[test]
+    fn test_parse_or_zero() {
+        let source = r#"formula test {
+            batch_size 1000
+            ingredients {
+                fish_meal min 2% max 8% or 0 as fish
+                corn max 70%
+            }
+        }"#;
+        let program = Parser::parse(source).unwrap();
+        match &program.items[0] {
+            Item::Formula(f) => {
+                assert!(f.ingredients[0].or_zero);
+                assert_eq!(f.ingredients[0].alias, Some("fish".to_string()));
+                assert!(!f.ingredients[1].or_zero);
+            }
+            _ => panic!("Expected formula"),
+        }
+
+        // Only `or 0` is meaningful
+        let source = "formula test { ingredients { fish_meal min 2% or 1 } }";
+        assert!(Parser::parse(source).is_err());
+    }
+
+    #[test]
+    fn test_parse_soft_constraint() {
+        let source = r#"formula test {
+            batch_size 1000
+            nutrients {
+                protein min 18 soft 500 as crude_protein
+                calcium / phosphorus min 1.5 soft 20
+                fiber max 5
+            }
+        }"#;
+        let program = Parser::parse(source).unwrap();
+        match &program.items[0] {
+            Item::Formula(f) => {
+                assert_eq!(f.nutrients[0].soft, Some(500.0));
+                assert_eq!(f.nutrients[0].alias, Some("crude_protein".to_string()));
+                assert_eq!(f.nutrients[1].soft, Some(20.0));
+                assert_eq!(f.nutrients[2].soft, None);
+            }
+            _ => panic!("Expected formula"),
+        }
+    }
+
+    #[test]
+    fn test_parse_const() {
+        let source = r#"const mixer_size 2000
+            const protein_ceiling protein_target + 4
+            ingredient corn { nutrients { protein corn_protein } }
+            formula test {
+                batch_size mixer_size
+                nutrients { protein min protein_target max 22 }
+            }"#;
+        let program = Parser::parse(source).unwrap();
+        match &program.items[1] {
+            Item::Const(c) => {
+                assert_eq!(c.name, "protein_ceiling");
+                assert!(matches!(c.value, Expr::BinaryOp { op: BinaryOp::Add, .. }));
+            }
+            _ => panic!("Expected const"),
+        }
+        match &program.items[2] {
+            Item::Ingredient(i) => {
+                assert_eq!(i.nutrients[0].value, None);
+                assert_eq!(i.nutrients[0].constant.as_ref().unwrap().parts, vec![ReferencePart::Ident("corn_protein".to_string())]);
+            }
+            _ => panic!("Expected ingredient"),
+        }
+        match &program.items[3] {
+            Item::Formula(f) => {
+                assert_eq!(f.properties[0].value, PropertyValue::Ident("mixer_size".to_string()));
+                let bounds = &f.nutrients[0].bounds;
+                assert!(bounds.min.as_ref().unwrap().constant.is_some());
+                assert!(bounds.max.as_ref().unwrap().constant.is_none());
+            }
+            _ => panic!("Expected formula"),
+        }
+    }
+
+    #[test]
+    fn test_parse_template_instance() {
+        let source = r#"template formula broiler(phase_protein, max_corn) {
+                nutrients { protein min phase_protein }
+                ingredients { corn max max_corn }
+            }
+            formula starter = broiler(22, 50%)
+            formula grower = broiler(grower_protein, 70%) {
+                name "Grower"
+            }"#;
+        let program = Parser::parse(source).unwrap();
+        assert_eq!(program.items.len(), 3);
+        match &program.items[0] {
+            Item::Formula(f) => {
+                assert!(f.is_template);
+                assert_eq!(f.params, vec!["phase_protein", "max_corn"]);
+                assert!(f.instance.is_none());
+            }
+            _ => panic!("Expected formula"),
+        }
+        match &program.items[1] {
+            Item::Formula(f) => {
+                let instance = f.instance.as_ref().unwrap();
+                assert_eq!(instance.template.parts, vec![ReferencePart::Ident("broiler".to_string())]);
+                assert_eq!(instance.args, vec![BoundValue::absolute(22.0), BoundValue::percent(50.0)]);
+                assert_eq!(&source[f.span.start..f.span.end], "formula starter = broiler(22, 50%)");
+            }
+            _ => panic!("Expected formula"),
+        }
+        match &program.items[2] {
+            Item::Formula(f) => {
+                let instance = f.instance.as_ref().unwrap();
+                assert!(instance.args[0].constant.is_some());
+                assert_eq!(f.properties[0].name, "name");
+            }
+            _ => panic!("Expected formula"),
+        }
+    }
+
+    #[test]
+    fn test_parse_plan() {
+        let source = r#"plan week_12 {
+                name "Week 12"
+                formulas {
+                    starter 20000
+                    grower grower_tonnage
+                }
+                available { corn 15000 }
+            }"#;
+        let program = Parser::parse(source).unwrap();
+        match &program.items[0] {
+            Item::Plan(p) => {
+                assert_eq!(p.name, "week_12");
+                assert_eq!(p.properties[0].name, "name");
+                assert_eq!(p.formulas.len(), 2);
+                assert_eq!(p.formulas[0].name.parts, vec![ReferencePart::Ident("starter".to_string())]);
+                assert_eq!(p.formulas[0].quantity.value, 20000.0);
+                assert!(p.formulas[1].quantity.constant.is_some());
+                assert_eq!(p.available[0].quantity.value, 15000.0);
+            }
+            _ => panic!("Expected plan"),
+        }
+
+        assert!(Parser::parse("plan week { formulas { starter 50% } }").is_err());
+    }
+
+    #[test]
+    fn test_parse_basis() {
+        let source = r#"
+            ingredient fish_meal {
+                cost 900
+                dry_matter 92
+                nutrients {
+                    basis dry_matter
+                    protein 68
+                    basis as_fed
+                    fat 9
+                }
+            }
+            formula calf {
+                batch 1000
+                nutrients {
+                    energy min 2800
+                    basis dm
+                    protein min 18
+                }
+            }
+        "#;
+        let program = Parser::parse(source).unwrap();
+        match &program.items[0] {
+            Item::Ingredient(i) => {
+                assert_eq!(i.properties[1].name, "dry_matter");
+                assert_eq!(i.nutrients[0].basis, Basis::DryMatter);
+                assert_eq!(i.nutrients[1].basis, Basis::AsFed);
+            }
+            _ => panic!("Expected ingredient"),
+        }
+        match &program.items[1] {
+            Item::Formula(f) => {
+                assert_eq!(f.nutrients[0].basis, Basis::AsFed);
+                assert_eq!(f.nutrients[1].basis, Basis::DryMatter);
+            }
+            _ => panic!("Expected formula"),
+        }
+
+        assert!(Parser::parse("ingredient corn { cost 1 nutrients { basis wet protein 8 } }").is_err());
+    }
+
+    #[test]
+    fn test_parse_derived_nutrient() {
+        let source = r#"
+            nutrient deb {
+                unit "mEq/kg"
+                = sodium * 434.8 + potassium * 255.7 - chloride * 282
+            }
+            nutrient protein {}
+        "#;
+        let program = Parser::parse(source).unwrap();
+        match &program.items[0] {
+            Item::Nutrient(n) => {
+                assert_eq!(n.properties.len(), 1);
+                let derived = n.derived.as_ref().unwrap();
+                assert_eq!(crate::formatter::expr_text(&derived.expr), "sodium * 434.8 + potassium * 255.7 - chloride * 282");
+                assert_eq!(&source[derived.span.start..derived.span.end], "= sodium * 434.8 + potassium * 255.7 - chloride * 282");
+            }
+            _ => panic!("Expected nutrient"),
+        }
+        match &program.items[1] {
+            Item::Nutrient(n) => assert!(n.derived.is_none()),
+            _ => panic!("Expected nutrient"),
+        }
+
+        assert!(Parser::parse("nutrient deb { = sodium = potassium }").is_err());
+    }
+
+    #[test]
+    fn test_parse_units() {
+        let source = r#"
+            ingredient wheat { cost 200 nutrients { energy 13.8 "MJ/kg" zinc base_zinc "ppm" protein 12 } }
+            formula broiler {
+                batch 1000
+                nutrients { energy min 12.5 "MJ/kg" max 3200 zinc max 40 "ppm" min 20 }
+            }
+        "#;
+        let program = Parser::parse(source).unwrap();
+        match &program.items[0] {
+            Item::Ingredient(i) => {
+                assert_eq!(i.nutrients[0].unit.as_deref(), Some("MJ/kg"));
+                assert_eq!(i.nutrients[1].unit.as_deref(), Some("ppm"));
+                assert_eq!(i.nutrients[2].unit, None);
+            }
+            _ => panic!("Expected ingredient"),
+        }
+        match &program.items[1] {
+            Item::Formula(f) => {
+                let energy = &f.nutrients[0].bounds;
+                assert_eq!(energy.min.as_ref().unwrap().unit.as_deref(), Some("MJ/kg"));
+                assert_eq!(energy.max.as_ref().unwrap().unit, None);
+                let zinc = &f.nutrients[1].bounds;
+                assert_eq!(zinc.max.as_ref().unwrap().unit.as_deref(), Some("ppm"));
+                assert_eq!(zinc.min.as_ref().unwrap().unit, None);
+            }
+            _ => panic!("Expected formula"),
+        }
+
+        assert!(Parser::parse(r#"formula f { nutrients { energy "MJ/kg" min 12 } }"#).is_err());
+        assert!(Parser::parse(r#"formula f { nutrients { energy min 12 "MJ/kg" "kcal/kg" } }"#).is_err());
+    }
 }
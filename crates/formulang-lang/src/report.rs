@@ -0,0 +1,651 @@
+//! Structured solve results
+//!
+//! Builds the report of a solved formula shared by the CLI and the WASM
+//! bindings, so both present the same numbers.
+
+use formulang_solver::{Analysis, ConstraintOp, ConstraintViolation, Solution, SolutionStatus};
+
+use crate::compiler::{CompiledFormula, CompiledPlan, Compiler, ConstraintSource, MissingNutrientValue};
+
+/// Outcome of a solve, including failures before the solver ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum SolveStatus {
+    Optimal,
+    Infeasible,
+    Unbounded,
+    /// The search for the best integer solution stopped at its node limit
+    #[cfg_attr(feature = "serde", serde(rename = "node_limit"))]
+    NodeLimit,
+    Error,
+}
+
+impl From<SolutionStatus> for SolveStatus {
+    fn from(status: SolutionStatus) -> Self {
+        match status {
+            SolutionStatus::Optimal => SolveStatus::Optimal,
+            SolutionStatus::Infeasible => SolveStatus::Infeasible,
+            SolutionStatus::Unbounded => SolveStatus::Unbounded,
+            SolutionStatus::NodeLimit => SolveStatus::NodeLimit,
+            SolutionStatus::Error => SolveStatus::Error,
+        }
+    }
+}
+
+/// Everything reported about one solved formula
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SolveResult {
+    pub status: SolveStatus,
+    pub formula: String,
+    pub formula_name: Option<String>,
+    pub formula_code: Option<String>,
+    /// Formula description, or the error message when status is `error`
+    pub description: Option<String>,
+    pub batch_size: f64,
+    /// Ingredient cost, leaving out soft constraint penalties
+    pub total_cost: f64,
+    /// Dry matter of the batch in percent, when known for every ingredient used
+    pub dry_matter: Option<f64>,
+    /// Ingredients used in the batch
+    pub ingredients: Vec<IngredientResult>,
+    /// Nutrient levels achieved, sorted by id
+    pub nutrients: Vec<NutrientResult>,
+    /// Present only for optimal solutions. Reduced costs and cost ranging
+    /// cover the ingredients only, not indicator or deviation variables.
+    pub analysis: Option<Analysis>,
+    pub violations: Vec<ConstraintViolation>,
+    pub conflict: Vec<String>,
+    pub soft_constraints: Vec<SoftConstraintResult>,
+    /// Where each constraint was defined, in the order of shadow prices
+    pub constraint_sources: Vec<ConstraintSource>,
+    /// Constrained nutrients some ingredients had no value for, taken as zero
+    pub missing_values: Vec<MissingNutrientValue>,
+    /// Formulas used as ingredients in the batch, each as solved on its own
+    pub sub_formulas: Vec<SolveResult>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IngredientResult {
+    pub id: String,
+    pub name: Option<String>,
+    pub code: Option<String>,
+    pub amount: f64,
+    pub percentage: f64,
+    pub unit_cost: f64,
+    pub cost: f64,
+    pub cost_percentage: f64,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NutrientResult {
+    pub id: String,
+    pub name: Option<String>,
+    pub code: Option<String>,
+    /// Level as fed
+    pub value: f64,
+    /// Level in the batch's dry matter, when that is known
+    pub dry_matter_value: Option<f64>,
+    pub unit: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SoftConstraintResult {
+    pub constraint: String,
+    /// `shortfall` for a missed minimum, `excess` for a missed maximum
+    pub kind: String,
+    pub deviation: f64,
+    /// Unit of the deviation, or for a ratio the row it is measured on
+    pub measure: Option<String>,
+    pub penalty_cost: f64,
+}
+
+/// Everything reported about a solved plan
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PlanResult {
+    pub status: SolveStatus,
+    pub plan: String,
+    pub plan_name: Option<String>,
+    /// Plan description, or the error message when status is `error`
+    pub description: Option<String>,
+    /// Ingredient cost of every formula at its planned quantity
+    pub total_cost: f64,
+    pub formulas: Vec<PlannedFormulaResult>,
+    /// Use of each limited ingredient
+    pub supplies: Vec<SupplyResult>,
+    /// Plan constraints are named `formula.constraint`, or `ingredient_available`
+    pub violations: Vec<ConstraintViolation>,
+    pub conflict: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PlannedFormulaResult {
+    pub quantity: f64,
+    /// Ingredient cost of the whole quantity
+    pub cost: f64,
+    /// The formula's solution for one batch
+    pub result: SolveResult,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SupplyResult {
+    pub ingredient: String,
+    pub name: Option<String>,
+    pub available: f64,
+    /// Amount the formulas use together
+    pub used: f64,
+    /// Change in the plan's cost per extra unit of stock, negative when
+    /// more stock would make the plan cheaper
+    pub shadow_price: f64,
+}
+
+impl PlanResult {
+    /// Whether the result holds a plan: the optimum, or the best one found
+    /// before the node limit
+    pub fn has_solution(&self) -> bool {
+        matches!(self.status, SolveStatus::Optimal | SolveStatus::NodeLimit)
+            && self.formulas.iter().all(|planned| planned.result.has_solution())
+    }
+
+    /// A result for a plan that could not be compiled or solved
+    pub fn error(plan: &str, message: impl Into<String>) -> Self {
+        Self {
+            status: SolveStatus::Error,
+            plan: plan.to_string(),
+            plan_name: None,
+            description: Some(message.into()),
+            total_cost: 0.0,
+            formulas: vec![],
+            supplies: vec![],
+            violations: vec![],
+            conflict: vec![],
+        }
+    }
+
+    /// Report a solution of `compiled`, with each formula as if solved alone
+    pub fn new(compiler: &Compiler, compiled: &CompiledPlan, solution: &Solution) -> Self {
+        let solved = solution.has_values();
+
+        let formulas: Vec<PlannedFormulaResult> = compiled
+            .formulas
+            .iter()
+            .map(|planned| {
+                let result = SolveResult::new(compiler, &planned.compiled, &planned.solution(solution));
+                PlannedFormulaResult {
+                    quantity: planned.quantity,
+                    cost: result.total_cost * planned.scale(),
+                    result,
+                }
+            })
+            .collect();
+
+        let supplies = compiled
+            .supplies
+            .iter()
+            .map(|supply| {
+                let row = &compiled.lp_problem.constraints[supply.constraint];
+                let used = row.coefficients.iter().zip(&solution.values).map(|(a, x)| a * x).sum();
+                SupplyResult {
+                    ingredient: supply.ingredient.clone(),
+                    name: compiler.symbols.ingredients.get(&supply.ingredient).and_then(|i| i.display_name.clone()),
+                    available: supply.available,
+                    used,
+                    shadow_price: match solution.analysis.shadow_prices.get(supply.constraint) {
+                        Some(sp) if solved => sp.value,
+                        _ => 0.0,
+                    },
+                }
+            })
+            .collect();
+
+        Self {
+            status: solution.status.into(),
+            plan: compiled.name.clone(),
+            plan_name: compiled.display_name.clone(),
+            description: compiled.description.clone(),
+            total_cost: formulas.iter().map(|f| f.cost).sum(),
+            formulas,
+            supplies,
+            violations: describe_conflict(solution, |row| {
+                compiled.formulas.iter().find_map(|planned| {
+                    let row = row.strip_prefix(&format!("{}.", planned.compiled.name))?;
+                    planned.compiled.moved_bound(row)
+                })
+            }),
+            conflict: solution.conflict.clone(),
+        }
+    }
+}
+
+impl SolveResult {
+    /// Whether the result holds a solution: the optimum, or the best one
+    /// found before the node limit
+    pub fn has_solution(&self) -> bool {
+        matches!(self.status, SolveStatus::Optimal | SolveStatus::NodeLimit) && self.analysis.is_some()
+    }
+
+    /// A result for a formula that could not be compiled or solved
+    pub fn error(formula: &str, message: impl Into<String>) -> Self {
+        Self {
+            status: SolveStatus::Error,
+            formula: formula.to_string(),
+            formula_name: None,
+            formula_code: None,
+            description: Some(message.into()),
+            batch_size: 0.0,
+            total_cost: 0.0,
+            dry_matter: None,
+            ingredients: vec![],
+            nutrients: vec![],
+            analysis: None,
+            violations: vec![],
+            conflict: vec![],
+            soft_constraints: vec![],
+            constraint_sources: vec![],
+            missing_values: vec![],
+            sub_formulas: vec![],
+        }
+    }
+
+    /// Report a solution of `compiled`, looking up display names and codes
+    /// in the compiler's symbol table
+    pub fn new(compiler: &Compiler, compiled: &CompiledFormula, solution: &Solution) -> Self {
+        let solved = solution.has_values();
+        let total_cost = compiled.ingredient_cost(&solution.values);
+
+        // Calculate ingredient results with costs
+        let mut ingredients = Vec::new();
+        let mut sub_formulas = Vec::new();
+        for (i, ing_id) in compiled.ingredients.iter().enumerate() {
+            let amount = solution.values.get(i).copied().unwrap_or(0.0);
+            if amount > 0.001 {
+                let unit_cost = compiled.ingredient_costs.get(i).copied().unwrap_or(0.0);
+                let cost = amount * unit_cost;
+                let (name, code) = match compiler.symbols.ingredients.get(ing_id) {
+                    Some(meta) => (meta.display_name.clone(), meta.code.clone()),
+                    None => match compiled.sub_formulas.iter().find(|sub| &sub.compiled.name == ing_id) {
+                        Some(sub) => {
+                            sub_formulas.push(SolveResult::new(compiler, &sub.compiled, &sub.solution));
+                            (sub.compiled.display_name.clone(), sub.compiled.code.clone())
+                        }
+                        None => (None, None),
+                    },
+                };
+
+                ingredients.push(IngredientResult {
+                    id: ing_id.clone(),
+                    name,
+                    code,
+                    amount,
+                    percentage: amount / compiled.batch_size * 100.0,
+                    unit_cost,
+                    cost,
+                    cost_percentage: if total_cost > 0.0 {
+                        cost / total_cost * 100.0
+                    } else {
+                        0.0
+                    },
+                });
+            }
+        }
+
+        // Calculate nutrient values achieved, as fed and in the dry matter
+        let dry_matter = solved.then(|| compiled.dry_matter(&solution.values)).flatten();
+        let mut nutrients = Vec::new();
+        for (i, nut_id) in compiled.nutrient_names.iter().enumerate() {
+            let mut total_value = 0.0;
+            for (j, amount) in solution.values.iter().enumerate() {
+                if *amount > 0.001
+                    && let Some(ing_nuts) = compiled.ingredient_nutrients.get(j)
+                    && let Some(nut_value) = ing_nuts.get(nut_id)
+                {
+                    // nutrient value is per 100 units, so scale by amount/100
+                    total_value += nut_value * amount / 100.0;
+                }
+            }
+            let meta = compiler.symbols.nutrients.get(nut_id);
+
+            // Convert to percentage of batch
+            let value = total_value / compiled.batch_size * 100.0;
+            nutrients.push(NutrientResult {
+                id: nut_id.clone(),
+                name: meta.and_then(|m| m.display_name.clone()),
+                code: meta.and_then(|m| m.code.clone()),
+                value,
+                dry_matter_value: dry_matter.filter(|dm| *dm > 0.0).map(|dm| value / dm * 100.0),
+                unit: compiled.nutrient_units.get(i).cloned().flatten(),
+            });
+        }
+        nutrients.sort_by(|a, b| a.id.cmp(&b.id));
+
+        // Report how far each soft constraint was missed
+        let soft_constraints = if solved {
+            compiled
+                .soft_constraints
+                .iter()
+                .map(|soft| {
+                    let deviation = compiled.soft_deviation(soft, &solution.values);
+                    SoftConstraintResult {
+                        constraint: soft.constraint.clone(),
+                        kind: match soft.op {
+                            ConstraintOp::Le => "excess".to_string(),
+                            _ => "shortfall".to_string(),
+                        },
+                        deviation,
+                        measure: soft.measure.clone(),
+                        penalty_cost: compiled.soft_penalty(soft, &solution.values),
+                    }
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        Self {
+            status: solution.status.into(),
+            formula: compiled.name.clone(),
+            formula_name: compiled.display_name.clone(),
+            formula_code: compiled.code.clone(),
+            description: compiled.description.clone(),
+            batch_size: compiled.batch_size,
+            total_cost,
+            dry_matter,
+            ingredients,
+            nutrients,
+            analysis: solved.then(|| analysis_result(compiled, solution)),
+            violations: describe_conflict(solution, |row| compiled.moved_bound(row)),
+            conflict: solution.conflict.clone(),
+            soft_constraints,
+            constraint_sources: compiled.constraint_sources.clone(),
+            missing_values: compiled.missing_values.clone(),
+            sub_formulas,
+        }
+    }
+
+    /// Where the constraint named `constraint` was defined
+    pub fn constraint_source(&self, constraint: &str) -> Option<&ConstraintSource> {
+        self.constraint_sources.iter().find(|s| s.constraint == constraint)
+    }
+}
+
+/// The solver's analysis with variables past the ingredients left out
+fn analysis_result(compiled: &CompiledFormula, solution: &Solution) -> Analysis {
+    let num_ingredients = compiled.ingredients.len();
+    let mut analysis = solution.analysis.clone();
+    analysis.reduced_costs.truncate(num_ingredients);
+    analysis.objective_sensitivity.truncate(num_ingredients);
+
+    // Prices and ranges of a row whose right-hand side isn't its bound
+    // aren't in the bound's units, so they are left out, and so is the row
+    // from the binding list that is read beside them
+    analysis.shadow_prices.retain(|sp| compiled.moved_bound(&sp.constraint).is_none());
+    analysis.rhs_sensitivity.retain(|range| compiled.moved_bound(&range.name).is_none());
+    analysis.binding_constraints.retain(|row| compiled.moved_bound(row).is_none());
+    analysis
+}
+
+/// The solver's conflict descriptions, except that rows whose right-hand
+/// side isn't their bound are named with the reason instead of quoting
+/// amounts of the row
+fn describe_conflict(solution: &Solution, moved_bound: impl Fn(&str) -> Option<String>) -> Vec<ConstraintViolation> {
+    solution
+        .violations
+        .iter()
+        .map(|v| {
+            let Some(reason) = moved_bound(&v.constraint) else {
+                return v.clone();
+            };
+            let others: Vec<&str> = solution
+                .conflict
+                .iter()
+                .filter(|name| **name != v.constraint)
+                .map(String::as_str)
+                .collect();
+            let with = if others.is_empty() { String::new() } else { format!(" with {}", others.join(", ")) };
+            ConstraintViolation {
+                description: format!("{}, {}, conflicts{}", v.constraint, reason, with),
+                ..v.clone()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+    use formulang_solver::Solver;
+
+    fn solve(source: &str, formula: &str) -> SolveResult {
+        let program = Parser::parse(source).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.load(&program).unwrap();
+        let compiled = compiler.compile_formula(formula).unwrap();
+        let solution = Solver::new().solve(&compiled.lp_problem);
+        SolveResult::new(&compiler, &compiled, &solution)
+    }
+
+    #[test]
+    fn test_optimal_result() {
+        let result = solve(
+            r#"
+            nutrient protein { name "Crude Protein" unit "%" }
+            nutrient fiber {}
+
+            ingredient corn {
+                name "Yellow Corn"
+                cost 100
+                nutrients { protein 8.0 fiber 2.0 }
+            }
+
+            ingredient soy {
+                cost 300
+                nutrients { protein 44.0 }
+            }
+
+            ingredient hulls {
+                cost 400
+                nutrients { protein 4.0 }
+            }
+
+            formula test {
+                batch_size 200
+                nutrients { protein min 17 }
+                ingredients { corn soy hulls }
+            }
+            "#,
+            "test",
+        );
+
+        assert_eq!(result.status, SolveStatus::Optimal);
+        // 150 corn and 50 soy give exactly 17% protein
+        assert_eq!(result.ingredients.len(), 2);
+        let corn = &result.ingredients[0];
+        assert_eq!(corn.name.as_deref(), Some("Yellow Corn"));
+        assert!((corn.amount - 150.0).abs() < 1e-6);
+        assert!((corn.percentage - 75.0).abs() < 1e-6);
+        assert!((result.total_cost - 30000.0).abs() < 1e-6);
+
+        let ids: Vec<&str> = result.nutrients.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, vec!["fiber", "protein"]);
+        assert!((result.nutrients[1].value - 17.0).abs() < 1e-6);
+        assert_eq!(result.nutrients[1].unit.as_deref(), Some("%"));
+        assert_eq!(result.dry_matter, None);
+        assert_eq!(result.nutrients[1].dry_matter_value, None);
+
+        let analysis = result.analysis.unwrap();
+        assert!(analysis.binding_constraints.contains(&"protein_min".to_string()));
+        assert_eq!(analysis.reduced_costs.len(), 3);
+        assert!(analysis.reduced_costs.iter().any(|rc| rc.variable == "hulls" && !rc.is_basic));
+        assert_eq!(analysis.objective_sensitivity.len(), 3);
+    }
+
+    #[test]
+    fn test_sub_formula_result() {
+        let result = solve(
+            r#"
+            nutrient vitamin_a {}
+            ingredient corn { cost 100 nutrients { vitamin_a 0 } }
+            ingredient vit_a_500 { cost 5000 nutrients { vitamin_a 500 } }
+            ingredient carrier { cost 50 nutrients { vitamin_a 0 } }
+            formula premix {
+                name "Vitamin Premix"
+                batch 100
+                nutrients { vitamin_a min 100 }
+                ingredients { vit_a_500 carrier }
+            }
+            formula feed {
+                batch 1000
+                nutrients { vitamin_a min 1 }
+                ingredients { corn premix }
+            }
+            "#,
+            "feed",
+        );
+
+        let premix = result.ingredients.iter().find(|ing| ing.id == "premix").unwrap();
+        assert_eq!(premix.name.as_deref(), Some("Vitamin Premix"));
+        assert!((premix.amount - 10.0).abs() < 1e-6);
+        assert!((premix.unit_cost - 1040.0).abs() < 1e-6);
+
+        assert_eq!(result.sub_formulas.len(), 1);
+        let sub = &result.sub_formulas[0];
+        assert_eq!(sub.formula, "premix");
+        assert!((sub.total_cost - 104000.0).abs() < 1e-6);
+        assert_eq!(sub.ingredients.len(), 2);
+    }
+
+    #[test]
+    fn test_dry_matter_result() {
+        let result = solve(
+            r#"
+            nutrient protein {}
+            ingredient hay { cost 100 moisture 20 nutrients { protein 8 } }
+            ingredient soy { cost 300 dry_matter 90 nutrients { protein 45 } }
+            formula calf {
+                batch 100
+                nutrients { protein min 8 }
+                ingredients { hay soy }
+            }
+            "#,
+            "calf",
+        );
+
+        // 100 hay: 80% dry matter, 8% protein as fed and 10% in the dry matter
+        assert!((result.dry_matter.unwrap() - 80.0).abs() < 1e-6);
+        assert!((result.nutrients[0].value - 8.0).abs() < 1e-6);
+        assert!((result.nutrients[0].dry_matter_value.unwrap() - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_conditional_bound_result() {
+        let result = solve(
+            r#"
+            nutrient protein {}
+            ingredient fish { cost 250 nutrients { protein 60 } }
+            formula test {
+                batch_size 100
+                ingredients { fish max 50% or 0 }
+            }
+            "#,
+            "test",
+        );
+
+        // The row is `fish <= 50 * fish_used`, so its 0 isn't quoted
+        assert_eq!(result.status, SolveStatus::Infeasible);
+        assert_eq!(result.conflict, vec!["fish_max", "batch_size"]);
+        assert_eq!(
+            result.violations[0].description,
+            "fish_max, which only applies when fish is used, conflicts with batch_size"
+        );
+
+        // Chalk is needed for phosphorus, so the cheaper calcium in lime is
+        // shut out by its indicator row
+        let result = solve(
+            r#"
+            nutrient calcium {}
+            nutrient phosphorus {}
+            ingredient corn { cost 20 nutrients { calcium 1 phosphorus 1 } }
+            ingredient chalk { cost 60 nutrients { calcium 30 phosphorus 10 } }
+            ingredient lime { cost 30 nutrients { calcium 38 phosphorus 0 } }
+            formula test {
+                batch_size 100
+                max_ingredients 2
+                nutrients { calcium min 12 phosphorus min 3 }
+                ingredients { corn min 50% chalk lime }
+            }
+            "#,
+            "test",
+        );
+        assert_eq!(result.status, SolveStatus::Optimal);
+        let analysis = result.analysis.unwrap();
+        assert!(analysis.binding_constraints.contains(&"calcium_min".to_string()));
+        assert!(!analysis.binding_constraints.contains(&"lime_link".to_string()));
+        assert!(analysis.shadow_prices.iter().all(|sp| sp.constraint != "lime_link"));
+    }
+
+    #[test]
+    fn test_dry_matter_bound_result() {
+        let source = r#"
+            nutrient protein {}
+            ingredient hay { cost 100 moisture 20 nutrients { protein 8 } }
+            ingredient soy { cost 300 dry_matter 90 nutrients { protein 45 } }
+            formula calf {
+                batch 100
+                nutrients { basis dry_matter protein min 20 }
+                ingredients { hay soy max 10% }
+            }
+            "#;
+
+        // The row's right-hand side is 0, so no amounts are quoted for it
+        let result = solve(source, "calf");
+        assert_eq!(result.status, SolveStatus::Infeasible);
+        assert_eq!(result.conflict, vec!["protein_min", "soy_max", "batch_size"]);
+        assert_eq!(
+            result.violations[0].description,
+            "protein_min, a level in the dry matter, conflicts with soy_max, batch_size"
+        );
+        assert!(result.violations[1].description.starts_with("soy_max allows at most 10.00"));
+
+        // 100 hay has 10% protein in the dry matter: 10 short, charged on the
+        // 80% of the batch that is dry matter
+        let result = solve(&source.replace("protein min 20", "protein min 20 soft 50"), "calf");
+        assert_eq!(result.status, SolveStatus::Optimal);
+        let soft = &result.soft_constraints[0];
+        assert!((soft.deviation - 10.0).abs() < 1e-6);
+        assert!((soft.penalty_cost - 400.0).abs() < 1e-6);
+        assert_eq!(soft.measure.as_deref(), Some("dry matter"));
+        let analysis = result.analysis.unwrap();
+        assert!(!analysis.binding_constraints.contains(&"protein_min".to_string()));
+        assert!(analysis.shadow_prices.iter().all(|sp| sp.constraint != "protein_min"));
+        assert!(analysis.rhs_sensitivity.iter().all(|range| range.name != "protein_min"));
+    }
+
+    #[test]
+    fn test_infeasible_result() {
+        let result = solve(
+            r#"
+            nutrient protein {}
+            ingredient corn { cost 100 nutrients { protein 8.0 } }
+            formula test {
+                batch_size 100
+                nutrients { protein min 20 }
+                ingredients { corn }
+            }
+            "#,
+            "test",
+        );
+
+        assert_eq!(result.status, SolveStatus::Infeasible);
+        assert!(result.analysis.is_none());
+        assert!(result.ingredients.is_empty());
+        assert!(!result.violations.is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+//! Units of measure for nutrient levels
+//!
+//! A nutrient's `unit` is shown as written, but the units listed here also
+//! have a dimension and a scale, so values and bounds given in one unit are
+//! converted to the nutrient's own unit when compiling.
+
+use thiserror::Error;
+
+use crate::compiler::closest_name;
+
+/// What a unit measures. Only units of the same dimension convert.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    /// Mass of nutrient per mass of feed, such as `%` or `mg/kg`
+    Concentration,
+    /// Energy per mass of feed, such as `kcal/kg` or `MJ/kg`
+    Energy,
+    /// Vitamin activity per mass of feed, such as `IU/kg`
+    Activity,
+}
+
+impl std::fmt::Display for Dimension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dimension::Concentration => write!(f, "a concentration"),
+            Dimension::Energy => write!(f, "an energy"),
+            Dimension::Activity => write!(f, "an activity"),
+        }
+    }
+}
+
+/// A known unit and its size in the base unit of its dimension (`%`,
+/// `kcal/kg` or `IU/kg`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unit {
+    pub name: &'static str,
+    pub dimension: Dimension,
+    pub scale: f64,
+}
+
+const KCAL_PER_MJ: f64 = 1000.0 / 4.184;
+const KG_PER_LB: f64 = 0.45359237;
+
+const fn unit(name: &'static str, dimension: Dimension, scale: f64) -> Unit {
+    Unit { name, dimension, scale }
+}
+
+/// Every unit that converts
+pub const UNITS: &[Unit] = &[
+    unit("%", Dimension::Concentration, 1.0),
+    unit("g/100g", Dimension::Concentration, 1.0),
+    unit("g/kg", Dimension::Concentration, 0.1),
+    unit("mg/g", Dimension::Concentration, 0.1),
+    unit("mg/kg", Dimension::Concentration, 1e-4),
+    unit("g/t", Dimension::Concentration, 1e-4),
+    unit("ppm", Dimension::Concentration, 1e-4),
+    unit("ug/g", Dimension::Concentration, 1e-4),
+    unit("µg/g", Dimension::Concentration, 1e-4),
+    unit("ug/kg", Dimension::Concentration, 1e-7),
+    unit("µg/kg", Dimension::Concentration, 1e-7),
+    unit("ppb", Dimension::Concentration, 1e-7),
+    unit("kcal/kg", Dimension::Energy, 1.0),
+    unit("cal/g", Dimension::Energy, 1.0),
+    unit("kcal/g", Dimension::Energy, 1000.0),
+    unit("Mcal/kg", Dimension::Energy, 1000.0),
+    unit("kcal/lb", Dimension::Energy, 1.0 / KG_PER_LB),
+    unit("Mcal/lb", Dimension::Energy, 1000.0 / KG_PER_LB),
+    unit("kJ/kg", Dimension::Energy, KCAL_PER_MJ / 1000.0),
+    unit("kJ/g", Dimension::Energy, KCAL_PER_MJ),
+    unit("MJ/kg", Dimension::Energy, KCAL_PER_MJ),
+    unit("IU/kg", Dimension::Activity, 1.0),
+    unit("IU/g", Dimension::Activity, 1000.0),
+    unit("kIU/kg", Dimension::Activity, 1000.0),
+    unit("MIU/kg", Dimension::Activity, 1e6),
+    unit("MIU/t", Dimension::Activity, 1000.0),
+    unit("IU/lb", Dimension::Activity, 1.0 / KG_PER_LB),
+];
+
+/// Look up a unit by name
+pub fn lookup(name: &str) -> Option<&'static Unit> {
+    UNITS.iter().find(|u| u.name == name)
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum UnitError {
+    #[error("unknown unit {unit}{}", suggestion.as_ref().map(|s| format!(". Did you mean {}?", s)).unwrap_or_default())]
+    Unknown {
+        unit: String,
+        /// A known unit with a similar name
+        suggestion: Option<String>,
+    },
+    #[error("{from} is {}, but {to} is {}", .from_dimension, .to_dimension)]
+    Incompatible {
+        from: String,
+        to: String,
+        from_dimension: Dimension,
+        to_dimension: Dimension,
+    },
+    #[error("{to} is not a known unit, so {from} cannot be converted to it")]
+    UnknownTarget { from: String, to: String },
+    #[error("no unit is declared to convert {from} to")]
+    NoUnit { from: String },
+    #[error("none is declared, so it cannot be combined with {to}")]
+    Undeclared { to: String },
+    #[error("a ratio has no unit, so it cannot be given in {unit}")]
+    Ratio { unit: String },
+}
+
+/// Factor that converts a value in `from` to the unit `to`. The same name
+/// always converts, even when it isn't a known unit.
+pub fn conversion(from: &str, to: Option<&str>) -> Result<f64, UnitError> {
+    let Some(to) = to else {
+        return Err(UnitError::NoUnit { from: from.to_string() });
+    };
+    if from == to {
+        return Ok(1.0);
+    }
+    let source = lookup(from).ok_or_else(|| UnitError::Unknown {
+        unit: from.to_string(),
+        suggestion: closest_name(from, UNITS.iter().map(|u| u.name)),
+    })?;
+    let target = lookup(to).ok_or_else(|| UnitError::UnknownTarget {
+        from: from.to_string(),
+        to: to.to_string(),
+    })?;
+    if source.dimension != target.dimension {
+        return Err(UnitError::Incompatible {
+            from: from.to_string(),
+            to: to.to_string(),
+            from_dimension: source.dimension,
+            to_dimension: target.dimension,
+        });
+    }
+    Ok(source.scale / target.scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion() {
+        assert!((conversion("MJ/kg", Some("kcal/kg")).unwrap() - 239.0057).abs() < 1e-4);
+        assert!((conversion("ppm", Some("%")).unwrap() - 1e-4).abs() < 1e-12);
+        assert_eq!(conversion("mg/kg", Some("ppm")).unwrap(), 1.0);
+        assert_eq!(conversion("mEq/kg", Some("mEq/kg")).unwrap(), 1.0);
+
+        assert!(matches!(
+            conversion("MJ/kg", Some("%")),
+            Err(UnitError::Incompatible { from_dimension: Dimension::Energy, .. })
+        ));
+        assert_eq!(
+            conversion("MJ/kgg", Some("kcal/kg")),
+            Err(UnitError::Unknown { unit: "MJ/kgg".to_string(), suggestion: Some("MJ/kg".to_string()) })
+        );
+        assert!(matches!(conversion("%", Some("ratio")), Err(UnitError::UnknownTarget { .. })));
+        assert!(matches!(conversion("%", None), Err(UnitError::NoUnit { .. })));
+    }
+}
@@ -0,0 +1,386 @@
+//! Least-cost formulation problems of increasing size, generated with the
+//! shape the compiler produces: min/max rows per nutrient, an inclusion limit
+//! per ingredient, and the batch size equality.
+//!
+//! Each size is solved by the revised simplex and by a dense tableau baseline,
+//! the two-phase method the solver used before it kept the matrix sparse.
+//! The dense tableau wins on the smallest size; the revised simplex only pulls
+//! ahead as the problem grows, which is the trade the switch was made for.
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use formulang_solver::{ConstraintOp, LpProblem, Solver};
+
+/// Small deterministic generator so every run solves the same problems
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn feed_problem(ingredients: usize, nutrients: usize) -> LpProblem {
+    let mut rng = Lcg(ingredients as u64 * 1000 + nutrients as u64);
+    let batch = 1000.0;
+
+    let names = (0..ingredients).map(|j| format!("ing{}", j)).collect();
+    let mut problem = LpProblem::new(names);
+    let costs = (0..ingredients).map(|_| 50.0 + 450.0 * rng.next()).collect();
+    problem.set_objective(costs, true);
+
+    // Nutrient content is sparse: each ingredient supplies about a third of them.
+    // Bounds bracket the content of an even mix, so the problem is feasible.
+    for i in 0..nutrients {
+        let content: Vec<f64> = (0..ingredients)
+            .map(|_| if rng.next() < 0.35 { 50.0 * rng.next() } else { 0.0 })
+            .collect();
+        let average = content.iter().sum::<f64>() / ingredients as f64;
+        problem.add_constraint(format!("n{}_min", i), content.clone(), ConstraintOp::Ge, 0.8 * average * batch);
+        problem.add_constraint(format!("n{}_max", i), content, ConstraintOp::Le, 1.5 * average * batch);
+    }
+
+    for j in 0..ingredients {
+        let mut row = vec![0.0; ingredients];
+        row[j] = 1.0;
+        problem.add_constraint(format!("ing{}_max", j), row, ConstraintOp::Le, 0.2 * batch);
+    }
+
+    problem.add_constraint("batch_size", vec![1.0; ingredients], ConstraintOp::Eq, batch);
+    problem
+}
+
+/// Dense two-phase tableau simplex kept as a baseline for the revised simplex.
+/// Like [`Solver::solve`] it prices every row and ranges every cost and RHS,
+/// but it only supports `x >= 0` bounds and skips infeasibility diagnosis.
+///
+/// This is a frozen reference copy of the solver as it was before the switch
+/// to the revised simplex (9c658c2~1:crates/formulang-solver/src/simplex.rs).
+/// Don't fix or optimize it along with the solver: it exists to be compared
+/// against, and changing it changes what the benchmark measures.
+mod dense_tableau {
+    use formulang_solver::{ConstraintOp, LpProblem};
+
+    const TOLERANCE: f64 = 1e-9;
+    const MAX_ITERATIONS: usize = 10000;
+
+    struct Tableau {
+        data: Vec<Vec<f64>>,
+        basic_vars: Vec<usize>,
+        /// First artificial column; artificials sit between it and the RHS
+        art_start: usize,
+        /// Whether each row was negated to make its RHS non-negative
+        row_flipped: Vec<bool>,
+        /// The column that started as +/- e_row, and its sign
+        unit_cols: Vec<(usize, f64)>,
+    }
+
+    /// What the baseline computes for an optimal solve
+    pub struct Optimum {
+        pub objective: f64,
+        pub shadow_prices: Vec<f64>,
+        pub cost_ranges: Vec<(f64, f64)>,
+        pub rhs_ranges: Vec<(f64, f64)>,
+    }
+
+    /// Solve and range the problem, or `None` if it is infeasible or unbounded
+    pub fn solve(problem: &LpProblem) -> Option<Optimum> {
+        let mut tableau = build_tableau(problem);
+        if tableau.art_start < tableau.data[0].len() - 1 && !phase1(&mut tableau) {
+            return None;
+        }
+        let exclude_from = tableau.art_start;
+        iterate(&mut tableau, exclude_from)?;
+
+        let n_vars = problem.num_variables();
+        let rhs_col = tableau.data[0].len() - 1;
+        let mut values = vec![0.0; n_vars];
+        for (i, &basic) in tableau.basic_vars.iter().enumerate() {
+            if basic < n_vars {
+                values[basic] = tableau.data[i][rhs_col];
+            }
+        }
+        let objective = values.iter().zip(&problem.objective.coefficients).map(|(x, c)| x * c).sum();
+
+        let obj_row = tableau.data.len() - 1;
+        let orient = if problem.objective.minimize { -1.0 } else { 1.0 };
+        let shadow_prices = (0..problem.num_constraints())
+            .map(|i| {
+                let (col, sign) = tableau.unit_cols[i];
+                let flip = if tableau.row_flipped[i] { -1.0 } else { 1.0 };
+                -orient * flip * sign * tableau.data[obj_row][col]
+            })
+            .collect();
+
+        let mut basic_row = vec![None; n_vars];
+        for (i, &basic) in tableau.basic_vars.iter().enumerate() {
+            if basic < n_vars {
+                basic_row[basic] = Some(i);
+            }
+        }
+        let cost_ranges = (0..n_vars).map(|j| cost_range(&tableau, j, basic_row[j])).collect();
+        let rhs_ranges = (0..problem.num_constraints()).map(|i| rhs_range(&tableau, i)).collect();
+
+        Some(Optimum { objective, shadow_prices, cost_ranges, rhs_ranges })
+    }
+
+    /// Allowed change of a variable's tableau cost before the basis changes
+    fn cost_range(tableau: &Tableau, var: usize, basic_row: Option<usize>) -> (f64, f64) {
+        let obj_row = tableau.data.len() - 1;
+        let Some(row) = basic_row else {
+            return (f64::NEG_INFINITY, (-tableau.data[obj_row][var]).max(0.0));
+        };
+
+        let mut lo = f64::NEG_INFINITY;
+        let mut hi = f64::INFINITY;
+        for k in 0..tableau.art_start {
+            if k == var || tableau.basic_vars.contains(&k) {
+                continue;
+            }
+            let alpha = tableau.data[row][k];
+            if alpha.abs() <= TOLERANCE {
+                continue;
+            }
+            let ratio = tableau.data[obj_row][k].min(0.0) / alpha;
+            if alpha > 0.0 {
+                lo = lo.max(ratio);
+            } else {
+                hi = hi.min(ratio);
+            }
+        }
+        (lo, hi)
+    }
+
+    /// Allowed change of a row's tableau RHS before a basic variable turns negative
+    fn rhs_range(tableau: &Tableau, constraint: usize) -> (f64, f64) {
+        let n_constraints = tableau.data.len() - 1;
+        let rhs_col = tableau.data[0].len() - 1;
+        let (col, sign) = tableau.unit_cols[constraint];
+
+        let mut lo = f64::NEG_INFINITY;
+        let mut hi = f64::INFINITY;
+        for r in 0..n_constraints {
+            let beta = sign * tableau.data[r][col];
+            if beta.abs() <= TOLERANCE {
+                continue;
+            }
+            if tableau.basic_vars[r] >= tableau.art_start {
+                return (0.0, 0.0);
+            }
+            let ratio = -tableau.data[r][rhs_col].max(0.0) / beta;
+            if beta > 0.0 {
+                lo = lo.max(ratio);
+            } else {
+                hi = hi.min(ratio);
+            }
+        }
+        (lo, hi)
+    }
+
+    fn build_tableau(problem: &LpProblem) -> Tableau {
+        let n_vars = problem.num_variables();
+        let n_constraints = problem.num_constraints();
+
+        let mut n_slack = 0;
+        let mut n_artificial = 0;
+        for c in &problem.constraints {
+            match c.op {
+                ConstraintOp::Le => {
+                    n_slack += 1;
+                    // A negative RHS flips the row to >=, so its slack can't start basic
+                    if c.rhs < 0.0 {
+                        n_artificial += 1;
+                    }
+                }
+                ConstraintOp::Ge => {
+                    n_slack += 1;
+                    n_artificial += 1;
+                }
+                ConstraintOp::Eq => n_artificial += 1,
+            }
+        }
+
+        let total_cols = n_vars + n_slack + n_artificial + 1;
+        let mut data = vec![vec![0.0; total_cols]; n_constraints + 1];
+        let mut basic_vars = vec![0; n_constraints];
+        let mut row_flipped = vec![false; n_constraints];
+        let mut unit_cols = vec![(0, 1.0); n_constraints];
+        let mut slack_idx = n_vars;
+        let mut artificial_idx = n_vars + n_slack;
+
+        for (i, c) in problem.constraints.iter().enumerate() {
+            // Keep the RHS non-negative by negating the row
+            let flip = c.rhs < 0.0;
+            let sign = if flip { -1.0 } else { 1.0 };
+            for (j, &coef) in c.coefficients.iter().enumerate() {
+                data[i][j] = sign * coef;
+            }
+            data[i][total_cols - 1] = sign * c.rhs;
+            row_flipped[i] = flip;
+
+            let (slack, artificial) = match c.op {
+                ConstraintOp::Le => (Some(sign), flip),
+                ConstraintOp::Ge => (Some(-sign), true),
+                ConstraintOp::Eq => (None, true),
+            };
+            if let Some(slack) = slack {
+                data[i][slack_idx] = slack;
+                basic_vars[i] = slack_idx;
+                unit_cols[i] = (slack_idx, sign);
+                slack_idx += 1;
+            }
+            if artificial {
+                data[i][artificial_idx] = 1.0;
+                basic_vars[i] = artificial_idx;
+                unit_cols[i] = (artificial_idx, 1.0);
+                artificial_idx += 1;
+            }
+        }
+
+        // The tableau maximizes, so a minimized objective is negated
+        for (j, &coef) in problem.objective.coefficients.iter().enumerate() {
+            data[n_constraints][j] = if problem.objective.minimize { -coef } else { coef };
+        }
+
+        Tableau {
+            data,
+            basic_vars,
+            art_start: n_vars + n_slack,
+            row_flipped,
+            unit_cols,
+        }
+    }
+
+    fn phase1(tableau: &mut Tableau) -> bool {
+        let n_constraints = tableau.data.len() - 1;
+        let n_cols = tableau.data[0].len();
+        let art_start = tableau.art_start;
+
+        // Maximize minus the sum of the artificials, priced out against the basis
+        let objective = std::mem::replace(&mut tableau.data[n_constraints], vec![0.0; n_cols]);
+        for j in art_start..n_cols - 1 {
+            tableau.data[n_constraints][j] = -1.0;
+        }
+        for i in 0..n_constraints {
+            if tableau.basic_vars[i] >= art_start {
+                for j in 0..n_cols {
+                    tableau.data[n_constraints][j] += tableau.data[i][j];
+                }
+            }
+        }
+
+        if iterate(tableau, n_cols - 1).is_none() {
+            return false;
+        }
+        let rhs_col = n_cols - 1;
+        if (0..n_constraints).any(|i| tableau.basic_vars[i] >= art_start && tableau.data[i][rhs_col].abs() > TOLERANCE) {
+            return false;
+        }
+
+        // Drive degenerate artificials out of the basis before phase 2
+        for i in 0..n_constraints {
+            if tableau.basic_vars[i] < art_start {
+                continue;
+            }
+            if let Some(col) = (0..art_start).find(|&j| tableau.data[i][j].abs() > TOLERANCE) {
+                pivot(tableau, i, col);
+            }
+        }
+
+        // Restore the real objective and price out the basic variables
+        tableau.data[n_constraints] = objective;
+        for i in 0..n_constraints {
+            let ratio = tableau.data[n_constraints][tableau.basic_vars[i]];
+            if ratio.abs() > TOLERANCE {
+                for j in 0..n_cols {
+                    tableau.data[n_constraints][j] -= ratio * tableau.data[i][j];
+                }
+            }
+        }
+        true
+    }
+
+    /// Pivot on the most positive reduced cost among the first `n_cols`
+    /// columns until none is left; `None` if the problem is unbounded
+    fn iterate(tableau: &mut Tableau, n_cols: usize) -> Option<()> {
+        let obj_row = tableau.data.len() - 1;
+        let rhs_col = tableau.data[0].len() - 1;
+        for _ in 0..MAX_ITERATIONS {
+            let mut entering = None;
+            let mut best = TOLERANCE;
+            for j in 0..n_cols {
+                if tableau.data[obj_row][j] > best {
+                    best = tableau.data[obj_row][j];
+                    entering = Some(j);
+                }
+            }
+            let Some(col) = entering else {
+                return Some(());
+            };
+
+            let mut leaving = None;
+            let mut min_ratio = f64::INFINITY;
+            for i in 0..obj_row {
+                let val = tableau.data[i][col];
+                if val > TOLERANCE {
+                    let ratio = tableau.data[i][rhs_col].max(0.0) / val;
+                    if ratio < min_ratio {
+                        min_ratio = ratio;
+                        leaving = Some(i);
+                    }
+                }
+            }
+            pivot(tableau, leaving?, col);
+        }
+        Some(())
+    }
+
+    fn pivot(tableau: &mut Tableau, row: usize, col: usize) {
+        tableau.basic_vars[row] = col;
+        let pivot_val = tableau.data[row][col];
+        for value in &mut tableau.data[row] {
+            *value /= pivot_val;
+        }
+        let pivot_row = tableau.data[row].clone();
+        for (i, data_row) in tableau.data.iter_mut().enumerate() {
+            let factor = data_row[col];
+            if i != row && factor != 0.0 {
+                for (value, p) in data_row.iter_mut().zip(&pivot_row) {
+                    *value -= factor * p;
+                }
+            }
+        }
+    }
+}
+
+fn bench_solve(c: &mut Criterion) {
+    let mut group = c.benchmark_group("solve");
+    group.sample_size(10);
+    for (ingredients, nutrients) in [(30, 10), (100, 30), (300, 80)] {
+        let problem = feed_problem(ingredients, nutrients);
+        let solver = Solver::new();
+        let size = format!("{}x{}", ingredients, nutrients);
+
+        // Both methods must reach the same optimum and prices for the timings to compare
+        let sparse = solver.solve(&problem);
+        let dense = dense_tableau::solve(&problem).expect("baseline finds the optimum");
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-6 * a.abs().max(1.0);
+        assert!(close(sparse.objective_value, dense.objective), "{}: objective differs", size);
+        for (price, dense_price) in sparse.analysis.shadow_prices.iter().zip(&dense.shadow_prices) {
+            assert!(close(price.value, *dense_price), "{}: shadow price of {} differs", size, price.constraint);
+        }
+        assert_eq!(dense.cost_ranges.len(), sparse.analysis.objective_sensitivity.len());
+        assert_eq!(dense.rhs_ranges.len(), sparse.analysis.rhs_sensitivity.len());
+
+        group.bench_with_input(BenchmarkId::new("revised", &size), &problem, |b, problem| {
+            b.iter(|| solver.solve(problem))
+        });
+        group.bench_with_input(BenchmarkId::new("dense_tableau", &size), &problem, |b, problem| {
+            b.iter(|| dense_tableau::solve(problem))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_solve);
+criterion_main!(benches);
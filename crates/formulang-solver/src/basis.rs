@@ -0,0 +1,177 @@
+/// Entries smaller than this are dropped from eta columns
+const DROP_TOLERANCE: f64 = 1e-12;
+
+/// Smallest pivot accepted when refactorizing
+const PIVOT_TOLERANCE: f64 = 1e-9;
+
+/// Column-wise sparse matrix
+pub(crate) struct SparseMatrix {
+    pub(crate) rows: usize,
+    /// Nonzero `(row, value)` entries of each column
+    pub(crate) columns: Vec<Vec<(usize, f64)>>,
+}
+
+impl SparseMatrix {
+    pub(crate) fn new(rows: usize) -> Self {
+        Self { rows, columns: Vec::new() }
+    }
+
+    /// Append a column, returning its index
+    pub(crate) fn push_column(&mut self, entries: Vec<(usize, f64)>) -> usize {
+        self.columns.push(entries);
+        self.columns.len() - 1
+    }
+
+    /// Dot product of a column with a dense row vector
+    pub(crate) fn dot(&self, col: usize, y: &[f64]) -> f64 {
+        self.columns[col].iter().map(|&(i, v)| v * y[i]).sum()
+    }
+
+    /// A column as a dense vector
+    pub(crate) fn dense_column(&self, col: usize) -> Vec<f64> {
+        let mut out = vec![0.0; self.rows];
+        for &(i, v) in &self.columns[col] {
+            out[i] = v;
+        }
+        out
+    }
+}
+
+/// One elementary transformation of the product-form inverse.
+/// Applied to a vector it divides the pivot entry by `pivot`, then
+/// subtracts `value * x[row]` from every other listed entry.
+struct Eta {
+    row: usize,
+    pivot: f64,
+    entries: Vec<(usize, f64)>,
+}
+
+impl Eta {
+    fn from_column(row: usize, column: &[f64]) -> Self {
+        let entries = column
+            .iter()
+            .enumerate()
+            .filter(|&(i, v)| i != row && v.abs() > DROP_TOLERANCE)
+            .map(|(i, &v)| (i, v))
+            .collect();
+        Self { row, pivot: column[row], entries }
+    }
+}
+
+/// Basis matrix kept as a product of eta matrices: B^-1 = E_k ... E_1.
+/// Each simplex pivot appends one eta; `refactor` rebuilds the product from
+/// the basic columns alone once it gets long.
+pub(crate) struct Basis {
+    /// Variable (matrix column) basic in each row position
+    pub(crate) heading: Vec<usize>,
+    etas: Vec<Eta>,
+    /// Pivots since the last refactorization
+    pub(crate) updates: usize,
+}
+
+impl Basis {
+    pub(crate) fn new(heading: Vec<usize>) -> Self {
+        Self { heading, etas: Vec::new(), updates: 0 }
+    }
+
+    /// Solve B x = a in place (FTRAN)
+    pub(crate) fn ftran(&self, x: &mut [f64]) {
+        for eta in &self.etas {
+            let xr = x[eta.row];
+            if xr == 0.0 {
+                continue;
+            }
+            let xr = xr / eta.pivot;
+            x[eta.row] = xr;
+            for &(i, v) in &eta.entries {
+                x[i] -= v * xr;
+            }
+        }
+    }
+
+    /// Solve y B = c in place (BTRAN)
+    pub(crate) fn btran(&self, y: &mut [f64]) {
+        for eta in self.etas.iter().rev() {
+            let sum: f64 = eta.entries.iter().map(|&(i, v)| v * y[i]).sum();
+            y[eta.row] = (y[eta.row] - sum) / eta.pivot;
+        }
+    }
+
+    /// Replace the variable basic at `row` by the one whose FTRAN'd column is `alpha`
+    pub(crate) fn replace(&mut self, row: usize, var: usize, alpha: &[f64]) {
+        self.etas.push(Eta::from_column(row, alpha));
+        self.heading[row] = var;
+        self.updates += 1;
+    }
+
+    /// Rebuild the eta file from the current basic columns. Unit-like columns
+    /// (slacks and artificials) are placed first since they need at most a
+    /// scaling; the rest are pivoted in on their largest remaining entry.
+    /// Returns `false` if the basis is numerically singular.
+    pub(crate) fn refactor(&mut self, matrix: &SparseMatrix) -> bool {
+        // On failure the current eta file stays in use; try again later
+        self.updates = 0;
+
+        let m = matrix.rows;
+        let mut fresh = Basis::new(vec![usize::MAX; m]);
+        let mut rest = Vec::new();
+
+        for &var in &self.heading {
+            match matrix.columns[var][..] {
+                [(i, v)] if fresh.heading[i] == usize::MAX && v.abs() > PIVOT_TOLERANCE => {
+                    fresh.heading[i] = var;
+                    if v != 1.0 {
+                        fresh.etas.push(Eta { row: i, pivot: v, entries: Vec::new() });
+                    }
+                }
+                _ => rest.push(var),
+            }
+        }
+
+        for var in rest {
+            let mut column = matrix.dense_column(var);
+            fresh.ftran(&mut column);
+            let pivot_row = (0..m)
+                .filter(|&i| fresh.heading[i] == usize::MAX)
+                .max_by(|&a, &b| column[a].abs().total_cmp(&column[b].abs()));
+            match pivot_row {
+                Some(r) if column[r].abs() > PIVOT_TOLERANCE => {
+                    fresh.etas.push(Eta::from_column(r, &column));
+                    fresh.heading[r] = var;
+                }
+                _ => return false,
+            }
+        }
+
+        *self = fresh;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refactor_and_solve() {
+        // B = [[2, 1], [0, 1]] with columns (2, 0) and (1, 1)
+        let mut matrix = SparseMatrix::new(2);
+        matrix.push_column(vec![(0, 2.0)]);
+        matrix.push_column(vec![(0, 1.0), (1, 1.0)]);
+        let mut basis = Basis::new(vec![0, 1]);
+        assert!(basis.refactor(&matrix));
+
+        // B x = (4, 3) -> x1 = 3 (column 1), x0 = 0.5 (column 0)
+        let mut x = vec![4.0, 3.0];
+        basis.ftran(&mut x);
+        let value = |var: usize, x: &[f64]| x[basis.heading.iter().position(|&v| v == var).unwrap()];
+        assert!((value(0, &x) - 0.5).abs() < 1e-12);
+        assert!((value(1, &x) - 3.0).abs() < 1e-12);
+
+        // y B = (c0, c1) in heading order, checked against the columns
+        let mut y: Vec<f64> = basis.heading.iter().map(|&v| [1.0, 5.0][v]).collect();
+        basis.btran(&mut y);
+        assert!((matrix.dot(0, &y) - 1.0).abs() < 1e-12);
+        assert!((matrix.dot(1, &y) - 5.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::problem::{ConstraintOp, LpProblem, VariableKind};
+use crate::simplex::Solver;
+use crate::solution::{Solution, SolutionStatus};
+
+/// Tolerance for deciding whether a relaxed value is already integral
+const INTEGRALITY_TOLERANCE: f64 = 1e-6;
+
+/// Outcome of a branch-and-bound search
+pub(crate) struct Search {
+    /// The best integer solution found
+    pub incumbent: Option<Solution>,
+    /// Whether the node limit cut the search short, so that a better
+    /// solution, or any solution at all, may have been missed
+    pub stopped: bool,
+}
+
+/// A variable bound added while branching
+#[derive(Debug, Clone, Copy)]
+struct Branch {
+    var: usize,
+    op: ConstraintOp,
+    value: f64,
+}
+
+impl Solver {
+    /// Depth-first branch-and-bound over the simplex relaxation.
+    ///
+    /// Each node is the original problem with variable bounds tightened along
+    /// its branch. A search cut short by the node limit returns the best
+    /// solution found with status `NodeLimit`, and is never diagnosed as
+    /// infeasible since it didn't rule every branch out.
+    pub(crate) fn solve_mip(&self, problem: &LpProblem) -> Solution {
+        let search = self.branch_and_bound(problem, false);
+        if let Some(incumbent) = search.incumbent {
+            let mut solution = self.finish_mip(problem, incumbent);
+            if search.stopped {
+                solution.status = SolutionStatus::NodeLimit;
+            }
+            return solution;
+        }
+        if search.stopped {
+            return Solution::node_limit();
+        }
+
+        match self.solve_relaxed(problem).status {
+            SolutionStatus::Unbounded => Solution::unbounded(),
+            // Either the continuous relaxation or the integer problem is infeasible
+            _ => self.diagnose_infeasible(problem),
+        }
+    }
+
+    /// Search for the best integer solution, or stop at the first one found
+    /// when `first_feasible` is set
+    pub(crate) fn branch_and_bound(&self, problem: &LpProblem, first_feasible: bool) -> Search {
+        let minimize = problem.objective.minimize;
+
+        let mut incumbent: Option<Solution> = None;
+        let mut stack: Vec<Vec<Branch>> = vec![Vec::new()];
+        let mut nodes = 0;
+        let mut stopped = false;
+
+        while let Some(branches) = stack.pop() {
+            if nodes >= self.max_nodes {
+                // Node limit reached, keep the best solution found so far
+                stopped = true;
+                break;
+            }
+            nodes += 1;
+
+            let node = with_branches(problem, &branches);
+            let solution = self.solve_relaxed(&node);
+            if solution.status != SolutionStatus::Optimal {
+                continue;
+            }
+
+            // Prune nodes whose relaxation can't beat the incumbent
+            if let Some(best) = &incumbent
+                && !improves(solution.objective_value, best.objective_value, minimize, self.tolerance)
+            {
+                continue;
+            }
+
+            match most_fractional(problem, &solution.values) {
+                None => {
+                    incumbent = Some(solution);
+                    if first_feasible {
+                        break;
+                    }
+                }
+                Some((var, value)) => {
+                    let down = Branch { var, op: ConstraintOp::Le, value: value.floor() };
+                    let up = Branch { var, op: ConstraintOp::Ge, value: value.ceil() };
+
+                    // Explore the nearer side first (it is pushed last)
+                    let (first, second) = if value - value.floor() < 0.5 { (down, up) } else { (up, down) };
+                    for branch in [second, first] {
+                        let mut child = branches.clone();
+                        child.push(branch);
+                        stack.push(child);
+                    }
+                }
+            }
+        }
+
+        Search { incumbent, stopped }
+    }
+
+    /// Fix every integer variable at its rounded incumbent value and solve the
+    /// LP that is left, so that shadow prices, reduced costs and ranges
+    /// describe the solution with the same integer choices rather than the
+    /// relaxation at the incumbent's node, where unbranched variables were free
+    fn finish_mip(&self, problem: &LpProblem, incumbent: Solution) -> Solution {
+        let mut fixed = problem.clone();
+        for (j, kind) in problem.variable_kinds.iter().enumerate() {
+            if *kind != VariableKind::Continuous {
+                let value = incumbent.values[j].round();
+                fixed.set_bounds(j, value, value);
+            }
+        }
+
+        // The incumbent satisfies the fixed LP, so this only fails on round-off
+        let mut solution = self.solve_relaxed(&fixed);
+        if solution.status != SolutionStatus::Optimal {
+            solution = incumbent;
+        }
+        for (j, kind) in problem.variable_kinds.iter().enumerate() {
+            if *kind != VariableKind::Continuous {
+                solution.values[j] = solution.values[j].round();
+            }
+        }
+        for rc in &mut solution.analysis.reduced_costs {
+            if let Some(j) = problem.variables.iter().position(|v| *v == rc.variable) {
+                rc.value = solution.values[j];
+            }
+        }
+        solution
+    }
+}
+
+/// The problem with branch bounds applied to its variables
+fn with_branches(problem: &LpProblem, branches: &[Branch]) -> LpProblem {
+    let mut node = problem.clone();
+    for branch in branches {
+        match branch.op {
+            ConstraintOp::Le => node.upper_bounds[branch.var] = node.upper_bounds[branch.var].min(branch.value),
+            ConstraintOp::Ge => node.lower_bounds[branch.var] = node.lower_bounds[branch.var].max(branch.value),
+            ConstraintOp::Eq => node.set_bounds(branch.var, branch.value, branch.value),
+        }
+    }
+    node
+}
+
+/// Whether `candidate` is strictly better than `best`
+fn improves(candidate: f64, best: f64, minimize: bool, tolerance: f64) -> bool {
+    let margin = tolerance.max(1e-9 * best.abs());
+    if minimize {
+        candidate < best - margin
+    } else {
+        candidate > best + margin
+    }
+}
+
+/// The integer variable whose relaxed value is furthest from a whole number
+fn most_fractional(problem: &LpProblem, values: &[f64]) -> Option<(usize, f64)> {
+    let mut best: Option<(usize, f64, f64)> = None;
+    for (j, kind) in problem.variable_kinds.iter().enumerate() {
+        if *kind == VariableKind::Continuous {
+            continue;
+        }
+        let value = values[j];
+        let fraction = (value - value.round()).abs();
+        if fraction > INTEGRALITY_TOLERANCE && best.is_none_or(|(_, _, f)| fraction > f) {
+            best = Some((j, value, fraction));
+        }
+    }
+    best.map(|(j, value, _)| (j, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_program() {
+        // Maximize 5x + 4y subject to 6x + 4y <= 24, x + 2y <= 6
+        // The LP optimum is (3, 1.5) = 21; the integer optimum is (4, 0) = 20
+        let mut problem = LpProblem::new(vec!["x".to_string(), "y".to_string()]);
+        problem.set_objective(vec![5.0, 4.0], false);
+        problem.add_constraint("c1", vec![6.0, 4.0], ConstraintOp::Le, 24.0);
+        problem.add_constraint("c2", vec![1.0, 2.0], ConstraintOp::Le, 6.0);
+        problem.set_variable_kind(0, VariableKind::Integer);
+        problem.set_variable_kind(1, VariableKind::Integer);
+
+        let solution = Solver::new().solve(&problem);
+
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        assert_eq!(solution.values, vec![4.0, 0.0]);
+        assert!((solution.objective_value - 20.0).abs() < 1e-6);
+        // Branching only tightens bounds, so no extra rows are reported
+        assert_eq!(solution.analysis.shadow_prices.len(), 2);
+    }
+
+    #[test]
+    fn test_binary_knapsack() {
+        // Weights 5, 4, 3 with capacity 8; values 10, 7, 6
+        let mut problem = LpProblem::new(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        problem.set_objective(vec![10.0, 7.0, 6.0], false);
+        problem.add_constraint("capacity", vec![5.0, 4.0, 3.0], ConstraintOp::Le, 8.0);
+        for j in 0..3 {
+            problem.set_variable_kind(j, VariableKind::Binary);
+        }
+
+        let solution = Solver::new().solve(&problem);
+
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        assert_eq!(solution.values, vec![1.0, 0.0, 1.0]);
+        assert!((solution.objective_value - 16.0).abs() < 1e-6);
+    }
+
+    /// Pick up to `count` of three ingredients to make 100 units with at least
+    /// 1200 calcium and 300 phosphorus: corn, chalk and a cheaper calcium
+    /// source, lime, that chalk's phosphorus keeps out
+    fn count_limited(count: f64) -> LpProblem {
+        let mut problem = LpProblem::new(vec!["corn".to_string(), "chalk".to_string(), "lime".to_string()]);
+        problem.set_objective(vec![20.0, 60.0, 30.0], true);
+        problem.add_constraint("calcium", vec![1.0, 30.0, 38.0], ConstraintOp::Ge, 1200.0);
+        problem.add_constraint("phosphorus", vec![1.0, 10.0, 0.0], ConstraintOp::Ge, 300.0);
+        problem.add_constraint("corn_min", vec![1.0, 0.0, 0.0], ConstraintOp::Ge, 50.0);
+        let used: Vec<usize> = ["corn", "chalk", "lime"]
+            .iter()
+            .map(|name| problem.add_variable(format!("{}_used", name), VariableKind::Binary))
+            .collect();
+        for (j, &u) in used.iter().enumerate() {
+            let mut row = vec![0.0; 6];
+            row[j] = 1.0;
+            row[u] = -100.0;
+            problem.add_constraint(format!("link{}", j), row, ConstraintOp::Le, 0.0);
+        }
+        problem.add_constraint("count", vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0], ConstraintOp::Le, count);
+        problem.add_constraint("batch", vec![1.0, 1.0, 1.0, 0.0, 0.0, 0.0], ConstraintOp::Eq, 100.0);
+        problem
+    }
+
+    #[test]
+    fn test_mip_analysis_with_integers_fixed() {
+        let problem = count_limited(2.0);
+        let solution = Solver::new().solve(&problem);
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        assert_eq!(&solution.values[3..], &[1.0, 1.0, 0.0]);
+
+        // Prices are those of the LP with the ingredient choice fixed, where
+        // the count row only holds fixed variables and is worth nothing
+        let mut fixed = problem.clone();
+        for (j, value) in [(3, 1.0), (4, 1.0), (5, 0.0)] {
+            fixed.set_bounds(j, value, value);
+        }
+        let expected = Solver::new().solve(&fixed);
+        for (price, expected) in solution.analysis.shadow_prices.iter().zip(&expected.analysis.shadow_prices) {
+            assert!((price.value - expected.value).abs() < 1e-6, "{}", price.constraint);
+        }
+        let count = solution.analysis.shadow_prices.iter().find(|sp| sp.constraint == "count").unwrap();
+        assert_eq!(count.value, 0.0);
+    }
+
+    #[test]
+    fn test_node_limit() {
+        let problem = count_limited(2.0);
+
+        // The root relaxation is fractional, so one node finds nothing; the
+        // search was cut short, which doesn't make the problem infeasible
+        let solution = Solver::new().with_max_nodes(1).solve(&problem);
+        assert_eq!(solution.status, SolutionStatus::NodeLimit);
+        assert!(solution.values.is_empty());
+        assert!(solution.conflict.is_empty());
+        assert!(!solution.has_values());
+
+        // A limit reached after finding a solution keeps it, unproven
+        let nodes = (2..100)
+            .find(|&n| Solver::new().with_max_nodes(n).solve(&problem).has_values())
+            .unwrap();
+        let solution = Solver::new().with_max_nodes(nodes).solve(&problem);
+        assert_eq!(solution.status, SolutionStatus::NodeLimit);
+        assert_eq!(solution.values.len(), 6);
+    }
+
+    #[test]
+    fn test_integer_infeasible() {
+        // 2x = 3 has no integer solution
+        let mut problem = LpProblem::new(vec!["x".to_string()]);
+        problem.set_objective(vec![1.0], true);
+        problem.add_constraint("half", vec![2.0], ConstraintOp::Eq, 3.0);
+        problem.set_variable_kind(0, VariableKind::Integer);
+
+        let solution = Solver::new().solve(&problem);
+
+        assert_eq!(solution.status, SolutionStatus::Infeasible);
+    }
+}
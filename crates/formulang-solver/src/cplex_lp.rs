@@ -0,0 +1,571 @@
+//! CPLEX LP format reading and writing
+//!
+//! The LP format restricts names more than MPS: characters such as `+`, `-`,
+//! `*` and spaces are replaced by `_` when writing, so a constraint like
+//! `(methionine+cystine)/lysine_min` is written as
+//! `(methionine_cystine)/lysine_min`. Other names are kept as they are.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::problem::{ConstraintOp, LpProblem, VariableKind};
+use crate::text_format::{FormatError, parse_number, unique_names};
+
+/// Characters allowed in LP format names besides letters and digits
+const NAME_SYMBOLS: &str = "!\"#$%&()/,.;?@_`'{}|~";
+
+/// Words that start a section or have a meaning of their own
+const KEYWORDS: &[&str] = &[
+    "minimize", "minimum", "min", "maximize", "maximum", "max", "subject", "such", "st", "s.t.", "st.",
+    "bounds", "bound", "general", "generals", "gen", "integer", "integers", "binary", "binaries", "bin",
+    "end", "free", "inf", "infinity",
+];
+
+/// Longest line written before terms continue on the next one
+const LINE_WIDTH: usize = 200;
+
+impl LpProblem {
+    /// Write the problem in CPLEX LP format
+    pub fn to_cplex_lp(&self, name: &str) -> String {
+        let columns = unique_names(&self.variables, valid_name);
+        let rows = unique_names(self.constraints.iter().map(|c| &c.name), valid_name);
+
+        let mut out = String::new();
+        let _ = writeln!(out, "\\ Problem: {}", name);
+        out.push_str(if self.objective.minimize { "Minimize\n" } else { "Maximize\n" });
+        // Every variable appears in the objective so that none is lost
+        let all: Vec<usize> = (0..columns.len()).collect();
+        write_terms(&mut out, " obj:", &all, &self.objective.coefficients, &columns);
+        out.push('\n');
+
+        out.push_str("Subject To\n");
+        for (constraint, row) in self.constraints.iter().zip(&rows) {
+            let nonzero: Vec<usize> = (0..columns.len())
+                .filter(|&j| constraint.coefficients.get(j).is_some_and(|&c| c != 0.0))
+                .collect();
+            // A row needs at least one term
+            let terms = if nonzero.is_empty() && !columns.is_empty() { vec![0] } else { nonzero };
+            write_terms(&mut out, &format!(" {}:", row), &terms, &constraint.coefficients, &columns);
+            let op = match constraint.op {
+                ConstraintOp::Le => "<=",
+                ConstraintOp::Ge => ">=",
+                ConstraintOp::Eq => "=",
+            };
+            let _ = writeln!(out, " {} {}", op, constraint.rhs);
+        }
+
+        let mut bounds = String::new();
+        for (j, column) in columns.iter().enumerate() {
+            if self.variable_kinds[j] == VariableKind::Binary {
+                continue;
+            }
+            let _ = match (self.lower_bounds[j], self.upper_bounds[j]) {
+                (0.0, f64::INFINITY) => continue,
+                (f64::NEG_INFINITY, f64::INFINITY) => writeln!(bounds, " {} free", column),
+                (lower, upper) if lower == upper => writeln!(bounds, " {} = {}", column, lower),
+                (0.0, upper) => writeln!(bounds, " {} <= {}", column, upper),
+                (lower, upper) => {
+                    writeln!(bounds, " {} <= {} <= {}", format_bound(lower), column, format_bound(upper))
+                }
+            };
+        }
+        if !bounds.is_empty() {
+            out.push_str("Bounds\n");
+            out.push_str(&bounds);
+        }
+
+        for (kind, section) in [(VariableKind::Integer, "General"), (VariableKind::Binary, "Binary")] {
+            let names: Vec<&str> = columns
+                .iter()
+                .zip(&self.variable_kinds)
+                .filter(|(_, k)| **k == kind)
+                .map(|(name, _)| name.as_str())
+                .collect();
+            if !names.is_empty() {
+                let _ = writeln!(out, "{}\n {}", section, names.join(" "));
+            }
+        }
+
+        out.push_str("End\n");
+        out
+    }
+
+    /// Read a problem in CPLEX LP format. Quadratic terms, ranges,
+    /// semi-continuous variables, and special ordered sets are not supported.
+    pub fn from_cplex_lp(text: &str) -> Result<Self, FormatError> {
+        let tokens = tokenize(text)?;
+        let mut reader = Reader {
+            tokens,
+            pos: 0,
+            columns: Vec::new(),
+            column_index: HashMap::new(),
+        };
+        reader.read()
+    }
+}
+
+/// Replace characters the LP format does not allow in names, and prefix
+/// names that would read as a number or a keyword
+fn valid_name(name: &str) -> String {
+    let mut valid: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || NAME_SYMBOLS.contains(c) { c } else { '_' })
+        .collect();
+    let starts_badly = valid.starts_with(|c: char| c.is_ascii_digit() || c == '.');
+    if valid.is_empty() || starts_badly || KEYWORDS.contains(&valid.to_ascii_lowercase().as_str()) {
+        valid.insert(0, '_');
+    }
+    valid
+}
+
+fn format_bound(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write `label` followed by the terms `coefficients[j] columns[j]` for each
+/// `j` in `indices`, wrapping long lines
+fn write_terms(out: &mut String, label: &str, indices: &[usize], coefficients: &[f64], columns: &[String]) {
+    let mut line = label.to_string();
+    for (n, &j) in indices.iter().enumerate() {
+        let value = coefficients.get(j).copied().unwrap_or(0.0);
+        let term = match (n, value < 0.0) {
+            (0, false) => format!(" {} {}", value, columns[j]),
+            (0, true) => format!(" - {} {}", -value, columns[j]),
+            (_, false) => format!(" + {} {}", value, columns[j]),
+            (_, true) => format!(" - {} {}", -value, columns[j]),
+        };
+        if line.len() + term.len() > LINE_WIDTH {
+            out.push_str(&line);
+            out.push('\n');
+            line = String::from("   ");
+        }
+        line.push_str(&term);
+    }
+    out.push_str(&line);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Sign(f64),
+    Op(ConstraintOp),
+    Colon,
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, FormatError> {
+    let mut tokens = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        // Comments run from a backslash to the end of the line
+        let content = raw.split('\\').next().unwrap_or("");
+        let chars: Vec<char> = content.chars().collect();
+        let mut k = 0;
+        while k < chars.len() {
+            let c = chars[k];
+            let start = k;
+            k += 1;
+            let token = match c {
+                _ if c.is_whitespace() => continue,
+                ':' => Token::Colon,
+                '+' => Token::Sign(1.0),
+                '-' => Token::Sign(-1.0),
+                '<' | '>' | '=' => {
+                    // `<=`, `=<`, `<`, `>=`, `=>`, `>` and `=`
+                    if k < chars.len() && matches!(chars[k], '<' | '>' | '=') {
+                        k += 1;
+                    }
+                    let op: String = chars[start..k].iter().collect();
+                    Token::Op(match op.as_str() {
+                        "<" | "<=" | "=<" => ConstraintOp::Le,
+                        ">" | ">=" | "=>" => ConstraintOp::Ge,
+                        "=" | "==" => ConstraintOp::Eq,
+                        _ => return Err(FormatError::new(line, format!("unknown operator {}", op))),
+                    })
+                }
+                _ if c.is_ascii_digit() || c == '.' => {
+                    while k < chars.len() {
+                        let d = chars[k];
+                        let exponent_sign = matches!(d, '+' | '-') && matches!(chars[k - 1], 'e' | 'E');
+                        if d.is_ascii_digit() || d == '.' || d == 'e' || d == 'E' || exponent_sign {
+                            k += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    let number: String = chars[start..k].iter().collect();
+                    Token::Number(parse_number(&number, line)?)
+                }
+                _ if c.is_ascii_alphabetic() || NAME_SYMBOLS.contains(c) => {
+                    while k < chars.len() && (chars[k].is_ascii_alphanumeric() || NAME_SYMBOLS.contains(chars[k])) {
+                        k += 1;
+                    }
+                    Token::Name(chars[start..k].iter().collect())
+                }
+                _ => return Err(FormatError::new(line, format!("unexpected character `{}`", c))),
+            };
+            tokens.push((token, line));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+    Objective(bool),
+    Constraints,
+    Bounds,
+    General,
+    Binary,
+    End,
+}
+
+/// A constraint as read: name, terms, operator, and right-hand side
+type Row = (String, Vec<(usize, f64)>, ConstraintOp, f64);
+
+struct Reader {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    columns: Vec<String>,
+    column_index: HashMap<String, usize>,
+}
+
+impl Reader {
+    fn read(&mut self) -> Result<LpProblem, FormatError> {
+        let mut minimize = true;
+        let mut costs: HashMap<usize, f64> = HashMap::new();
+        let mut rows: Vec<Row> = Vec::new();
+        let mut bounds: HashMap<usize, (f64, f64)> = HashMap::new();
+        let mut kinds: HashMap<usize, VariableKind> = HashMap::new();
+
+        let mut section = match self.section() {
+            Some(section @ Section::Objective(_)) => section,
+            _ => return Err(self.error("expected Minimize or Maximize")),
+        };
+        loop {
+            match section {
+                Section::Objective(min) => {
+                    minimize = min;
+                    self.label();
+                    for (j, value) in self.terms()? {
+                        *costs.entry(j).or_insert(0.0) += value;
+                    }
+                }
+                Section::Constraints => {
+                    while !self.at_section() && self.pos < self.tokens.len() {
+                        let name = self.label().unwrap_or_else(|| format!("R{}", rows.len() + 1));
+                        let terms = self.terms()?;
+                        let op = match self.next() {
+                            Some(Token::Op(op)) => op,
+                            _ => return Err(self.error("expected <=, >= or =")),
+                        };
+                        let rhs = self.value()?;
+                        rows.push((name, terms, op, rhs));
+                    }
+                }
+                Section::Bounds => {
+                    while !self.at_section() && self.pos < self.tokens.len() {
+                        self.bound(&mut bounds)?;
+                    }
+                }
+                Section::General | Section::Binary => {
+                    let kind = if section == Section::General {
+                        VariableKind::Integer
+                    } else {
+                        VariableKind::Binary
+                    };
+                    while !self.at_section() {
+                        match self.next() {
+                            Some(Token::Name(name)) => {
+                                let j = self.column(&name);
+                                kinds.insert(j, kind);
+                            }
+                            None => break,
+                            _ => return Err(self.error("expected a variable name")),
+                        }
+                    }
+                }
+                Section::End => break,
+            }
+
+            section = match self.section() {
+                Some(Section::Objective(_)) => return Err(self.error("a second objective is not supported")),
+                Some(next) => next,
+                None if self.pos >= self.tokens.len() => break,
+                None => return Err(self.error("unexpected token")),
+            };
+        }
+
+        let mut problem = LpProblem::new(self.columns.clone());
+        let n = problem.num_variables();
+        let mut objective = vec![0.0; n];
+        for (j, value) in costs {
+            objective[j] = value;
+        }
+        problem.set_objective(objective, minimize);
+        for (name, terms, op, rhs) in rows {
+            let mut coefficients = vec![0.0; n];
+            for (j, value) in terms {
+                coefficients[j] += value;
+            }
+            problem.add_constraint(name, coefficients, op, rhs);
+        }
+        for (j, (lower, upper)) in bounds {
+            problem.set_bounds(j, lower, upper);
+        }
+        for (j, kind) in kinds {
+            problem.set_variable_kind(j, kind);
+        }
+        Ok(problem)
+    }
+
+    fn peek(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek(0).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, message: &str) -> FormatError {
+        let index = self.pos.min(self.tokens.len().saturating_sub(1));
+        let line = self.tokens.get(index).map(|(_, line)| *line).unwrap_or(1);
+        FormatError::new(line, message)
+    }
+
+    /// Section keyword at the current token and how many tokens it spans
+    fn section_at(&self) -> Option<(Section, usize)> {
+        let Some(Token::Name(word)) = self.peek(0) else {
+            return None;
+        };
+        let second = match self.peek(1) {
+            Some(Token::Name(w)) => w.to_ascii_lowercase(),
+            _ => String::new(),
+        };
+        // A name followed by a colon is a label, whatever it is called
+        if self.peek(1) == Some(&Token::Colon) {
+            return None;
+        }
+        Some(match word.to_ascii_lowercase().as_str() {
+            "minimize" | "minimum" | "min" => (Section::Objective(true), 1),
+            "maximize" | "maximum" | "max" => (Section::Objective(false), 1),
+            "subject" if second == "to" => (Section::Constraints, 2),
+            "such" if second == "that" => (Section::Constraints, 2),
+            "st" | "s.t." | "st." => (Section::Constraints, 1),
+            "bounds" | "bound" => (Section::Bounds, 1),
+            "general" | "generals" | "gen" | "integer" | "integers" => (Section::General, 1),
+            "binary" | "binaries" | "bin" => (Section::Binary, 1),
+            "end" => (Section::End, 1),
+            _ => return None,
+        })
+    }
+
+    fn at_section(&self) -> bool {
+        self.section_at().is_some()
+    }
+
+    fn section(&mut self) -> Option<Section> {
+        let (section, len) = self.section_at()?;
+        self.pos += len;
+        Some(section)
+    }
+
+    /// Consume a `name:` label if there is one
+    fn label(&mut self) -> Option<String> {
+        if let (Some(Token::Name(name)), Some(Token::Colon)) = (self.peek(0), self.peek(1)) {
+            let name = name.clone();
+            self.pos += 2;
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    fn column(&mut self, name: &str) -> usize {
+        if let Some(&j) = self.column_index.get(name) {
+            return j;
+        }
+        self.columns.push(name.to_string());
+        self.column_index.insert(name.to_string(), self.columns.len() - 1);
+        self.columns.len() - 1
+    }
+
+    /// Read a linear expression up to an operator or section keyword
+    fn terms(&mut self) -> Result<Vec<(usize, f64)>, FormatError> {
+        let mut terms = Vec::new();
+        loop {
+            if self.at_section() || matches!(self.peek(0), None | Some(Token::Op(_))) {
+                return Ok(terms);
+            }
+            let mut coefficient = 1.0;
+            while let Some(Token::Sign(sign)) = self.peek(0) {
+                coefficient *= sign;
+                self.pos += 1;
+            }
+            if let Some(Token::Number(value)) = self.peek(0) {
+                coefficient *= value;
+                self.pos += 1;
+            }
+            match self.next() {
+                Some(Token::Name(name)) => {
+                    let j = self.column(&name);
+                    terms.push((j, coefficient));
+                }
+                _ => return Err(self.error("expected a variable name")),
+            }
+        }
+    }
+
+    /// Read a signed number, which may be infinite
+    fn value(&mut self) -> Result<f64, FormatError> {
+        let mut sign = 1.0;
+        while let Some(Token::Sign(s)) = self.peek(0) {
+            sign *= s;
+            self.pos += 1;
+        }
+        match self.next() {
+            Some(Token::Number(value)) => Ok(sign * value),
+            Some(Token::Name(word)) if matches!(word.to_ascii_lowercase().as_str(), "inf" | "infinity") => {
+                Ok(sign * f64::INFINITY)
+            }
+            _ => Err(self.error("expected a number")),
+        }
+    }
+
+    /// Whether the current token starts a value rather than a variable name
+    fn at_value(&self) -> bool {
+        match self.peek(0) {
+            Some(Token::Number(_) | Token::Sign(_)) => true,
+            Some(Token::Name(word)) => {
+                matches!(word.to_ascii_lowercase().as_str(), "inf" | "infinity")
+                    && matches!(self.peek(1), Some(Token::Op(_)))
+            }
+            _ => false,
+        }
+    }
+
+    /// Read one bound: `x free`, `x op v`, `v op x`, or `v op x op v`
+    fn bound(&mut self, bounds: &mut HashMap<usize, (f64, f64)>) -> Result<(), FormatError> {
+        let apply = |(lower, upper): &mut (f64, f64), op: ConstraintOp, value: f64, variable_first: bool| {
+            match (op, variable_first) {
+                (ConstraintOp::Eq, _) => (*lower, *upper) = (value, value),
+                (ConstraintOp::Le, true) | (ConstraintOp::Ge, false) => *upper = value,
+                (ConstraintOp::Ge, true) | (ConstraintOp::Le, false) => *lower = value,
+            }
+        };
+
+        if self.at_value() {
+            let value = self.value()?;
+            let Some(Token::Op(op)) = self.next() else {
+                return Err(self.error("expected <=, >= or ="));
+            };
+            let Some(Token::Name(name)) = self.next() else {
+                return Err(self.error("expected a variable name"));
+            };
+            let j = self.column(&name);
+            let entry = bounds.entry(j).or_insert((0.0, f64::INFINITY));
+            apply(entry, op, value, false);
+            if let Some(Token::Op(op)) = self.peek(0).cloned() {
+                self.pos += 1;
+                let value = self.value()?;
+                apply(bounds.get_mut(&j).unwrap(), op, value, true);
+            }
+            return Ok(());
+        }
+
+        let Some(Token::Name(name)) = self.next() else {
+            return Err(self.error("expected a bound"));
+        };
+        let j = self.column(&name);
+        let entry = bounds.entry(j).or_insert((0.0, f64::INFINITY));
+        match self.next() {
+            Some(Token::Name(word)) if word.eq_ignore_ascii_case("free") => {
+                *entry = (f64::NEG_INFINITY, f64::INFINITY);
+            }
+            Some(Token::Op(op)) => {
+                let value = self.value()?;
+                apply(entry, op, value, true);
+            }
+            _ => return Err(self.error("expected <=, >=, = or free")),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Solver;
+    use crate::mps::tests::{assert_same_problem, sample_problem};
+
+    #[test]
+    fn test_cplex_lp_round_trip() {
+        let lp = sample_problem();
+        let text = lp.to_cplex_lp("starter");
+        assert!(text.contains(" protein_min: 8.5 corn + 48 grains.soy >= 1800\n"));
+        assert!(text.contains(" 5 <= grains.soy <= 60\n"));
+        assert!(text.contains(" bags free\n"));
+        assert!(text.contains("Binary\n corn_used\n"));
+
+        let read = LpProblem::from_cplex_lp(&text).unwrap();
+        assert_same_problem(&lp, &read);
+
+        let solver = Solver::new();
+        let (a, b) = (solver.solve(&lp), solver.solve(&read));
+        assert_eq!(a.status, b.status);
+        assert!((a.objective_value - b.objective_value).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cplex_lp_names() {
+        let mut lp = LpProblem::new(vec!["x".to_string(), "2nd".to_string()]);
+        lp.add_constraint("(methionine+cystine)/lysine_min", vec![1.0, -0.75], ConstraintOp::Ge, 0.0);
+        lp.add_constraint("st", vec![1.0, 1.0], ConstraintOp::Eq, 1.0);
+        let text = lp.to_cplex_lp("names");
+        let read = LpProblem::from_cplex_lp(&text).unwrap();
+        assert_eq!(read.variables, vec!["x", "_2nd"]);
+        assert_eq!(read.constraints[0].name, "(methionine_cystine)/lysine_min");
+        assert_eq!(read.constraints[1].name, "_st");
+        assert_eq!(read.constraints[0].coefficients, vec![1.0, -0.75]);
+    }
+
+    #[test]
+    fn test_cplex_lp_handwritten() {
+        let text = r"
+\ A small example
+Maximize
+ profit: x + 2 y - 0.5z
+Subject To
+ c1: x + y <= 4
+ -x
+   + y >= -1e0
+Bounds
+ y <= 3
+ -inf <= z <= 10
+General
+ y
+End
+";
+        let lp = LpProblem::from_cplex_lp(text).unwrap();
+        assert!(!lp.objective.minimize);
+        assert_eq!(lp.variables, vec!["x", "y", "z"]);
+        assert_eq!(lp.objective.coefficients, vec![1.0, 2.0, -0.5]);
+        assert_eq!(lp.constraints[1].name, "R2");
+        assert_eq!(lp.constraints[1].coefficients, vec![-1.0, 1.0, 0.0]);
+        assert_eq!(lp.constraints[1].rhs, -1.0);
+        assert_eq!(lp.upper_bounds[1], 3.0);
+        assert_eq!((lp.lower_bounds[2], lp.upper_bounds[2]), (f64::NEG_INFINITY, 10.0));
+        assert_eq!(lp.variable_kinds[1], VariableKind::Integer);
+
+        let err = LpProblem::from_cplex_lp("Minimize\n obj: x\nSubject To\n c1: x >=\n").unwrap_err();
+        assert_eq!(err.line, 4);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::problem::{ConstraintOp, LpProblem};
+use crate::simplex::Solver;
+use crate::solution::{ConstraintViolation, SolutionStatus};
+
+impl Solver {
+    /// Find an irreducible infeasible subset (IIS): a set of constraints that
+    /// cannot all hold together, but would if any one of them were removed.
+    ///
+    /// Returns the constraint names in problem order, or an empty list if the
+    /// problem is feasible. Variable bounds are always kept.
+    pub fn find_iis(&self, problem: &LpProblem) -> Vec<String> {
+        self.iis_indices(problem)
+            .into_iter()
+            .map(|i| problem.constraints[i].name.clone())
+            .collect()
+    }
+
+    /// Deletion filter: drop each constraint in turn, and leave it out for good
+    /// if what remains is still infeasible. What survives is irreducible.
+    pub(crate) fn iis_indices(&self, problem: &LpProblem) -> Vec<usize> {
+        // Integrality only matters once the continuous relaxation is feasible
+        let integer = problem.has_integer_variables() && self.is_lp_feasible(problem);
+        let feasible = |p: &LpProblem| {
+            if integer {
+                self.branch_and_bound(p, true).incumbent.is_some()
+            } else {
+                self.is_lp_feasible(p)
+            }
+        };
+
+        if feasible(problem) {
+            return Vec::new();
+        }
+
+        let mut kept: Vec<usize> = (0..problem.num_constraints()).collect();
+        let mut pos = 0;
+        while pos < kept.len() {
+            let mut candidate = kept.clone();
+            candidate.remove(pos);
+            if feasible(&subproblem(problem, &candidate)) {
+                // Needed to explain the infeasibility
+                pos += 1;
+            } else {
+                kept = candidate;
+            }
+        }
+        kept
+    }
+
+    /// Describe each member of a conflict by the closest its left-hand side
+    /// can get to the required value while the rest of the conflict holds
+    pub(crate) fn explain_conflict(&self, problem: &LpProblem, conflict: &[usize]) -> Vec<ConstraintViolation> {
+        conflict
+            .iter()
+            .map(|&i| {
+                let c = &problem.constraints[i];
+                let others: Vec<usize> = conflict.iter().copied().filter(|&k| k != i).collect();
+                let rest = subproblem(problem, &others);
+                let with = if others.is_empty() {
+                    String::new()
+                } else {
+                    let names: Vec<&str> = others.iter().map(|&k| problem.constraints[k].name.as_str()).collect();
+                    format!(" with {}", names.join(", "))
+                };
+
+                let closest = match c.op {
+                    ConstraintOp::Ge => self.extreme_lhs(&rest, &c.coefficients, false),
+                    ConstraintOp::Le => self.extreme_lhs(&rest, &c.coefficients, true),
+                    ConstraintOp::Eq => {
+                        let lowest = self.extreme_lhs(&rest, &c.coefficients, true);
+                        let highest = self.extreme_lhs(&rest, &c.coefficients, false);
+                        match (lowest, highest) {
+                            (Some(lo), _) if lo > c.rhs => Some(lo),
+                            (_, Some(hi)) if hi < c.rhs => Some(hi),
+                            _ => None,
+                        }
+                    }
+                };
+
+                let Some(actual) = closest else {
+                    return ConstraintViolation {
+                        constraint: c.name.clone(),
+                        required: c.rhs,
+                        actual: c.rhs,
+                        violation_amount: 0.0,
+                        description: format!("{} conflicts{}", c.name, with),
+                    };
+                };
+
+                let description = match c.op {
+                    ConstraintOp::Ge => format!(
+                        "{} needs at least {:.2}, but at most {:.2} is possible{}",
+                        c.name, c.rhs, actual, with
+                    ),
+                    ConstraintOp::Le => format!(
+                        "{} allows at most {:.2}, but at least {:.2} is needed{}",
+                        c.name, c.rhs, actual, with
+                    ),
+                    ConstraintOp::Eq => format!(
+                        "{} requires exactly {:.2}, but {:.2} is the closest possible{}",
+                        c.name, c.rhs, actual, with
+                    ),
+                };
+                ConstraintViolation {
+                    constraint: c.name.clone(),
+                    required: c.rhs,
+                    actual,
+                    violation_amount: (c.rhs - actual).abs(),
+                    description,
+                }
+            })
+            .collect()
+    }
+
+    /// Smallest (or largest) value of `coefficients · x` over the problem's constraints
+    fn extreme_lhs(&self, problem: &LpProblem, coefficients: &[f64], minimize: bool) -> Option<f64> {
+        let mut probe = problem.clone();
+        probe.set_objective(coefficients.to_vec(), minimize);
+
+        if probe.has_integer_variables() {
+            self.branch_and_bound(&probe, false).incumbent.map(|s| s.objective_value)
+        } else {
+            let solution = self.solve_relaxed(&probe);
+            (solution.status == SolutionStatus::Optimal).then_some(solution.objective_value)
+        }
+    }
+}
+
+/// The problem restricted to the given constraints
+fn subproblem(problem: &LpProblem, keep: &[usize]) -> LpProblem {
+    let mut sub = problem.clone();
+    sub.constraints = keep.iter().map(|&i| problem.constraints[i].clone()).collect();
+    sub
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::problem::VariableKind;
+
+    /// corn (8% protein) and soy (44% protein) in a 100 unit batch
+    fn feed_problem() -> LpProblem {
+        let mut problem = LpProblem::new(vec!["corn".to_string(), "soy".to_string()]);
+        problem.set_objective(vec![100.0, 300.0], true);
+        problem
+    }
+
+    fn finish(problem: &mut LpProblem) {
+        problem.add_constraint("batch_size", vec![1.0, 1.0], ConstraintOp::Eq, 100.0);
+    }
+
+    #[test]
+    fn test_iis_nutrient_min_and_batch() {
+        let mut problem = feed_problem();
+        problem.add_constraint("protein_min", vec![8.0, 44.0], ConstraintOp::Ge, 5000.0);
+        problem.add_constraint("protein_max", vec![8.0, 44.0], ConstraintOp::Le, 6000.0);
+        problem.add_constraint("corn_max", vec![1.0, 0.0], ConstraintOp::Le, 70.0);
+        finish(&mut problem);
+
+        let solution = Solver::new().solve(&problem);
+
+        assert_eq!(solution.status, SolutionStatus::Infeasible);
+        assert_eq!(solution.conflict, vec!["protein_min", "batch_size"]);
+        // All soy gives at most 4400
+        let protein = &solution.violations[0];
+        assert_eq!(protein.constraint, "protein_min");
+        assert!((protein.actual - 4400.0).abs() < 1e-6);
+        assert!((protein.violation_amount - 600.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_iis_min_max_and_batch() {
+        // Protein needs soy above 30% but soy is capped at 20%
+        let mut problem = feed_problem();
+        problem.add_constraint("protein_min", vec![8.0, 44.0], ConstraintOp::Ge, 2000.0);
+        problem.add_constraint("corn_min", vec![1.0, 0.0], ConstraintOp::Ge, 10.0);
+        problem.add_constraint("soy_max", vec![0.0, 1.0], ConstraintOp::Le, 20.0);
+        problem.add_constraint("corn_max", vec![1.0, 0.0], ConstraintOp::Le, 90.0);
+        finish(&mut problem);
+
+        let conflict = Solver::new().find_iis(&problem);
+
+        assert_eq!(conflict, vec!["protein_min", "soy_max", "batch_size"]);
+    }
+
+    #[test]
+    fn test_iis_feasible() {
+        let mut problem = feed_problem();
+        problem.add_constraint("protein_min", vec![8.0, 44.0], ConstraintOp::Ge, 2000.0);
+        finish(&mut problem);
+
+        assert!(Solver::new().find_iis(&problem).is_empty());
+    }
+
+    #[test]
+    fn test_iis_integer() {
+        // x + y = 3 is fine on its own, but not with x = y in whole numbers
+        let mut problem = LpProblem::new(vec!["x".to_string(), "y".to_string()]);
+        problem.set_objective(vec![1.0, 1.0], true);
+        problem.add_constraint("sum", vec![1.0, 1.0], ConstraintOp::Eq, 3.0);
+        problem.add_constraint("equal", vec![1.0, -1.0], ConstraintOp::Eq, 0.0);
+        problem.add_constraint("x_max", vec![1.0, 0.0], ConstraintOp::Le, 10.0);
+        problem.set_variable_kind(0, VariableKind::Integer);
+
+        let solution = Solver::new().solve(&problem);
+
+        assert_eq!(solution.status, SolutionStatus::Infeasible);
+        assert_eq!(solution.conflict, vec!["sum", "equal"]);
+    }
+}
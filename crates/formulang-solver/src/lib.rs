@@ -1,7 +1,16 @@
+mod basis;
+mod branch_and_bound;
+mod cplex_lp;
+mod iis;
+mod mps;
 mod problem;
+#[cfg(feature = "serde")]
+mod serde_float;
 mod simplex;
 mod solution;
+mod text_format;
 
-pub use problem::{Constraint, ConstraintOp, LpProblem, Objective};
+pub use problem::{Constraint, ConstraintOp, LpProblem, Objective, VariableKind};
 pub use simplex::Solver;
-pub use solution::{Analysis, ConstraintViolation, Solution, SolutionStatus};
+pub use solution::{Analysis, ConstraintViolation, ReducedCost, SensitivityRange, ShadowPrice, Solution, SolutionStatus};
+pub use text_format::FormatError;
@@ -0,0 +1,397 @@
+//! Free-format MPS reading and writing
+//!
+//! Integer variables are marked with `INTORG`/`INTEND` markers and binary
+//! ones with `BV` bounds. `OBJSENSE` is written only for maximization.
+//! Ranged rows (`RANGES`) and objective constants are not supported.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::problem::{ConstraintOp, LpProblem, VariableKind};
+use crate::text_format::{FormatError, parse_number, unique_names};
+
+/// Name of the objective row
+const OBJECTIVE_ROW: &str = "COST";
+
+impl LpProblem {
+    /// Write the problem in free-format MPS. Names keep their spelling, with
+    /// whitespace replaced since MPS fields are separated by it.
+    pub fn to_mps(&self, name: &str) -> String {
+        let fix = |s: &str| s.split_whitespace().collect::<Vec<_>>().join("_");
+        let columns = unique_names(&self.variables, fix);
+        let rows = unique_names(
+            std::iter::once(&OBJECTIVE_ROW.to_string())
+                .chain(self.constraints.iter().map(|c| &c.name))
+                .collect::<Vec<_>>(),
+            fix,
+        );
+        let (objective, rows) = (&rows[0], &rows[1..]);
+
+        let mut out = String::new();
+        let _ = writeln!(out, "NAME {}", fix(name));
+        if !self.objective.minimize {
+            out.push_str("OBJSENSE\n    MAX\n");
+        }
+
+        out.push_str("ROWS\n");
+        let _ = writeln!(out, " N  {}", objective);
+        for (constraint, row) in self.constraints.iter().zip(rows) {
+            let kind = match constraint.op {
+                ConstraintOp::Le => "L",
+                ConstraintOp::Ge => "G",
+                ConstraintOp::Eq => "E",
+            };
+            let _ = writeln!(out, " {}  {}", kind, row);
+        }
+
+        out.push_str("COLUMNS\n");
+        let mut in_integer_block = false;
+        for (j, column) in columns.iter().enumerate() {
+            // Binary variables are marked by their bound instead
+            let integer = self.variable_kinds[j] == VariableKind::Integer;
+            if integer != in_integer_block {
+                let marker = if integer { "INTORG" } else { "INTEND" };
+                let _ = writeln!(out, "    MARKER    'MARKER'    '{}'", marker);
+                in_integer_block = integer;
+            }
+            // The objective entry is always written so that every column appears
+            let cost = self.objective.coefficients.get(j).copied().unwrap_or(0.0);
+            let _ = writeln!(out, "    {}  {}  {}", column, objective, cost);
+            for (constraint, row) in self.constraints.iter().zip(rows) {
+                let value = constraint.coefficients.get(j).copied().unwrap_or(0.0);
+                if value != 0.0 {
+                    let _ = writeln!(out, "    {}  {}  {}", column, row, value);
+                }
+            }
+        }
+        if in_integer_block {
+            out.push_str("    MARKER    'MARKER'    'INTEND'\n");
+        }
+
+        out.push_str("RHS\n");
+        for (constraint, row) in self.constraints.iter().zip(rows) {
+            if constraint.rhs != 0.0 {
+                let _ = writeln!(out, "    RHS  {}  {}", row, constraint.rhs);
+            }
+        }
+
+        let mut bounds = String::new();
+        for (j, column) in columns.iter().enumerate() {
+            let (lower, upper) = (self.lower_bounds[j], self.upper_bounds[j]);
+            let mut bound = |kind: &str, value: Option<f64>| {
+                let _ = match value {
+                    Some(value) => writeln!(bounds, " {} BND  {}  {}", kind, column, value),
+                    None => writeln!(bounds, " {} BND  {}", kind, column),
+                };
+            };
+            match self.variable_kinds[j] {
+                VariableKind::Binary => bound("BV", None),
+                _ if lower == f64::NEG_INFINITY && upper == f64::INFINITY => bound("FR", None),
+                _ if lower == upper => bound("FX", Some(lower)),
+                kind => {
+                    if lower == f64::NEG_INFINITY {
+                        bound("MI", None);
+                    } else if lower != 0.0 {
+                        bound("LO", Some(lower));
+                    }
+                    if upper != f64::INFINITY {
+                        bound("UP", Some(upper));
+                    } else if kind == VariableKind::Integer {
+                        // Some readers default integer columns to an upper bound of 1
+                        bound("PL", None);
+                    }
+                }
+            }
+        }
+        if !bounds.is_empty() {
+            out.push_str("BOUNDS\n");
+            out.push_str(&bounds);
+        }
+
+        out.push_str("ENDATA\n");
+        out
+    }
+
+    /// Read a problem in free-format MPS. Fixed-format files read the same
+    /// way as long as their names contain no spaces.
+    pub fn from_mps(text: &str) -> Result<Self, FormatError> {
+        #[derive(PartialEq)]
+        enum Section {
+            None,
+            Name,
+            ObjSense,
+            Rows,
+            Columns,
+            Rhs,
+            Bounds,
+            End,
+        }
+
+        let mut section = Section::None;
+        let mut minimize = true;
+        let mut objective_row: Option<String> = None;
+        let mut row_index: HashMap<String, usize> = HashMap::new();
+        let mut rows: Vec<(String, ConstraintOp)> = Vec::new();
+        let mut column_index: HashMap<String, usize> = HashMap::new();
+        let mut columns: Vec<String> = Vec::new();
+        let mut kinds: Vec<VariableKind> = Vec::new();
+        let mut costs: Vec<f64> = Vec::new();
+        // (row, column, value) entries of the constraint matrix
+        let mut entries: Vec<(usize, usize, f64)> = Vec::new();
+        let mut rhs: Vec<f64> = Vec::new();
+        let mut bounds: Vec<(f64, f64)> = Vec::new();
+        let mut integer_block = false;
+
+        for (i, raw) in text.lines().enumerate() {
+            let line = i + 1;
+            if raw.trim().is_empty() || raw.starts_with('*') {
+                continue;
+            }
+            let fields: Vec<&str> = raw.split_whitespace().collect();
+
+            // Section headers start in the first column
+            if !raw.starts_with([' ', '\t']) {
+                section = match fields[0].to_ascii_uppercase().as_str() {
+                    "NAME" => Section::Name,
+                    "OBJSENSE" => {
+                        if let Some(sense) = fields.get(1) {
+                            minimize = parse_sense(sense, line)?;
+                        }
+                        Section::ObjSense
+                    }
+                    "ROWS" => Section::Rows,
+                    "COLUMNS" => Section::Columns,
+                    "RHS" => Section::Rhs,
+                    "BOUNDS" => Section::Bounds,
+                    "RANGES" => return Err(FormatError::new(line, "RANGES are not supported")),
+                    "ENDATA" => Section::End,
+                    other => return Err(FormatError::new(line, format!("unknown section {}", other))),
+                };
+                continue;
+            }
+
+            match section {
+                Section::None | Section::Name => {
+                    return Err(FormatError::new(line, "data before the ROWS section"));
+                }
+                Section::End => break,
+                Section::ObjSense => minimize = parse_sense(fields[0], line)?,
+                Section::Rows => {
+                    let [kind, name] = fields[..] else {
+                        return Err(FormatError::new(line, "expected a row type and name"));
+                    };
+                    let op = match kind.to_ascii_uppercase().as_str() {
+                        "N" => {
+                            // Further free rows carry no constraint
+                            objective_row.get_or_insert_with(|| name.to_string());
+                            continue;
+                        }
+                        "L" => ConstraintOp::Le,
+                        "G" => ConstraintOp::Ge,
+                        "E" => ConstraintOp::Eq,
+                        _ => return Err(FormatError::new(line, format!("unknown row type {}", kind))),
+                    };
+                    row_index.insert(name.to_string(), rows.len());
+                    rows.push((name.to_string(), op));
+                    rhs.push(0.0);
+                }
+                Section::Columns => {
+                    if fields.get(1).map(|f| f.trim_matches('\'')) == Some("MARKER") {
+                        match fields.get(2).map(|f| f.trim_matches('\'')) {
+                            Some("INTORG") => integer_block = true,
+                            Some("INTEND") => integer_block = false,
+                            _ => return Err(FormatError::new(line, "unknown marker")),
+                        }
+                        continue;
+                    }
+                    if fields.len().is_multiple_of(2) {
+                        return Err(FormatError::new(line, "expected a column name and row/value pairs"));
+                    }
+                    let j = *column_index.entry(fields[0].to_string()).or_insert_with(|| {
+                        columns.push(fields[0].to_string());
+                        kinds.push(if integer_block {
+                            VariableKind::Integer
+                        } else {
+                            VariableKind::Continuous
+                        });
+                        costs.push(0.0);
+                        bounds.push((0.0, f64::INFINITY));
+                        columns.len() - 1
+                    });
+                    for pair in fields[1..].chunks(2) {
+                        let value = parse_number(pair[1], line)?;
+                        if objective_row.as_deref() == Some(pair[0]) {
+                            costs[j] = value;
+                        } else if let Some(&r) = row_index.get(pair[0]) {
+                            entries.push((r, j, value));
+                        } else {
+                            return Err(FormatError::new(line, format!("unknown row {}", pair[0])));
+                        }
+                    }
+                }
+                Section::Rhs => {
+                    // The RHS set name is optional in free MPS
+                    let pairs = if !fields.len().is_multiple_of(2) { &fields[1..] } else { &fields[..] };
+                    for pair in pairs.chunks(2) {
+                        let value = parse_number(pair[1], line)?;
+                        if objective_row.as_deref() == Some(pair[0]) {
+                            return Err(FormatError::new(line, "objective constants are not supported"));
+                        }
+                        let r = *row_index
+                            .get(pair[0])
+                            .ok_or_else(|| FormatError::new(line, format!("unknown row {}", pair[0])))?;
+                        rhs[r] = value;
+                    }
+                }
+                Section::Bounds => {
+                    let kind = fields[0].to_ascii_uppercase();
+                    let takes_value = !matches!(kind.as_str(), "FR" | "MI" | "PL" | "BV");
+                    // The bound set name is optional in free MPS
+                    let expected = if takes_value { 3 } else { 2 };
+                    let column = match fields.len() {
+                        n if n == expected => fields[1],
+                        n if n == expected + 1 => fields[2],
+                        _ => return Err(FormatError::new(line, "wrong number of fields in bound")),
+                    };
+                    let j = *column_index
+                        .get(column)
+                        .ok_or_else(|| FormatError::new(line, format!("unknown column {}", column)))?;
+                    let value = if takes_value {
+                        parse_number(fields[fields.len() - 1], line)?
+                    } else {
+                        0.0
+                    };
+                    let (lower, upper) = &mut bounds[j];
+                    match kind.as_str() {
+                        "UP" => *upper = value,
+                        "LO" => *lower = value,
+                        "FX" => (*lower, *upper) = (value, value),
+                        "FR" => (*lower, *upper) = (f64::NEG_INFINITY, f64::INFINITY),
+                        "MI" => *lower = f64::NEG_INFINITY,
+                        "PL" => *upper = f64::INFINITY,
+                        "BV" => kinds[j] = VariableKind::Binary,
+                        "LI" => (*lower, kinds[j]) = (value, VariableKind::Integer),
+                        "UI" => (*upper, kinds[j]) = (value, VariableKind::Integer),
+                        _ => return Err(FormatError::new(line, format!("unknown bound type {}", kind))),
+                    }
+                }
+            }
+        }
+
+        let mut problem = LpProblem::new(columns);
+        problem.set_objective(costs, minimize);
+        let mut coefficients = vec![vec![0.0; problem.num_variables()]; rows.len()];
+        for (r, j, value) in entries {
+            coefficients[r][j] = value;
+        }
+        for (((name, op), row), rhs) in rows.into_iter().zip(coefficients).zip(rhs) {
+            problem.add_constraint(name, row, op, rhs);
+        }
+        for (j, (kind, (lower, upper))) in kinds.into_iter().zip(bounds).enumerate() {
+            problem.set_variable_kind(j, kind);
+            problem.set_bounds(j, lower, upper);
+        }
+        Ok(problem)
+    }
+}
+
+fn parse_sense(sense: &str, line: usize) -> Result<bool, FormatError> {
+    match sense.to_ascii_uppercase().as_str() {
+        "MIN" | "MINIMIZE" => Ok(true),
+        "MAX" | "MAXIMIZE" => Ok(false),
+        _ => Err(FormatError::new(line, format!("unknown objective sense {}", sense))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::Solver;
+
+    pub(crate) fn sample_problem() -> LpProblem {
+        let mut lp = LpProblem::new(vec!["corn".to_string(), "grains.soy".to_string()]);
+        lp.set_objective(vec![150.0, 450.0], true);
+        lp.add_constraint("protein_min", vec![8.5, 48.0], ConstraintOp::Ge, 1800.0);
+        lp.add_constraint("calcium/phosphorus_max", vec![0.02, -0.6], ConstraintOp::Le, 0.0);
+        lp.add_constraint("batch_size", vec![1.0, 1.0], ConstraintOp::Eq, 100.0);
+        let used = lp.add_variable("corn_used", VariableKind::Binary);
+        let bags = lp.add_variable("bags", VariableKind::Integer);
+        lp.add_constraint("corn_link", vec![1.0, 0.0, -100.0], ConstraintOp::Le, 0.0);
+        lp.add_constraint("bag_size", vec![1.0, 1.0, 0.0, -25.0], ConstraintOp::Eq, 0.0);
+        lp.set_bounds(1, 5.0, 60.0);
+        lp.set_bounds(bags, f64::NEG_INFINITY, f64::INFINITY);
+        assert_eq!(used, 2);
+        lp
+    }
+
+    pub(crate) fn assert_same_problem(a: &LpProblem, b: &LpProblem) {
+        assert_eq!(a.variables, b.variables);
+        assert_eq!(a.variable_kinds, b.variable_kinds);
+        assert_eq!(a.lower_bounds, b.lower_bounds);
+        assert_eq!(a.upper_bounds, b.upper_bounds);
+        assert_eq!(a.objective.coefficients, b.objective.coefficients);
+        assert_eq!(a.objective.minimize, b.objective.minimize);
+        assert_eq!(a.num_constraints(), b.num_constraints());
+        for (x, y) in a.constraints.iter().zip(&b.constraints) {
+            assert_eq!(x.name, y.name);
+            assert_eq!(x.coefficients, y.coefficients);
+            assert_eq!(x.op, y.op);
+            assert_eq!(x.rhs, y.rhs);
+        }
+    }
+
+    #[test]
+    fn test_mps_round_trip() {
+        let lp = sample_problem();
+        let text = lp.to_mps("starter");
+        assert!(text.contains(" G  protein_min\n"));
+        assert!(text.contains(" BV BND  corn_used\n"));
+        assert!(text.contains(" FR BND  bags\n"));
+
+        let read = LpProblem::from_mps(&text).unwrap();
+        assert_same_problem(&lp, &read);
+
+        let solver = Solver::new();
+        let (a, b) = (solver.solve(&lp), solver.solve(&read));
+        assert_eq!(a.status, b.status);
+        assert!((a.objective_value - b.objective_value).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mps_fixed_format() {
+        let text = "\
+NAME          TESTLP
+OBJSENSE
+    MAX
+ROWS
+ N  PROFIT
+ L  LIM1
+ G  LIM2
+COLUMNS
+    X1        PROFIT         1.0   LIM1           1.0
+    X1        LIM2           1.0
+    X2        PROFIT         2.0   LIM1           1.0
+RHS
+    RHS       LIM1           4.0   LIM2           1.0
+BOUNDS
+ UP BND       X2             3.0
+ENDATA
+";
+        let lp = LpProblem::from_mps(text).unwrap();
+        assert!(!lp.objective.minimize);
+        assert_eq!(lp.variables, vec!["X1", "X2"]);
+        assert_eq!(lp.constraints[0].coefficients, vec![1.0, 1.0]);
+        assert_eq!(lp.constraints[1].rhs, 1.0);
+        assert_eq!(lp.upper_bounds[1], 3.0);
+
+        let solution = Solver::new().solve(&lp);
+        assert!((solution.objective_value - 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mps_errors() {
+        let err = LpProblem::from_mps("ROWS\n N COST\n L c1\nCOLUMNS\n x c2 1\n").unwrap_err();
+        assert_eq!(err.line, 5);
+        assert!(err.message.contains("unknown row c2"));
+    }
+}
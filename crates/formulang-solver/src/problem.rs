@@ -1,8 +1,17 @@
 /// Represents a linear programming problem
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LpProblem {
     /// Variable names
     pub variables: Vec<String>,
+    /// Domain of each variable (continuous unless marked otherwise)
+    pub variable_kinds: Vec<VariableKind>,
+    /// Lower bound of each variable (0 by default, may be `f64::NEG_INFINITY`)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_float::floats"))]
+    pub lower_bounds: Vec<f64>,
+    /// Upper bound of each variable (`f64::INFINITY` by default)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_float::floats"))]
+    pub upper_bounds: Vec<f64>,
     /// Objective function coefficients (costs)
     pub objective: Objective,
     /// Constraints
@@ -10,6 +19,7 @@ pub struct LpProblem {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Objective {
     /// Coefficients for each variable
     pub coefficients: Vec<f64>,
@@ -18,6 +28,7 @@ pub struct Objective {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Constraint {
     /// Name/label for the constraint (for diagnostics)
     pub name: String,
@@ -30,6 +41,20 @@ pub struct Constraint {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum VariableKind {
+    /// Any real value within its bounds
+    Continuous,
+    /// Whole numbers within its bounds
+    Integer,
+    /// Either 0 or 1, whatever its stated bounds
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum ConstraintOp {
     /// Less than or equal (<=)
     Le,
@@ -44,6 +69,9 @@ impl LpProblem {
         let n = variables.len();
         Self {
             variables,
+            variable_kinds: vec![VariableKind::Continuous; n],
+            lower_bounds: vec![0.0; n],
+            upper_bounds: vec![f64::INFINITY; n],
             objective: Objective {
                 coefficients: vec![0.0; n],
                 minimize: true,
@@ -56,7 +84,43 @@ impl LpProblem {
         self.objective = Objective { coefficients, minimize };
     }
 
-    pub fn add_constraint(&mut self, name: impl Into<String>, coefficients: Vec<f64>, op: ConstraintOp, rhs: f64) {
+    /// Add a variable after construction, returning its index.
+    /// Existing constraints and the objective get a zero coefficient for it.
+    pub fn add_variable(&mut self, name: impl Into<String>, kind: VariableKind) -> usize {
+        self.variables.push(name.into());
+        self.variable_kinds.push(kind);
+        self.lower_bounds.push(0.0);
+        self.upper_bounds.push(f64::INFINITY);
+        let n = self.variables.len();
+        self.objective.coefficients.resize(n, 0.0);
+        for c in &mut self.constraints {
+            c.coefficients.resize(n, 0.0);
+        }
+        n - 1
+    }
+
+    pub fn set_variable_kind(&mut self, index: usize, kind: VariableKind) {
+        self.variable_kinds[index] = kind;
+    }
+
+    /// Bound a variable to `lower <= x <= upper`. Bounds are handled by the
+    /// solver directly rather than as constraint rows.
+    pub fn set_bounds(&mut self, index: usize, lower: f64, upper: f64) {
+        self.lower_bounds[index] = lower;
+        self.upper_bounds[index] = upper;
+    }
+
+    /// Whether any variable is restricted to whole numbers
+    pub fn has_integer_variables(&self) -> bool {
+        self.variable_kinds.iter().any(|k| *k != VariableKind::Continuous)
+    }
+
+    /// Add a constraint. Coefficient vectors shorter than the variable list
+    /// are padded with zeros.
+    pub fn add_constraint(&mut self, name: impl Into<String>, mut coefficients: Vec<f64>, op: ConstraintOp, rhs: f64) {
+        if coefficients.len() < self.variables.len() {
+            coefficients.resize(self.variables.len(), 0.0);
+        }
         self.constraints.push(Constraint {
             name: name.into(),
             coefficients,
@@ -0,0 +1,109 @@
+//! Serde helpers for floats that may be infinite
+//!
+//! JSON has no infinity, and serde_json writes it as `null`, which cannot be
+//! read back as a number. Variable bounds, sensitivity ranges, and the
+//! objective of an infeasible problem are often infinite, so these are
+//! written as the strings `"inf"` and `"-inf"` instead.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Float {
+    Number(f64),
+    Text(String),
+}
+
+impl From<f64> for Float {
+    fn from(value: f64) -> Self {
+        if value == f64::INFINITY {
+            Float::Text("inf".to_string())
+        } else if value == f64::NEG_INFINITY {
+            Float::Text("-inf".to_string())
+        } else if value.is_nan() {
+            Float::Text("nan".to_string())
+        } else {
+            Float::Number(value)
+        }
+    }
+}
+
+impl Float {
+    fn into_f64<E: serde::de::Error>(self) -> Result<f64, E> {
+        match self {
+            Float::Number(value) => Ok(value),
+            Float::Text(text) => match text.as_str() {
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                "nan" => Ok(f64::NAN),
+                _ => Err(E::custom(format!("expected a number, \"inf\", or \"-inf\", got \"{}\"", text))),
+            },
+        }
+    }
+}
+
+/// For `f64` fields
+pub mod float {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        Float::from(*value).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        Float::deserialize(deserializer)?.into_f64()
+    }
+}
+
+/// For `Vec<f64>` fields
+pub mod floats {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(values: &[f64], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(|v| Float::from(*v)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f64>, D::Error> {
+        Vec::<Float>::deserialize(deserializer)?
+            .into_iter()
+            .map(Float::into_f64)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ConstraintOp, LpProblem, Solver, SolutionStatus, VariableKind};
+
+    #[test]
+    fn test_problem_and_solution_round_trip() {
+        let mut lp = LpProblem::new(vec!["x".to_string(), "y".to_string()]);
+        lp.set_objective(vec![2.0, 3.0], true);
+        lp.set_variable_kind(1, VariableKind::Integer);
+        lp.set_bounds(0, f64::NEG_INFINITY, 8.0);
+        lp.add_constraint("total", vec![1.0, 1.0], ConstraintOp::Ge, 4.5);
+        lp.add_constraint("x_min", vec![1.0, 0.0], ConstraintOp::Ge, 0.0);
+
+        let json = serde_json::to_string(&lp).unwrap();
+        assert!(json.contains(r#""lower_bounds":["-inf",0.0]"#));
+        assert!(json.contains(r#""upper_bounds":[8.0,"inf"]"#));
+        let replayed: LpProblem = serde_json::from_str(&json).unwrap();
+        assert_eq!(replayed.upper_bounds[1], f64::INFINITY);
+        assert_eq!(replayed.constraints[0].op, ConstraintOp::Ge);
+
+        let solution = Solver::new().solve(&replayed);
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        let json = serde_json::to_string(&solution).unwrap();
+        assert!(json.contains(r#""status":"optimal""#));
+        let restored: crate::Solution = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.values, solution.values);
+        assert_eq!(
+            restored.analysis.objective_sensitivity.len(),
+            solution.analysis.objective_sensitivity.len()
+        );
+
+        let infeasible = serde_json::to_string(&crate::Solution::infeasible()).unwrap();
+        let restored: crate::Solution = serde_json::from_str(&infeasible).unwrap();
+        assert_eq!(restored.objective_value, f64::INFINITY);
+    }
+}
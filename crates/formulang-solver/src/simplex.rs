@@ -1,12 +1,25 @@
-use crate::problem::{ConstraintOp, LpProblem};
-use crate::solution::{Analysis, ConstraintViolation, ReducedCost, SensitivityRange, ShadowPrice, Solution, SolutionStatus};
+use crate::basis::{Basis, SparseMatrix};
+use crate::problem::{ConstraintOp, LpProblem, VariableKind};
+use crate::solution::{Analysis, ReducedCost, SensitivityRange, ShadowPrice, Solution, SolutionStatus};
 
-/// Simplex solver for linear programming problems
+/// Pivots between refactorizations of the basis
+const REFACTOR_INTERVAL: usize = 64;
+
+/// Consecutive degenerate pivots before switching to Bland's rule, which
+/// cannot cycle
+const DEGENERATE_LIMIT: usize = 50;
+
+/// Smallest entry of the entering column accepted as a pivot
+const PIVOT_TOLERANCE: f64 = 1e-9;
+
+/// Revised simplex solver for linear programming problems
 pub struct Solver {
     /// Maximum iterations before giving up
     max_iterations: usize,
     /// Tolerance for floating point comparisons
-    tolerance: f64,
+    pub(crate) tolerance: f64,
+    /// Maximum branch-and-bound nodes explored for integer problems
+    pub(crate) max_nodes: usize,
 }
 
 impl Default for Solver {
@@ -14,6 +27,7 @@ impl Default for Solver {
         Self {
             max_iterations: 10000,
             tolerance: 1e-9,
+            max_nodes: 10000,
         }
     }
 }
@@ -33,500 +47,238 @@ impl Solver {
         self
     }
 
-    /// Solve the LP problem using the two-phase simplex method
+    pub fn with_max_nodes(mut self, max: usize) -> Self {
+        self.max_nodes = max;
+        self
+    }
+
+    /// Solve the problem. Pure LPs go straight to the two-phase simplex;
+    /// problems with integer or binary variables use branch-and-bound.
     pub fn solve(&self, problem: &LpProblem) -> Solution {
-        // Convert to standard form and solve
-        let mut tableau = match self.build_tableau(problem) {
-            Ok(t) => t,
-            Err(_) => return self.solve_with_relaxation(problem),
+        if problem.has_integer_variables() {
+            self.solve_mip(problem)
+        } else {
+            self.solve_lp(problem)
+        }
+    }
+
+    /// Solve the LP problem using the two-phase simplex method,
+    /// ignoring any integrality requirements
+    pub(crate) fn solve_lp(&self, problem: &LpProblem) -> Solution {
+        let Some(mut simplex) = Simplex::new(problem) else {
+            return self.diagnose_infeasible(problem);
         };
 
         // Phase 1: Find initial basic feasible solution
-        if tableau.has_artificial {
-            if !self.phase1(&mut tableau) {
-                return self.solve_with_relaxation(problem);
-            }
+        if !self.phase1(&mut simplex) {
+            return self.diagnose_infeasible(problem);
         }
 
         // Phase 2: Optimize
-        match self.phase2(&mut tableau) {
+        match self.phase2(&mut simplex) {
             SimplexResult::Optimal => {}
             SimplexResult::Unbounded => return Solution::unbounded(),
-            SimplexResult::Infeasible => return self.solve_with_relaxation(problem),
         }
 
-        // Extract solution and add violations field (empty for optimal)
-        let mut solution = self.extract_solution(&tableau, problem);
-        solution.violations = Vec::new();
-        solution
+        self.extract_solution(&simplex, problem)
     }
 
-    /// When the original problem is infeasible, try to find a "best effort" solution
-    /// by relaxing constraints and reporting which ones are violated
-    fn solve_with_relaxation(&self, problem: &LpProblem) -> Solution {
-        // Strategy: solve with only <= constraints (upper bounds) to get a baseline,
-        // then check which >= constraints (lower bounds) are violated
-
-        // Create a relaxed problem with only upper bound constraints
-        let mut relaxed = LpProblem::new(problem.variables.clone());
-        relaxed.set_objective(
-            problem.objective.coefficients.clone(),
-            problem.objective.minimize,
-        );
-
-        // Add only <= constraints (these are usually max limits)
-        // Also add equality constraints that sum to batch size
-        for c in &problem.constraints {
-            match c.op {
-                ConstraintOp::Le => {
-                    relaxed.add_constraint(
-                        c.name.clone(),
-                        c.coefficients.clone(),
-                        c.op,
-                        c.rhs,
-                    );
-                }
-                ConstraintOp::Eq => {
-                    // Keep equality constraints as they define the problem structure
-                    relaxed.add_constraint(
-                        c.name.clone(),
-                        c.coefficients.clone(),
-                        c.op,
-                        c.rhs,
-                    );
-                }
-                ConstraintOp::Ge => {
-                    // Skip >= constraints in relaxed problem
-                }
-            }
-        }
-
-        // Try to solve the relaxed problem
-        let relaxed_solution = self.solve_relaxed(&relaxed);
-
-        if relaxed_solution.status != SolutionStatus::Optimal {
-            // Even relaxed problem fails - analyze direct conflicts
-            return self.analyze_conflicts(problem);
-        }
-
-        // Check which constraints are violated
-        let violations = self.find_violations(problem, &relaxed_solution.values);
+    /// Explain an infeasible problem with an irreducible infeasible subset of
+    /// its constraints. Constraints that may be missed on purpose belong in the
+    /// model as penalized deviation variables (goal programming).
+    pub(crate) fn diagnose_infeasible(&self, problem: &LpProblem) -> Solution {
+        let conflict = self.iis_indices(problem);
+        let mut solution = Solution::infeasible_with_violations(self.explain_conflict(problem, &conflict));
+        solution.conflict = conflict
+            .iter()
+            .map(|&i| problem.constraints[i].name.clone())
+            .collect();
+        solution
+    }
 
-        if violations.is_empty() {
-            // No violations means original should have been feasible - return as optimal
-            let mut sol = relaxed_solution;
-            sol.violations = Vec::new();
-            return sol;
+    /// Whether the constraints admit any solution, ignoring integrality
+    pub(crate) fn is_lp_feasible(&self, problem: &LpProblem) -> bool {
+        match Simplex::new(problem) {
+            Some(mut simplex) => self.phase1(&mut simplex),
+            None => false,
         }
-
-        // Return infeasible with relaxed solution and violations
-        Solution::infeasible_with_relaxed(
-            relaxed_solution.values,
-            relaxed_solution.objective_value,
-            violations,
-        )
     }
 
-    /// Solve without infeasibility recovery (used for relaxed problems)
-    fn solve_relaxed(&self, problem: &LpProblem) -> Solution {
-        let mut tableau = match self.build_tableau(problem) {
-            Ok(t) => t,
-            Err(_) => return Solution::infeasible(),
+    /// Solve without infeasibility diagnosis (used for subproblems)
+    pub(crate) fn solve_relaxed(&self, problem: &LpProblem) -> Solution {
+        let Some(mut simplex) = Simplex::new(problem) else {
+            return Solution::infeasible();
         };
 
-        if tableau.has_artificial {
-            if !self.phase1(&mut tableau) {
-                return Solution::infeasible();
-            }
+        if !self.phase1(&mut simplex) {
+            return Solution::infeasible();
         }
 
-        match self.phase2(&mut tableau) {
+        match self.phase2(&mut simplex) {
             SimplexResult::Optimal => {}
             SimplexResult::Unbounded => return Solution::unbounded(),
-            SimplexResult::Infeasible => return Solution::infeasible(),
         }
 
-        let mut solution = self.extract_solution(&tableau, problem);
-        solution.violations = Vec::new();
-        solution
+        self.extract_solution(&simplex, problem)
     }
 
-    /// Find which constraints are violated by a given solution
-    fn find_violations(&self, problem: &LpProblem, values: &[f64]) -> Vec<ConstraintViolation> {
-        let mut violations = Vec::new();
-
-        for c in &problem.constraints {
-            // Calculate LHS value
-            let mut lhs = 0.0;
-            for (j, &coef) in c.coefficients.iter().enumerate() {
-                if j < values.len() {
-                    lhs += coef * values[j];
-                }
-            }
-
-            let (is_violated, violation_amount, description) = match c.op {
-                ConstraintOp::Le => {
-                    if lhs > c.rhs + self.tolerance {
-                        let amt = lhs - c.rhs;
-                        (true, amt, format!("{} exceeds maximum of {:.2} by {:.2}", c.name, c.rhs, amt))
-                    } else {
-                        (false, 0.0, String::new())
-                    }
-                }
-                ConstraintOp::Ge => {
-                    if lhs < c.rhs - self.tolerance {
-                        let amt = c.rhs - lhs;
-                        (true, amt, format!("{} is below minimum of {:.2} by {:.2}", c.name, c.rhs, amt))
-                    } else {
-                        (false, 0.0, String::new())
-                    }
-                }
-                ConstraintOp::Eq => {
-                    let diff = (lhs - c.rhs).abs();
-                    if diff > self.tolerance {
-                        (true, diff, format!("{} requires exactly {:.2} but got {:.2}", c.name, c.rhs, lhs))
-                    } else {
-                        (false, 0.0, String::new())
-                    }
-                }
-            };
-
-            if is_violated {
-                violations.push(ConstraintViolation {
-                    constraint: c.name.clone(),
-                    required: c.rhs,
-                    actual: lhs,
-                    violation_amount,
-                    description,
-                });
-            }
+    /// Minimize the sum of artificials. Returns false if it can't reach zero.
+    fn phase1(&self, simplex: &mut Simplex) -> bool {
+        let n_cols = simplex.num_columns();
+        if n_cols == simplex.art_start {
+            return true; // The slack basis is already feasible
         }
 
-        // Sort by violation amount (worst first)
-        violations.sort_by(|a, b| b.violation_amount.partial_cmp(&a.violation_amount).unwrap_or(std::cmp::Ordering::Equal));
-
-        violations
-    }
-
-    /// Analyze direct constraint conflicts when even relaxed solve fails
-    fn analyze_conflicts(&self, problem: &LpProblem) -> Solution {
-        let mut violations = Vec::new();
-
-        // Look for obvious conflicts: min > max on the same expression
-        // Group constraints by their coefficient pattern
-        use std::collections::HashMap;
-        let mut constraint_groups: HashMap<Vec<i64>, Vec<&crate::problem::Constraint>> = HashMap::new();
-
-        for c in &problem.constraints {
-            // Create a key from coefficient signs (simplified pattern matching)
-            let key: Vec<i64> = c.coefficients.iter().map(|&x| {
-                if x.abs() < self.tolerance { 0 }
-                else if x > 0.0 { 1 }
-                else { -1 }
-            }).collect();
-            constraint_groups.entry(key).or_default().push(c);
-        }
-
-        // Check each group for conflicts
-        for (_key, constraints) in constraint_groups {
-            let mut min_bound: Option<(f64, &str)> = None;
-            let mut max_bound: Option<(f64, &str)> = None;
-
-            for c in &constraints {
-                match c.op {
-                    ConstraintOp::Ge => {
-                        if min_bound.is_none() || c.rhs > min_bound.unwrap().0 {
-                            min_bound = Some((c.rhs, &c.name));
-                        }
-                    }
-                    ConstraintOp::Le => {
-                        if max_bound.is_none() || c.rhs < max_bound.unwrap().0 {
-                            max_bound = Some((c.rhs, &c.name));
-                        }
-                    }
-                    ConstraintOp::Eq => {
-                        min_bound = Some((c.rhs, &c.name));
-                        max_bound = Some((c.rhs, &c.name));
-                    }
-                }
-            }
-
-            if let (Some((min_val, min_name)), Some((max_val, max_name))) = (min_bound, max_bound) {
-                if min_val > max_val + self.tolerance {
-                    violations.push(ConstraintViolation {
-                        constraint: format!("{} vs {}", min_name, max_name),
-                        required: min_val,
-                        actual: max_val,
-                        violation_amount: min_val - max_val,
-                        description: format!(
-                            "Conflict: {} requires >= {:.2} but {} requires <= {:.2}",
-                            min_name, min_val, max_name, max_val
-                        ),
-                    });
-                }
-            }
+        let mut cost = vec![0.0; n_cols];
+        for c in &mut cost[simplex.art_start..] {
+            *c = 1.0;
         }
+        // Phase 1 is bounded below by zero, so it can't be unbounded
+        self.iterate(simplex, &cost);
 
-        Solution::infeasible_with_violations(violations)
-    }
-
-    fn build_tableau(&self, problem: &LpProblem) -> Result<Tableau, ()> {
-        let n_vars = problem.num_variables();
-        let n_constraints = problem.num_constraints();
-
-        // Count slack and artificial variables needed
-        let mut n_slack = 0;
-        let mut n_artificial = 0;
-
-        for c in &problem.constraints {
-            match c.op {
-                ConstraintOp::Le => n_slack += 1,
-                ConstraintOp::Ge => {
-                    n_slack += 1; // surplus
-                    n_artificial += 1;
-                }
-                ConstraintOp::Eq => n_artificial += 1,
-            }
+        let infeasibility: f64 = simplex.x[simplex.art_start..].iter().sum();
+        let scale = simplex.rhs.iter().fold(1.0, |acc: f64, b| acc.max(b.abs()));
+        if infeasibility > self.tolerance * scale {
+            return false;
         }
 
-        let total_cols = n_vars + n_slack + n_artificial + 1; // +1 for RHS
-        let total_rows = n_constraints + 1; // +1 for objective
-
-        let mut tableau = Tableau {
-            data: vec![vec![0.0; total_cols]; total_rows],
-            basic_vars: vec![0; n_constraints],
-            n_vars,
-            n_slack,
-            n_artificial,
-            has_artificial: n_artificial > 0,
-            constraint_names: problem.constraints.iter().map(|c| c.name.clone()).collect(),
-            variable_names: problem.variables.clone(),
-        };
-
-        // Fill in constraint rows
-        let mut slack_idx = n_vars;
-        let mut artificial_idx = n_vars + n_slack;
-
-        for (i, c) in problem.constraints.iter().enumerate() {
-            // Original variables
-            for (j, &coef) in c.coefficients.iter().enumerate() {
-                tableau.data[i][j] = coef;
-            }
-
-            // RHS (ensure non-negative)
-            let mut rhs = c.rhs;
-            let mut flip = false;
-            if rhs < 0.0 {
-                rhs = -rhs;
-                flip = true;
-                for j in 0..n_vars {
-                    tableau.data[i][j] = -tableau.data[i][j];
-                }
-            }
-            tableau.data[i][total_cols - 1] = rhs;
-
-            // Add slack/surplus/artificial
-            match c.op {
-                ConstraintOp::Le => {
-                    let sign = if flip { -1.0 } else { 1.0 };
-                    tableau.data[i][slack_idx] = sign;
-                    tableau.basic_vars[i] = slack_idx;
-                    slack_idx += 1;
-                }
-                ConstraintOp::Ge => {
-                    let sign = if flip { 1.0 } else { -1.0 };
-                    tableau.data[i][slack_idx] = sign; // surplus
-                    slack_idx += 1;
-                    tableau.data[i][artificial_idx] = 1.0; // artificial
-                    tableau.basic_vars[i] = artificial_idx;
-                    artificial_idx += 1;
-                }
-                ConstraintOp::Eq => {
-                    tableau.data[i][artificial_idx] = 1.0;
-                    tableau.basic_vars[i] = artificial_idx;
-                    artificial_idx += 1;
-                }
+        // Drive degenerate artificials out of the basis where some other
+        // column can take their place, then pin them all to zero
+        for row in 0..simplex.rhs.len() {
+            if simplex.basis.heading[row] >= simplex.art_start {
+                simplex.replace_artificial(row);
             }
         }
-
-        // Objective row (last row)
-        // Simplex maximizes, so for minimization we negate the coefficients
-        // The objective row stores -c for the reduced costs
-        let obj_row = n_constraints;
-        for (j, &coef) in problem.objective.coefficients.iter().enumerate() {
-            tableau.data[obj_row][j] = if problem.objective.minimize { -coef } else { coef };
+        for j in simplex.art_start..n_cols {
+            simplex.upper[j] = 0.0;
         }
 
-        Ok(tableau)
+        true
     }
 
-    fn phase1(&self, tableau: &mut Tableau) -> bool {
-        // Create auxiliary objective: minimize sum of artificial variables
-        // We negate to turn it into maximization (maximize -sum = minimize sum)
-        let n_constraints = tableau.data.len() - 1;
-        let n_cols = tableau.data[0].len();
-        let art_start = tableau.n_vars + tableau.n_slack;
-
-        // Save original objective
-        let orig_obj = tableau.data[n_constraints].clone();
+    fn phase2(&self, simplex: &mut Simplex) -> SimplexResult {
+        let cost = simplex.cost.clone();
+        self.iterate(simplex, &cost)
+    }
 
-        // Set phase 1 objective: maximize -artificials (= minimize artificials)
-        for j in 0..n_cols {
-            tableau.data[n_constraints][j] = 0.0;
-        }
-        for j in art_start..(art_start + tableau.n_artificial) {
-            tableau.data[n_constraints][j] = -1.0;  // Negated for maximization
-        }
+    /// Primal simplex iterations under `cost` from the current feasible basis
+    fn iterate(&self, simplex: &mut Simplex, cost: &[f64]) -> SimplexResult {
+        let mut degenerate = 0;
 
-        // Make objective row consistent with basic artificial variables
-        // For each basic artificial, add its row to cancel the -1 coefficient
-        for i in 0..n_constraints {
-            if tableau.basic_vars[i] >= art_start {
-                for j in 0..n_cols {
-                    tableau.data[n_constraints][j] += tableau.data[i][j];
-                }
+        for _ in 0..self.max_iterations {
+            if simplex.basis.updates >= REFACTOR_INTERVAL {
+                simplex.refactor();
             }
-        }
 
-        // Solve phase 1
-        for _ in 0..self.max_iterations {
-            let Some(pivot_col) = self.find_pivot_column(tableau) else {
-                break;
-            };
-            let Some(pivot_row) = self.find_pivot_row(tableau, pivot_col) else {
-                // Unbounded in phase 1 means infeasible original
-                return false;
+            let y = simplex.duals(cost);
+            let bland = degenerate > DEGENERATE_LIMIT;
+            let Some((entering, d)) = self.find_entering(simplex, cost, &y, bland) else {
+                return SimplexResult::Optimal;
             };
-            self.pivot(tableau, pivot_row, pivot_col);
-        }
 
-        // Check if all artificials are zero
-        let rhs_col = n_cols - 1;
-        for i in 0..n_constraints {
-            if tableau.basic_vars[i] >= art_start {
-                if tableau.data[i][rhs_col].abs() > self.tolerance {
-                    return false; // Infeasible
-                }
-            }
-        }
+            // Move the entering variable up if its reduced cost is negative, down otherwise
+            let dir = if d < 0.0 { 1.0 } else { -1.0 };
+            let mut alpha = simplex.matrix.dense_column(entering);
+            simplex.basis.ftran(&mut alpha);
 
-        // Restore original objective and adjust for basic variables
-        tableau.data[n_constraints] = orig_obj;
-        for i in 0..n_constraints {
-            let basic = tableau.basic_vars[i];
-            if tableau.data[n_constraints][basic].abs() > self.tolerance {
-                let ratio = tableau.data[n_constraints][basic];
-                for j in 0..n_cols {
-                    tableau.data[n_constraints][j] -= ratio * tableau.data[i][j];
+            let flip = simplex.upper[entering] - simplex.lower[entering];
+            let step = match self.find_leaving(simplex, &alpha, dir, bland) {
+                Some((row, ratio)) if ratio < flip => {
+                    simplex.pivot(entering, dir, ratio, row, &alpha);
+                    ratio
                 }
-            }
-        }
-
-        true
-    }
-
-    fn phase2(&self, tableau: &mut Tableau) -> SimplexResult {
-        // Exclude artificial variable columns from pivoting
-        let exclude_from = tableau.n_vars + tableau.n_slack;
-
-        for _ in 0..self.max_iterations {
-            let Some(pivot_col) = self.find_pivot_column_excluding(tableau, exclude_from) else {
-                return SimplexResult::Optimal;
-            };
-            let Some(pivot_row) = self.find_pivot_row(tableau, pivot_col) else {
-                return SimplexResult::Unbounded;
+                _ if flip.is_finite() => {
+                    simplex.flip(entering, dir, flip, &alpha);
+                    flip
+                }
+                _ => return SimplexResult::Unbounded,
             };
-            self.pivot(tableau, pivot_row, pivot_col);
+
+            degenerate = if step <= self.tolerance { degenerate + 1 } else { 0 };
         }
         SimplexResult::Optimal // Max iterations reached, return best found
     }
 
-    fn find_pivot_column(&self, tableau: &Tableau) -> Option<usize> {
-        self.find_pivot_column_excluding(tableau, 0)
-    }
-
-    fn find_pivot_column_excluding(&self, tableau: &Tableau, exclude_from: usize) -> Option<usize> {
-        let obj_row = tableau.data.len() - 1;
-        // Exclude RHS and any columns >= exclude_from
-        let n_cols = if exclude_from > 0 {
-            exclude_from
-        } else {
-            tableau.data[0].len() - 1
-        };
-
-        // Look for the most positive reduced cost (can improve objective)
-        let mut max_val = self.tolerance;
-        let mut max_col = None;
+    /// Nonbasic column whose reduced cost can improve the objective: the
+    /// largest one (Dantzig's rule), or the first one under Bland's rule.
+    /// Returns the column and its reduced cost.
+    fn find_entering(&self, simplex: &Simplex, cost: &[f64], y: &[f64], bland: bool) -> Option<(usize, f64)> {
+        let mut best: Option<(usize, f64)> = None;
 
-        for j in 0..n_cols {
-            if tableau.data[obj_row][j] > max_val {
-                max_val = tableau.data[obj_row][j];
-                max_col = Some(j);
+        for (j, &c) in cost.iter().enumerate() {
+            let state = simplex.state[j];
+            if state == State::Basic || simplex.lower[j] == simplex.upper[j] {
+                continue;
             }
-        }
-
-        max_col
-    }
-
-    fn find_pivot_row(&self, tableau: &Tableau, col: usize) -> Option<usize> {
-        let n_constraints = tableau.data.len() - 1;
-        let rhs_col = tableau.data[0].len() - 1;
-
-        let mut min_ratio = f64::INFINITY;
-        let mut min_row = None;
-
-        for i in 0..n_constraints {
-            let val = tableau.data[i][col];
-            if val > self.tolerance {
-                let ratio = tableau.data[i][rhs_col] / val;
-                if ratio >= 0.0 && ratio < min_ratio {
-                    min_ratio = ratio;
-                    min_row = Some(i);
-                }
+            let d = c - simplex.matrix.dot(j, y);
+            let tol = self.tolerance * (1.0 + c.abs());
+            let improves = match state {
+                State::AtLower => d < -tol,
+                State::AtUpper => d > tol,
+                State::Free => d.abs() > tol,
+                State::Basic => false,
+            };
+            if !improves {
+                continue;
+            }
+            if bland {
+                return Some((j, d));
+            }
+            if best.is_none_or(|(_, best_d)| d.abs() > best_d.abs()) {
+                best = Some((j, d));
             }
         }
 
-        min_row
+        best
     }
 
-    fn pivot(&self, tableau: &mut Tableau, row: usize, col: usize) {
-        let n_rows = tableau.data.len();
-        let n_cols = tableau.data[0].len();
-
-        // Update basic variable
-        tableau.basic_vars[row] = col;
-
-        // Scale pivot row
-        let pivot_val = tableau.data[row][col];
-        for j in 0..n_cols {
-            tableau.data[row][j] /= pivot_val;
-        }
+    /// Ratio test: the row whose basic variable first reaches a bound as the
+    /// entering variable moves in direction `dir`, and the step length.
+    /// Among near-ties the largest pivot is preferred for stability (or the
+    /// lowest variable index under Bland's rule).
+    fn find_leaving(&self, simplex: &Simplex, alpha: &[f64], dir: f64, bland: bool) -> Option<(usize, f64)> {
+        let ratios: Vec<(usize, f64)> = alpha
+            .iter()
+            .enumerate()
+            .filter(|&(_, a)| a.abs() > PIVOT_TOLERANCE)
+            .filter_map(|(row, &a)| {
+                let var = simplex.basis.heading[row];
+                // The basic variable moves by -dir * a per unit step.
+                // Round-off can leave it slightly past its bound; treat that
+                // as being at the bound rather than skipping the row.
+                let change = dir * a;
+                if change > 0.0 {
+                    let lower = simplex.lower[var];
+                    lower.is_finite().then(|| (row, (simplex.x[var] - lower).max(0.0) / change))
+                } else {
+                    let upper = simplex.upper[var];
+                    upper.is_finite().then(|| (row, (upper - simplex.x[var]).max(0.0) / -change))
+                }
+            })
+            .collect();
 
-        // Eliminate column in other rows
-        for i in 0..n_rows {
-            if i != row {
-                let factor = tableau.data[i][col];
-                for j in 0..n_cols {
-                    tableau.data[i][j] -= factor * tableau.data[row][j];
+        let min_ratio = ratios.iter().map(|&(_, r)| r).fold(f64::INFINITY, f64::min);
+        let tie = min_ratio + self.tolerance;
+        ratios
+            .into_iter()
+            .filter(|&(_, r)| r <= tie)
+            .min_by(|&(a, _), &(b, _)| {
+                if bland {
+                    simplex.basis.heading[a].cmp(&simplex.basis.heading[b])
+                } else {
+                    alpha[b].abs().total_cmp(&alpha[a].abs())
                 }
-            }
-        }
+            })
+            .map(|(row, _)| (row, min_ratio))
     }
 
-    fn extract_solution(&self, tableau: &Tableau, problem: &LpProblem) -> Solution {
+    fn extract_solution(&self, simplex: &Simplex, problem: &LpProblem) -> Solution {
         let n_vars = problem.num_variables();
-        let n_constraints = problem.num_constraints();
-        let n_cols = tableau.data[0].len();
-        let rhs_col = n_cols - 1;
-
-        // Extract variable values
-        let mut values = vec![0.0; n_vars];
-        for i in 0..n_constraints {
-            let basic = tableau.basic_vars[i];
-            if basic < n_vars {
-                values[basic] = tableau.data[i][rhs_col];
-            }
-        }
+
+        let values: Vec<f64> = simplex.x[..n_vars]
+            .iter()
+            .map(|&v| clean(v, self.tolerance))
+            .collect();
 
         // Calculate objective value
         let mut objective_value = 0.0;
@@ -535,7 +287,7 @@ impl Solver {
         }
 
         // Perform analysis
-        let analysis = self.analyze(tableau, problem, &values);
+        let analysis = self.analyze(simplex, problem, &values);
 
         Solution {
             status: SolutionStatus::Optimal,
@@ -543,41 +295,46 @@ impl Solver {
             objective_value,
             analysis,
             violations: Vec::new(),
+            conflict: Vec::new(),
         }
     }
 
-    fn analyze(&self, tableau: &Tableau, problem: &LpProblem, values: &[f64]) -> Analysis {
-        let n_vars = problem.num_variables();
-        let n_constraints = problem.num_constraints();
-        let n_cols = tableau.data[0].len();
-        let obj_row = n_constraints;
+    fn analyze(&self, simplex: &Simplex, problem: &LpProblem, values: &[f64]) -> Analysis {
+        // The simplex always minimizes, so maximization costs are negated in it.
+        // `sense` converts its quantities back to the caller's objective.
+        let sense = simplex.sense;
 
-        // Shadow prices: negative of objective row entries for slack variables
+        // Shadow prices: d(objective)/d(rhs) for each constraint, which is
+        // the row's dual value y = c_B B^-1
+        let y = simplex.duals(&simplex.cost);
         let mut shadow_prices = Vec::new();
         for (i, constraint) in problem.constraints.iter().enumerate() {
-            let slack_col = n_vars + i;
-            if slack_col < n_cols - 1 {
-                let value = -tableau.data[obj_row][slack_col];
-                let interpretation = if value.abs() < self.tolerance {
-                    "Non-binding constraint".to_string()
-                } else if value > 0.0 {
-                    format!("Increasing RHS by 1 unit would decrease cost by {:.4}", value)
-                } else {
-                    format!("Increasing RHS by 1 unit would increase cost by {:.4}", -value)
-                };
-                shadow_prices.push(ShadowPrice {
-                    constraint: constraint.name.clone(),
-                    value,
-                    interpretation,
-                });
-            }
+            let value = clean(sense * y[i], self.tolerance);
+
+            let interpretation = if value.abs() < self.tolerance {
+                "Non-binding constraint".to_string()
+            } else if value > 0.0 {
+                format!("Increasing RHS by 1 unit would increase cost by {:.4}", value)
+            } else {
+                format!("Increasing RHS by 1 unit would decrease cost by {:.4}", -value)
+            };
+            shadow_prices.push(ShadowPrice {
+                constraint: constraint.name.clone(),
+                value,
+                interpretation,
+            });
         }
 
-        // Reduced costs
+        // Reduced cost of every column, in the simplex's (minimizing) terms
+        let d: Vec<f64> = (0..simplex.num_columns())
+            .map(|j| simplex.cost[j] - simplex.matrix.dot(j, &y))
+            .collect();
+
+        // Reduced costs, expressed in the caller's objective (>= 0 for a minimization)
         let mut reduced_costs = Vec::new();
         for (j, var_name) in problem.variables.iter().enumerate() {
-            let is_basic = tableau.basic_vars.contains(&j);
-            let rc = if is_basic { 0.0 } else { tableau.data[obj_row][j] };
+            let is_basic = simplex.state[j] == State::Basic;
+            let rc = if is_basic { 0.0 } else { clean(sense * d[j], self.tolerance) };
             reduced_costs.push(ReducedCost {
                 variable: var_name.clone(),
                 value: values[j],
@@ -593,24 +350,43 @@ impl Solver {
             .map(|sp| sp.constraint.clone())
             .collect();
 
-        // Sensitivity ranges (simplified - would need more computation for exact ranges)
-        let objective_sensitivity = problem.variables.iter().enumerate().map(|(j, name)| {
-            SensitivityRange {
-                name: name.clone(),
-                current: problem.objective.coefficients[j],
-                lower_bound: f64::NEG_INFINITY, // Placeholder
-                upper_bound: f64::INFINITY,     // Placeholder
-            }
-        }).collect();
-
-        let rhs_sensitivity = problem.constraints.iter().map(|c| {
-            SensitivityRange {
-                name: c.name.clone(),
-                current: c.rhs,
-                lower_bound: 0.0,           // Placeholder
-                upper_bound: f64::INFINITY, // Placeholder
-            }
-        }).collect();
+        let objective_sensitivity = problem
+            .variables
+            .iter()
+            .enumerate()
+            .map(|(j, name)| {
+                let current = problem.objective.coefficients[j];
+                let (lo, hi) = self.cost_range(simplex, j, &d);
+                // Interval [lo, hi] on the minimized coefficient; for
+                // maximization the cost moves in the opposite direction.
+                let (lower_bound, upper_bound) = if sense > 0.0 {
+                    (current + lo, current + hi)
+                } else {
+                    (current - hi, current - lo)
+                };
+                SensitivityRange {
+                    name: name.clone(),
+                    current,
+                    lower_bound,
+                    upper_bound,
+                }
+            })
+            .collect();
+
+        let rhs_sensitivity = problem
+            .constraints
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let (lo, hi) = self.rhs_range(simplex, i);
+                SensitivityRange {
+                    name: c.name.clone(),
+                    current: c.rhs,
+                    lower_bound: c.rhs + lo,
+                    upper_bound: c.rhs + hi,
+                }
+            })
+            .collect();
 
         Analysis {
             shadow_prices,
@@ -620,23 +396,334 @@ impl Solver {
             rhs_sensitivity,
         }
     }
+
+    /// Allowed change (decrease, increase) of a variable's minimized cost
+    /// before the current basis stops being optimal
+    fn cost_range(&self, simplex: &Simplex, var: usize, d: &[f64]) -> (f64, f64) {
+        if simplex.lower[var] == simplex.upper[var] {
+            return (f64::NEG_INFINITY, f64::INFINITY);
+        }
+        let row = match simplex.state[var] {
+            // Non-basic: it enters once its reduced cost changes sign
+            State::AtLower => return (-d[var].max(0.0), f64::INFINITY),
+            State::AtUpper => return (f64::NEG_INFINITY, (-d[var]).max(0.0)),
+            State::Free => return (0.0, 0.0),
+            State::Basic => simplex.basis.heading.iter().position(|&v| v == var).unwrap(),
+        };
+
+        // Basic: shifting its cost by delta changes every non-basic reduced
+        // cost d_k by -delta * alpha_k, where alpha_k is column k's entry in
+        // this row of B^-1 A; each must keep the sign that makes it optimal.
+        let mut rho = vec![0.0; simplex.rhs.len()];
+        rho[row] = 1.0;
+        simplex.basis.btran(&mut rho);
+
+        let mut lo = f64::NEG_INFINITY;
+        let mut hi = f64::INFINITY;
+        for (k, &dk) in d.iter().enumerate() {
+            if simplex.state[k] == State::Basic || simplex.lower[k] == simplex.upper[k] {
+                continue;
+            }
+            let alpha = simplex.matrix.dot(k, &rho);
+            if alpha.abs() <= self.tolerance {
+                continue;
+            }
+            // At a lower bound d_k must stay >= 0, at an upper bound <= 0
+            let (limit, is_lower_limit) = match simplex.state[k] {
+                State::AtLower => (dk.max(0.0) / alpha, alpha < 0.0),
+                State::AtUpper => (dk.min(0.0) / alpha, alpha > 0.0),
+                _ => return (0.0, 0.0),
+            };
+            if is_lower_limit {
+                lo = lo.max(limit);
+            } else {
+                hi = hi.min(limit);
+            }
+        }
+        (lo, hi)
+    }
+
+    /// Allowed change (decrease, increase) of a constraint's RHS before some
+    /// basic variable is driven past one of its bounds
+    fn rhs_range(&self, simplex: &Simplex, constraint: usize) -> (f64, f64) {
+        // How the basic variables move per unit increase of the RHS
+        let mut beta = vec![0.0; simplex.rhs.len()];
+        beta[constraint] = 1.0;
+        simplex.basis.ftran(&mut beta);
+
+        let mut lo = f64::NEG_INFINITY;
+        let mut hi = f64::INFINITY;
+        for (row, &b) in beta.iter().enumerate() {
+            if b.abs() <= self.tolerance {
+                continue;
+            }
+            let var = simplex.basis.heading[row];
+            let x = simplex.x[var];
+            // Room to each bound, never counted as negative
+            let down = (simplex.lower[var] - x).min(0.0);
+            let up = (simplex.upper[var] - x).max(0.0);
+            if b > 0.0 {
+                lo = lo.max(down / b);
+                hi = hi.min(up / b);
+            } else {
+                lo = lo.max(up / b);
+                hi = hi.min(down / b);
+            }
+        }
+        (lo, hi)
+    }
+}
+
+/// Snap values within tolerance of zero to exactly zero (avoids `-0.0000` noise)
+fn clean(value: f64, tolerance: f64) -> f64 {
+    if value.abs() < tolerance { 0.0 } else { value }
+}
+
+/// Where a column currently sits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Basic,
+    AtLower,
+    AtUpper,
+    /// Non-basic with no finite bound, held at zero
+    Free,
 }
 
-struct Tableau {
-    data: Vec<Vec<f64>>,
-    basic_vars: Vec<usize>,
-    n_vars: usize,
-    n_slack: usize,
-    n_artificial: usize,
-    has_artificial: bool,
-    constraint_names: Vec<String>,
-    variable_names: Vec<String>,
+/// Working form of a problem: `A x + s = b`, with the variable bounds kept
+/// as bounds and one slack per row bounded by the row's sense (`s >= 0` for
+/// `<=`, `s <= 0` for `>=`, `s = 0` for `=`). Rows the starting point misses
+/// get an artificial column as well, which phase 1 drives to zero.
+///
+/// Columns are the problem's variables, then the slacks, then the artificials.
+struct Simplex {
+    matrix: SparseMatrix,
+    rhs: Vec<f64>,
+    lower: Vec<f64>,
+    upper: Vec<f64>,
+    /// Objective of every column, negated for maximization so the simplex
+    /// always minimizes
+    cost: Vec<f64>,
+    /// 1 for minimization, -1 for maximization
+    sense: f64,
+    /// Current value of every column
+    x: Vec<f64>,
+    state: Vec<State>,
+    basis: Basis,
+    art_start: usize,
+}
+
+impl Simplex {
+    /// Set up the slack basis. Returns `None` if some variable's bounds cross.
+    fn new(problem: &LpProblem) -> Option<Self> {
+        let n_vars = problem.num_variables();
+        let n_rows = problem.num_constraints();
+
+        let mut columns = vec![Vec::new(); n_vars];
+        for (i, c) in problem.constraints.iter().enumerate() {
+            for (j, &coef) in c.coefficients.iter().enumerate().take(n_vars) {
+                if coef != 0.0 {
+                    columns[j].push((i, coef));
+                }
+            }
+        }
+        let mut matrix = SparseMatrix::new(n_rows);
+        for column in columns {
+            matrix.push_column(column);
+        }
+
+        let mut lower = problem.lower_bounds.clone();
+        let mut upper = problem.upper_bounds.clone();
+        for (j, kind) in problem.variable_kinds.iter().enumerate() {
+            if *kind == VariableKind::Binary {
+                lower[j] = lower[j].max(0.0);
+                upper[j] = upper[j].min(1.0);
+            }
+        }
+        if lower.iter().zip(&upper).any(|(l, u)| l > u) {
+            return None;
+        }
+
+        let sense = if problem.objective.minimize { 1.0 } else { -1.0 };
+        let mut cost: Vec<f64> = problem.objective.coefficients.iter().map(|c| sense * c).collect();
+        cost.resize(n_vars, 0.0);
+
+        // Variables start at a finite bound, or zero if they have none
+        let mut x = Vec::new();
+        let mut state = Vec::new();
+        for j in 0..n_vars {
+            let (value, s) = if lower[j].is_finite() {
+                (lower[j], State::AtLower)
+            } else if upper[j].is_finite() {
+                (upper[j], State::AtUpper)
+            } else {
+                (0.0, State::Free)
+            };
+            x.push(value);
+            state.push(s);
+        }
+
+        let rhs: Vec<f64> = problem.constraints.iter().map(|c| c.rhs).collect();
+        let mut residual = rhs.clone();
+        for (j, &value) in x.iter().enumerate() {
+            for &(i, coef) in &matrix.columns[j] {
+                residual[i] -= coef * value;
+            }
+        }
+
+        // Slacks take up each row's residual where their bounds allow it
+        let mut heading = vec![0; n_rows];
+        let mut gaps = Vec::new();
+        for (i, c) in problem.constraints.iter().enumerate() {
+            let col = matrix.push_column(vec![(i, 1.0)]);
+            let (lo, hi) = match c.op {
+                ConstraintOp::Le => (0.0, f64::INFINITY),
+                ConstraintOp::Ge => (f64::NEG_INFINITY, 0.0),
+                ConstraintOp::Eq => (0.0, 0.0),
+            };
+            lower.push(lo);
+            upper.push(hi);
+            cost.push(0.0);
+
+            let value = residual[i].clamp(lo, hi);
+            x.push(value);
+            if value == residual[i] {
+                state.push(State::Basic);
+                heading[i] = col;
+            } else {
+                state.push(if value == lo { State::AtLower } else { State::AtUpper });
+                gaps.push((i, residual[i] - value));
+            }
+        }
+
+        // Artificials cover whatever the slacks can't
+        let art_start = matrix.columns.len();
+        for (i, gap) in gaps {
+            heading[i] = matrix.push_column(vec![(i, gap.signum())]);
+            lower.push(0.0);
+            upper.push(f64::INFINITY);
+            cost.push(0.0);
+            x.push(gap.abs());
+            state.push(State::Basic);
+        }
+
+        let mut basis = Basis::new(heading);
+        basis.refactor(&matrix);
+
+        Some(Self {
+            matrix,
+            rhs,
+            lower,
+            upper,
+            cost,
+            sense,
+            x,
+            state,
+            basis,
+            art_start,
+        })
+    }
+
+    fn num_columns(&self) -> usize {
+        self.matrix.columns.len()
+    }
+
+    /// Dual values y = c_B B^-1 for the given costs
+    fn duals(&self, cost: &[f64]) -> Vec<f64> {
+        let mut y: Vec<f64> = self.basis.heading.iter().map(|&var| cost[var]).collect();
+        self.basis.btran(&mut y);
+        y
+    }
+
+    /// Move the entering column by `step` and swap it into the basis at `row`
+    fn pivot(&mut self, entering: usize, dir: f64, step: f64, row: usize, alpha: &[f64]) {
+        self.advance(entering, dir, step, alpha);
+
+        // The leaving variable lands exactly on the bound it reached
+        let leaving = self.basis.heading[row];
+        if dir * alpha[row] > 0.0 {
+            self.x[leaving] = self.lower[leaving];
+            self.state[leaving] = State::AtLower;
+        } else {
+            self.x[leaving] = self.upper[leaving];
+            self.state[leaving] = State::AtUpper;
+        }
+
+        self.state[entering] = State::Basic;
+        self.basis.replace(row, entering, alpha);
+    }
+
+    /// Move the entering column all the way to its other bound, staying non-basic
+    fn flip(&mut self, entering: usize, dir: f64, step: f64, alpha: &[f64]) {
+        self.advance(entering, dir, step, alpha);
+        if dir > 0.0 {
+            self.x[entering] = self.upper[entering];
+            self.state[entering] = State::AtUpper;
+        } else {
+            self.x[entering] = self.lower[entering];
+            self.state[entering] = State::AtLower;
+        }
+    }
+
+    fn advance(&mut self, entering: usize, dir: f64, step: f64, alpha: &[f64]) {
+        if step == 0.0 {
+            return;
+        }
+        for (row, &a) in alpha.iter().enumerate() {
+            self.x[self.basis.heading[row]] -= dir * step * a;
+        }
+        self.x[entering] += dir * step;
+    }
+
+    /// Swap a zero-valued artificial basic at `row` for any non-artificial
+    /// column with a nonzero entry in that row of B^-1 A. If there is none
+    /// the row is redundant and the artificial stays, pinned at zero.
+    fn replace_artificial(&mut self, row: usize) {
+        let mut rho = vec![0.0; self.rhs.len()];
+        rho[row] = 1.0;
+        self.basis.btran(&mut rho);
+
+        let entering = (0..self.art_start)
+            .filter(|&j| self.state[j] != State::Basic)
+            .map(|j| (j, self.matrix.dot(j, &rho)))
+            .filter(|&(_, a)| a.abs() > PIVOT_TOLERANCE)
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()));
+
+        if let Some((j, _)) = entering {
+            let mut alpha = self.matrix.dense_column(j);
+            self.basis.ftran(&mut alpha);
+            let leaving = self.basis.heading[row];
+            self.x[leaving] = 0.0;
+            self.state[leaving] = State::AtLower;
+            self.state[j] = State::Basic;
+            self.basis.replace(row, j, &alpha);
+        }
+    }
+
+    /// Refactorize the basis and recompute the basic values from the
+    /// non-basic ones, shedding the error the updates accumulated
+    fn refactor(&mut self) {
+        if !self.basis.refactor(&self.matrix) {
+            return;
+        }
+
+        let mut values = self.rhs.clone();
+        for j in 0..self.num_columns() {
+            if self.state[j] != State::Basic && self.x[j] != 0.0 {
+                for &(i, coef) in &self.matrix.columns[j] {
+                    values[i] -= coef * self.x[j];
+                }
+            }
+        }
+        self.basis.ftran(&mut values);
+        for (row, &var) in self.basis.heading.iter().enumerate() {
+            self.x[var] = values[row];
+        }
+    }
 }
 
 enum SimplexResult {
     Optimal,
     Unbounded,
-    Infeasible,
 }
 
 #[cfg(test)]
@@ -714,4 +801,130 @@ mod tests {
 
         assert_eq!(solution.status, SolutionStatus::Infeasible);
     }
+
+    #[test]
+    fn test_shadow_prices() {
+        // Minimize 2x + 3y, x + y >= 4, x <= 3, y <= 3 -> x=3, y=1
+        let mut problem = LpProblem::new(vec!["x".to_string(), "y".to_string()]);
+        problem.set_objective(vec![2.0, 3.0], true);
+        problem.add_constraint("sum", vec![1.0, 1.0], ConstraintOp::Ge, 4.0);
+        problem.add_constraint("x_max", vec![1.0, 0.0], ConstraintOp::Le, 3.0);
+        problem.add_constraint("y_max", vec![0.0, 1.0], ConstraintOp::Le, 3.0);
+
+        let solution = Solver::new().solve(&problem);
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+
+        let prices: Vec<f64> = solution.analysis.shadow_prices.iter().map(|sp| sp.value).collect();
+        // One more unit of `sum` is supplied by y at cost 3
+        assert!((prices[0] - 3.0).abs() < 1e-6, "sum = {}", prices[0]);
+        // One more unit of x displaces one unit of y: 2 - 3
+        assert!((prices[1] + 1.0).abs() < 1e-6, "x_max = {}", prices[1]);
+        assert!(prices[2].abs() < 1e-6, "y_max = {}", prices[2]);
+        assert_eq!(solution.analysis.binding_constraints, vec!["sum", "x_max"]);
+    }
+
+    #[test]
+    fn test_objective_ranging() {
+        // Same problem: x stays at its bound while it is no dearer than y,
+        // y stays at 1 while it is no cheaper than x
+        let mut problem = LpProblem::new(vec!["x".to_string(), "y".to_string()]);
+        problem.set_objective(vec![2.0, 3.0], true);
+        problem.add_constraint("sum", vec![1.0, 1.0], ConstraintOp::Ge, 4.0);
+        problem.add_constraint("x_max", vec![1.0, 0.0], ConstraintOp::Le, 3.0);
+        problem.add_constraint("y_max", vec![0.0, 1.0], ConstraintOp::Le, 3.0);
+
+        let solution = Solver::new().solve(&problem);
+        let ranges = &solution.analysis.objective_sensitivity;
+
+        assert_eq!(ranges[0].lower_bound, f64::NEG_INFINITY);
+        assert!((ranges[0].upper_bound - 3.0).abs() < 1e-6, "x upper = {}", ranges[0].upper_bound);
+        assert!((ranges[1].lower_bound - 2.0).abs() < 1e-6, "y lower = {}", ranges[1].lower_bound);
+        assert_eq!(ranges[1].upper_bound, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_objective_ranging_nonbasic() {
+        // Minimize 1x + 5y, x + y = 10 -> y is out of the solution until it costs <= 1
+        let mut problem = LpProblem::new(vec!["x".to_string(), "y".to_string()]);
+        problem.set_objective(vec![1.0, 5.0], true);
+        problem.add_constraint("total", vec![1.0, 1.0], ConstraintOp::Eq, 10.0);
+
+        let solution = Solver::new().solve(&problem);
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+
+        let y = &solution.analysis.reduced_costs[1];
+        assert!(!y.is_basic);
+        assert!((y.reduced_cost - 4.0).abs() < 1e-6, "y reduced cost = {}", y.reduced_cost);
+
+        let range = &solution.analysis.objective_sensitivity[1];
+        assert!((range.lower_bound - 1.0).abs() < 1e-6, "y lower = {}", range.lower_bound);
+        assert_eq!(range.upper_bound, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_rhs_ranging() {
+        let mut problem = LpProblem::new(vec!["x".to_string(), "y".to_string()]);
+        problem.set_objective(vec![2.0, 3.0], true);
+        problem.add_constraint("sum", vec![1.0, 1.0], ConstraintOp::Ge, 4.0);
+        problem.add_constraint("x_max", vec![1.0, 0.0], ConstraintOp::Le, 3.0);
+        problem.add_constraint("y_max", vec![0.0, 1.0], ConstraintOp::Le, 3.0);
+
+        let solution = Solver::new().solve(&problem);
+        let ranges = &solution.analysis.rhs_sensitivity;
+
+        // sum: y absorbs the change while 0 <= y <= 3
+        assert!((ranges[0].lower_bound - 3.0).abs() < 1e-6, "sum lower = {}", ranges[0].lower_bound);
+        assert!((ranges[0].upper_bound - 6.0).abs() < 1e-6, "sum upper = {}", ranges[0].upper_bound);
+        // x_max: y = 4 - x_max must stay within [0, 3]
+        assert!((ranges[1].lower_bound - 1.0).abs() < 1e-6, "x_max lower = {}", ranges[1].lower_bound);
+        assert!((ranges[1].upper_bound - 4.0).abs() < 1e-6, "x_max upper = {}", ranges[1].upper_bound);
+        // y_max: non-binding, can drop to the current y
+        assert!((ranges[2].lower_bound - 1.0).abs() < 1e-6, "y_max lower = {}", ranges[2].lower_bound);
+        assert_eq!(ranges[2].upper_bound, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_rhs_ranging_negative_rhs() {
+        // Minimize x subject to -x <= -2 (the slack basis starts infeasible)
+        let mut problem = LpProblem::new(vec!["x".to_string()]);
+        problem.set_objective(vec![1.0], true);
+        problem.add_constraint("neg", vec![-1.0], ConstraintOp::Le, -2.0);
+
+        let solution = Solver::new().solve(&problem);
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        assert!((solution.values[0] - 2.0).abs() < 1e-6);
+
+        // Raising the RHS toward zero lowers x, and therefore cost
+        let sp = &solution.analysis.shadow_prices[0];
+        assert!((sp.value + 1.0).abs() < 1e-6, "shadow price = {}", sp.value);
+
+        let range = &solution.analysis.rhs_sensitivity[0];
+        assert_eq!(range.lower_bound, f64::NEG_INFINITY);
+        assert!(range.upper_bound.abs() < 1e-6, "upper = {}", range.upper_bound);
+    }
+
+    #[test]
+    fn test_variable_bounds() {
+        // Maximize x + y subject to x + 2y <= 10, 1 <= x <= 4, y >= -1
+        // x sits at its upper bound without a constraint row: x=4, y=3
+        let mut problem = LpProblem::new(vec!["x".to_string(), "y".to_string()]);
+        problem.set_objective(vec![1.0, 1.0], false);
+        problem.add_constraint("sum", vec![1.0, 2.0], ConstraintOp::Le, 10.0);
+        problem.set_bounds(0, 1.0, 4.0);
+        problem.set_bounds(1, -1.0, f64::INFINITY);
+
+        let solution = Solver::new().solve(&problem);
+
+        assert_eq!(solution.status, SolutionStatus::Optimal);
+        assert!((solution.values[0] - 4.0).abs() < 1e-6, "x = {}", solution.values[0]);
+        assert!((solution.values[1] - 3.0).abs() < 1e-6, "y = {}", solution.values[1]);
+        assert_eq!(problem.num_constraints(), 1);
+        // One more unit of x is worth 1 directly, minus the 0.5 it costs y
+        let x = &solution.analysis.reduced_costs[0];
+        assert!(!x.is_basic);
+        assert!((x.reduced_cost - 0.5).abs() < 1e-6, "x reduced cost = {}", x.reduced_cost);
+
+        problem.set_bounds(0, 5.0, 4.0);
+        assert_eq!(Solver::new().solve(&problem).status, SolutionStatus::Infeasible);
+    }
 }
@@ -1,19 +1,26 @@
 /// The result of solving an LP problem
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Solution {
     /// Solution status
     pub status: SolutionStatus,
     /// Optimal values for each variable
     pub values: Vec<f64>,
     /// Optimal objective value
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_float::float"))]
     pub objective_value: f64,
     /// Detailed analysis
     pub analysis: Analysis,
     /// Constraint violations (populated when infeasible)
     pub violations: Vec<ConstraintViolation>,
+    /// Irreducible infeasible subset: names of a smallest set of constraints
+    /// that cannot all hold together (populated when infeasible)
+    pub conflict: Vec<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum SolutionStatus {
     /// An optimal solution was found
     Optimal,
@@ -21,32 +28,40 @@ pub enum SolutionStatus {
     Infeasible,
     /// The problem is unbounded
     Unbounded,
+    /// Branch-and-bound stopped at its node limit. The values are the best
+    /// integer solution found, which may not be optimal, or empty if none was.
+    #[cfg_attr(feature = "serde", serde(rename = "node_limit"))]
+    NodeLimit,
     /// Solver encountered an error
     Error,
 }
 
 /// Detailed analysis of the optimal solution
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Analysis {
     /// Shadow prices (dual values) for each constraint
-    /// Indicates how much the objective would change per unit relaxation
+    /// Indicates how much the objective would change per unit increase of the RHS
     pub shadow_prices: Vec<ShadowPrice>,
 
     /// Reduced costs for each variable
-    /// For non-basic variables, indicates how much cost must change to enter solution
+    /// For non-basic variables, indicates how much cost must decrease to enter solution
     pub reduced_costs: Vec<ReducedCost>,
 
     /// Which constraints are binding (tight) at optimum
     pub binding_constraints: Vec<String>,
 
     /// Sensitivity ranges for objective coefficients
+    /// Each coefficient can move within its range without changing the optimal basis
     pub objective_sensitivity: Vec<SensitivityRange>,
 
     /// Sensitivity ranges for constraint RHS values
+    /// Each RHS can move within its range without changing its shadow price
     pub rhs_sensitivity: Vec<SensitivityRange>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ShadowPrice {
     /// Constraint name
     pub constraint: String,
@@ -57,6 +72,7 @@ pub struct ShadowPrice {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReducedCost {
     /// Variable name
     pub variable: String,
@@ -69,19 +85,23 @@ pub struct ReducedCost {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SensitivityRange {
     /// Variable or constraint name
     pub name: String,
     /// Current value
     pub current: f64,
     /// Lower bound of range where solution structure stays same
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_float::float"))]
     pub lower_bound: f64,
     /// Upper bound of range where solution structure stays same
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_float::float"))]
     pub upper_bound: f64,
 }
 
 /// Information about a violated constraint
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConstraintViolation {
     /// Constraint name
     pub constraint: String,
@@ -103,6 +123,7 @@ impl Solution {
             objective_value: f64::INFINITY,
             analysis: Analysis::empty(),
             violations: Vec::new(),
+            conflict: Vec::new(),
         }
     }
 
@@ -113,20 +134,19 @@ impl Solution {
             objective_value: f64::INFINITY,
             analysis: Analysis::empty(),
             violations,
+            conflict: Vec::new(),
         }
     }
 
-    pub fn infeasible_with_relaxed(
-        values: Vec<f64>,
-        objective_value: f64,
-        violations: Vec<ConstraintViolation>,
-    ) -> Self {
+    /// Branch-and-bound stopped at its node limit before finding any solution
+    pub fn node_limit() -> Self {
         Self {
-            status: SolutionStatus::Infeasible,
-            values,
-            objective_value,
+            status: SolutionStatus::NodeLimit,
+            values: Vec::new(),
+            objective_value: f64::INFINITY,
             analysis: Analysis::empty(),
-            violations,
+            violations: Vec::new(),
+            conflict: Vec::new(),
         }
     }
 
@@ -137,6 +157,17 @@ impl Solution {
             objective_value: f64::NEG_INFINITY,
             analysis: Analysis::empty(),
             violations: Vec::new(),
+            conflict: Vec::new(),
+        }
+    }
+
+    /// Whether the values are a feasible solution: the optimum, or the best
+    /// one found before the node limit
+    pub fn has_values(&self) -> bool {
+        match self.status {
+            SolutionStatus::Optimal => true,
+            SolutionStatus::NodeLimit => !self.values.is_empty(),
+            _ => false,
         }
     }
 }
@@ -0,0 +1,58 @@
+//! Shared pieces of the MPS and CPLEX LP text formats
+
+use thiserror::Error;
+
+/// Error reading a problem from MPS or CPLEX LP text
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("line {line}: {message}")]
+pub struct FormatError {
+    /// 1-based line of the problem text
+    pub line: usize,
+    pub message: String,
+}
+
+impl FormatError {
+    pub(crate) fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+/// Parse a number, accepting `inf`/`infinity` with an optional sign
+pub(crate) fn parse_number(text: &str, line: usize) -> Result<f64, FormatError> {
+    let lower = text.to_ascii_lowercase();
+    match lower.trim_start_matches(['+', '-']) {
+        "inf" | "infinity" => Ok(if lower.starts_with('-') {
+            f64::NEG_INFINITY
+        } else {
+            f64::INFINITY
+        }),
+        _ => text
+            .parse()
+            .map_err(|_| FormatError::new(line, format!("expected a number, got `{}`", text))),
+    }
+}
+
+/// Make names valid and distinct, replacing characters `valid` rejects with
+/// `_` and numbering repeats. Names that need no change are kept as they are.
+pub(crate) fn unique_names<'a>(
+    names: impl IntoIterator<Item = &'a String>,
+    valid: impl Fn(&str) -> String,
+) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    names
+        .into_iter()
+        .map(|name| {
+            let base = valid(name);
+            let mut candidate = base.clone();
+            let mut n = 2;
+            while !seen.insert(candidate.clone()) {
+                candidate = format!("{}_{}", base, n);
+                n += 1;
+            }
+            candidate
+        })
+        .collect()
+}
@@ -404,6 +404,7 @@ interface WasmDiagnostic {
   start: number;
   end: number;
   severity: string;
+  code: string;
   message: string;
 }
 
@@ -567,6 +568,7 @@ export function createDiagnosticsUpdater(monaco: typeof Monaco) {
           markers.push({
             severity: d.severity === 'error' ? 8 : 4, // Error or Warning
             message: d.message,
+            code: d.code,
             startLineNumber: startPos.lineNumber,
             startColumn: startPos.column,
             endLineNumber: endPos.lineNumber,
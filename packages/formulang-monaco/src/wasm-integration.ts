@@ -9,6 +9,7 @@ interface FormulangWasm {
   get_semantic_tokens(source: string): SemanticToken[];
   get_completions(source: string, position: number): Completion[];
   get_hover(source: string, position: number): HoverInfo | null;
+  format(source: string): string;
 }
 
 interface TokenInfo {
@@ -28,6 +29,7 @@ interface Diagnostic {
   start: number;
   end: number;
   severity: string;
+  code: string;
   message: string;
 }
 
@@ -135,6 +137,7 @@ export function createDiagnosticsProvider(
                 ? monaco.MarkerSeverity.Warning
                 : monaco.MarkerSeverity.Info,
           message: d.message,
+          code: d.code,
           startLineNumber: startPos.lineNumber,
           startColumn: startPos.column,
           endLineNumber: endPos.lineNumber,
@@ -246,6 +249,25 @@ export function createWasmHoverProvider(): Monaco.languages.HoverProvider {
   };
 }
 
+/**
+ * Create a WASM-powered formatting provider
+ */
+export function createWasmFormattingProvider(): Monaco.languages.DocumentFormattingEditProvider {
+  return {
+    provideDocumentFormattingEdits(model) {
+      if (!wasmModule) return [];
+
+      try {
+        const formatted = wasmModule.format(model.getValue());
+        return [{ range: model.getFullModelRange(), text: formatted }];
+      } catch (error) {
+        // Source that doesn't parse is left alone; diagnostics report why
+        return [];
+      }
+    },
+  };
+}
+
 /**
  * Register WASM-powered language features
  * This should be called after initWasm() and registerFormulang()
@@ -269,6 +291,11 @@ export function registerWasmFeatures(monaco: typeof Monaco): Monaco.IDisposable[
   // Register WASM-powered hover provider
   disposables.push(monaco.languages.registerHoverProvider(LANGUAGE_ID, createWasmHoverProvider()));
 
+  // Register WASM-powered formatter
+  disposables.push(
+    monaco.languages.registerDocumentFormattingEditProvider(LANGUAGE_ID, createWasmFormattingProvider())
+  );
+
   // Set up diagnostics
   const diagnostics = createDiagnosticsProvider(monaco);
   disposables.push({ dispose: diagnostics.dispose });
@@ -289,5 +316,6 @@ export default {
   createDiagnosticsProvider,
   createWasmCompletionProvider,
   createWasmHoverProvider,
+  createWasmFormattingProvider,
   registerWasmFeatures,
 };
@@ -10,6 +10,7 @@ import {
   type ParseResult,
 } from "@/components/results-panel";
 import { TableConfigProvider } from "@/hooks/use-table-config";
+import type { ConstraintSource } from "@/components/results-table/types";
 
 const STORAGE_KEY = "formulang-playground-code";
 const THEME_STORAGE_KEY = "formulang-playground-theme";
@@ -81,6 +82,9 @@ ingredient limestone {
   name "Limestone"
   cost 50
   nuts {
+    protein 0.0
+    energy 0.0
+    fiber 0.0
     calcium 38.0
     phosphorus 0.0
   }
@@ -90,6 +94,9 @@ ingredient dicalcium_phosphate {
   name "Dicalcium Phosphate"
   cost 600
   nuts {
+    protein 0.0
+    energy 0.0
+    fiber 0.0
     calcium 22.0
     phosphorus 18.5
   }
@@ -118,6 +125,23 @@ formula starter {
 }
 `;
 
+// Unbounded ends of a range come through as "inf" and "-inf"
+interface WasmSensitivityRange {
+  name: string;
+  current: number;
+  lower_bound: number | "-inf";
+  upper_bound: number | "inf";
+}
+
+interface WasmConstraintSource {
+  constraint: string;
+  formula: string;
+  file?: string;
+  span: { start: number; end: number };
+  chain: string[];
+  overrides: string[];
+}
+
 interface WasmSolveResult {
   status: string;
   formula: string;
@@ -126,6 +150,7 @@ interface WasmSolveResult {
   description?: string;
   batch_size: number;
   total_cost: number;
+  dry_matter?: number;
   ingredients: Array<{
     id: string;
     name?: string;
@@ -141,6 +166,7 @@ interface WasmSolveResult {
     name?: string;
     code?: string;
     value: number;
+    dry_matter_value?: number;
     unit?: string;
   }>;
   analysis?: {
@@ -150,6 +176,14 @@ interface WasmSolveResult {
       value: number;
       interpretation: string;
     }>;
+    reduced_costs: Array<{
+      variable: string;
+      value: number;
+      reduced_cost: number;
+      is_basic: boolean;
+    }>;
+    objective_sensitivity: WasmSensitivityRange[];
+    rhs_sensitivity: WasmSensitivityRange[];
   };
   violations: Array<{
     constraint: string;
@@ -158,6 +192,26 @@ interface WasmSolveResult {
     violation_amount: number;
     description: string;
   }>;
+  constraint_sources: WasmConstraintSource[];
+  missing_values: Array<{
+    ingredient: string;
+    nutrient: string;
+    formula: string;
+    span: { start: number; end: number };
+  }>;
+  sub_formulas: WasmSolveResult[];
+}
+
+// Source of a constraint in the editor, for jumping to its definition
+function constraintSource(result: WasmSolveResult, constraint: string): ConstraintSource | undefined {
+  const source = result.constraint_sources.find((s) => s.constraint === constraint);
+  return source && {
+    formula: source.formula,
+    start: source.span.start,
+    end: source.span.end,
+    chain: source.chain,
+    overrides: source.overrides,
+  };
 }
 
 function App() {
@@ -284,7 +338,10 @@ function App() {
             analysis: result.analysis
               ? {
                   bindingConstraints: result.analysis.binding_constraints,
-                  shadowPrices: result.analysis.shadow_prices,
+                  shadowPrices: result.analysis.shadow_prices.map((sp) => ({
+                    ...sp,
+                    source: constraintSource(result, sp.constraint),
+                  })),
                 }
               : undefined,
             violations: result.violations.map((v) => ({
@@ -293,6 +350,7 @@ function App() {
               actual: v.actual,
               violationAmount: v.violation_amount,
               description: v.description,
+              source: constraintSource(result, v.constraint),
             })),
           },
         }));
@@ -17,10 +17,20 @@ export interface Nutrient {
   unit?: string;
 }
 
+// Where a constraint is defined in the editor source
+export interface ConstraintSource {
+  formula: string;
+  start: number;
+  end: number;
+  chain: string[];
+  overrides: string[];
+}
+
 export interface ShadowPrice {
   constraint: string;
   value: number;
   interpretation: string;
+  source?: ConstraintSource;
 }
 
 export interface Violation {
@@ -29,6 +39,7 @@ export interface Violation {
   actual: number;
   violationAmount: number;
   description: string;
+  source?: ConstraintSource;
 }
 
 export interface SolveResult {